@@ -0,0 +1,175 @@
+//! A bounded log of the recent operations on and state transitions of each peripheral.
+//!
+//! When a device misbehaves in production, the last few interactions with it are often the most
+//! useful thing to attach to an error report. Recording is off by default; enable it with
+//! [`set_activity_log_capacity`] and retrieve the log with
+//! [`Peripheral::recent_activity`](super::Peripheral::recent_activity). Values are summarised
+//! according to the [`ValueLogPolicy`](super::redaction::ValueLogPolicy), as in log messages.
+
+use crate::Result;
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets how many records are kept for each peripheral. This applies to all adapters and
+/// peripherals. Zero, the default, disables recording.
+pub fn set_activity_log_capacity(capacity: usize) {
+    CAPACITY.store(capacity, Ordering::Relaxed);
+}
+
+/// Returns how many records are kept for each peripheral.
+pub fn activity_log_capacity() -> usize {
+    CAPACITY.load(Ordering::Relaxed)
+}
+
+/// An operation on a peripheral, or a change in its state.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActivityRecord {
+    /// When the operation started, or the transition was observed.
+    pub time: SystemTime,
+    /// The name of the operation or transition, e.g. `read` or `disconnected`.
+    pub operation: &'static str,
+    /// A summary of the arguments, e.g. the characteristic UUID.
+    pub details: String,
+    /// How long the operation took. Zero for state transitions.
+    pub duration: Duration,
+    /// The error the operation failed with, if it did.
+    pub error: Option<String>,
+}
+
+impl Display for ActivityRecord {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let since_epoch = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        write!(
+            f,
+            "{}.{:03} {}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis(),
+            self.operation
+        )?;
+        if !self.details.is_empty() {
+            write!(f, " {}", self.details)?;
+        }
+        if !self.duration.is_zero() {
+            write!(f, " ({:?})", self.duration)?;
+        }
+        match &self.error {
+            Some(error) => write!(f, ": {}", error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The ring buffer of records for one peripheral.
+#[derive(Debug, Default)]
+pub(crate) struct ActivityLog {
+    records: Mutex<VecDeque<ActivityRecord>>,
+}
+
+impl ActivityLog {
+    fn push(&self, record: ActivityRecord, capacity: usize) {
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Records a state transition, if recording is enabled.
+    pub(crate) fn transition(&self, operation: &'static str) {
+        let capacity = activity_log_capacity();
+        if capacity > 0 {
+            let record = ActivityRecord {
+                time: SystemTime::now(),
+                operation,
+                details: String::new(),
+                duration: Duration::ZERO,
+                error: None,
+            };
+            self.push(record, capacity);
+        }
+    }
+
+    /// Runs the given operation, recording it and its outcome if recording is enabled. `details`
+    /// is only called if the record will be kept.
+    pub(crate) async fn operation<T>(
+        &self,
+        operation: &'static str,
+        details: impl FnOnce() -> String,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if activity_log_capacity() == 0 {
+            return future.await;
+        }
+        let details = details();
+        let time = SystemTime::now();
+        let start = Instant::now();
+        let result = future.await;
+        let record = ActivityRecord {
+            time,
+            operation,
+            details,
+            duration: start.elapsed(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        // The capacity may have changed meanwhile.
+        let capacity = activity_log_capacity();
+        if capacity > 0 {
+            self.push(record, capacity);
+        }
+        result
+    }
+
+    /// Returns the records, oldest first.
+    pub(crate) fn records(&self) -> Vec<ActivityRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(operation: &'static str) -> ActivityRecord {
+        ActivityRecord {
+            time: SystemTime::UNIX_EPOCH + Duration::from_millis(1500),
+            operation,
+            details: String::new(),
+            duration: Duration::ZERO,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn keeps_most_recent() {
+        let log = ActivityLog::default();
+        for operation in ["connect", "read", "write", "disconnect"] {
+            log.push(record(operation), 3);
+        }
+        let operations: Vec<_> = log.records().iter().map(|r| r.operation).collect();
+        assert_eq!(operations, ["read", "write", "disconnect"]);
+        // Shrinking the capacity drops the oldest records on the next push.
+        log.push(record("connect"), 1);
+        assert_eq!(log.records(), [record("connect")]);
+    }
+
+    #[test]
+    fn display() {
+        let mut record = record("read");
+        record.details = "00002a37-0000-1000-8000-00805f9b34fb".to_string();
+        record.duration = Duration::from_millis(12);
+        record.error = Some("Not connected".to_string());
+        assert_eq!(
+            record.to_string(),
+            "1.500 read 00002a37-0000-1000-8000-00805f9b34fb (12ms): Not connected"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Advertising in the peripheral role.
+//!
+//! [`Central::start_advertising`] makes the local adapter advertise an [`Advertisement`], so that
+//! the same application can act as both ends of a connection, or broadcast data to scanners. See
+//! [`Capabilities::advertising`](super::Capabilities::advertising) for which backends support it.
+//!
+//! Most controllers stop a connectable advertisement once a central connects in response to it.
+//! An [`AdvertisingSession`] advertises again according to a [`ResumePolicy`], and reports the
+//! centrals coming and going.
+
+use super::{Central, CentralEvent};
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+use futures::stream::{Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// The contents of an advertisement. New fields may be added in future releases, so construct
+/// this with [`Default`] and the `with_` methods.
+///
+/// Legacy advertisements only have 31 bytes of data, so the platform may fail to advertise if too
+/// much is included; the local name is the first thing to shorten.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Advertisement {
+    pub local_name: Option<String>,
+    /// The UUIDs of the services to advertise.
+    pub services: Vec<Uuid>,
+    /// Manufacturer-specific data, keyed by company ID.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Service data, keyed by service UUID.
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// Whether to include the adapter's transmit power, so that scanners can estimate the path
+    /// loss.
+    pub include_tx_power: bool,
+    /// Whether centrals may connect in response to the advertisement. Otherwise it is only a
+    /// broadcast, which CoreBluetooth can't make.
+    pub connectable: bool,
+}
+
+impl Advertisement {
+    pub fn with_local_name(mut self, local_name: impl Into<String>) -> Self {
+        self.local_name = Some(local_name.into());
+        self
+    }
+
+    pub fn with_service(mut self, service: Uuid) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    pub fn with_manufacturer_data(mut self, company_id: u16, data: impl Into<Vec<u8>>) -> Self {
+        self.manufacturer_data.insert(company_id, data.into());
+        self
+    }
+
+    pub fn with_service_data(mut self, service: Uuid, data: impl Into<Vec<u8>>) -> Self {
+        self.service_data.insert(service, data.into());
+        self
+    }
+
+    pub fn with_tx_power(mut self) -> Self {
+        self.include_tx_power = true;
+        self
+    }
+
+    pub fn with_connectable(mut self) -> Self {
+        self.connectable = true;
+        self
+    }
+}
+
+/// When an [`AdvertisingSession`] advertises again after a central connects.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ResumePolicy {
+    /// Leave advertising to the platform.
+    #[default]
+    Never,
+    /// Advertise again as soon as a central connects, and whenever one disconnects, so that
+    /// several centrals can connect where the controller supports that in the peripheral role.
+    WhileConnected,
+    /// Advertise again once every connected central has disconnected, for peripherals which only
+    /// serve one central at a time.
+    AfterDisconnect,
+}
+
+/// What happens to the advertisement of an [`AdvertisingSession`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AdvertisingEvent {
+    /// A device connected to the adapter. The platform doesn't say whether it connected in
+    /// response to the advertisement, so connections made in the central role are reported too.
+    CentralConnected(PeripheralId),
+    /// A device connected to the adapter disconnected.
+    CentralDisconnected(PeripheralId),
+    /// The advertisement was started again, according to the session's [`ResumePolicy`].
+    Resumed,
+    /// Starting the advertisement again failed. It will be tried again at the next connection or
+    /// disconnection the policy applies to.
+    ResumeFailed(Error),
+}
+
+/// An advertisement which is started again according to a [`ResumePolicy`], and the [`Stream`] of
+/// what happens to it. The advertisement is left running when the session is dropped, but no
+/// longer resumed; use [`stop`](Self::stop) to stop it too.
+pub struct AdvertisingSession<C> {
+    /// Boxed so that the session can be polled without pinning the central.
+    central: Box<C>,
+    events: mpsc::UnboundedReceiver<AdvertisingEvent>,
+    driver: JoinHandle<()>,
+}
+
+impl<C: Debug> Debug for AdvertisingSession<C> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("AdvertisingSession")
+            .field("central", &self.central)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: Central + 'static> AdvertisingSession<C> {
+    /// Starts advertising on the adapter, replacing any advertisement already made through it.
+    pub async fn start(
+        central: &C,
+        advertisement: Advertisement,
+        policy: ResumePolicy,
+    ) -> Result<Self> {
+        // Subscribe first so that no connection is missed.
+        let central_events = central.events().await?;
+        central.start_advertising(advertisement.clone()).await?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let driver = tokio::spawn(drive(
+            central.clone(),
+            advertisement,
+            policy,
+            central_events,
+            sender,
+        ));
+        Ok(Self {
+            central: Box::new(central.clone()),
+            events,
+            driver,
+        })
+    }
+
+    /// Stops advertising, and ends the session.
+    pub async fn stop(self) -> Result<()> {
+        self.driver.abort();
+        self.central.stop_advertising().await
+    }
+}
+
+impl<C> Stream for AdvertisingSession<C> {
+    type Item = AdvertisingEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<AdvertisingEvent>> {
+        self.get_mut().events.poll_recv(cx)
+    }
+}
+
+impl<C> Drop for AdvertisingSession<C> {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+/// Follows the adapter's connections, advertising again as the policy says.
+async fn drive<C: Central>(
+    central: C,
+    advertisement: Advertisement,
+    policy: ResumePolicy,
+    mut central_events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    sender: mpsc::UnboundedSender<AdvertisingEvent>,
+) {
+    let mut connected = HashSet::new();
+    while let Some(event) = central_events.next().await {
+        let resume = match event {
+            CentralEvent::DeviceConnected(id) => {
+                if !connected.insert(id.clone()) {
+                    continue;
+                }
+                let _ = sender.send(AdvertisingEvent::CentralConnected(id));
+                policy == ResumePolicy::WhileConnected
+            }
+            CentralEvent::DeviceDisconnected(id) => {
+                if !connected.remove(&id) {
+                    continue;
+                }
+                let _ = sender.send(AdvertisingEvent::CentralDisconnected(id));
+                policy == ResumePolicy::WhileConnected
+                    || (policy == ResumePolicy::AfterDisconnect && connected.is_empty())
+            }
+            _ => false,
+        };
+        if resume {
+            let event = match central.start_advertising(advertisement.clone()).await {
+                Ok(()) => AdvertisingEvent::Resumed,
+                Err(e) => AdvertisingEvent::ResumeFailed(e),
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{BDAddr, Peripheral as _};
+    use crate::mock;
+    use std::time::Duration;
+
+    fn name(event: &AdvertisingEvent) -> &'static str {
+        match event {
+            AdvertisingEvent::CentralConnected(_) => "connected",
+            AdvertisingEvent::CentralDisconnected(_) => "disconnected",
+            AdvertisingEvent::Resumed => "resumed",
+            AdvertisingEvent::ResumeFailed(_) => "failed",
+        }
+    }
+
+    /// Connects two devices and disconnects them again, checking that the session reports the
+    /// expected events and returning how many times advertising was started.
+    async fn connect_twice(policy: ResumePolicy, expected: &[&str]) -> u32 {
+        let central = mock::Central::new();
+        let first = central.add(BDAddr::from([1, 0, 0, 0, 0, 0]), &[]);
+        let second = central.add(BDAddr::from([2, 0, 0, 0, 0, 0]), &[]);
+        let advertisement = Advertisement::default().with_connectable();
+        let mut session = AdvertisingSession::start(&central, advertisement, policy)
+            .await
+            .unwrap();
+        first.connect().await.unwrap();
+        second.connect().await.unwrap();
+        first.disconnect().await.unwrap();
+        second.disconnect().await.unwrap();
+
+        let mut events = vec![];
+        for _ in expected {
+            let event = tokio::time::timeout(Duration::from_secs(1), session.next()).await;
+            events.push(name(&event.unwrap().unwrap()));
+        }
+        assert_eq!(events, expected);
+        assert!(session.events.try_recv().is_err());
+        central.advertising_starts()
+    }
+
+    #[tokio::test]
+    async fn never_resumes() {
+        let expected = ["connected", "connected", "disconnected", "disconnected"];
+        assert_eq!(connect_twice(ResumePolicy::Never, &expected).await, 1);
+    }
+
+    #[tokio::test]
+    async fn resumes_while_connected() {
+        let expected = [
+            "connected",
+            "resumed",
+            "connected",
+            "resumed",
+            "disconnected",
+            "resumed",
+            "disconnected",
+            "resumed",
+        ];
+        assert_eq!(
+            connect_twice(ResumePolicy::WhileConnected, &expected).await,
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_after_last_disconnection() {
+        let expected = [
+            "connected",
+            "connected",
+            "disconnected",
+            "disconnected",
+            "resumed",
+        ];
+        assert_eq!(
+            connect_twice(ResumePolicy::AfterDisconnect, &expected).await,
+            2
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Helpers for the Immediate Alert and Link Loss services, which tracker tags and other "Find Me"
+//! devices use to beep or flash.
+//!
+//! [`alert`] makes a connected device alert straight away, e.g. to find it, and
+//! [`set_link_loss_alert`] configures how it alerts when the connection is lost, e.g. when it is
+//! left behind. Both only work once the device's services have been discovered with
+//! [`Peripheral::discover_services`].
+
+use super::bleuuid::uuid_from_u16;
+use super::{Characteristic, Peripheral, WriteType};
+use crate::{Error, Result};
+use uuid::Uuid;
+
+/// The UUID of the Immediate Alert service.
+pub const IMMEDIATE_ALERT_SERVICE: Uuid = uuid_from_u16(0x1802);
+/// The UUID of the Link Loss service.
+pub const LINK_LOSS_SERVICE: Uuid = uuid_from_u16(0x1803);
+/// The UUID of the Alert Level characteristic, which both services have.
+pub const ALERT_LEVEL: Uuid = uuid_from_u16(0x2a06);
+
+/// How strongly a device should alert.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum AlertLevel {
+    /// Stop alerting, or don't alert.
+    None = 0,
+    Mild = 1,
+    High = 2,
+}
+
+impl TryFrom<u8> for AlertLevel {
+    type Error = u8;
+
+    /// Converts a value of the Alert Level characteristic, returning it back if it isn't valid.
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Mild),
+            2 => Ok(Self::High),
+            value => Err(value),
+        }
+    }
+}
+
+fn alert_level_characteristic<P: Peripheral>(
+    peripheral: &P,
+    service: Uuid,
+) -> Result<Characteristic> {
+    peripheral
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| {
+            characteristic.service_uuid == service && characteristic.uuid == ALERT_LEVEL
+        })
+        .ok_or(Error::NoSuchCharacteristic)
+}
+
+/// Makes the device alert at the given level until told to stop with [`AlertLevel::None`], or
+/// until the connection is lost.
+pub async fn alert<P: Peripheral>(peripheral: &P, level: AlertLevel) -> Result<()> {
+    let characteristic = alert_level_characteristic(peripheral, IMMEDIATE_ALERT_SERVICE)?;
+    // The Immediate Alert service only supports writing without response.
+    peripheral
+        .write(&characteristic, &[level as u8], WriteType::WithoutResponse)
+        .await
+}
+
+/// Sets how strongly the device alerts when the connection is lost. Devices usually reset this
+/// to [`AlertLevel::None`] once they reconnect, so set it again after every connection.
+pub async fn set_link_loss_alert<P: Peripheral>(peripheral: &P, level: AlertLevel) -> Result<()> {
+    let characteristic = alert_level_characteristic(peripheral, LINK_LOSS_SERVICE)?;
+    peripheral
+        .write(&characteristic, &[level as u8], WriteType::WithResponse)
+        .await
+}
+
+/// Returns how strongly the device alerts when the connection is lost.
+pub async fn link_loss_alert<P: Peripheral>(peripheral: &P) -> Result<AlertLevel> {
+    let characteristic = alert_level_characteristic(peripheral, LINK_LOSS_SERVICE)?;
+    let value = peripheral.read(&characteristic).await?;
+    match value.as_slice() {
+        [level] => AlertLevel::try_from(*level).map_err(|_| Error::InvalidValue(1)),
+        _ => Err(Error::InvalidValue(1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alert_level_values() {
+        for level in [AlertLevel::None, AlertLevel::Mild, AlertLevel::High] {
+            assert_eq!(AlertLevel::try_from(level as u8), Ok(level));
+        }
+        assert_eq!(AlertLevel::try_from(3), Err(3));
+    }
+}
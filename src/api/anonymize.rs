@@ -0,0 +1,225 @@
+//! Privacy-preserving device identifiers for exporting scan analytics.
+//!
+//! A [`DeviceHasher`] derives a stable keyed hash from a device address or [`PeripheralId`], so that
+//! the same device always maps to the same [`DeviceHash`] for a given salt while the raw address
+//! can't be recovered or correlated with exports using a different salt. [`AnonymizedCentralEvent`]
+//! mirrors [`CentralEvent`] with every device identifier replaced by its hash, and can be
+//! serialized in place of the original event.
+
+use super::{BDAddr, CentralEvent};
+use crate::platform::PeripheralId;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use uuid::Uuid;
+
+/// A salted hash of a device identifier, formatted as 16 lowercase hex digits.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DeviceHash(pub u64);
+
+impl Display for DeviceHash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Derives [`DeviceHash`]es using SipHash-2-4 keyed with an application-chosen salt. The output is
+/// stable across runs, platforms and btleplug versions for the same salt.
+#[derive(Clone)]
+pub struct DeviceHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl fmt::Debug for DeviceHasher {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Don't leak the salt into logs.
+        f.debug_struct("DeviceHasher").finish_non_exhaustive()
+    }
+}
+
+impl DeviceHasher {
+    /// Creates a hasher with the given salt, which should be random and kept secret.
+    pub fn new(salt: [u8; 16]) -> Self {
+        Self {
+            k0: u64::from_le_bytes(salt[..8].try_into().unwrap()),
+            k1: u64::from_le_bytes(salt[8..].try_into().unwrap()),
+        }
+    }
+
+    /// Hashes a device address.
+    pub fn hash_address(&self, address: &BDAddr) -> DeviceHash {
+        DeviceHash(siphash24(self.k0, self.k1, address.as_ref()))
+    }
+
+    /// Hashes a platform peripheral ID.
+    pub fn hash_id(&self, id: &PeripheralId) -> DeviceHash {
+        DeviceHash(siphash24(self.k0, self.k1, id.to_string().as_bytes()))
+    }
+
+    /// Converts an event into one which identifies the device only by its hash.
+    pub fn anonymize_event(&self, event: &CentralEvent) -> AnonymizedCentralEvent {
+        match event {
+            CentralEvent::DeviceDiscovered(id) => {
+                AnonymizedCentralEvent::DeviceDiscovered(self.hash_id(id))
+            }
+            CentralEvent::CachedDeviceDiscovered(id) => {
+                AnonymizedCentralEvent::CachedDeviceDiscovered(self.hash_id(id))
+            }
+            CentralEvent::DeviceUpdated(id) => {
+                AnonymizedCentralEvent::DeviceUpdated(self.hash_id(id))
+            }
+            CentralEvent::DeviceConnected(id) => {
+                AnonymizedCentralEvent::DeviceConnected(self.hash_id(id))
+            }
+            CentralEvent::DeviceDisconnected(id) => {
+                AnonymizedCentralEvent::DeviceDisconnected(self.hash_id(id))
+            }
+            CentralEvent::DeviceLost(id) => AnonymizedCentralEvent::DeviceLost(self.hash_id(id)),
+            CentralEvent::ManufacturerDataAdvertisement {
+                id,
+                manufacturer_data,
+            } => AnonymizedCentralEvent::ManufacturerDataAdvertisement {
+                device: self.hash_id(id),
+                manufacturer_data: manufacturer_data.clone(),
+            },
+            CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                AnonymizedCentralEvent::ServiceDataAdvertisement {
+                    device: self.hash_id(id),
+                    service_data: service_data.clone(),
+                }
+            }
+            CentralEvent::ServicesAdvertisement { id, services } => {
+                AnonymizedCentralEvent::ServicesAdvertisement {
+                    device: self.hash_id(id),
+                    services: services.clone(),
+                }
+            }
+            CentralEvent::NameResolved { id, .. } => {
+                AnonymizedCentralEvent::NameResolved(self.hash_id(id))
+            }
+            CentralEvent::BatteryLevelChanged { id, level } => {
+                AnonymizedCentralEvent::BatteryLevelChanged {
+                    device: self.hash_id(id),
+                    level: *level,
+                }
+            }
+        }
+    }
+}
+
+/// A [`CentralEvent`] with the peripheral ID replaced by a [`DeviceHash`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AnonymizedCentralEvent {
+    DeviceDiscovered(DeviceHash),
+    CachedDeviceDiscovered(DeviceHash),
+    DeviceUpdated(DeviceHash),
+    DeviceConnected(DeviceHash),
+    DeviceDisconnected(DeviceHash),
+    DeviceLost(DeviceHash),
+    ManufacturerDataAdvertisement {
+        device: DeviceHash,
+        manufacturer_data: HashMap<u16, Vec<u8>>,
+    },
+    ServiceDataAdvertisement {
+        device: DeviceHash,
+        service_data: HashMap<Uuid, Vec<u8>>,
+    },
+    ServicesAdvertisement {
+        device: DeviceHash,
+        services: Vec<Uuid>,
+    },
+    /// The name itself is left out, as device names often identify their owner.
+    NameResolved(DeviceHash),
+    BatteryLevelChanged {
+        device: DeviceHash,
+        level: u8,
+    },
+}
+
+/// SipHash-2-4, as specified by Aumasson and Bernstein.
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    compress(u64::from_le_bytes(last));
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference_key() -> (u64, u64) {
+        let key: Vec<u8> = (0..16).collect();
+        let hasher = DeviceHasher::new(key.try_into().unwrap());
+        (hasher.k0, hasher.k1)
+    }
+
+    #[test]
+    fn siphash_reference_vectors() {
+        let (k0, k1) = reference_key();
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(k0, k1, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(k0, k1, &message), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn hash_depends_on_salt() {
+        let address = BDAddr::from([1, 2, 3, 4, 5, 6]);
+        let a = DeviceHasher::new([0; 16]);
+        let b = DeviceHasher::new([1; 16]);
+        assert_eq!(a.hash_address(&address), a.hash_address(&address));
+        assert_ne!(a.hash_address(&address), b.hash_address(&address));
+        assert_eq!(a.hash_address(&address).to_string().len(), 16);
+    }
+}
@@ -0,0 +1,96 @@
+//! Error codes returned by a remote device's Attribute Protocol server.
+
+use std::fmt::{self, Display, Formatter};
+
+/// An ATT error code, as sent by the remote device in an Error Response. Codes in the range
+/// `0x80..=0x9f` are application-specific, and `0xe0..=0xff` are defined by profiles.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct AttError(pub u8);
+
+impl AttError {
+    pub const INVALID_HANDLE: Self = Self(0x01);
+    pub const READ_NOT_PERMITTED: Self = Self(0x02);
+    pub const WRITE_NOT_PERMITTED: Self = Self(0x03);
+    pub const INVALID_PDU: Self = Self(0x04);
+    pub const INSUFFICIENT_AUTHENTICATION: Self = Self(0x05);
+    pub const REQUEST_NOT_SUPPORTED: Self = Self(0x06);
+    pub const INVALID_OFFSET: Self = Self(0x07);
+    pub const INSUFFICIENT_AUTHORIZATION: Self = Self(0x08);
+    pub const PREPARE_QUEUE_FULL: Self = Self(0x09);
+    pub const ATTRIBUTE_NOT_FOUND: Self = Self(0x0a);
+    pub const ATTRIBUTE_NOT_LONG: Self = Self(0x0b);
+    pub const INSUFFICIENT_ENCRYPTION_KEY_SIZE: Self = Self(0x0c);
+    pub const INVALID_ATTRIBUTE_VALUE_LENGTH: Self = Self(0x0d);
+    pub const UNLIKELY_ERROR: Self = Self(0x0e);
+    pub const INSUFFICIENT_ENCRYPTION: Self = Self(0x0f);
+    pub const UNSUPPORTED_GROUP_TYPE: Self = Self(0x10);
+    pub const INSUFFICIENT_RESOURCES: Self = Self(0x11);
+
+    /// Returns the name given to this error code by the Bluetooth Core Specification, if any.
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self.0 {
+            0x01 => "Invalid Handle",
+            0x02 => "Read Not Permitted",
+            0x03 => "Write Not Permitted",
+            0x04 => "Invalid PDU",
+            0x05 => "Insufficient Authentication",
+            0x06 => "Request Not Supported",
+            0x07 => "Invalid Offset",
+            0x08 => "Insufficient Authorization",
+            0x09 => "Prepare Queue Full",
+            0x0a => "Attribute Not Found",
+            0x0b => "Attribute Not Long",
+            0x0c => "Insufficient Encryption Key Size",
+            0x0d => "Invalid Attribute Value Length",
+            0x0e => "Unlikely Error",
+            0x0f => "Insufficient Encryption",
+            0x10 => "Unsupported Group Type",
+            0x11 => "Insufficient Resources",
+            _ => return None,
+        })
+    }
+
+    /// Returns true if the device rejected the operation because the link isn't sufficiently
+    /// secure, meaning that it will likely succeed after pairing.
+    ///
+    /// On macOS and iOS pairing is driven by the operating system, which prompts the user the
+    /// first time a protected characteristic is accessed; the operation which triggered the prompt
+    /// still fails with one of these errors, and should be retried once the user has accepted.
+    pub fn requires_pairing(self) -> bool {
+        matches!(
+            self,
+            Self::INSUFFICIENT_AUTHENTICATION
+                | Self::INSUFFICIENT_ENCRYPTION
+                | Self::INSUFFICIENT_ENCRYPTION_KEY_SIZE
+        )
+    }
+}
+
+impl Display for AttError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} (0x{:02x})", name, self.0),
+            None if (0x80..=0x9f).contains(&self.0) => {
+                write!(f, "Application error 0x{:02x}", self.0)
+            }
+            None => write!(f, "Unknown error 0x{:02x}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            AttError::INSUFFICIENT_ENCRYPTION.to_string(),
+            "Insufficient Encryption (0x0f)"
+        );
+        assert_eq!(AttError(0x80).to_string(), "Application error 0x80");
+        assert_eq!(AttError(0x42).to_string(), "Unknown error 0x42");
+        assert!(AttError::INSUFFICIENT_AUTHENTICATION.requires_pairing());
+        assert!(!AttError::INSUFFICIENT_AUTHORIZATION.requires_pairing());
+    }
+}
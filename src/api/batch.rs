@@ -0,0 +1,148 @@
+//! Batches of GATT operations, run back to back by [`Peripheral::batch`].
+//!
+//! Setting up a device often takes several steps, such as writing a configuration, reading it back
+//! and subscribing to notifications. Queuing them in an [`OperationBatch`] runs them one after
+//! another and returns all of their results together, and on Linux keeps operations from other
+//! tasks from being interleaved with them.
+
+use super::{Characteristic, Peripheral, WriteType};
+use crate::Result;
+
+/// An operation queued in an [`OperationBatch`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BatchOperation {
+    Write {
+        characteristic: Characteristic,
+        data: Vec<u8>,
+        write_type: WriteType,
+    },
+    Read(Characteristic),
+    Subscribe(Characteristic),
+    Unsubscribe(Characteristic),
+}
+
+/// The result of a [`BatchOperation`] which succeeded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BatchResult {
+    Written,
+    /// The value read.
+    Read(Vec<u8>),
+    Subscribed,
+    Unsubscribed,
+}
+
+/// The operations queued by the closure passed to [`Peripheral::batch`], in the order they will
+/// run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OperationBatch {
+    operations: Vec<BatchOperation>,
+}
+
+impl OperationBatch {
+    /// Queues a write with response.
+    pub fn write(&mut self, characteristic: &Characteristic, data: &[u8]) -> &mut Self {
+        self.write_with_type(characteristic, data, WriteType::WithResponse)
+    }
+
+    pub fn write_with_type(
+        &mut self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> &mut Self {
+        self.operations.push(BatchOperation::Write {
+            characteristic: characteristic.clone(),
+            data: data.to_vec(),
+            write_type,
+        });
+        self
+    }
+
+    pub fn read(&mut self, characteristic: &Characteristic) -> &mut Self {
+        self.operations
+            .push(BatchOperation::Read(characteristic.clone()));
+        self
+    }
+
+    pub fn subscribe(&mut self, characteristic: &Characteristic) -> &mut Self {
+        self.operations
+            .push(BatchOperation::Subscribe(characteristic.clone()));
+        self
+    }
+
+    pub fn unsubscribe(&mut self, characteristic: &Characteristic) -> &mut Self {
+        self.operations
+            .push(BatchOperation::Unsubscribe(characteristic.clone()));
+        self
+    }
+
+    /// Returns the operations queued so far.
+    pub fn operations(&self) -> &[BatchOperation] {
+        &self.operations
+    }
+
+    /// Runs the operations on the peripheral one after another, stopping at the first which
+    /// fails.
+    pub(crate) async fn run<P: Peripheral>(self, peripheral: &P) -> Result<Vec<BatchResult>> {
+        let mut results = Vec::with_capacity(self.operations.len());
+        for operation in self.operations {
+            results.push(match operation {
+                BatchOperation::Write {
+                    characteristic,
+                    data,
+                    write_type,
+                } => {
+                    peripheral.write(&characteristic, &data, write_type).await?;
+                    BatchResult::Written
+                }
+                BatchOperation::Read(characteristic) => {
+                    BatchResult::Read(peripheral.read(&characteristic).await?)
+                }
+                BatchOperation::Subscribe(characteristic) => {
+                    peripheral.subscribe(&characteristic).await?;
+                    BatchResult::Subscribed
+                }
+                BatchOperation::Unsubscribe(characteristic) => {
+                    peripheral.unsubscribe(&characteristic).await?;
+                    BatchResult::Unsubscribed
+                }
+            });
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::CharPropFlags;
+    use uuid::Uuid;
+
+    #[test]
+    fn queues_in_order() {
+        let characteristic = Characteristic {
+            uuid: Uuid::from_u128(1),
+            service_uuid: Uuid::from_u128(2),
+            service_primary: true,
+            properties: CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY,
+            descriptors: Default::default(),
+        };
+        let mut batch = OperationBatch::default();
+        batch
+            .write(&characteristic, &[1])
+            .read(&characteristic)
+            .subscribe(&characteristic);
+        assert_eq!(
+            batch.operations(),
+            [
+                BatchOperation::Write {
+                    characteristic: characteristic.clone(),
+                    data: vec![1],
+                    write_type: WriteType::WithResponse,
+                },
+                BatchOperation::Read(characteristic.clone()),
+                BatchOperation::Subscribe(characteristic),
+            ]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! The Battery Service, which [`Peripheral::battery_level`] reads where the platform doesn't track
+//! battery levels itself, and [`BatteryServer`] serves in the peripheral role.
+
+use super::bleuuid::uuid_from_u16;
+use super::gatt_server::{CharacteristicHandler, LocalCharacteristic, LocalService};
+use super::{AttError, Central, CharPropFlags, Peripheral};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The UUID of the Battery Service.
+pub const BATTERY_SERVICE: Uuid = uuid_from_u16(0x180f);
+/// The UUID of the Battery Level characteristic, a percentage from 0 to 100.
+pub const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2a19);
+
+/// Reads the Battery Level characteristic of the Battery Service, or returns `None` if service
+/// discovery didn't find it.
+pub(crate) async fn read_battery_service<P: Peripheral>(peripheral: &P) -> Result<Option<u8>> {
+    let Some(characteristic) = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| {
+            characteristic.service_uuid == BATTERY_SERVICE && characteristic.uuid == BATTERY_LEVEL
+        })
+    else {
+        return Ok(None);
+    };
+    match peripheral.read(&characteristic).await?.as_slice() {
+        [level] if *level <= 100 => Ok(Some(*level)),
+        _ => Err(Error::InvalidValue(1)),
+    }
+}
+
+/// Serves a Battery Service with a level which can be changed, notifying the centrals subscribed
+/// to it.
+///
+/// ```no_run
+/// # async fn example<C: btleplug::api::Central>(adapter: C) -> btleplug::Result<()> {
+/// use btleplug::api::battery::BatteryServer;
+///
+/// let battery = BatteryServer::new(100);
+/// adapter.register_gatt_services(vec![battery.service()]).await?;
+/// battery.set_level(&adapter, 99).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct BatteryServer {
+    level: Arc<AtomicU8>,
+}
+
+#[derive(Debug)]
+struct BatteryLevel(Arc<AtomicU8>);
+
+#[async_trait]
+impl CharacteristicHandler for BatteryLevel {
+    async fn read(&self, offset: usize) -> std::result::Result<Vec<u8>, AttError> {
+        if offset > 1 {
+            return Err(AttError::INVALID_OFFSET);
+        }
+        Ok(vec![self.0.load(Ordering::SeqCst)][offset..].to_vec())
+    }
+}
+
+impl BatteryServer {
+    /// Creates a server starting at the given level, in percent. Levels above 100 are treated
+    /// as 100.
+    pub fn new(level: u8) -> Self {
+        Self {
+            level: Arc::new(AtomicU8::new(level.min(100))),
+        }
+    }
+
+    /// Returns the service to register with [`Central::register_gatt_services`], whose Battery
+    /// Level can be read and notified.
+    pub fn service(&self) -> LocalService {
+        LocalService::new(BATTERY_SERVICE).with_characteristic(LocalCharacteristic::new(
+            BATTERY_LEVEL,
+            CharPropFlags::READ | CharPropFlags::NOTIFY,
+            BatteryLevel(self.level.clone()),
+        ))
+    }
+
+    /// Returns the current level, in percent.
+    pub fn level(&self) -> u8 {
+        self.level.load(Ordering::SeqCst)
+    }
+
+    /// Changes the level, treating levels above 100 as 100, and notifies the centrals subscribed
+    /// to it through the adapter the service is registered on. Notifications are only sent when
+    /// the level actually changes.
+    pub async fn set_level<C: Central>(&self, central: &C, level: u8) -> Result<()> {
+        let level = level.min(100);
+        if self.level.swap(level, Ordering::SeqCst) != level {
+            central
+                .notify_local_characteristic(BATTERY_SERVICE, BATTERY_LEVEL, &[level])
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn battery_level() {
+        let battery = BatteryServer::new(150);
+        assert_eq!(battery.level(), 100);
+        let service = battery.service();
+        assert_eq!(service.uuid, BATTERY_SERVICE);
+        let handler = &service.characteristics[0].handler;
+        assert_eq!(handler.read(0).await, Ok(vec![100]));
+
+        let adapter = crate::mock::Central::new();
+        battery.set_level(&adapter, 42).await.unwrap();
+        assert_eq!(handler.read(0).await, Ok(vec![42]));
+        assert_eq!(handler.read(1).await, Ok(vec![]));
+        assert_eq!(handler.read(2).await, Err(AttError::INVALID_OFFSET));
+        // Nothing is sent if the level doesn't change.
+        battery.set_level(&adapter, 42).await.unwrap();
+        assert_eq!(
+            adapter.notified(),
+            [(BATTERY_SERVICE, BATTERY_LEVEL, vec![42])]
+        );
+    }
+}
@@ -106,7 +106,7 @@ impl TryFrom<u64> for BDAddr {
 impl From<BDAddr> for u64 {
     fn from(addr: BDAddr) -> Self {
         let mut slice = [0; 8];
-        (&mut slice[2..]).copy_from_slice(&addr.into_inner());
+        slice[2..].copy_from_slice(&addr.into_inner());
         u64::from_be_bytes(slice)
     }
 }
@@ -127,6 +127,19 @@ impl FromStr for BDAddr {
     }
 }
 
+/// The kind of a random device address, which is given by its two most significant bits.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RandomAddressKind {
+    /// A new address is picked every so often, and can't be traced back to the device.
+    NonResolvablePrivate,
+    /// A new address is picked every so often, which bonded devices can resolve to the identity
+    /// address with the device's Identity Resolving Key.
+    ResolvablePrivate,
+    /// The address stays the same at least until the device is power cycled, so it identifies
+    /// the device like a public address.
+    Static,
+}
+
 impl BDAddr {
     /// Destruct the address into the underlying array.
     pub fn into_inner(self) -> [u8; 6] {
@@ -135,7 +148,18 @@ impl BDAddr {
 
     /// Check if this address is a randomly generated.
     pub fn is_random_static(&self) -> bool {
-        self.address[5] & 0b11 == 0b11
+        self.random_kind() == Some(RandomAddressKind::Static)
+    }
+
+    /// Returns the kind of random address this would be, if it is a random address. `None` means
+    /// the reserved pattern, which no valid random address uses.
+    pub fn random_kind(&self) -> Option<RandomAddressKind> {
+        match self.address[0] >> 6 {
+            0b00 => Some(RandomAddressKind::NonResolvablePrivate),
+            0b01 => Some(RandomAddressKind::ResolvablePrivate),
+            0b11 => Some(RandomAddressKind::Static),
+            _ => None,
+        }
     }
 
     /// Parses a Bluetooth address with colons `:` as delimiters.
@@ -402,6 +426,22 @@ mod tests {
     /// A u64 with the same value as `ADDR`.
     const HEX: u64 = 0x00_00_1f_2a_00_cc_22_f1;
 
+    #[test]
+    fn random_kind() {
+        let addr = |msb| BDAddr::from([msb, 0x00, 0xaa, 0xbb, 0xcc, 0xdd]);
+        assert_eq!(
+            addr(0x2a).random_kind(),
+            Some(RandomAddressKind::NonResolvablePrivate)
+        );
+        assert_eq!(
+            addr(0x4a).random_kind(),
+            Some(RandomAddressKind::ResolvablePrivate)
+        );
+        assert_eq!(addr(0x8a).random_kind(), None);
+        assert!(addr(0xca).is_random_static());
+        assert!(!addr(0x4a).is_random_static());
+    }
+
     #[test]
     fn parse_addr() {
         let addr = BDAddr::from([0x2a, 0x00, 0xaa, 0xbb, 0xcc, 0xdd]);
@@ -1,11 +1,22 @@
 //! Utilities for dealing with BLE UUIDs, converting to and from their short formats.
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
 use uuid::Uuid;
 
 const BLUETOOTH_BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
 const BLUETOOTH_BASE_MASK: u128 = 0x00000000_ffff_ffff_ffff_ffffffffffff;
 const BLUETOOTH_BASE_MASK_16: u128 = 0xffff0000_ffff_ffff_ffff_ffffffffffff;
 
+/// The Characteristic User Description descriptor, a UTF-8 description of the characteristic.
+pub const CHARACTERISTIC_USER_DESCRIPTION: Uuid = uuid_from_u16(0x2901);
+
+/// The Client Characteristic Configuration descriptor, which enables notifications and
+/// indications.
+pub const CLIENT_CHARACTERISTIC_CONFIGURATION: Uuid = uuid_from_u16(0x2902);
+
 // TODO: Make these functions part of the `BleUuid` trait once const fn is allowed there.
 /// Convert a 32-bit BLE short UUID to a full 128-bit UUID by filling in the standard Bluetooth Base
 /// UUID.
@@ -62,6 +73,59 @@ impl BleUuid for Uuid {
     }
 }
 
+/// A pattern matching a set of UUIDs, for use in [`ScanFilter`](crate::api::ScanFilter) and
+/// [`Peripheral::find_characteristics`](crate::api::Peripheral::find_characteristics).
+///
+/// Vendors often allocate a base UUID and number their services and characteristics sequentially
+/// within it, the same way the Bluetooth SIG does with 16-bit aliases, so that whole families of
+/// UUIDs differ only in one short segment.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum UuidPattern {
+    /// Matches exactly the given UUID.
+    Exact(Uuid),
+    /// Matches the given 16-bit alias on the standard Bluetooth Base UUID.
+    Short(u16),
+    /// Matches any UUID which is the same as the given base apart from the 16 bits in the position
+    /// of a Bluetooth 16-bit alias, i.e. `xxxx` in `0000xxxx-0000-1000-8000-00805f9b34fb`.
+    Family(Uuid),
+    /// Matches any UUID starting with the given number of bits, between 0 and 128, of the given
+    /// prefix.
+    Prefix { prefix: Uuid, bits: u8 },
+    /// Matches any UUID which has the same bits as `value` wherever `mask` is set.
+    Masked { value: Uuid, mask: u128 },
+}
+
+impl UuidPattern {
+    /// Returns true if the given UUID matches this pattern.
+    pub fn matches(&self, uuid: &Uuid) -> bool {
+        let (value, mask) = match *self {
+            Self::Exact(value) => (value.as_u128(), u128::MAX),
+            Self::Short(short) => (uuid_from_u16(short).as_u128(), u128::MAX),
+            Self::Family(base) => (base.as_u128(), BLUETOOTH_BASE_MASK_16),
+            Self::Prefix { prefix, bits } => {
+                let mask = match bits {
+                    0 => 0,
+                    bits => u128::MAX << (128 - u32::from(bits.min(128))),
+                };
+                (prefix.as_u128(), mask)
+            }
+            Self::Masked { value, mask } => (value.as_u128(), mask),
+        };
+        uuid.as_u128() & mask == value & mask
+    }
+}
+
+impl From<Uuid> for UuidPattern {
+    fn from(uuid: Uuid) -> Self {
+        Self::Exact(uuid)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,4 +204,26 @@ mod tests {
         let uuid = Uuid::parse_str(uuid_str).unwrap();
         assert_eq!(uuid.to_short_string(), uuid_str);
     }
+
+    #[test]
+    fn uuid_pattern_matches() {
+        let heart_rate = uuid_from_u16(0x180d);
+        assert!(UuidPattern::Short(0x180d).matches(&heart_rate));
+        assert!(!UuidPattern::Short(0x180f).matches(&heart_rate));
+        assert!(UuidPattern::from(heart_rate).matches(&heart_rate));
+
+        let base = Uuid::parse_str("6e400000-b5a3-f393-e0a9-e50e24dcca9e").unwrap();
+        let rx = Uuid::parse_str("6e400002-b5a3-f393-e0a9-e50e24dcca9e").unwrap();
+        let other = Uuid::parse_str("6e400002-b5a3-f393-e0a9-e50e24dcca9f").unwrap();
+        assert!(UuidPattern::Family(base).matches(&rx));
+        assert!(!UuidPattern::Family(base).matches(&other));
+        assert!(UuidPattern::Family(uuid_from_u16(0)).matches(&heart_rate));
+
+        let prefix = |bits| UuidPattern::Prefix { prefix: base, bits };
+        assert!(prefix(16).matches(&other));
+        assert!(!prefix(32).matches(&other));
+        assert!(prefix(0).matches(&Uuid::nil()));
+        assert!(prefix(128).matches(&base));
+        assert!(!prefix(128).matches(&rx));
+    }
 }
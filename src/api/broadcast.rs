@@ -0,0 +1,96 @@
+//! Receiving broadcast protocols which put their payload in service data, such as Exposure
+//! Notifications.
+//!
+//! These protocols send a fresh payload in every advertisement under a 16-bit service UUID, and
+//! receivers want each one rather than the latest state of the device. A
+//! [`ServiceDataBroadcast`] provides the scan options for such a protocol, with duplicate
+//! advertisements still reported, and turns a [`Central`]'s events into a stream of
+//! [`ServiceDataFrame`]s.
+
+use super::bleuuid::uuid_from_u16;
+use super::{Central, CentralEvent, ScanFilter, ScanOptions};
+use crate::platform::PeripheralId;
+use crate::Result;
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// The service UUID of the Exposure Notification protocol.
+pub const EXPOSURE_NOTIFICATION_SERVICE: Uuid = uuid_from_u16(0xfd6f);
+
+/// A payload received from a device under the service of a [`ServiceDataBroadcast`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceDataFrame {
+    pub id: PeripheralId,
+    pub data: Vec<u8>,
+}
+
+/// A broadcast protocol identified by the service its payloads are sent under.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ServiceDataBroadcast {
+    pub service: Uuid,
+}
+
+impl ServiceDataBroadcast {
+    pub fn new(service: Uuid) -> Self {
+        Self { service }
+    }
+
+    /// The Exposure Notification protocol, whose payloads are a 16 byte rolling proximity
+    /// identifier followed by 4 bytes of encrypted metadata.
+    pub fn exposure_notification() -> Self {
+        Self::new(EXPOSURE_NOTIFICATION_SERVICE)
+    }
+
+    /// Returns the options to scan for the protocol with, which only find devices advertising its
+    /// service and keep reporting their advertisements when they repeat.
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions::default()
+            .with_filter(ScanFilter::default().with_services([self.service]))
+            .with_filter_duplicates(false)
+    }
+
+    /// Starts scanning for the protocol, and returns the stream of payloads received.
+    pub async fn scan<C: Central>(
+        &self,
+        central: &C,
+    ) -> Result<Pin<Box<dyn Stream<Item = ServiceDataFrame> + Send>>> {
+        // Subscribe before scanning so that no advertisement is missed.
+        let frames = self.frames(central).await?;
+        central.start_scan_with_options(self.scan_options()).await?;
+        Ok(frames)
+    }
+
+    /// Returns the stream of payloads received by the given central, which must be scanning
+    /// already, e.g. with [`ServiceDataBroadcast::scan_options`].
+    pub async fn frames<C: Central>(
+        &self,
+        central: &C,
+    ) -> Result<Pin<Box<dyn Stream<Item = ServiceDataFrame> + Send>>> {
+        let service = self.service;
+        let events = central.events().await?;
+        Ok(Box::pin(events.filter_map(move |event| {
+            futures::future::ready(match event {
+                CentralEvent::ServiceDataAdvertisement {
+                    id,
+                    mut service_data,
+                } => service_data
+                    .remove(&service)
+                    .map(|data| ServiceDataFrame { id, data }),
+                _ => None,
+            })
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_options() {
+        let options = ServiceDataBroadcast::exposure_notification().scan_options();
+        assert_eq!(options.filter.services, vec![EXPOSURE_NOTIFICATION_SERVICE]);
+        assert!(!options.filter_duplicates);
+    }
+}
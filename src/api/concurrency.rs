@@ -0,0 +1,106 @@
+//! Limits on how many operations may be outstanding at once.
+//!
+//! On Linux every GATT operation is a D-Bus method call to bluetoothd with a 30 second timeout,
+//! and bluetoothd degrades badly when dozens of these pile up, as happens when many devices
+//! reconnect at once. Operations beyond the limits wait for earlier ones to finish before being
+//! sent. The limits apply to all adapters and peripherals, and are currently only enforced by the
+//! BlueZ backend. Zero, the default, means no limit.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+static MAX_OPERATIONS: AtomicUsize = AtomicUsize::new(0);
+static MAX_OPERATIONS_PER_PERIPHERAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets how many operations may be outstanding at once over all peripherals.
+pub fn set_max_concurrent_operations(limit: usize) {
+    MAX_OPERATIONS.store(limit, Ordering::Relaxed);
+}
+
+/// Returns how many operations may be outstanding at once over all peripherals.
+pub fn max_concurrent_operations() -> usize {
+    MAX_OPERATIONS.load(Ordering::Relaxed)
+}
+
+/// Sets how many operations may be outstanding at once on each peripheral.
+pub fn set_max_concurrent_operations_per_peripheral(limit: usize) {
+    MAX_OPERATIONS_PER_PERIPHERAL.store(limit, Ordering::Relaxed);
+}
+
+/// Returns how many operations may be outstanding at once on each peripheral.
+pub fn max_concurrent_operations_per_peripheral() -> usize {
+    MAX_OPERATIONS_PER_PERIPHERAL.load(Ordering::Relaxed)
+}
+
+/// Counts outstanding operations against a limit which may change at any time, so unlike a
+/// semaphore it doesn't have a fixed number of permits.
+#[derive(Debug, Default)]
+pub(crate) struct Limiter {
+    outstanding: Mutex<usize>,
+    released: Notify,
+}
+
+impl Limiter {
+    /// Waits until fewer than `limit` operations are outstanding, then counts one more until the
+    /// returned permit is dropped.
+    pub(crate) async fn acquire(self: &Arc<Self>, limit: usize) -> Permit {
+        loop {
+            // Create the future first so that a release between the check and the await isn't
+            // missed.
+            let released = self.released.notified();
+            if self.try_acquire(limit) {
+                return Permit {
+                    limiter: self.clone(),
+                };
+            }
+            released.await;
+        }
+    }
+
+    fn try_acquire(&self, limit: usize) -> bool {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        if limit == 0 || *outstanding < limit {
+            *outstanding += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// An outstanding operation, counted until this is dropped.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    limiter: Arc<Limiter>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.limiter.outstanding.lock().unwrap() -= 1;
+        self.limiter.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_release() {
+        let limiter = Arc::new(Limiter::default());
+        let first = limiter.acquire(1).await;
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move {
+                limiter.acquire(1).await;
+            }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        // Zero means no limit.
+        drop(limiter.acquire(0).await);
+        drop(first);
+        waiting.await.unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+//! Connection attempts which can be cancelled. See [`Peripheral::connect_cancellable`].
+//!
+//! Dropping the future returned by [`Peripheral::connect`] stops waiting for the connection, but
+//! the platform may carry on trying to connect in the background, and take the device with it
+//! once it succeeds. A [`ConnectAttempt`] also tells the platform to give up, so that e.g. a GUI
+//! can switch to another device straight away when the user changes their mind.
+
+use super::Peripheral;
+use crate::{Error, Result};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+
+/// A connection attempt running in the background, which resolves to the result of
+/// [`Peripheral::connect`]. Dropping it lets the attempt carry on.
+#[derive(Debug)]
+pub struct ConnectAttempt<P> {
+    /// Boxed so that the attempt can be polled without pinning the peripheral.
+    peripheral: Box<P>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl<P: Peripheral + 'static> ConnectAttempt<P> {
+    pub(crate) fn start(peripheral: P) -> Self {
+        let task = tokio::spawn({
+            let peripheral = peripheral.clone();
+            async move { peripheral.connect().await }
+        });
+        Self {
+            peripheral: Box::new(peripheral),
+            task,
+        }
+    }
+
+    /// Returns true if the attempt has finished, whether it succeeded or not.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops the attempt and asks the platform to give up connecting, or to disconnect if the
+    /// connection was made in the meantime. See [`Peripheral::cancel_connect`].
+    pub async fn cancel(self) -> Result<()> {
+        self.task.abort();
+        self.peripheral.cancel_connect().await
+    }
+}
+
+impl<P> Future for ConnectAttempt<P> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().task)
+            .poll(cx)
+            .map(|result| result.unwrap_or_else(|e| Err(Error::Other(e.into()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::BDAddr;
+    use crate::mock;
+
+    #[tokio::test]
+    async fn resolves_to_connection() {
+        let device = mock::Central::new().add(BDAddr::default(), &[]);
+        device.connect_cancellable().await.unwrap();
+        assert!(device.is_connected().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn cancel_leaves_device_disconnected() {
+        let device = mock::Central::new().add(BDAddr::default(), &[]);
+        device.connect_cancellable().cancel().await.unwrap();
+        assert!(!device.is_connected().await.unwrap());
+    }
+}
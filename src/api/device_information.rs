@@ -0,0 +1,145 @@
+//! The Device Information Service, which identifies the manufacturer, model and versions of a
+//! device. [`DeviceInformation::service`] serves it in the peripheral role.
+
+use super::bleuuid::uuid_from_u16;
+use super::gatt_server::{CharacteristicHandler, LocalCharacteristic, LocalService};
+use super::{AttError, CharPropFlags};
+use async_trait::async_trait;
+use uuid::Uuid;
+
+/// The UUID of the Device Information Service.
+pub const DEVICE_INFORMATION_SERVICE: Uuid = uuid_from_u16(0x180a);
+/// The UUID of the Manufacturer Name String characteristic.
+pub const MANUFACTURER_NAME: Uuid = uuid_from_u16(0x2a29);
+/// The UUID of the Model Number String characteristic.
+pub const MODEL_NUMBER: Uuid = uuid_from_u16(0x2a24);
+/// The UUID of the Serial Number String characteristic.
+pub const SERIAL_NUMBER: Uuid = uuid_from_u16(0x2a25);
+/// The UUID of the Hardware Revision String characteristic.
+pub const HARDWARE_REVISION: Uuid = uuid_from_u16(0x2a27);
+/// The UUID of the Firmware Revision String characteristic.
+pub const FIRMWARE_REVISION: Uuid = uuid_from_u16(0x2a26);
+/// The UUID of the Software Revision String characteristic.
+pub const SOFTWARE_REVISION: Uuid = uuid_from_u16(0x2a28);
+
+/// The strings a device identifies itself with. Each one which is set becomes a read-only
+/// characteristic of the service. New fields may be added in future releases, so construct this
+/// with [`Default`] and the `with_` methods.
+///
+/// ```
+/// use btleplug::api::device_information::DeviceInformation;
+///
+/// let service = DeviceInformation::default()
+///     .with_manufacturer_name("Example Ltd")
+///     .with_model_number("EX-1")
+///     .service();
+/// assert_eq!(service.characteristics.len(), 2);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct DeviceInformation {
+    pub manufacturer_name: Option<String>,
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub software_revision: Option<String>,
+}
+
+/// Answers reads of a value which never changes.
+#[derive(Debug)]
+struct FixedValue(Vec<u8>);
+
+#[async_trait]
+impl CharacteristicHandler for FixedValue {
+    async fn read(&self, offset: usize) -> Result<Vec<u8>, AttError> {
+        self.0
+            .get(offset..)
+            .map(<[u8]>::to_vec)
+            .ok_or(AttError::INVALID_OFFSET)
+    }
+}
+
+impl DeviceInformation {
+    pub fn with_manufacturer_name(mut self, manufacturer_name: impl Into<String>) -> Self {
+        self.manufacturer_name = Some(manufacturer_name.into());
+        self
+    }
+
+    pub fn with_model_number(mut self, model_number: impl Into<String>) -> Self {
+        self.model_number = Some(model_number.into());
+        self
+    }
+
+    pub fn with_serial_number(mut self, serial_number: impl Into<String>) -> Self {
+        self.serial_number = Some(serial_number.into());
+        self
+    }
+
+    pub fn with_hardware_revision(mut self, hardware_revision: impl Into<String>) -> Self {
+        self.hardware_revision = Some(hardware_revision.into());
+        self
+    }
+
+    pub fn with_firmware_revision(mut self, firmware_revision: impl Into<String>) -> Self {
+        self.firmware_revision = Some(firmware_revision.into());
+        self
+    }
+
+    pub fn with_software_revision(mut self, software_revision: impl Into<String>) -> Self {
+        self.software_revision = Some(software_revision.into());
+        self
+    }
+
+    /// Returns the service to register with
+    /// [`Central::register_gatt_services`](super::Central::register_gatt_services).
+    pub fn service(&self) -> LocalService {
+        [
+            (MANUFACTURER_NAME, &self.manufacturer_name),
+            (MODEL_NUMBER, &self.model_number),
+            (SERIAL_NUMBER, &self.serial_number),
+            (HARDWARE_REVISION, &self.hardware_revision),
+            (FIRMWARE_REVISION, &self.firmware_revision),
+            (SOFTWARE_REVISION, &self.software_revision),
+        ]
+        .into_iter()
+        .filter_map(|(uuid, value)| Some((uuid, value.as_ref()?)))
+        .fold(
+            LocalService::new(DEVICE_INFORMATION_SERVICE),
+            |service, (uuid, value)| {
+                service.with_characteristic(LocalCharacteristic::new(
+                    uuid,
+                    CharPropFlags::READ,
+                    FixedValue(value.as_bytes().to_vec()),
+                ))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn service() {
+        let service = DeviceInformation::default()
+            .with_manufacturer_name("Example")
+            .with_firmware_revision("1.2")
+            .service();
+        assert_eq!(service.uuid, DEVICE_INFORMATION_SERVICE);
+        assert!(service.primary);
+        let uuids: Vec<Uuid> = service.characteristics.iter().map(|c| c.uuid).collect();
+        assert_eq!(uuids, [MANUFACTURER_NAME, FIRMWARE_REVISION]);
+
+        let handler = &service.characteristics[0].handler;
+        assert_eq!(handler.read(0).await, Ok(b"Example".to_vec()));
+        assert_eq!(handler.read(4).await, Ok(b"ple".to_vec()));
+        assert_eq!(handler.read(7).await, Ok(vec![]));
+        assert_eq!(handler.read(8).await, Err(AttError::INVALID_OFFSET));
+        assert_eq!(
+            handler.write(vec![], 0).await,
+            Err(AttError::WRITE_NOT_PERMITTED)
+        );
+    }
+}
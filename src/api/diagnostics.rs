@@ -0,0 +1,248 @@
+//! Measurements for tuning connections.
+//!
+//! The time a write with response takes to be acknowledged is dominated by the connection
+//! interval, so a histogram of these latencies shows the effect of changing the connection
+//! parameters without needing a sniffer. See
+//! [`Peripheral::write_latency`](super::Peripheral::write_latency).
+//!
+//! Where the platform reports every advertising packet, the time between them gives an estimate of
+//! the advertising interval, reported as [`PeripheralProperties::advertising_interval`]. This
+//! differs between device models and firmware versions, and bounds how long connecting can take.
+//!
+//! [`PeripheralProperties::advertising_interval`]: super::PeripheralProperties::advertising_interval
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in milliseconds. The last bucket has no upper bound.
+const BUCKET_BOUNDS_MS: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+/// A histogram of latencies.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatencyHistogram {
+    counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl LatencyHistogram {
+    /// Records a latency.
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| latency < Duration::from_millis(*bound))
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    /// Returns the number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.total / count as u32),
+        }
+    }
+
+    /// Returns the upper bound of the bucket containing the given percentile, between 0 and 100,
+    /// or the maximum latency if that is lower.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, bucket_count) in self.counts.iter().enumerate() {
+            seen += bucket_count;
+            if seen >= rank {
+                let bound = BUCKET_BOUNDS_MS
+                    .get(bucket)
+                    .map(|bound| Duration::from_millis(*bound));
+                return match (bound, self.max) {
+                    (Some(bound), Some(max)) => Some(bound.min(max)),
+                    (_, max) => max,
+                };
+            }
+        }
+        self.max
+    }
+
+    /// Returns the number of latencies in each bucket, along with the bucket's upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.counts.iter().enumerate().map(|(bucket, count)| {
+            let bound = BUCKET_BOUNDS_MS
+                .get(bucket)
+                .map(|bound| Duration::from_millis(*bound));
+            (bound, *count)
+        })
+    }
+}
+
+/// How many of the most recent gaps between packets an estimate is based on.
+const MAX_GAPS: usize = 32;
+
+/// The fewest gaps an estimate is made from.
+const MIN_GAPS: usize = 4;
+
+/// The longest advertising interval the specification allows, plus the random delay.
+const MAX_GAP: Duration = Duration::from_millis(10_250);
+
+/// Advertisers add a random delay of up to 10 ms to every interval, so that devices which happen
+/// to have the same interval don't keep colliding.
+const MAX_ADVERTISING_DELAY: Duration = Duration::from_millis(10);
+
+/// An estimate of how often a device advertises.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdvertisingInterval {
+    /// The estimated interval, without the random delay which advertisers add to each interval.
+    /// If the scanner regularly misses packets, e.g. because its scan window is short, this may be
+    /// a multiple of the actual interval.
+    pub interval: Duration,
+    /// How consistent the observed packets were with the estimate, between 0 and 1. This is low
+    /// until enough packets have been seen, and for devices which change their interval.
+    pub confidence: f32,
+}
+
+/// Estimates the advertising interval of a device from the times its packets were received. The
+/// Windows backend uses this for [`PeripheralProperties::advertising_interval`], and it can be
+/// fed from other sources of packet timings, such as a capture.
+///
+/// [`PeripheralProperties::advertising_interval`]: super::PeripheralProperties::advertising_interval
+#[derive(Clone, Debug, Default)]
+pub struct AdvertisingIntervalEstimator {
+    last_received: Option<Duration>,
+    gaps: VecDeque<Duration>,
+}
+
+impl AdvertisingIntervalEstimator {
+    /// Records that a packet was received at the given time, relative to any fixed epoch.
+    pub fn record(&mut self, received_at: Duration) {
+        if let Some(gap) = self
+            .last_received
+            .and_then(|last| received_at.checked_sub(last))
+        {
+            if gap > MAX_GAP {
+                // The device stopped advertising or went out of range for a while, and may have
+                // changed its interval since.
+                self.gaps.clear();
+            } else if !gap.is_zero() {
+                if self.gaps.len() == MAX_GAPS {
+                    self.gaps.pop_front();
+                }
+                self.gaps.push_back(gap);
+            }
+        }
+        self.last_received = Some(received_at);
+    }
+
+    /// Returns the current estimate, or `None` if too few packets have been seen.
+    ///
+    /// Missed packets show up as gaps of several intervals, so the estimate is based on the
+    /// shortest gaps, and the confidence is the proportion of gaps which are close to a multiple of
+    /// it, scaled down while there are few of them.
+    pub fn estimate(&self) -> Option<AdvertisingInterval> {
+        if self.gaps.len() < MIN_GAPS {
+            return None;
+        }
+        let shortest = *self.gaps.iter().min()?;
+        let single: Vec<_> = self
+            .gaps
+            .iter()
+            .filter(|gap| **gap < shortest + shortest / 2)
+            .collect();
+        let mean_gap = single.iter().copied().sum::<Duration>() / single.len() as u32;
+        let tolerance = MAX_ADVERTISING_DELAY.min(mean_gap / 4);
+        let consistent = self
+            .gaps
+            .iter()
+            .filter(|gap| {
+                let multiple = (gap.as_secs_f64() / mean_gap.as_secs_f64()).round();
+                let expected = mean_gap.mul_f64(multiple);
+                gap.abs_diff(expected) <= tolerance
+            })
+            .count();
+        let confidence = consistent as f32 / self.gaps.len() as f32
+            * (self.gaps.len() as f32 / (MAX_GAPS / 2) as f32).min(1.0);
+        Some(AdvertisingInterval {
+            // The random delay averages half its maximum.
+            interval: mean_gap.saturating_sub(MAX_ADVERTISING_DELAY / 2),
+            confidence,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.percentile(50.0), None);
+        for ms in [3, 4, 8, 30, 2000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.min(), Some(Duration::from_millis(3)));
+        assert_eq!(histogram.max(), Some(Duration::from_secs(2)));
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(409)));
+        assert_eq!(histogram.percentile(40.0), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.percentile(60.0), Some(Duration::from_millis(10)));
+        assert_eq!(histogram.percentile(100.0), Some(Duration::from_secs(2)));
+        let buckets: Vec<_> = histogram.buckets().map(|(_, count)| count).collect();
+        assert_eq!(buckets, [0, 0, 2, 1, 0, 1, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn advertising_interval() {
+        let mut estimator = AdvertisingIntervalEstimator::default();
+        // A 100 ms interval with delays of 2 to 8 ms, missing every fifth packet.
+        let mut received_at = Duration::from_secs(1);
+        for packet in 0..40u64 {
+            received_at += Duration::from_millis(100 + 2 + packet % 7);
+            if packet % 5 != 4 {
+                estimator.record(received_at);
+            }
+            if packet == 2 {
+                assert_eq!(estimator.estimate(), None);
+            }
+        }
+        let estimate = estimator.estimate().unwrap();
+        assert!(
+            estimate.interval.abs_diff(Duration::from_millis(100)) < Duration::from_millis(3),
+            "{:?}",
+            estimate
+        );
+        assert_eq!(estimate.confidence, 1.0);
+
+        // A long silence starts the estimate over.
+        estimator.record(received_at + Duration::from_secs(20));
+        assert_eq!(estimator.estimate(), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Approximate energy use of scanning.
+//!
+//! While scanning, the controller listens for a scan window at the start of every scan interval,
+//! so its receiver is on for `window / interval` of the time. Platforms don't generally say what
+//! interval and window they use, so [`estimate_scan_duty_cycle`] uses a model of each platform's
+//! behaviour. The figures are approximate; the actual duty cycle also depends on the controller,
+//! on other applications scanning at the same time and, for dual-mode controllers, on time spent
+//! on BR/EDR inquiry.
+//!
+//! [`ScanPreset`]s choose sensible options for common trade-offs between latency and power on each
+//! platform, without having to know which of them each platform supports.
+
+use super::{ScanMode, ScanOptions, ScanTransport};
+use std::time::Duration;
+
+/// The scan interval and window, which determine the radio duty cycle while scanning.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ScanDutyCycle {
+    pub interval: Duration,
+    pub window: Duration,
+}
+
+impl ScanDutyCycle {
+    /// Returns the fraction of time the receiver is on, between 0 and 1.
+    pub fn ratio(&self) -> f64 {
+        if self.interval.is_zero() {
+            return 0.0;
+        }
+        (self.window.as_secs_f64() / self.interval.as_secs_f64()).min(1.0)
+    }
+
+    /// Returns the average current drawn while scanning, given the current drawn by the radio
+    /// while receiving and while idle, in the same unit as these.
+    pub fn average_current(&self, receive_current: f64, idle_current: f64) -> f64 {
+        let ratio = self.ratio();
+        receive_current * ratio + idle_current * (1.0 - ratio)
+    }
+}
+
+/// BlueZ starts discovery with the kernel's discovery scan parameters, where the window equals the
+/// interval.
+#[cfg(target_os = "linux")]
+const PLATFORM_DUTY_CYCLE: ScanDutyCycle = ScanDutyCycle {
+    interval: Duration::from_micros(11_250),
+    window: Duration::from_micros(11_250),
+};
+
+/// Scans use the default `SCAN_MODE_LOW_POWER` of Android's `ScanSettings`.
+#[cfg(target_os = "android")]
+const PLATFORM_DUTY_CYCLE: ScanDutyCycle = ScanDutyCycle {
+    interval: Duration::from_millis(5120),
+    window: Duration::from_millis(512),
+};
+
+/// Windows and CoreBluetooth scan more or less continuously for a foreground application. On iOS
+/// scanning is much less frequent in the background, which this doesn't account for.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const PLATFORM_DUTY_CYCLE: ScanDutyCycle = ScanDutyCycle {
+    interval: Duration::from_millis(30),
+    window: Duration::from_millis(30),
+};
+
+/// Estimates the duty cycle the current platform scans with for the given options. To see what
+/// was actually achieved, where the platform can tell, use
+/// [`Central::scan_duty_cycle`](super::Central::scan_duty_cycle).
+pub fn estimate_scan_duty_cycle(_options: &ScanOptions) -> ScanDutyCycle {
+    PLATFORM_DUTY_CYCLE
+}
+
+/// A trade-off between how quickly devices are reported and how much power scanning uses, which
+/// [`ScanPreset::options`] resolves to the current platform's options.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ScanPreset {
+    /// Report every advertisement received, e.g. while the user is waiting to pick a device.
+    LowLatency,
+    /// Report each device's changes without repeating identical advertisements.
+    #[default]
+    Balanced,
+    /// Use as little power as the platform allows, e.g. for long-running background scans, at the
+    /// cost of missing scan response data where scanning passively.
+    LowPower,
+}
+
+impl ScanPreset {
+    /// Returns the options this preset resolves to on the current platform, which can be logged
+    /// or adjusted further, e.g. with a filter.
+    ///
+    /// On Linux, presets only scan for LE devices, as BR/EDR inquiry takes time away from LE
+    /// scanning, and all but `LowLatency` filter out duplicate advertisements so that bluetoothd
+    /// and this process wake up less. BlueZ always scans actively with the kernel's parameters.
+    /// On Windows, `LowPower` scans passively. Other platforms don't currently support any of
+    /// these options, so every preset resolves to the defaults.
+    pub fn options(self) -> ScanOptions {
+        let mut options = ScanOptions::default();
+        if cfg!(target_os = "linux") {
+            options = options
+                .with_transport(ScanTransport::Le)
+                .with_filter_duplicates(self != Self::LowLatency);
+        }
+        if cfg!(target_os = "windows") && self == Self::LowPower {
+            options = options.with_mode(ScanMode::Passive);
+        }
+        options
+    }
+}
+
+impl From<ScanPreset> for ScanOptions {
+    fn from(preset: ScanPreset) -> Self {
+        preset.options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_cycle() {
+        let duty_cycle = ScanDutyCycle {
+            interval: Duration::from_millis(100),
+            window: Duration::from_millis(25),
+        };
+        assert_eq!(duty_cycle.ratio(), 0.25);
+        assert_eq!(duty_cycle.average_current(10.0, 2.0), 4.0);
+        let zero = ScanDutyCycle {
+            interval: Duration::ZERO,
+            window: Duration::ZERO,
+        };
+        assert_eq!(zero.ratio(), 0.0);
+    }
+
+    #[test]
+    fn presets() {
+        let low_latency = ScanPreset::LowLatency.options();
+        assert!(!low_latency.filter_duplicates);
+        assert_eq!(low_latency.mode, ScanMode::Active);
+        assert_eq!(
+            ScanOptions::from(ScanPreset::Balanced).mode,
+            ScanMode::Active
+        );
+        if cfg!(target_os = "linux") {
+            assert!(ScanPreset::LowPower.options().filter_duplicates);
+        }
+    }
+}
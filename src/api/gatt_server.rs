@@ -0,0 +1,260 @@
+//! Hosting GATT services in the peripheral role.
+//!
+//! [`Central::register_gatt_services`](super::Central::register_gatt_services) publishes
+//! [`LocalService`]s on the local adapter, so that centrals connecting to it can discover them.
+//! Each [`LocalCharacteristic`] answers reads and writes through its [`CharacteristicHandler`],
+//! and [`Central::notify_local_characteristic`](super::Central::notify_local_characteristic)
+//! sends its new value to the centrals subscribed to it. Together with
+//! [`advertising`](super::advertising), this lets btleplug act as a peripheral. See
+//! [`Capabilities::gatt_server`](super::Capabilities::gatt_server) for which backends support it.
+//!
+//! Servers which keep state for each client can follow the centrals using them through
+//! [`Central::gatt_server_events`](super::Central::gatt_server_events).
+
+use super::{AttError, BDAddr, CharPropFlags};
+use async_trait::async_trait;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Answers the requests centrals make of a local characteristic. The default implementations
+/// refuse them.
+#[async_trait]
+pub trait CharacteristicHandler: Debug + Send + Sync {
+    /// Returns the value of the characteristic, starting from the given offset for a long read.
+    async fn read(&self, _offset: usize) -> Result<Vec<u8>, AttError> {
+        Err(AttError::READ_NOT_PERMITTED)
+    }
+
+    /// Writes a value to the characteristic, starting from the given offset for a long write.
+    async fn write(&self, _value: Vec<u8>, _offset: usize) -> Result<(), AttError> {
+        Err(AttError::WRITE_NOT_PERMITTED)
+    }
+
+    /// A central subscribed to notifications or indications, if `notifying` is true, or the last
+    /// one unsubscribed.
+    async fn notifying_changed(&self, _notifying: bool) {}
+}
+
+/// How long the values written to a [`LocalCharacteristic`] may be.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ValueLength {
+    /// Any length, as far as the platform allows.
+    #[default]
+    Any,
+    /// At most this many bytes.
+    Max(usize),
+    /// Exactly this many bytes. A long write may be made in several parts, but each must end at
+    /// the end of the value.
+    Fixed(usize),
+}
+
+impl ValueLength {
+    /// Checks that a value written at the given offset fits, returning the ATT error to reject the
+    /// write with otherwise.
+    pub fn check(self, offset: usize, length: usize) -> Result<(), AttError> {
+        let (max, fixed) = match self {
+            ValueLength::Any => return Ok(()),
+            ValueLength::Max(max) => (max, false),
+            ValueLength::Fixed(fixed) => (fixed, true),
+        };
+        if offset > max {
+            Err(AttError::INVALID_OFFSET)
+        } else if offset + length > max || (fixed && offset + length != max) {
+            Err(AttError::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Checks the contents of a value written to a characteristic, returning the ATT error to reject it
+/// with if it isn't acceptable. It is passed the value as written, without the offset.
+pub type Validator = Arc<dyn Fn(&[u8]) -> Result<(), AttError> + Send + Sync>;
+
+/// A characteristic of a [`LocalService`].
+#[derive(Clone)]
+pub struct LocalCharacteristic {
+    pub uuid: Uuid,
+    /// The operations centrals may perform on the characteristic. Requests the handler doesn't
+    /// support should be left out, rather than refused.
+    pub properties: CharPropFlags,
+    pub handler: Arc<dyn CharacteristicHandler>,
+    /// Writes of values outside these bounds are rejected without reaching the handler.
+    pub length: ValueLength,
+    /// Writes the validator rejects don't reach the handler either.
+    pub validator: Option<Validator>,
+}
+
+impl LocalCharacteristic {
+    pub fn new(
+        uuid: Uuid,
+        properties: CharPropFlags,
+        handler: impl CharacteristicHandler + 'static,
+    ) -> Self {
+        Self {
+            uuid,
+            properties,
+            handler: Arc::new(handler),
+            length: ValueLength::Any,
+            validator: None,
+        }
+    }
+
+    pub fn with_max_length(mut self, max: usize) -> Self {
+        self.length = ValueLength::Max(max);
+        self
+    }
+
+    pub fn with_fixed_length(mut self, length: usize) -> Self {
+        self.length = ValueLength::Fixed(length);
+        self
+    }
+
+    pub fn with_validator(
+        mut self,
+        validator: impl Fn(&[u8]) -> Result<(), AttError> + Send + Sync + 'static,
+    ) -> Self {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Checks a write against the characteristic's length and validator, before it is passed to
+    /// the handler.
+    pub fn check_write(&self, value: &[u8], offset: usize) -> Result<(), AttError> {
+        self.length.check(offset, value.len())?;
+        match &self.validator {
+            Some(validator) => validator(value),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Debug for LocalCharacteristic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("LocalCharacteristic")
+            .field("uuid", &self.uuid)
+            .field("properties", &self.properties)
+            .field("handler", &self.handler)
+            .field("length", &self.length)
+            .field("validator", &self.validator.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// A GATT service hosted on the local adapter.
+#[derive(Clone, Debug)]
+pub struct LocalService {
+    pub uuid: Uuid,
+    /// Whether this is a primary service, rather than one only included by other services.
+    pub primary: bool,
+    pub characteristics: Vec<LocalCharacteristic>,
+}
+
+impl LocalService {
+    /// Creates a primary service with no characteristics.
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            primary: true,
+            characteristics: vec![],
+        }
+    }
+
+    pub fn with_characteristic(mut self, characteristic: LocalCharacteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+}
+
+/// Something a central did to the services registered on the local adapter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ServerEvent {
+    /// A central made its first request of the services since it connected. A central which
+    /// connects without using them, e.g. to scan the services of another application, isn't
+    /// reported.
+    CentralConnected(BDAddr),
+    /// A central which had made requests of the services disconnected.
+    CentralDisconnected(BDAddr),
+    /// The ATT MTU of a central's connection was exchanged, or changed since it was last reported.
+    MtuChanged { central: BDAddr, mtu: u16 },
+    /// A central subscribed to notifications or indications of a characteristic. `central` is
+    /// `None` where the platform doesn't say which central it was. BlueZ doesn't, and only reports
+    /// the first subscription to each characteristic. CoreBluetooth reports each one, but doesn't
+    /// tell centrals' addresses, and reports no other events.
+    SubscriptionAdded {
+        central: Option<BDAddr>,
+        service: Uuid,
+        characteristic: Uuid,
+    },
+    /// A central unsubscribed from a characteristic. As for
+    /// [`SubscriptionAdded`](Self::SubscriptionAdded), BlueZ only reports the last one, without
+    /// saying which central it was.
+    SubscriptionRemoved {
+        central: Option<BDAddr>,
+        service: Uuid,
+        characteristic: Uuid,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Refuse;
+
+    impl CharacteristicHandler for Refuse {}
+
+    #[test]
+    fn max_length() {
+        let length = ValueLength::Max(4);
+        assert_eq!(length.check(0, 4), Ok(()));
+        assert_eq!(length.check(2, 2), Ok(()));
+        assert_eq!(length.check(4, 0), Ok(()));
+        assert_eq!(
+            length.check(0, 5),
+            Err(AttError::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        );
+        assert_eq!(
+            length.check(3, 2),
+            Err(AttError::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        );
+        assert_eq!(length.check(5, 0), Err(AttError::INVALID_OFFSET));
+        assert_eq!(ValueLength::Any.check(1000, 1000), Ok(()));
+    }
+
+    #[test]
+    fn fixed_length() {
+        let length = ValueLength::Fixed(4);
+        assert_eq!(length.check(0, 4), Ok(()));
+        assert_eq!(length.check(3, 1), Ok(()));
+        assert_eq!(
+            length.check(0, 3),
+            Err(AttError::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        );
+        assert_eq!(
+            length.check(0, 5),
+            Err(AttError::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        );
+        assert_eq!(length.check(5, 0), Err(AttError::INVALID_OFFSET));
+    }
+
+    #[test]
+    fn check_write() {
+        let characteristic = LocalCharacteristic::new(Uuid::nil(), CharPropFlags::WRITE, Refuse)
+            .with_max_length(2)
+            .with_validator(|value| match value {
+                [0] => Err(AttError(0x80)),
+                _ => Ok(()),
+            });
+        assert_eq!(characteristic.check_write(&[1, 2], 0), Ok(()));
+        assert_eq!(characteristic.check_write(&[0], 0), Err(AttError(0x80)));
+        // The length is checked first.
+        assert_eq!(
+            characteristic.check_write(&[0, 0, 0], 0),
+            Err(AttError::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        );
+    }
+}
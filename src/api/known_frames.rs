@@ -0,0 +1,199 @@
+//! Recognition of well-known vendor advertising frames.
+//!
+//! Phones, laptops and accessories fill the air with advertisements for their vendors' own
+//! protocols. [`KnownFrame::recognize`] classifies the common ones from a device's manufacturer
+//! and service data, so scanner applications can label or filter them out. Only the frame type and
+//! its unencrypted identifiers are decoded; the payloads themselves are left alone.
+
+use super::bleuuid::uuid_from_u16;
+use super::{CentralEvent, PeripheralProperties};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Apple's company ID, under which it advertises Continuity messages.
+pub const APPLE_COMPANY_ID: u16 = 0x004c;
+/// Microsoft's company ID, under which it advertises Swift Pair beacons.
+pub const MICROSOFT_COMPANY_ID: u16 = 0x0006;
+/// The service data UUID of Google Fast Pair advertisements.
+pub const FAST_PAIR_SERVICE: Uuid = uuid_from_u16(0xfe2c);
+
+/// The type of an Apple Continuity message. Apple's manufacturer data may contain several.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ContinuityType {
+    IBeacon,
+    AirDrop,
+    HomeKit,
+    /// Sent by AirPods and Beats headphones, e.g. when their case is opened.
+    ProximityPairing,
+    HeySiri,
+    AirPlayTarget,
+    AirPlaySource,
+    MagicSwitch,
+    Handoff,
+    /// Offers the Wi-Fi password to nearby devices.
+    WiFiSettings,
+    InstantHotspot,
+    NearbyAction,
+    /// Sent by most Apple devices to announce their state to each other.
+    NearbyInfo,
+    /// Sent by lost Apple devices and AirTags for the Find My network.
+    FindMy,
+    Other(u8),
+}
+
+impl From<u8> for ContinuityType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x02 => Self::IBeacon,
+            0x05 => Self::AirDrop,
+            0x06 => Self::HomeKit,
+            0x07 => Self::ProximityPairing,
+            0x08 => Self::HeySiri,
+            0x09 => Self::AirPlayTarget,
+            0x0a => Self::AirPlaySource,
+            0x0b => Self::MagicSwitch,
+            0x0c => Self::Handoff,
+            0x0d => Self::WiFiSettings,
+            0x0e => Self::InstantHotspot,
+            0x0f => Self::NearbyAction,
+            0x10 => Self::NearbyInfo,
+            0x12 => Self::FindMy,
+            value => Self::Other(value),
+        }
+    }
+}
+
+/// A well-known vendor frame found in a device's advertisements.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum KnownFrame {
+    /// An Apple Continuity message.
+    AppleContinuity(ContinuityType),
+    /// A Google Fast Pair advertisement. The model ID is only advertised while the device is
+    /// discoverable.
+    FastPair { model_id: Option<u32> },
+    /// A Microsoft Swift Pair beacon, with the display name it advertises.
+    SwiftPair { display_name: Option<String> },
+}
+
+impl KnownFrame {
+    /// Returns the frames found in the given manufacturer and service data.
+    pub fn from_data(
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Vec<KnownFrame> {
+        let mut frames = vec![];
+        if let Some(data) = manufacturer_data.get(&APPLE_COMPANY_ID) {
+            frames.extend(continuity_types(data).map(KnownFrame::AppleContinuity));
+        }
+        if let Some(frame) = manufacturer_data
+            .get(&MICROSOFT_COMPANY_ID)
+            .and_then(|data| swift_pair(data))
+        {
+            frames.push(frame);
+        }
+        if let Some(data) = service_data.get(&FAST_PAIR_SERVICE) {
+            // Discoverable devices advertise just their model ID; otherwise the data starts with a
+            // flags byte and an account key filter.
+            let model_id = match data.as_slice() {
+                [a, b, c] => Some(u32::from_be_bytes([0, *a, *b, *c])),
+                _ => None,
+            };
+            frames.push(KnownFrame::FastPair { model_id });
+        }
+        frames
+    }
+
+    /// Returns the frames found in a device's advertisements.
+    pub fn recognize(properties: &PeripheralProperties) -> Vec<KnownFrame> {
+        Self::from_data(&properties.manufacturer_data, &properties.service_data)
+    }
+
+    /// Returns the frames found in the data carried by an advertisement event, or nothing for
+    /// other events.
+    pub fn from_event(event: &CentralEvent) -> Vec<KnownFrame> {
+        match event {
+            CentralEvent::ManufacturerDataAdvertisement {
+                manufacturer_data, ..
+            } => Self::from_data(manufacturer_data, &HashMap::new()),
+            CentralEvent::ServiceDataAdvertisement { service_data, .. } => {
+                Self::from_data(&HashMap::new(), service_data)
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Returns the types of the messages in Apple manufacturer data, which is a sequence of type,
+/// length and value.
+fn continuity_types(mut data: &[u8]) -> impl Iterator<Item = ContinuityType> + '_ {
+    std::iter::from_fn(move || {
+        let [message_type, length, rest @ ..] = data else {
+            return None;
+        };
+        data = rest.get(usize::from(*length)..).unwrap_or_default();
+        Some(ContinuityType::from(*message_type))
+    })
+}
+
+/// Parses a Swift Pair beacon: Microsoft's beacon ID 3, a scenario byte, a reserved byte and then
+/// the UTF-8 display name.
+fn swift_pair(data: &[u8]) -> Option<KnownFrame> {
+    let [0x03, scenario, _reserved, name @ ..] = data else {
+        return None;
+    };
+    // Scenarios 1 and 2 put a BR/EDR address and class of device before the name.
+    let name = match scenario {
+        0x00 => name,
+        0x01 | 0x02 => name.get(9..).unwrap_or_default(),
+        _ => return None,
+    };
+    let display_name = std::str::from_utf8(name)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string);
+    Some(KnownFrame::SwiftPair { display_name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apple_continuity() {
+        let manufacturer_data = HashMap::from([(
+            APPLE_COMPANY_ID,
+            vec![0x10, 0x02, 0x0b, 0x1c, 0x12, 0x01, 0x00, 0x99, 0x00],
+        )]);
+        assert_eq!(
+            KnownFrame::from_data(&manufacturer_data, &HashMap::new()),
+            vec![
+                KnownFrame::AppleContinuity(ContinuityType::NearbyInfo),
+                KnownFrame::AppleContinuity(ContinuityType::FindMy),
+                KnownFrame::AppleContinuity(ContinuityType::Other(0x99)),
+            ]
+        );
+    }
+
+    #[test]
+    fn swift_pair_and_fast_pair() {
+        let manufacturer_data =
+            HashMap::from([(MICROSOFT_COMPANY_ID, b"\x03\x00\x80Mouse".to_vec())]);
+        let service_data = HashMap::from([(FAST_PAIR_SERVICE, vec![0x00, 0x00, 0x0c])]);
+        assert_eq!(
+            KnownFrame::from_data(&manufacturer_data, &service_data),
+            vec![
+                KnownFrame::SwiftPair {
+                    display_name: Some("Mouse".to_string())
+                },
+                KnownFrame::FastPair {
+                    model_id: Some(0x00000c)
+                },
+            ]
+        );
+        let service_data = HashMap::from([(FAST_PAIR_SERVICE, vec![0x00, 0x60, 0x11, 0x22])]);
+        assert_eq!(
+            KnownFrame::from_data(&HashMap::new(), &service_data),
+            vec![KnownFrame::FastPair { model_id: None }]
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! L2CAP connection-oriented channels.
+//!
+//! A channel is a flow-controlled, bidirectional stream to a service on the device, identified by
+//! its Protocol/Service Multiplexer (PSM). It bypasses GATT altogether, so it has far more
+//! throughput than writes and notifications, which is what protocols such as Object Transfer are
+//! built on. Open one with [`Peripheral::open_l2cap_channel`](super::Peripheral::open_l2cap_channel).
+//!
+//! Each write is sent as one SDU, and each read returns at most one SDU, so a buffer smaller than
+//! the SDU the device sent loses the rest of it.
+
+use std::fmt::Debug;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The platform's implementation of a channel.
+pub(crate) trait ChannelStream: AsyncRead + AsyncWrite + Debug + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Debug + Send + Sync> ChannelStream for T {}
+
+/// An open L2CAP connection-oriented channel. The channel is closed when this is dropped.
+#[derive(Debug)]
+pub struct L2capChannel {
+    psm: u16,
+    stream: Pin<Box<dyn ChannelStream>>,
+}
+
+impl L2capChannel {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn new(psm: u16, stream: impl ChannelStream + 'static) -> Self {
+        Self {
+            psm,
+            stream: Box::pin(stream),
+        }
+    }
+
+    /// Returns the PSM the channel was opened to.
+    pub fn psm(&self) -> u16 {
+        self.psm
+    }
+}
+
+impl AsyncRead for L2capChannel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.stream.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for L2capChannel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.stream.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stream.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stream.as_mut().poll_shutdown(cx)
+    }
+}
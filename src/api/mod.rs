@@ -21,13 +21,38 @@
 //! use btleplug::platform::{Adapter, Manager, Peripheral};
 //! ```
 
+pub mod activity;
+pub mod advertising;
+pub mod alert;
+pub mod anonymize;
+mod att;
+pub mod batch;
+pub mod battery;
 pub(crate) mod bdaddr;
 pub mod bleuuid;
+pub mod broadcast;
+pub mod concurrency;
+pub mod connect;
+pub mod device_information;
+pub mod diagnostics;
+pub mod energy;
+pub mod gatt_server;
+pub mod known_frames;
+pub mod l2cap;
+pub mod pairing;
+pub mod pairing_mode;
+mod poll;
+pub mod redaction;
+pub mod replay;
+pub mod resolver;
+pub mod self_test;
+pub mod tags;
+pub mod throttle;
 
-use crate::Result;
+use crate::{Error, Result};
 use async_trait::async_trait;
 use bitflags::bitflags;
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
@@ -36,10 +61,14 @@ use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 use uuid::Uuid;
 
-pub use self::bdaddr::{BDAddr, ParseBDAddrError};
+pub use self::att::AttError;
+pub use self::bdaddr::{BDAddr, ParseBDAddrError, RandomAddressKind};
+pub use self::bleuuid::UuidPattern;
 
 use crate::platform::PeripheralId;
 
@@ -48,19 +77,15 @@ use crate::platform::PeripheralId;
     derive(Serialize, Deserialize),
     serde(crate = "serde_cr")
 )]
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum AddressType {
     Random,
+    #[default]
     Public,
 }
 
-impl Default for AddressType {
-    fn default() -> Self {
-        AddressType::Public
-    }
-}
-
 impl AddressType {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(v: &str) -> Option<AddressType> {
         match v {
             "public" => Some(AddressType::Public),
@@ -85,6 +110,64 @@ impl AddressType {
     }
 }
 
+/// The address of an LE device together with its type. The public and random address spaces are
+/// separate, so two different devices can have the same [`BDAddr`] with different types; only the
+/// pair identifies a device.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LeAddress {
+    pub address: BDAddr,
+    pub address_type: AddressType,
+}
+
+impl LeAddress {
+    pub fn new(address: BDAddr, address_type: AddressType) -> Self {
+        Self {
+            address,
+            address_type,
+        }
+    }
+
+    /// Returns the public address `address`.
+    pub fn public(address: BDAddr) -> Self {
+        Self::new(address, AddressType::Public)
+    }
+
+    /// Returns the random address `address`, which may be static or private.
+    pub fn random(address: BDAddr) -> Self {
+        Self::new(address, AddressType::Random)
+    }
+
+    /// Returns true if this is a public or static random address, which keeps identifying the
+    /// device rather than being replaced every so often for privacy.
+    pub fn is_identity(&self) -> bool {
+        match self.address_type {
+            AddressType::Public => true,
+            AddressType::Random => self.address.is_random_static(),
+        }
+    }
+}
+
+impl Display for LeAddress {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let address_type = match self.address_type {
+            AddressType::Public => "public",
+            AddressType::Random => "random",
+        };
+        write!(f, "{} ({})", self.address, address_type)
+    }
+}
+
+impl From<LeAddress> for BDAddr {
+    fn from(address: LeAddress) -> Self {
+        address.address
+    }
+}
+
 /// A notification sent from a peripheral due to a change in a value.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ValueNotification {
@@ -92,10 +175,55 @@ pub struct ValueNotification {
     pub uuid: Uuid,
     /// The new value of the characteristic.
     pub value: Vec<u8>,
+    /// When the notification was received, taken as early as the platform allows. This is
+    /// monotonic and shared by all peripherals, so notifications from several devices can be
+    /// aligned by it; see also [`Peripheral::notification_latency`].
+    ///
+    /// On Windows this is converted from the timestamp the platform gives the notification, so it
+    /// excludes the time spent queued for this process. Elsewhere it is taken when the
+    /// notification reaches btleplug.
+    pub received_at: Instant,
+    /// Whether the value was read by [`Peripheral::notifications_with_fallback`] because the
+    /// characteristic couldn't be subscribed to, rather than notified by the device.
+    pub emulated: bool,
+}
+
+impl ValueNotification {
+    /// Returns a notification of the characteristic with the given UUID, received now.
+    pub fn new(uuid: Uuid, value: Vec<u8>) -> Self {
+        Self {
+            uuid,
+            value,
+            received_at: Instant::now(),
+            emulated: false,
+        }
+    }
+
+    /// Sets when the notification was received, for one timestamped by the platform.
+    pub fn with_received_at(mut self, received_at: Instant) -> Self {
+        self.received_at = received_at;
+        self
+    }
+
+    /// Sets whether the value was read rather than notified by the device.
+    pub fn with_emulated(mut self, emulated: bool) -> Self {
+        self.emulated = emulated;
+        self
+    }
+}
+
+bitflags! {
+    /// The value of a Client Characteristic Configuration descriptor.
+    #[derive(Default)]
+    pub struct ClientConfiguration: u16 {
+        const NOTIFICATION = 0x0001;
+        const INDICATION = 0x0002;
+    }
 }
 
 bitflags! {
     /// A set of properties that indicate what operations are supported by a Characteristic.
+    #[derive(Default)]
     pub struct CharPropFlags: u8 {
         const BROADCAST = 0x01;
         const READ = 0x02;
@@ -108,15 +236,64 @@ bitflags! {
     }
 }
 
-impl Default for CharPropFlags {
-    fn default() -> Self {
-        Self { bits: 0 }
+bitflags! {
+    /// The contents of the Flags field of an advertisement, which indicates the discoverable mode
+    /// of a device and whether it supports BR/EDR (Bluetooth Classic).
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
+    #[derive(Default)]
+    pub struct AdvertisingFlags: u8 {
+        const LE_LIMITED_DISCOVERABLE = 0x01;
+        const LE_GENERAL_DISCOVERABLE = 0x02;
+        const BR_EDR_NOT_SUPPORTED = 0x04;
+        const SIMULTANEOUS_LE_BR_EDR_CONTROLLER = 0x08;
+        const SIMULTANEOUS_LE_BR_EDR_HOST = 0x10;
+    }
+}
+
+/// The discoverable mode of a device, as indicated by its [`AdvertisingFlags`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DiscoverableMode {
+    /// The device is not advertising itself as discoverable.
+    NonDiscoverable,
+    /// The device is discoverable for a limited time, which usually means it has been put into
+    /// pairing or setup mode by the user.
+    Limited,
+    /// The device is discoverable indefinitely.
+    General,
+}
+
+impl AdvertisingFlags {
+    /// Returns the discoverable mode indicated by these flags.
+    pub fn discoverable_mode(&self) -> DiscoverableMode {
+        if self.contains(AdvertisingFlags::LE_LIMITED_DISCOVERABLE) {
+            DiscoverableMode::Limited
+        } else if self.contains(AdvertisingFlags::LE_GENERAL_DISCOVERABLE) {
+            DiscoverableMode::General
+        } else {
+            DiscoverableMode::NonDiscoverable
+        }
+    }
+
+    /// Returns true if the device supports BR/EDR (Bluetooth Classic) as well as LE.
+    pub fn supports_br_edr(&self) -> bool {
+        !self.contains(AdvertisingFlags::BR_EDR_NOT_SUPPORTED)
     }
 }
 
 /// A GATT service. Services are groups of characteristics, which may be standard or
-/// device-specific.
+/// device-specific. New fields may be added in future releases, so construct this with
+/// [`Service::new`].
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+#[non_exhaustive]
 pub struct Service {
     /// The UUID for this service.
     pub uuid: Uuid,
@@ -124,6 +301,55 @@ pub struct Service {
     pub primary: bool,
     /// The characteristics of this service.
     pub characteristics: BTreeSet<Characteristic>,
+    /// The attribute handles the service occupies on the device, where the platform exposes them.
+    /// Only BlueZ does.
+    pub handles: Option<HandleRange>,
+    /// The UUIDs of the services this service includes, which are among the device's services
+    /// too. Only BlueZ and CoreBluetooth report them.
+    pub included_services: Vec<Uuid>,
+}
+
+impl Service {
+    /// Creates a service with no known handles or included services.
+    pub fn new(uuid: Uuid, primary: bool, characteristics: BTreeSet<Characteristic>) -> Self {
+        Self {
+            uuid,
+            primary,
+            characteristics,
+            handles: None,
+            included_services: vec![],
+        }
+    }
+}
+
+/// The range of attribute handles a GATT service occupies, from its declaration to its last
+/// attribute.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct HandleRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl HandleRange {
+    /// Returns whether the given attribute handle belongs to the service.
+    pub fn contains(&self, handle: u16) -> bool {
+        (self.start..=self.end).contains(&handle)
+    }
+}
+
+/// A GATT service together with the device it belongs to. See [`Peripheral::services_raw`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RawService {
+    pub uuid: Uuid,
+    /// Whether this is a primary service.
+    pub primary: bool,
+    /// The device the service belongs to.
+    pub peripheral: PeripheralId,
+    /// The UUIDs of the services this service includes.
+    pub includes: Vec<Uuid>,
+    /// The handle of the service declaration, where the platform exposes it.
+    pub handle: Option<u16>,
 }
 
 /// A Bluetooth characteristic. Characteristics are the main way you will interact with other
@@ -140,6 +366,8 @@ pub struct Characteristic {
     pub uuid: Uuid,
     /// The UUID of the service this characteristic belongs to.
     pub service_uuid: Uuid,
+    /// Whether the service this characteristic belongs to is a primary service.
+    pub service_primary: bool,
     /// The set of properties for this characteristic, which indicate what functionality it
     /// supports. If you attempt an operation that is not supported by the characteristics (for
     /// example setting notify on one without the NOTIFY flag), that operation will fail.
@@ -152,8 +380,8 @@ impl Display for Characteristic {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "uuid: {:?}, char properties: {:?}",
-            self.uuid, self.properties
+            "uuid: {:?}, service: {:?}, char properties: {:?}",
+            self.uuid, self.service_uuid, self.properties
         )
     }
 }
@@ -202,7 +430,21 @@ pub struct PeripheralProperties {
     pub service_data: HashMap<Uuid, Vec<u8>>,
     /// Advertised services for this device
     pub services: Vec<Uuid>,
+    /// The GAP Appearance of the device, such as a heart rate belt or a keyboard. The upper 10
+    /// bits are the category and the lower 6 the subcategory, as assigned by the Bluetooth SIG.
+    pub appearance: Option<u16>,
+    /// The Class of Device of a BR/EDR device, as reported by BlueZ.
     pub class: Option<u32>,
+    /// The Flags field of the most recent advertisement, if the platform exposes it.
+    pub advertising_flags: Option<AdvertisingFlags>,
+    /// An estimate of how often the device advertises, where the platform reports every
+    /// advertising packet received while scanning.
+    pub advertising_interval: Option<diagnostics::AdvertisingInterval>,
+    /// When the most recent advertisement was received, on the wall clock, for platforms which
+    /// timestamp advertisements themselves. Windows stamps each packet as it arrives from the
+    /// controller, before it is queued for this process; BlueZ and CoreBluetooth don't expose
+    /// packet timestamps, and Android's are on a boot-relative clock which isn't propagated yet.
+    pub last_advertisement: Option<SystemTime>,
 }
 
 #[cfg_attr(
@@ -216,8 +458,511 @@ pub struct ScanFilter {
     /// If the filter contains at least one service UUID, only devices supporting at least one of
     /// the given services will be available.
     pub services: Vec<Uuid>,
+    /// Further services to match by pattern. Platforms can only filter scans by exact UUIDs, so
+    /// these aren't applied to the scan itself; use [`ScanFilter::matches`] to check discovered
+    /// devices against them.
+    pub service_patterns: Vec<UuidPattern>,
+    /// Manufacturer data to match, any of which a device must advertise. Only Windows applies
+    /// these to the scan itself, and only when there is a single filter without a mask, so other
+    /// devices are still discovered otherwise; use [`ScanFilter::matches`] to check discovered
+    /// devices against them.
+    pub manufacturer_data: Vec<ManufacturerDataFilter>,
+}
+
+impl ScanFilter {
+    /// Sets the services to scan for, replacing any set before.
+    pub fn with_services(mut self, services: impl Into<Vec<Uuid>>) -> Self {
+        self.services = services.into();
+        self
+    }
+
+    /// Sets the service patterns to match discovered devices against, replacing any set before.
+    pub fn with_service_patterns(mut self, service_patterns: impl Into<Vec<UuidPattern>>) -> Self {
+        self.service_patterns = service_patterns.into();
+        self
+    }
+
+    /// Sets the manufacturer data to match, replacing any filters set before.
+    pub fn with_manufacturer_data(
+        mut self,
+        manufacturer_data: impl Into<Vec<ManufacturerDataFilter>>,
+    ) -> Self {
+        self.manufacturer_data = manufacturer_data.into();
+        self
+    }
+
+    /// Returns true if a device with the given properties advertises one of the services or
+    /// service patterns of this filter, or if the filter has neither, and likewise matches one of
+    /// its manufacturer data filters.
+    pub fn matches(&self, properties: &PeripheralProperties) -> bool {
+        let services_match = (self.services.is_empty() && self.service_patterns.is_empty())
+            || properties.services.iter().any(|service| {
+                self.services.contains(service)
+                    || self
+                        .service_patterns
+                        .iter()
+                        .any(|pattern| pattern.matches(service))
+            });
+        let manufacturer_data_match = self.manufacturer_data.is_empty()
+            || self
+                .manufacturer_data
+                .iter()
+                .any(|filter| filter.matches(&properties.manufacturer_data));
+        services_match && manufacturer_data_match
+    }
+}
+
+/// Matches manufacturer data advertised under a company ID, by a prefix of its bytes.
+///
+/// Proprietary devices often identify themselves by a product code at the start of their
+/// manufacturer data rather than by a service UUID.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ManufacturerDataFilter {
+    /// The company ID the data must be advertised under.
+    pub company_id: u16,
+    /// The bytes the data must start with, wherever `mask` is set.
+    pub prefix: Vec<u8>,
+    /// Which bits of each byte of `prefix` must match. Bytes beyond the end of the mask must match
+    /// exactly, so an empty mask matches the whole prefix.
+    pub mask: Vec<u8>,
+}
+
+impl ManufacturerDataFilter {
+    /// Matches any data advertised under the given company ID.
+    pub fn new(company_id: u16) -> Self {
+        Self {
+            company_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<Vec<u8>>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_mask(mut self, mask: impl Into<Vec<u8>>) -> Self {
+        self.mask = mask.into();
+        self
+    }
+
+    /// Returns true if the mask doesn't exclude any bits of the prefix.
+    pub fn is_exact_prefix(&self) -> bool {
+        self.mask
+            .iter()
+            .take(self.prefix.len())
+            .all(|&mask| mask == 0xff)
+    }
+
+    /// Returns true if the data advertised under the filter's company ID, if any, matches it.
+    pub fn matches(&self, manufacturer_data: &HashMap<u16, Vec<u8>>) -> bool {
+        manufacturer_data.get(&self.company_id).is_some_and(|data| {
+            data.len() >= self.prefix.len()
+                && self.prefix.iter().enumerate().all(|(i, &prefix)| {
+                    let mask = self.mask.get(i).copied().unwrap_or(0xff);
+                    data[i] & mask == prefix & mask
+                })
+        })
+    }
+}
+
+/// Options for [`Central::start_scan_with_options`]. New options may be added in future releases,
+/// so construct this with [`Default`] and the `with_` methods.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ScanOptions {
+    /// Which devices to scan for.
+    pub filter: ScanFilter,
+    /// Only report devices received with an RSSI above this, in dBm. Only supported on Linux.
+    pub rssi_threshold: Option<i16>,
+    /// Only report devices with a path loss, the advertised TX power minus the RSSI, below this,
+    /// in dB. Devices which don't advertise their TX power aren't reported. Only supported on
+    /// Linux, and not together with `rssi_threshold`.
+    pub pathloss_threshold: Option<u16>,
+    /// Which kinds of scan to perform. Only supported on Linux.
+    pub transport: ScanTransport,
+    /// Whether to send scan requests. Passive scanning is only supported on Windows.
+    pub mode: ScanMode,
+    /// Only report the first advertisement from each device with the same data, rather than
+    /// every one received. Only supported on Linux.
+    pub filter_duplicates: bool,
+}
+
+impl ScanOptions {
+    pub fn with_filter(mut self, filter: ScanFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_rssi_threshold(mut self, rssi_threshold: i16) -> Self {
+        self.rssi_threshold = Some(rssi_threshold);
+        self
+    }
+
+    pub fn with_pathloss_threshold(mut self, pathloss_threshold: u16) -> Self {
+        self.pathloss_threshold = Some(pathloss_threshold);
+        self
+    }
+
+    pub fn with_transport(mut self, transport: ScanTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: ScanMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_filter_duplicates(mut self, filter_duplicates: bool) -> Self {
+        self.filter_duplicates = filter_duplicates;
+        self
+    }
+}
+
+/// Whether to actively scan for devices.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ScanMode {
+    /// Send a scan request to each device found, to get the additional data in its scan response.
+    #[default]
+    Active,
+    /// Only listen for advertisements. This takes less airtime and power, but devices' scan
+    /// response data, often including their name, isn't received.
+    Passive,
+}
+
+/// Which kinds of scan to perform, for adapters which support both Bluetooth Low Energy and
+/// Classic.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ScanTransport {
+    /// Interleave LE scanning and BR/EDR inquiry, if both are enabled on the adapter.
+    #[default]
+    Auto,
+    /// Only scan for LE devices.
+    Le,
+    /// Only perform BR/EDR inquiry.
+    BrEdr,
+}
+
+/// Options for [`Peripheral::connect_with_options`]. New options may be added in future releases,
+/// so construct this with [`Default`] and the `with_` methods.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ConnectOptions {
+    /// Fail with [`Error::TimedOut`] if the connection isn't established within this time, giving
+    /// up the attempt so that the device doesn't connect later. By default the platform's own
+    /// timeout applies.
+    pub timeout: Option<Duration>,
+    /// If the device hasn't advertised a name, find out its GAP Device Name once connected,
+    /// update [`PeripheralProperties::local_name`] and emit [`CentralEvent::NameResolved`]. This
+    /// is supported on Linux and Windows, which read the name themselves after connecting.
+    pub resolve_name: bool,
+}
+
+impl ConnectOptions {
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_name_resolution(mut self) -> Self {
+        self.resolve_name = true;
+        self
+    }
+}
+
+/// Options for [`Peripheral::subscribe_with_options`]. There are currently none, but new options
+/// may be added in future releases, so construct this with [`Default`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct SubscribeOptions {}
+
+/// Options for [`Peripheral::read_verified`]. New options may be added in future releases, so
+/// construct this with [`Default`] and the `with_` methods.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ReadVerification {
+    /// How many reads to make before giving up. Defaults to 3.
+    pub attempts: usize,
+    /// How long to wait before reading again after an invalid value. Defaults to 100 ms, as
+    /// devices which return invalid values just after connecting usually need a moment to settle.
+    pub retry_delay: Duration,
+    /// Whether a value is valid. By default any non-empty value is.
+    pub is_valid: fn(&[u8]) -> bool,
+    /// Only accept a value once two consecutive reads have returned it. Defaults to false.
+    pub require_identical: bool,
+}
+
+impl Default for ReadVerification {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            retry_delay: Duration::from_millis(100),
+            is_valid: |value| !value.is_empty(),
+            require_identical: false,
+        }
+    }
+}
+
+impl ReadVerification {
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn with_validator(mut self, is_valid: fn(&[u8]) -> bool) -> Self {
+        self.is_valid = is_valid;
+        self
+    }
+
+    pub fn with_identical_reads(mut self) -> Self {
+        self.require_identical = true;
+        self
+    }
+}
+
+bitflags! {
+    /// The roles an adapter can take in connections.
+    #[derive(Default)]
+    pub struct AdapterRoles: u8 {
+        const CENTRAL = 0x01;
+        const PERIPHERAL = 0x02;
+        /// Both roles at once, in different connections.
+        const CENTRAL_PERIPHERAL = 0x04;
+    }
+}
+
+/// Identifying details of an adapter, for choosing between several. See
+/// [`Central::adapter_details`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct AdapterInfo {
+    /// The platform's name for the adapter, e.g. `hci0` on Linux.
+    pub id: String,
+    /// The address of the controller.
+    pub address: BDAddr,
+    /// The name the adapter is known by to other devices.
+    pub alias: Option<String>,
+    /// The vendor, product and version of the controller, in the platform's format, e.g.
+    /// `usb:v1D6Bp0246d0537` on Linux.
+    pub modalias: Option<String>,
+    /// The roles the adapter supports. This is empty if the platform doesn't report them.
+    pub roles: AdapterRoles,
+}
+
+/// The power and discovery state of an adapter. See [`Central::adapter_state`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub struct AdapterState {
+    pub powered: bool,
+    /// Whether the adapter is blocked, e.g. by rfkill or airplane mode, so that it can't be
+    /// powered on until it is unblocked.
+    pub blocked: bool,
+    /// Whether the adapter is scanning, whether or not through btleplug.
+    pub discovering: bool,
+    /// Whether the adapter is discoverable by other devices. Only reported on Linux.
+    pub discoverable: bool,
+}
+
+/// Optional features supported by a backend, as returned by [`Central::capabilities`]. Operations
+/// for unsupported features fail with [`Error::NotSupported`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Whether the adapter can advertise in the peripheral role.
+    pub advertising: bool,
+    /// Whether extended (Bluetooth 5) advertising is available.
+    pub extended_advertising: bool,
+    /// Whether local GATT services can be published in the peripheral role.
+    pub gatt_server: bool,
+    /// Whether the peripheral role can be used while connected to devices in the central role, and
+    /// the other way around, e.g. to relay data from sensors while being configurable as a
+    /// peripheral. This also depends on the controller, so operations which would need both roles
+    /// on one that can't take them at once fail with [`Error::NotSupported`].
+    pub concurrent_roles: bool,
+    /// Whether L2CAP connection-oriented channels can be opened.
+    pub l2cap: bool,
+    /// Whether a specific ATT MTU can be requested for a connection.
+    pub mtu_request: bool,
+    /// Whether the LE PHYs connections may use can be chosen with
+    /// [`Central::set_preferred_phys`], which applies to all of the adapter's connections rather
+    /// than a single one.
+    pub phy_control: bool,
+    /// Whether pairing requests can be handled by an application-provided agent.
+    pub pairing_agent: bool,
+    /// Whether [`Peripheral::set_protection_level`] is supported.
+    pub protection_level: bool,
+    /// Whether [`ScanMode::Passive`] is supported.
+    pub passive_scan: bool,
+}
+
+/// Something which should wake the host from sleep when the platform supports it. See
+/// [`Central::register_wake_trigger`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WakeTrigger {
+    /// Wake when an advertisement matching the given filter is received, e.g. through a
+    /// `BluetoothLEAdvertisementWatcherTrigger` on Windows.
+    Advertisement(ScanFilter),
+    /// Wake when the given bonded device connects or becomes available.
+    BondedDevice(PeripheralId),
 }
 
+/// The LE physical layer used by a connection.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Phy {
+    /// LE 1M, the PHY which all LE devices support.
+    Le1M,
+    /// LE 2M, for higher throughput.
+    Le2M,
+    /// LE Coded, for longer range.
+    LeCoded,
+}
+
+/// The security level of a connection, as defined by LE security mode 1.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum SecurityLevel {
+    /// No encryption or authentication.
+    None,
+    /// Encrypted, but without authentication (i.e. "Just Works" pairing).
+    Unauthenticated,
+    /// Encrypted with an authenticated (MITM protected) pairing.
+    Authenticated,
+    /// Encrypted with an authenticated LE Secure Connections pairing.
+    AuthenticatedSecureConnections,
+}
+
+/// Whether a GATT operation may be answered from the platform's attribute cache rather than the
+/// device itself. Only Windows keeps such a cache; other platforms always go to the device.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheMode {
+    /// Use cached values where available.
+    Cached,
+    /// Always query the device.
+    Uncached,
+}
+
+/// Connection parameters to request with [`Peripheral::request_connection_params`]. The central
+/// chooses an interval between the minimum and maximum, so a wider range is more likely to be
+/// accepted.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectionParameters {
+    /// The shortest connection interval, from 7.5 ms to 4 s in steps of 1.25 ms.
+    pub min_interval: Duration,
+    /// The longest connection interval, from 7.5 ms to 4 s in steps of 1.25 ms.
+    pub max_interval: Duration,
+    /// The number of connection events the peripheral may skip when it has nothing to send, up to
+    /// 499.
+    pub peripheral_latency: u16,
+    /// How long the link may go without a packet before it is considered lost, from 100 ms to
+    /// 32 s in steps of 10 ms.
+    pub supervision_timeout: Duration,
+}
+
+impl ConnectionParameters {
+    /// Returns whether the parameters are within the limits of the Core Specification, including
+    /// that the supervision timeout is more than twice as long as the peripheral may stay silent
+    /// for.
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Like [`is_valid`](Self::is_valid), but fails with [`Error::InvalidParameters`] saying which
+    /// limit the parameters are outside of.
+    pub fn validate(&self) -> Result<()> {
+        let interval_range = Duration::from_micros(7500)..=Duration::from_secs(4);
+        let silent = self.max_interval * (u32::from(self.peripheral_latency) + 1);
+        let problem = if !interval_range.contains(&self.min_interval)
+            || !interval_range.contains(&self.max_interval)
+        {
+            "the connection interval must be from 7.5 ms to 4 s"
+        } else if self.min_interval > self.max_interval {
+            "the minimum connection interval is longer than the maximum"
+        } else if self.peripheral_latency > 499 {
+            "the peripheral latency must be at most 499"
+        } else if !(Duration::from_millis(100)..=Duration::from_secs(32))
+            .contains(&self.supervision_timeout)
+        {
+            "the supervision timeout must be from 100 ms to 32 s"
+        } else if self.supervision_timeout <= silent * 2 {
+            "the supervision timeout must be more than twice the maximum interval times the \
+             peripheral latency plus one"
+        } else {
+            return Ok(());
+        };
+        Err(Error::InvalidParameters(problem.to_owned()))
+    }
+}
+
+/// Information about the current connection to a peripheral. Each platform exposes a different
+/// subset of these, so any field may be `None` if it is not known.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    /// The negotiated ATT MTU.
+    pub mtu: Option<u16>,
+    /// The connection interval.
+    pub connection_interval: Option<Duration>,
+    /// The number of connection events the peripheral may skip.
+    pub peripheral_latency: Option<u16>,
+    /// The supervision timeout, after which the connection is considered lost.
+    pub supervision_timeout: Option<Duration>,
+    /// The PHY used by the connection.
+    pub phy: Option<Phy>,
+    /// The security level of the connection, so that applications can check the link wasn't
+    /// downgraded. On Windows this is the level of the device's pairing, which Windows encrypts
+    /// the link with once connected. BlueZ doesn't report it.
+    pub security_level: Option<SecurityLevel>,
+    /// When the connection was established, if it was observed by this process.
+    pub connected_since: Option<SystemTime>,
+}
+
+/// The ATT MTU every connection starts with, before a larger one is negotiated.
+pub const DEFAULT_ATT_MTU: u16 = 23;
+
 /// The type of write operation to use.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum WriteType {
@@ -228,17 +973,83 @@ pub enum WriteType {
     WithoutResponse,
 }
 
+/// Writes to be executed atomically with the ATT reliable write procedure, from
+/// [`Peripheral::reliable_write_transaction`]. The device checks each write as it is queued and
+/// applies either all of them or none. Nothing is sent until the transaction is committed, so
+/// dropping it without committing aborts it.
+#[derive(Clone, Debug)]
+pub struct ReliableWriteTransaction<P> {
+    peripheral: P,
+    writes: Vec<(Characteristic, Vec<u8>)>,
+}
+
+impl<P: Peripheral> ReliableWriteTransaction<P> {
+    /// Queues a write of the characteristic.
+    pub fn write(&mut self, characteristic: &Characteristic, data: &[u8]) -> &mut Self {
+        self.writes.push((characteristic.clone(), data.to_vec()));
+        self
+    }
+
+    /// Returns the writes queued so far.
+    pub fn writes(&self) -> &[(Characteristic, Vec<u8>)] {
+        &self.writes
+    }
+
+    /// Sends the queued writes and executes them, failing if the device rejects any of them.
+    pub async fn commit(self) -> Result<()> {
+        self.peripheral.reliable_write(&self.writes).await
+    }
+}
+
 /// Peripheral is the device that you would like to communicate with (the "server" of BLE). This
 /// struct contains both the current state of the device (its properties, characteristics, etc.)
 /// as well as functions for communication.
+///
+/// Cloning a peripheral is cheap, and the clone shares the original's state rather than being an
+/// independent object. To refer to a peripheral without keeping hold of it, as
+/// [`CentralEvent`]s do, use its [`PeripheralId`] and get it back with [`Central::peripheral`].
 #[async_trait]
 pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Returns the unique identifier of the peripheral.
     fn id(&self) -> PeripheralId;
 
-    /// Returns the MAC address of the peripheral.
+    /// Returns the MAC address of the peripheral. This alone doesn't identify an LE device, as
+    /// public and random addresses may coincide; key on [`id`](Self::id) or
+    /// [`le_address`](Self::le_address) instead.
     fn address(&self) -> BDAddr;
 
+    /// Returns the address of the peripheral together with its type. If the platform hasn't
+    /// reported the type, it is assumed to be public.
+    async fn le_address(&self) -> Result<LeAddress> {
+        let address_type = self
+            .properties()
+            .await?
+            .and_then(|properties| properties.address_type)
+            .unwrap_or_default();
+        Ok(LeAddress::new(self.address(), address_type))
+    }
+
+    /// Returns the identity address of the peripheral, which stays the same while it rotates
+    /// private addresses, or `None` if it is only known by a private address.
+    ///
+    /// Once a device using resolvable private addresses is bonded, the platform learns its
+    /// identity and resolves its later addresses itself. BlueZ then replaces the address of the
+    /// device with the identity address, and keeps reporting it under the same [`id`](Self::id).
+    /// Unbonded devices can be followed across rotations with an
+    /// [`AddressResolver`](resolver::AddressResolver) instead.
+    async fn identity_address(&self) -> Result<Option<LeAddress>> {
+        let address = self.le_address().await?;
+        Ok(address.is_identity().then_some(address))
+    }
+
+    /// Returns the battery level of the device in percent, or `None` if it doesn't report one. On
+    /// Linux this is what BlueZ read from the device, and changes are reported as
+    /// [`CentralEvent::BatteryLevelChanged`]. Elsewhere it is read from the Battery Service,
+    /// once the device's services have been discovered.
+    async fn battery_level(&self) -> Result<Option<u8>> {
+        battery::read_battery_service(self).await
+    }
+
     /// Returns the set of properties associated with the peripheral. These may be updated over time
     /// as additional advertising reports are received.
     async fn properties(&self) -> Result<Option<PeripheralProperties>>;
@@ -247,6 +1058,22 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// `discover_services` is called.
     fn services(&self) -> BTreeSet<Service>;
 
+    /// Returns the discovered services as [`RawService`]s, whose included services and declaration
+    /// handle come from [`Service::included_services`] and [`Service::handles`].
+    async fn services_raw(&self) -> Result<Vec<RawService>> {
+        Ok(self
+            .services()
+            .into_iter()
+            .map(|service| RawService {
+                uuid: service.uuid,
+                primary: service.primary,
+                peripheral: self.id(),
+                includes: service.included_services,
+                handle: service.handles.map(|handles| handles.start),
+            })
+            .collect())
+    }
+
     /// The set of characteristics we've discovered for this device. This will be empty until
     /// `discover_services` is called.
     fn characteristics(&self) -> BTreeSet<Characteristic> {
@@ -256,20 +1083,193 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
             .collect()
     }
 
+    /// Returns a rough estimate of how long after arriving at the host a notification is
+    /// timestamped with [`ValueNotification::received_at`], i.e. the latency added by the platform
+    /// stack, or `None` if it isn't known. Subtract it from timestamps to compare notifications
+    /// received through different platforms.
+    ///
+    /// None of the built-in backends measure this, since no platform reports when a packet reached
+    /// the host, so they all return `None`. It's here for wrappers which know their latency.
+    fn notification_latency(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Pairs with the device, bonding to it if it asks to be, so that it allows access to
+    /// characteristics which need an encrypted or authenticated link. The platform may ask the
+    /// [`PairingAgent`](pairing::PairingAgent) registered with
+    /// [`Central::register_pairing_agent`] to confirm the pairing or enter a passkey. Does nothing
+    /// if the device is already paired.
+    ///
+    /// On Windows the device must be connected first, and Windows shows its own prompts if no
+    /// agent is registered.
+    async fn pair(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Pairing is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Removes the pairing and any bond with the device. On Linux this makes BlueZ forget the
+    /// device altogether, so it must be discovered again before it can be used.
+    async fn unpair(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Pairing is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Makes the platform forget the device, along with its cached services and any bond, so that
+    /// the next connection starts afresh, e.g. after its firmware changed its GATT database. It
+    /// must be discovered again before it can be used, and its [`Peripheral`] can no longer be.
+    async fn forget(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Forgetting devices is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Asks for exclusive access to the services discovered so far, so that other applications
+    /// can't use them until this peripheral disconnects. Returns [`Error::InUse`] if another
+    /// application already has them open. This is only supported on Windows.
+    async fn request_exclusive_access(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Exclusive access is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns a histogram of how long writes with response to the characteristic took to be
+    /// acknowledged, or `None` if there haven't been any or the platform doesn't track them.
+    fn write_latency(
+        &self,
+        _characteristic: &Characteristic,
+    ) -> Option<diagnostics::LatencyHistogram> {
+        None
+    }
+
+    /// Returns the most recent operations on this peripheral and changes to its state, oldest
+    /// first. This is empty unless enabled with [`activity::set_activity_log_capacity`], or if the
+    /// platform doesn't support it.
+    fn recent_activity(&self) -> Vec<activity::ActivityRecord> {
+        Vec::new()
+    }
+
+    /// Returns the discovered characteristics whose UUIDs match the given pattern.
+    fn find_characteristics(&self, pattern: &UuidPattern) -> BTreeSet<Characteristic> {
+        self.characteristics()
+            .into_iter()
+            .filter(|characteristic| pattern.matches(&characteristic.uuid))
+            .collect()
+    }
+
     /// Returns true iff we are currently connected to the device.
     async fn is_connected(&self) -> Result<bool>;
 
+    /// Asks the platform to make the connection as fast as it can, for bulk transfers such as
+    /// firmware updates: the largest MTU, the 2M PHY and a short connection interval, as far as the
+    /// platform allows each to be requested. Returns what was negotiated.
+    ///
+    /// BlueZ and CoreBluetooth negotiate the MTU and PHY themselves and don't choose a short
+    /// connection interval, so there this only reports the current connection; on Linux, use
+    /// [`Peripheral::request_connection_params`] for the interval. On
+    /// Windows 11 this requests throughput-optimized connection parameters for as long as the
+    /// connection lasts.
+    async fn optimize_throughput(&self) -> Result<ConnectionInfo> {
+        self.connection_info().await
+    }
+
+    /// Asks for the connection to use the given parameters: short intervals for throughput and
+    /// latency, or long intervals and peripheral latency to save power. The device may reject them
+    /// or negotiate others, so check [`Peripheral::connection_info`] where it reports them.
+    ///
+    /// On Linux this goes through the kernel's management interface, so it needs the
+    /// `CAP_NET_ADMIN` capability, and kernels older than 6.11 only apply the parameters from the
+    /// next connection. Other platforms don't let applications choose the parameters. Parameters
+    /// outside the limits checked by [`ConnectionParameters::validate`] fail with
+    /// [`Error::InvalidParameters`].
+    async fn request_connection_params(&self, _parameters: ConnectionParameters) -> Result<()> {
+        Err(Error::NotSupported(
+            "Requesting connection parameters is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns the negotiated ATT MTU of the connection. A write without response can carry at most
+    /// three bytes less than this. If the platform doesn't report the MTU, as BlueZ before 5.62
+    /// doesn't, this returns [`DEFAULT_ATT_MTU`] rather than the negotiated value. That is always
+    /// safe to split writes by, but may be smaller than necessary.
+    async fn mtu(&self) -> Result<u16> {
+        Ok(self.connection_info().await?.mtu.unwrap_or(DEFAULT_ATT_MTU))
+    }
+
+    /// Asks the platform to negotiate the given ATT MTU with the device, returning the MTU agreed
+    /// on, which may be smaller. See [`Capabilities::mtu_request`].
+    async fn request_mtu(&self, _mtu: u16) -> Result<u16> {
+        Err(Error::NotSupported(
+            "Requesting an MTU is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns information about the current connection to the device, such as the MTU and
+    /// connection parameters, as far as the platform exposes them. Returns
+    /// [`Error::NotConnected`] if the device is not connected.
+    async fn connection_info(&self) -> Result<ConnectionInfo> {
+        if self.is_connected().await? {
+            Ok(ConnectionInfo::default())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
     /// Creates a connection to the device. If this method returns Ok there has been successful
     /// connection. Note that peripherals allow only one connection at a time. Operations that
     /// attempt to communicate with a device will fail until it is connected.
     async fn connect(&self) -> Result<()>;
 
+    /// Like [`connect`](Self::connect), with additional options.
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        match options.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.connect()).await {
+                Ok(result) => result,
+                Err(_) => {
+                    abandon_connect(self).await;
+                    Err(Error::TimedOut(timeout))
+                }
+            },
+            None => self.connect().await,
+        }
+    }
+
+    /// Starts connecting to the device in the background, and returns a handle which resolves to
+    /// the result of [`connect`](Self::connect) or can [`cancel`](connect::ConnectAttempt::cancel)
+    /// the attempt.
+    ///
+    /// This requires a Tokio runtime, as the attempt is driven by a spawned task.
+    fn connect_cancellable(&self) -> connect::ConnectAttempt<Self>
+    where
+        Self: 'static,
+    {
+        connect::ConnectAttempt::start(self.clone())
+    }
+
+    /// Makes the platform give up a connection attempt in progress, e.g. one whose
+    /// [`connect`](Self::connect) future has been dropped, including any pairing it started. If
+    /// the connection has been made already, this disconnects instead. Only supported on Linux,
+    /// where BlueZ keeps trying to connect until told otherwise.
+    async fn cancel_connect(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Cancelling a connection is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Terminates a connection to the device.
     async fn disconnect(&self) -> Result<()>;
 
     /// Discovers all services for the device, including their characteristics.
     async fn discover_services(&self) -> Result<()>;
 
+    /// Like [`discover_services`](Self::discover_services), but allows forcing the platform to
+    /// query the device rather than answering from its cache, e.g. after a firmware update changed
+    /// the device's GATT database.
+    async fn discover_services_with_cache_mode(&self, _cache_mode: CacheMode) -> Result<()> {
+        self.discover_services().await
+    }
+
     /// Write some data to the characteristic. Returns an error if the write couldn't be sent or (in
     /// the case of a write-with-response) if the device returns an error.
     async fn write(
@@ -283,18 +1283,269 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// or the response from the device.
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
 
+    /// Reads the value of the characteristic from the given offset onwards. Plain
+    /// [`read`](Self::read) and [`write`](Self::write) already handle values longer than the MTU
+    /// allows, as the platforms issue the Read Blob or Prepare and Execute Write requests
+    /// themselves; this is for devices which use offsets to page through larger values.
+    ///
+    /// Where the platform can't read from an offset, this reads the whole value and skips to the
+    /// offset.
+    async fn read_with_offset(
+        &self,
+        characteristic: &Characteristic,
+        offset: usize,
+    ) -> Result<Vec<u8>> {
+        let value = self.read(characteristic).await?;
+        value
+            .get(offset..)
+            .map(<[u8]>::to_vec)
+            .ok_or(Error::Att(AttError::INVALID_OFFSET))
+    }
+
+    /// Writes part of the value of the characteristic, starting at the given offset, with a long
+    /// write.
+    async fn write_with_offset(
+        &self,
+        _characteristic: &Characteristic,
+        _offset: usize,
+        _data: &[u8],
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Writing at an offset is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Opens an L2CAP connection-oriented channel to the given PSM on the device. Devices usually
+    /// publish the PSM of their service in a GATT characteristic. See [`Capabilities::l2cap`].
+    async fn open_l2cap_channel(&self, _psm: u16) -> Result<l2cap::L2capChannel> {
+        Err(Error::NotSupported(
+            "L2CAP channels are not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Runs the operations `build` queues one after another, and returns their results in order.
+    /// The first operation to fail stops the batch, and its error is returned. On Linux, other
+    /// operations on the peripheral wait until the batch has finished, so that multi-step setup
+    /// sequences aren't interleaved with those of other tasks.
+    async fn batch<F>(&self, build: F) -> Result<Vec<batch::BatchResult>>
+    where
+        F: FnOnce(&mut batch::OperationBatch) + Send,
+    {
+        let mut operations = batch::OperationBatch::default();
+        build(&mut operations);
+        operations.run(self).await
+    }
+
+    /// Starts a transaction of writes which the device applies atomically, for updates which
+    /// mustn't be left half done, such as firmware images split over several characteristics.
+    fn reliable_write_transaction(&self) -> ReliableWriteTransaction<Self> {
+        ReliableWriteTransaction {
+            peripheral: self.clone(),
+            writes: Vec::new(),
+        }
+    }
+
+    /// Executes the given writes atomically. This is usually called by
+    /// [`ReliableWriteTransaction::commit`]. BlueZ can only write one characteristic reliably
+    /// at a time, so there this fails with [`Error::NotSupported`] for more than one write.
+    async fn reliable_write(&self, _writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        Err(Error::NotSupported(
+            "Reliable writes are not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Like [`write`](Self::write), but fails with [`Error::TimedOut`] if the write takes longer
+    /// than the given timeout, rather than waiting for the platform's default timeout.
+    ///
+    /// This must be called from within a Tokio runtime with the time driver enabled.
+    async fn write_with_timeout(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+        timeout: Duration,
+    ) -> Result<()> {
+        tokio::time::timeout(timeout, self.write(characteristic, data, write_type))
+            .await
+            .map_err(|_| Error::TimedOut(timeout))?
+    }
+
+    /// Like [`read`](Self::read), but fails with [`Error::TimedOut`] if the read takes longer than
+    /// the given timeout, rather than waiting for the platform's default timeout.
+    ///
+    /// This must be called from within a Tokio runtime with the time driver enabled.
+    async fn read_with_timeout(
+        &self,
+        characteristic: &Characteristic,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        tokio::time::timeout(timeout, self.read(characteristic))
+            .await
+            .map_err(|_| Error::TimedOut(timeout))?
+    }
+
+    /// Like [`read`](Self::read), but allows the value to be answered from the platform's cache.
+    /// Plain `read` always queries the device.
+    async fn read_with_cache_mode(
+        &self,
+        characteristic: &Characteristic,
+        _cache_mode: CacheMode,
+    ) -> Result<Vec<u8>> {
+        self.read(characteristic).await
+    }
+
+    /// Reads the characteristic until it returns a valid value, for devices which return empty or
+    /// garbage values for a while after connecting. If all the attempts return invalid values this
+    /// fails with [`Error::InvalidValue`]. Errors from the reads themselves aren't retried.
+    ///
+    /// This must be called from within a Tokio runtime with the time driver enabled.
+    async fn read_verified(
+        &self,
+        characteristic: &Characteristic,
+        verification: ReadVerification,
+    ) -> Result<Vec<u8>> {
+        let mut previous: Option<Vec<u8>> = None;
+        for attempt in 0..verification.attempts {
+            if attempt > 0 {
+                tokio::time::sleep(verification.retry_delay).await;
+            }
+            let value = self.read(characteristic).await?;
+            if !(verification.is_valid)(&value) {
+                previous = None;
+                continue;
+            }
+            if !verification.require_identical || previous.as_ref() == Some(&value) {
+                return Ok(value);
+            }
+            previous = Some(value);
+        }
+        Err(Error::InvalidValue(verification.attempts))
+    }
+
+    /// Returns a stream of the results of reading the characteristic every `interval`, for devices
+    /// whose values change without notifying. Reads are paused while the device is disconnected
+    /// and resume when it reconnects, and a tick is skipped rather than made up for if the previous
+    /// read or other traffic held the device up, so the reads never pile up. The reads stop when
+    /// the stream is dropped.
+    ///
+    /// This must be polled from within a Tokio runtime with the time driver enabled.
+    fn poll(
+        &self,
+        characteristic: &Characteristic,
+        interval: Duration,
+    ) -> Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send>>
+    where
+        Self: 'static,
+    {
+        poll::poll_characteristic(self.clone(), characteristic.clone(), interval)
+    }
+
+    /// Requires the given security level for all subsequent operations on the characteristic, so
+    /// that the platform encrypts (and if necessary pairs) the link before accessing it. This is
+    /// only supported on Windows; elsewhere the required security is negotiated by the OS when the
+    /// device rejects an operation.
+    async fn set_protection_level(
+        &self,
+        _characteristic: &Characteristic,
+        _level: SecurityLevel,
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting a protection level is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
+    /// Like [`subscribe`](Self::subscribe), with additional options.
+    async fn subscribe_with_options(
+        &self,
+        characteristic: &Characteristic,
+        _options: SubscribeOptions,
+    ) -> Result<()> {
+        self.subscribe(characteristic).await
+    }
+
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
+    /// Returns the characteristics for which notifications or indications are currently enabled.
+    /// On Linux this is BlueZ's view, which includes subscriptions made by other processes.
+    async fn subscriptions(&self) -> Result<BTreeSet<Characteristic>> {
+        Err(Error::NotSupported(
+            "Listing subscriptions is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Unsubscribes from every characteristic returned by [`subscriptions`](Self::subscriptions).
+    async fn unsubscribe_all(&self) -> Result<()> {
+        for characteristic in self.subscriptions().await? {
+            self.unsubscribe(&characteristic).await?;
+        }
+        Ok(())
+    }
+
     /// Returns a stream of notifications for characteristic value updates. The stream will receive
     /// a notification when a value notification or indication is received from the device.
     /// The stream will remain valid across connections and can be queried before any connection
     /// is made.
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>;
 
+    /// Subscribes to each of the characteristics, and returns a stream of their notifications
+    /// along with the others from [`notifications`](Self::notifications). Characteristics which
+    /// can't be subscribed to, because they don't support notifying, the device has no Client
+    /// Characteristic Configuration descriptor for them, or the platform rejects the subscription,
+    /// are read every `interval` instead as by [`poll`](Self::poll). Their values are delivered
+    /// as notifications flagged [`emulated`](ValueNotification::emulated), the first value read
+    /// and then each time it changes, so applications can consume both the same way.
+    ///
+    /// This must be called from within a Tokio runtime with the time driver enabled.
+    async fn notifications_with_fallback(
+        &self,
+        characteristics: &[Characteristic],
+        interval: Duration,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>
+    where
+        Self: 'static,
+    {
+        poll::notifications_with_fallback(self, characteristics, interval).await
+    }
+
+    /// Waits for a notification or indication from the characteristic whose value matches the
+    /// predicate, e.g. for a device to report that it is ready, and returns the value. Subscribes
+    /// to the characteristic first unless notifications are already enabled, and leaves the
+    /// subscription in place afterwards. Fails with [`Error::TimedOut`] if no matching value
+    /// arrives within the timeout, or with [`Error::NotConnected`] if the platform stops
+    /// delivering notifications first.
+    ///
+    /// This must be called from within a Tokio runtime with the time driver enabled.
+    async fn wait_for_notification<P>(
+        &self,
+        characteristic: &Characteristic,
+        mut predicate: P,
+        timeout: Duration,
+    ) -> Result<Vec<u8>>
+    where
+        P: FnMut(&[u8]) -> bool + Send,
+    {
+        // Get the stream before subscribing, so that a value sent straight away isn't missed.
+        let mut notifications = self.notifications().await?;
+        if !self.is_notifying(characteristic).await.unwrap_or(false) {
+            self.subscribe(characteristic).await?;
+        }
+        let matching = async {
+            while let Some(notification) = notifications.next().await {
+                if notification.uuid == characteristic.uuid && predicate(&notification.value) {
+                    return Ok(notification.value);
+                }
+            }
+            Err(Error::NotConnected)
+        };
+        tokio::time::timeout(timeout, matching)
+            .await
+            .map_err(|_| Error::TimedOut(timeout))?
+    }
+
     /// Write some data to the descriptor. Returns an error if the write couldn't be sent or (in
     /// the case of a write-with-response) if the device returns an error.
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
@@ -302,6 +1553,69 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Sends a read descriptor request to the device. Returns either an error if the request
     /// was not accepted or the response from the device.
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Reads the descriptor of the characteristic with the given UUID, failing with
+    /// [`Error::NoSuchDescriptor`] if the characteristic has none.
+    async fn read_descriptor_by_uuid(
+        &self,
+        characteristic: &Characteristic,
+        uuid: Uuid,
+    ) -> Result<Vec<u8>> {
+        let descriptor = characteristic
+            .descriptors
+            .iter()
+            .find(|descriptor| descriptor.uuid == uuid)
+            .ok_or(Error::NoSuchDescriptor)?;
+        self.read_descriptor(descriptor).await
+    }
+
+    /// Reads the Characteristic User Description of the characteristic.
+    async fn read_user_description(&self, characteristic: &Characteristic) -> Result<String> {
+        let value = self
+            .read_descriptor_by_uuid(characteristic, bleuuid::CHARACTERISTIC_USER_DESCRIPTION)
+            .await?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    /// Reads the Client Characteristic Configuration of the characteristic. BlueZ handles this
+    /// descriptor itself and doesn't expose it, so on Linux use
+    /// [`is_notifying`](Self::is_notifying) instead.
+    async fn read_client_configuration(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<ClientConfiguration> {
+        let value = self
+            .read_descriptor_by_uuid(characteristic, bleuuid::CLIENT_CHARACTERISTIC_CONFIGURATION)
+            .await?;
+        let bits = match value[..] {
+            [low] => u16::from(low),
+            [low, high, ..] => u16::from_le_bytes([low, high]),
+            [] => 0,
+        };
+        Ok(ClientConfiguration::from_bits_truncate(bits))
+    }
+
+    /// Returns true if notifications or indications are enabled for the characteristic, as far as
+    /// the platform knows. On Linux this comes from BlueZ, elsewhere from the Client Characteristic
+    /// Configuration descriptor.
+    async fn is_notifying(&self, characteristic: &Characteristic) -> Result<bool> {
+        Ok(!self
+            .read_client_configuration(characteristic)
+            .await?
+            .is_empty())
+    }
+}
+
+/// Stops a connection attempt whose future was dropped, as the platform may otherwise keep trying
+/// and connect later. Disconnects instead where the platform can't cancel an attempt.
+async fn abandon_connect<P: Peripheral>(peripheral: &P) {
+    let result = match peripheral.cancel_connect().await {
+        Err(Error::NotSupported(_)) => peripheral.disconnect().await,
+        result => result,
+    };
+    if let Err(e) = result {
+        log::debug!("Failed to abandon connection attempt: {}", e);
+    }
 }
 
 #[cfg_attr(
@@ -312,9 +1626,19 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
 #[derive(Debug, Clone)]
 pub enum CentralEvent {
     DeviceDiscovered(PeripheralId),
+    /// Emitted by [`Central::load_cached_peripherals`] for each device the platform already knew
+    /// about, in place of a `DeviceDiscovered` event. The device hasn't necessarily been seen
+    /// since, so it may be out of range.
+    CachedDeviceDiscovered(PeripheralId),
+    /// Emitted when the properties of a device have changed, including when its advertised
+    /// manufacturer data, service data or services change while it is connected. The new payload
+    /// is carried by the accompanying advertisement event.
     DeviceUpdated(PeripheralId),
     DeviceConnected(PeripheralId),
     DeviceDisconnected(PeripheralId),
+    /// Emitted when the platform has forgotten about a device, e.g. because BlueZ removed it after
+    /// it stopped advertising. Its [`Peripheral`] can no longer be used.
+    DeviceLost(PeripheralId),
     /// Emitted when a Manufacturer Data advertisement has been received from a device
     ManufacturerDataAdvertisement {
         id: PeripheralId,
@@ -330,6 +1654,50 @@ pub enum CentralEvent {
         id: PeripheralId,
         services: Vec<Uuid>,
     },
+    /// Emitted when the name of a device which didn't advertise one has been read from the device
+    /// after connecting. See [`ConnectOptions::resolve_name`].
+    NameResolved {
+        id: PeripheralId,
+        name: String,
+    },
+    /// Emitted when the battery level of a device has changed, in percent. Only BlueZ reports
+    /// this. See [`Peripheral::battery_level`].
+    BatteryLevelChanged {
+        id: PeripheralId,
+        level: u8,
+    },
+}
+
+impl CentralEvent {
+    /// Returns the ID of the peripheral this event concerns.
+    pub fn peripheral_id(&self) -> &PeripheralId {
+        match self {
+            CentralEvent::DeviceDiscovered(id)
+            | CentralEvent::CachedDeviceDiscovered(id)
+            | CentralEvent::DeviceUpdated(id)
+            | CentralEvent::DeviceConnected(id)
+            | CentralEvent::DeviceDisconnected(id)
+            | CentralEvent::DeviceLost(id)
+            | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+            | CentralEvent::ServiceDataAdvertisement { id, .. }
+            | CentralEvent::ServicesAdvertisement { id, .. }
+            | CentralEvent::NameResolved { id, .. }
+            | CentralEvent::BatteryLevelChanged { id, .. } => id,
+        }
+    }
+}
+
+/// A [`CentralEvent`] along with the tags its peripheral had when the event was received. See
+/// [`Central::tagged_events`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone)]
+pub struct TaggedCentralEvent {
+    pub event: CentralEvent,
+    pub tags: BTreeSet<String>,
 }
 
 /// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
@@ -342,6 +1710,19 @@ pub trait Central: Send + Sync + Clone {
     /// occur for this Central module. See [`CentralEvent`] for the full set of possible events.
     async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>>;
 
+    /// Like [`events`](Central::events), but with `DeviceUpdated` events limited to at most one per
+    /// `min_interval` for each device, always delivering the latest. See
+    /// [`throttle::throttle_device_updates`].
+    async fn throttled_events(
+        &self,
+        min_interval: Duration,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(throttle::throttle_device_updates(
+            self.events().await?,
+            min_interval,
+        ))
+    }
+
     /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
     /// although this may depend on your Bluetooth adapter. Discovered devices will be announced
     /// to subscribers of `events` and will be available via `peripherals()`.
@@ -351,24 +1732,279 @@ pub trait Central: Send + Sync + Clone {
     /// a filter, but must be able to handle devices, which do not fit into the filter.
     async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
 
+    /// Like [`start_scan`](Self::start_scan), with additional options. This is where new scan
+    /// parameters are added, so prefer it in new code.
+    async fn start_scan_with_options(&self, options: ScanOptions) -> Result<()> {
+        self.start_scan(options.filter).await
+    }
+
     /// Stops scanning for BLE devices.
     async fn stop_scan(&self) -> Result<()>;
 
     /// Returns the list of [`Peripheral`]s that have been discovered so far. Note that this list
-    /// may contain peripherals that are no longer available.
+    /// may contain peripherals that are no longer available. On Linux it also includes every
+    /// device BlueZ already knows of, such as those paired from the desktop or connected by
+    /// another application, without needing a scan.
     async fn peripherals(&self) -> Result<Vec<Self::Peripheral>>;
 
     /// Returns a particular [`Peripheral`] by its address if it has been discovered.
     async fn peripheral(&self, id: &PeripheralId) -> Result<Self::Peripheral>;
 
+    /// Returns the peripherals which are currently connected, including those connected before
+    /// this process started, such as by an earlier run of it which exited without disconnecting.
+    /// Where the platform has already discovered their services, these are loaded so that the
+    /// peripherals can be used straight away, without reconnecting; otherwise call
+    /// [`Peripheral::discover_services`].
+    async fn connected_peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        let mut connected = vec![];
+        for peripheral in self.peripherals().await? {
+            if peripheral.is_connected().await? {
+                connected.push(peripheral);
+            }
+        }
+        Ok(connected)
+    }
+
+    /// Makes the platform forget a device it has discovered. See [`Peripheral::forget`].
+    async fn remove_peripheral(&self, id: &PeripheralId) -> Result<()> {
+        self.peripheral(id).await?.forget().await
+    }
+
+    /// Loads the devices the platform remembers from earlier sessions, such as paired devices,
+    /// without scanning, and emits a [`CentralEvent::CachedDeviceDiscovered`] for each of them.
+    /// This gives applications the same starting point on every platform before they scan.
+    ///
+    /// On Linux these are the devices BlueZ knows about on the adapter, and on Windows the paired
+    /// LE devices. Not supported on other platforms, as btleplug doesn't persist any identifiers
+    /// itself.
+    async fn load_cached_peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        Err(Error::NotSupported(
+            "Loading cached peripherals is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Add a [`Peripheral`] from a MAC address without a scan result. Not supported on all Bluetooth systems.
     async fn add_peripheral(&self, address: &PeripheralId) -> Result<Self::Peripheral>;
 
+    /// Returns the LE PHYs the adapter supports for connections. Every adapter supports 1M.
+    ///
+    /// On Linux this goes through the kernel's management interface, so it may need the
+    /// `CAP_NET_ADMIN` capability. Windows 11 reports it for the default adapter.
+    async fn supported_phys(&self) -> Result<BTreeSet<Phy>> {
+        Err(Error::NotSupported(
+            "Querying PHYs is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Makes the adapter prefer the given PHYs for its connections: Coded for range or 2M for
+    /// throughput. The controller and the device negotiate which one is used, so check
+    /// [`ConnectionInfo::phy`] where the platform reports it. 1M stays allowed, as every device
+    /// supports it.
+    ///
+    /// This is only supported on Linux, where it applies to all of the adapter's connections and
+    /// needs the `CAP_NET_ADMIN` capability.
+    async fn set_preferred_phys(&self, _phys: BTreeSet<Phy>) -> Result<()> {
+        Err(Error::NotSupported(
+            "Choosing PHYs is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Get information about the Bluetooth adapter being used, such as the model or type.
     ///
     /// The details of this are platform-specific andyou should not attempt to parse it, but it may
     /// be useful for debug logs.
     async fn adapter_info(&self) -> Result<String>;
+
+    /// Returns the address, name and other identifying details of the adapter, to tell apart
+    /// several attached to the same machine.
+    async fn adapter_details(&self) -> Result<AdapterInfo> {
+        Err(Error::NotSupported(
+            "Querying adapter details is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns whether the adapter is powered, blocked, discovering and discoverable.
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        Err(Error::NotSupported(
+            "Querying the adapter state is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Powers the adapter on or off. Fails with [`Error::AdapterBlocked`] if the adapter is
+    /// blocked, as that can only be undone outside btleplug.
+    async fn set_powered(&self, _powered: bool) -> Result<()> {
+        Err(Error::NotSupported(
+            "Powering the adapter is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns the optional features supported by this backend and adapter.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Checks that the adapter is present and usable, including that it can scan, and reports the
+    /// outcome of each check. This starts and stops a scan, so shouldn't be called while scanning.
+    /// See [`self_test`].
+    async fn self_test(&self) -> self_test::SelfTestReport {
+        let mut report = self_test::SelfTestReport::default();
+        if report.record("adapter", self.adapter_info().await.map(|_| ())) {
+            self_test::check_scanning(self, &mut report).await;
+        } else {
+            report.skip("start_scan");
+            report.skip("stop_scan");
+        }
+        report
+    }
+
+    /// Returns the duty cycle the adapter is actually scanning with, for platforms which can report
+    /// it. Otherwise, [`energy::estimate_scan_duty_cycle`] gives a model-based estimate.
+    async fn scan_duty_cycle(&self) -> Result<energy::ScanDutyCycle> {
+        Err(Error::NotSupported(
+            "Reporting the scan duty cycle is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Starts advertising in the peripheral role, replacing any advertisement already made through
+    /// this adapter. See [`advertising`] and [`Capabilities::advertising`].
+    async fn start_advertising(&self, _advertisement: advertising::Advertisement) -> Result<()> {
+        Err(Error::NotSupported(
+            "Advertising is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Stops the advertisement started by [`start_advertising`](Self::start_advertising), if any.
+    async fn stop_advertising(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Advertising is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Hosts the given GATT services on this adapter, replacing any registered before. See
+    /// [`gatt_server`] and [`Capabilities::gatt_server`].
+    async fn register_gatt_services(
+        &self,
+        _services: Vec<gatt_server::LocalService>,
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Stops hosting the services registered by
+    /// [`register_gatt_services`](Self::register_gatt_services), if any.
+    async fn unregister_gatt_services(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Sends a new value of a registered characteristic to the centrals subscribed to it, if any.
+    async fn notify_local_characteristic(
+        &self,
+        _service: Uuid,
+        _characteristic: Uuid,
+        _value: &[u8],
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns a stream of what centrals do to the services registered by
+    /// [`register_gatt_services`](Self::register_gatt_services), from now on. See
+    /// [`gatt_server::ServerEvent`].
+    async fn gatt_server_events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = gatt_server::ServerEvent> + Send>>> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Registers the agent which responds to pairing requests, such as to confirm a passkey,
+    /// replacing any registered before. See [`pairing`].
+    async fn register_pairing_agent(&self, _agent: Arc<dyn pairing::PairingAgent>) -> Result<()> {
+        Err(Error::NotSupported(
+            "Pairing agents are not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Asks the platform to wake the host when the given trigger fires, so that applications can
+    /// let the system sleep between device appearances. This generally requires the application to
+    /// be registered for background Bluetooth activity with the operating system (a background
+    /// task on Windows, state restoration on macOS and iOS), and returns
+    /// [`Error::NotSupported`] where btleplug can't register the trigger itself.
+    async fn register_wake_trigger(&self, _trigger: WakeTrigger) -> Result<()> {
+        Err(Error::NotSupported(
+            "Wake triggers are not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns where this adapter keeps the tags of its peripherals, or `None` if it doesn't
+    /// support tags, in which case they are ignored.
+    fn peripheral_tags(&self) -> Option<&tags::PeripheralTags> {
+        None
+    }
+
+    /// Assigns a tag to a peripheral. Tags are arbitrary strings such as `"room-42"`, kept in memory
+    /// by btleplug rather than on the device, which applications can use to organise devices. They
+    /// can be assigned before the peripheral has been discovered, and are kept when it disconnects,
+    /// but may be forgotten along with the peripheral once the platform removes it.
+    fn add_tag(&self, id: &PeripheralId, tag: &str) {
+        if let Some(tags) = self.peripheral_tags() {
+            tags.add(id, tag);
+        }
+    }
+
+    /// Removes a tag from a peripheral, if it had it.
+    fn remove_tag(&self, id: &PeripheralId, tag: &str) {
+        if let Some(tags) = self.peripheral_tags() {
+            tags.remove(id, tag);
+        }
+    }
+
+    /// Returns the tags currently assigned to a peripheral.
+    fn tags(&self, id: &PeripheralId) -> BTreeSet<String> {
+        self.peripheral_tags()
+            .map(|tags| tags.get(id))
+            .unwrap_or_default()
+    }
+
+    /// Returns the discovered peripherals which have the given tag.
+    async fn peripherals_with_tag(&self, tag: &str) -> Result<Vec<Self::Peripheral>> {
+        Ok(self
+            .peripherals()
+            .await?
+            .into_iter()
+            .filter(|peripheral| self.tags(&peripheral.id()).contains(tag))
+            .collect())
+    }
+
+    /// Like [`events`](Central::events), but each event is accompanied by the tags of the
+    /// peripheral it concerns.
+    async fn tagged_events(&self) -> Result<Pin<Box<dyn Stream<Item = TaggedCentralEvent> + Send>>>
+    where
+        Self: 'static,
+    {
+        let central = self.clone();
+        Ok(Box::pin(self.events().await?.map(move |event| {
+            let tags = central.tags(event.peripheral_id());
+            TaggedCentralEvent { event, tags }
+        })))
+    }
+}
+
+/// An adapter was added to or removed from the system, e.g. by plugging in or unplugging a USB
+/// dongle, or by the controller resetting. See [`Manager::events`].
+#[derive(Clone, Debug)]
+pub enum ManagerEvent<A> {
+    AdapterAdded(A),
+    /// The adapter with the given ID, as in [`AdapterInfo::id`], was removed. Operations on it
+    /// fail from now on, and peripherals found through it are gone.
+    AdapterRemoved {
+        id: String,
+    },
 }
 
 /// The Manager is the entry point to the library, providing access to all the Bluetooth adapters on
@@ -396,4 +2032,128 @@ pub trait Manager {
 
     /// Get a list of all Bluetooth adapters on the system. Each adapter implements [`Central`].
     async fn adapters(&self) -> Result<Vec<Self::Adapter>>;
+
+    /// Returns a stream of adapters being added to and removed from the system from now on, so
+    /// that long-running applications can recover when an adapter comes back after a reset.
+    async fn events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = ManagerEvent<Self::Adapter>> + Send>>> {
+        Err(Error::NotSupported(
+            "Adapter hot-plug events are not supported on this platform".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::bleuuid::uuid_from_u16;
+
+    fn with_manufacturer_data(company_id: u16, data: &[u8]) -> HashMap<u16, Vec<u8>> {
+        HashMap::from([(company_id, data.to_vec())])
+    }
+
+    #[test]
+    fn manufacturer_data_filter_prefix() {
+        let filter = ManufacturerDataFilter::new(0x004c).with_prefix([0x02, 0x15]);
+        assert!(filter.matches(&with_manufacturer_data(0x004c, &[0x02, 0x15, 0x01])));
+        assert!(filter.matches(&with_manufacturer_data(0x004c, &[0x02, 0x15])));
+        assert!(!filter.matches(&with_manufacturer_data(0x004c, &[0x02, 0x16, 0x01])));
+        assert!(!filter.matches(&with_manufacturer_data(0x004c, &[0x02])));
+        assert!(!filter.matches(&with_manufacturer_data(0x0059, &[0x02, 0x15])));
+        assert!(filter.is_exact_prefix());
+    }
+
+    #[test]
+    fn manufacturer_data_filter_mask() {
+        // Only the high nibble of the first byte matters, and the second byte must match exactly
+        // as it is beyond the end of the mask.
+        let filter = ManufacturerDataFilter::new(0x004c)
+            .with_prefix([0x12, 0x34])
+            .with_mask([0xf0]);
+        assert!(filter.matches(&with_manufacturer_data(0x004c, &[0x1f, 0x34])));
+        assert!(!filter.matches(&with_manufacturer_data(0x004c, &[0x22, 0x34])));
+        assert!(!filter.matches(&with_manufacturer_data(0x004c, &[0x12, 0x35])));
+        assert!(!filter.is_exact_prefix());
+    }
+
+    #[test]
+    fn manufacturer_data_filter_empty_prefix() {
+        let filter = ManufacturerDataFilter::new(0x004c);
+        assert!(filter.matches(&with_manufacturer_data(0x004c, &[])));
+        assert!(!filter.matches(&HashMap::new()));
+    }
+
+    #[test]
+    fn scan_filter_empty() {
+        assert!(ScanFilter::default().matches(&PeripheralProperties::default()));
+    }
+
+    #[test]
+    fn connection_parameter_limits() {
+        let parameters = ConnectionParameters {
+            min_interval: Duration::from_micros(7500),
+            max_interval: Duration::from_millis(15),
+            peripheral_latency: 0,
+            supervision_timeout: Duration::from_millis(100),
+        };
+        assert!(parameters.is_valid());
+        let invalid = [
+            ConnectionParameters {
+                min_interval: Duration::from_millis(5),
+                ..parameters
+            },
+            ConnectionParameters {
+                min_interval: Duration::from_millis(20),
+                ..parameters
+            },
+            ConnectionParameters {
+                peripheral_latency: 500,
+                ..parameters
+            },
+            ConnectionParameters {
+                supervision_timeout: Duration::from_secs(33),
+                ..parameters
+            },
+            // 15 ms * 4 * 2 is longer than the timeout.
+            ConnectionParameters {
+                peripheral_latency: 3,
+                ..parameters
+            },
+        ];
+        for parameters in invalid {
+            assert!(!parameters.is_valid(), "{:?}", parameters);
+            assert!(matches!(
+                parameters.validate(),
+                Err(Error::InvalidParameters(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn scan_filter_matches() {
+        let heart_rate = uuid_from_u16(0x180d);
+        let filter = ScanFilter::default()
+            .with_services([heart_rate])
+            .with_service_patterns([UuidPattern::Short(0x180f)])
+            .with_manufacturer_data([ManufacturerDataFilter::new(0x004c).with_prefix([0x02])]);
+        let properties = PeripheralProperties {
+            services: vec![heart_rate],
+            manufacturer_data: with_manufacturer_data(0x004c, &[0x02, 0x15]),
+            ..Default::default()
+        };
+        assert!(filter.matches(&properties));
+        assert!(filter.matches(&PeripheralProperties {
+            services: vec![uuid_from_u16(0x180f)],
+            ..properties.clone()
+        }));
+        assert!(!filter.matches(&PeripheralProperties {
+            services: vec![uuid_from_u16(0x1810)],
+            ..properties.clone()
+        }));
+        assert!(!filter.matches(&PeripheralProperties {
+            manufacturer_data: with_manufacturer_data(0x004c, &[0x03]),
+            ..properties
+        }));
+    }
 }
@@ -0,0 +1,63 @@
+//! Pairing with and bonding to peripherals.
+//!
+//! Many devices, particularly medical devices and HID peripherals, refuse access to their GATT
+//! services until they have been bonded. [`Peripheral::pair`](super::Peripheral::pair) starts
+//! pairing, and the platform asks the [`PairingAgent`] registered with
+//! [`Central::register_pairing_agent`](super::Central::register_pairing_agent) to complete
+//! whichever association model the two devices settle on. Which model that is depends on the
+//! [`IoCapability`] the agent declares and the device's own input and output capabilities.
+
+use crate::platform::PeripheralId;
+use async_trait::async_trait;
+use std::fmt::Debug;
+
+/// The input and output capabilities of the local device, as far as pairing is concerned.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum IoCapability {
+    /// Can display a passkey, but not take input.
+    DisplayOnly,
+    /// Can display a passkey and ask the user to confirm it.
+    DisplayYesNo,
+    /// Can ask the user to enter a passkey, but not display one.
+    KeyboardOnly,
+    /// Can neither display nor enter a passkey, so only "Just Works" pairing is possible.
+    NoInputNoOutput,
+    /// Can both display and enter a passkey.
+    #[default]
+    KeyboardDisplay,
+}
+
+/// Responds to the platform's requests while pairing. The default implementations reject the
+/// request, or do nothing where there is nothing to reject.
+#[async_trait]
+pub trait PairingAgent: Debug + Send + Sync {
+    /// Returns the capabilities this agent supports.
+    fn io_capability(&self) -> IoCapability {
+        IoCapability::default()
+    }
+
+    /// Shows the user a passkey they must enter on the device.
+    async fn display_passkey(&self, _id: PeripheralId, _passkey: u32) {}
+
+    /// Asks the user for the passkey the device is displaying, returning `None` to reject the
+    /// pairing.
+    async fn request_passkey(&self, _id: PeripheralId) -> Option<u32> {
+        None
+    }
+
+    /// Asks the user to confirm that the device is displaying the same passkey, for numeric
+    /// comparison. Returns true to accept the pairing.
+    async fn confirm_passkey(&self, _id: PeripheralId, _passkey: u32) -> bool {
+        false
+    }
+
+    /// Asks whether to accept a "Just Works" pairing, which has no passkey. Returns true to
+    /// accept it.
+    async fn authorize(&self, _id: PeripheralId) -> bool {
+        false
+    }
+
+    /// The platform cancelled the outstanding request, e.g. because the device gave up or it
+    /// timed out. Any prompt shown to the user should be dismissed.
+    async fn cancel(&self) {}
+}
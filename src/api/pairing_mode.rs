@@ -0,0 +1,183 @@
+//! Detection of devices which are currently in pairing or setup mode.
+//!
+//! Many devices only enter a connectable setup mode after a button press, and indicate this either
+//! by advertising as limited discoverable or by setting some bits in their manufacturer data. A
+//! [`PairingModeDetector`] recognises both, and can turn a [`Central`]'s event stream into a
+//! stream of [`PairingModeEvent`]s.
+
+use super::{Central, CentralEvent, DiscoverableMode, Peripheral, PeripheralProperties};
+use crate::platform::PeripheralId;
+use crate::Result;
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// Matches some bits of the manufacturer data advertised under a particular company ID.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManufacturerDataRule {
+    /// The company ID the manufacturer data must be advertised under.
+    pub company_id: u16,
+    /// The index of the byte to check within the manufacturer data.
+    pub offset: usize,
+    /// Which bits of the byte to compare.
+    pub mask: u8,
+    /// The value the masked bits must have.
+    pub value: u8,
+}
+
+impl ManufacturerDataRule {
+    /// Returns true if the given properties match this rule.
+    pub fn matches(&self, properties: &PeripheralProperties) -> bool {
+        properties
+            .manufacturer_data
+            .get(&self.company_id)
+            .and_then(|data| data.get(self.offset))
+            .is_some_and(|byte| byte & self.mask == self.value & self.mask)
+    }
+}
+
+/// Decides whether a device is in pairing mode based on its advertisements. A device is
+/// considered to be in pairing mode if it matches any of the configured criteria.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PairingModeDetector {
+    /// Whether advertising as limited discoverable indicates pairing mode. Defaults to true.
+    pub limited_discoverable: bool,
+    /// Manufacturer data rules indicating pairing mode.
+    pub manufacturer_data: Vec<ManufacturerDataRule>,
+}
+
+impl Default for PairingModeDetector {
+    fn default() -> Self {
+        Self {
+            limited_discoverable: true,
+            manufacturer_data: Vec::new(),
+        }
+    }
+}
+
+/// A change in the pairing mode of a device, as determined by a [`PairingModeDetector`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PairingModeEvent {
+    /// The device has started advertising that it is in pairing mode.
+    Entered(PeripheralId),
+    /// The device is no longer advertising that it is in pairing mode.
+    Exited(PeripheralId),
+}
+
+impl PairingModeDetector {
+    /// Returns true if the given properties indicate that the device is in pairing mode.
+    pub fn is_in_pairing_mode(&self, properties: &PeripheralProperties) -> bool {
+        let limited = self.limited_discoverable
+            && properties
+                .advertising_flags
+                .map(|flags| flags.discoverable_mode())
+                == Some(DiscoverableMode::Limited);
+        limited
+            || self
+                .manufacturer_data
+                .iter()
+                .any(|rule| rule.matches(properties))
+    }
+
+    /// Returns a stream of [`PairingModeEvent`]s for the devices discovered by the given central.
+    /// This stream only reports changes, so nothing is emitted for devices which are never in
+    /// pairing mode.
+    pub async fn watch<C>(
+        self,
+        central: &C,
+    ) -> Result<Pin<Box<dyn Stream<Item = PairingModeEvent> + Send>>>
+    where
+        C: Central + 'static,
+    {
+        let events = central.events().await?;
+        let central = central.clone();
+        let detector = Arc::new(self);
+        let in_pairing_mode = Arc::new(Mutex::new(HashSet::new()));
+        Ok(Box::pin(events.filter_map(move |event| {
+            let central = central.clone();
+            let detector = detector.clone();
+            let in_pairing_mode = in_pairing_mode.clone();
+            async move {
+                let id = match event {
+                    CentralEvent::DeviceDiscovered(id)
+                    | CentralEvent::DeviceUpdated(id)
+                    | CentralEvent::ManufacturerDataAdvertisement { id, .. } => id,
+                    _ => return None,
+                };
+                let properties = central
+                    .peripheral(&id)
+                    .await
+                    .ok()?
+                    .properties()
+                    .await
+                    .ok()??;
+                let now = detector.is_in_pairing_mode(&properties);
+                let mut in_pairing_mode = in_pairing_mode.lock().unwrap();
+                if now && in_pairing_mode.insert(id.clone()) {
+                    Some(PairingModeEvent::Entered(id))
+                } else if !now && in_pairing_mode.remove(&id) {
+                    Some(PairingModeEvent::Exited(id))
+                } else {
+                    None
+                }
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::AdvertisingFlags;
+
+    #[test]
+    fn limited_discoverable() {
+        let detector = PairingModeDetector::default();
+        let mut properties = PeripheralProperties {
+            advertising_flags: Some(AdvertisingFlags::LE_GENERAL_DISCOVERABLE),
+            ..Default::default()
+        };
+        assert!(!detector.is_in_pairing_mode(&properties));
+
+        properties.advertising_flags = Some(AdvertisingFlags::LE_LIMITED_DISCOVERABLE);
+        assert!(detector.is_in_pairing_mode(&properties));
+
+        let detector = PairingModeDetector {
+            limited_discoverable: false,
+            ..Default::default()
+        };
+        assert!(!detector.is_in_pairing_mode(&properties));
+    }
+
+    #[test]
+    fn manufacturer_data_bits() {
+        let detector = PairingModeDetector {
+            limited_discoverable: false,
+            manufacturer_data: vec![ManufacturerDataRule {
+                company_id: 0x1234,
+                offset: 1,
+                mask: 0x80,
+                value: 0x80,
+            }],
+        };
+        let mut properties = PeripheralProperties::default();
+        assert!(!detector.is_in_pairing_mode(&properties));
+
+        properties
+            .manufacturer_data
+            .insert(0x1234, vec![0x00, 0x01]);
+        assert!(!detector.is_in_pairing_mode(&properties));
+
+        properties
+            .manufacturer_data
+            .insert(0x1234, vec![0x00, 0x81]);
+        assert!(detector.is_in_pairing_mode(&properties));
+
+        properties.manufacturer_data.remove(&0x1234);
+        properties
+            .manufacturer_data
+            .insert(0x4321, vec![0xff, 0xff]);
+        assert!(!detector.is_in_pairing_mode(&properties));
+    }
+}
@@ -0,0 +1,131 @@
+//! Periodic reads of a characteristic. See [`Peripheral::poll`] and
+//! [`Peripheral::notifications_with_fallback`].
+
+use super::{CharPropFlags, Characteristic, Peripheral, ValueNotification};
+use crate::{Error, Result};
+use futures::future::ready;
+use futures::stream::{self, Stream, StreamExt};
+use log::debug;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{self as tokio_time, Interval, MissedTickBehavior};
+
+struct State<P> {
+    peripheral: P,
+    characteristic: Characteristic,
+    period: Duration,
+    /// Created on first poll, as creating it requires a Tokio runtime.
+    interval: Option<Interval>,
+}
+
+/// Reads the characteristic every `period` while the peripheral is connected. Ticks which are
+/// missed because a read took longer than the period, or because the peripheral was
+/// disconnected, are skipped rather than caught up, so a slow device sees at most one read per
+/// period. Reading is paused while disconnected and resumes once the peripheral reconnects.
+pub(crate) fn poll_characteristic<P: Peripheral + 'static>(
+    peripheral: P,
+    characteristic: Characteristic,
+    period: Duration,
+) -> Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send>> {
+    let state = State {
+        peripheral,
+        characteristic,
+        period,
+        interval: None,
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        let period = state.period;
+        let interval = state.interval.get_or_insert_with(|| {
+            let mut interval = tokio_time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        loop {
+            interval.tick().await;
+            match state.peripheral.is_connected().await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return Some((Err(e), state)),
+            }
+            match state.peripheral.read(&state.characteristic).await {
+                // The peripheral disconnected since the check, so pause as above.
+                Err(Error::NotConnected) => continue,
+                result => return Some((result, state)),
+            }
+        }
+    }))
+}
+
+/// Whether a failed subscription means that notifications aren't available for the
+/// characteristic, rather than that something is wrong with the connection.
+fn can_poll_instead(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::NotSupported(_) | Error::PermissionDenied | Error::NoSuchDescriptor | Error::Att(_)
+    )
+}
+
+pub(crate) async fn notifications_with_fallback<P: Peripheral + 'static>(
+    peripheral: &P,
+    characteristics: &[Characteristic],
+    interval: Duration,
+) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+    // Get the stream before subscribing, so that a value sent straight away isn't missed.
+    let mut streams = vec![peripheral.notifications().await?];
+    for characteristic in characteristics {
+        let readable = characteristic.properties.contains(CharPropFlags::READ);
+        if characteristic
+            .properties
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+        {
+            match peripheral.subscribe(characteristic).await {
+                Ok(()) => continue,
+                Err(e) if readable && can_poll_instead(&e) => {
+                    debug!(
+                        "Polling {} as subscribing failed: {}",
+                        characteristic.uuid, e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        } else if !readable {
+            return Err(Error::NotSupported(format!(
+                "Characteristic {} can neither notify nor be read",
+                characteristic.uuid
+            )));
+        }
+        streams.push(emulated_notifications(
+            peripheral.clone(),
+            characteristic.clone(),
+            interval,
+        ));
+    }
+    Ok(Box::pin(stream::select_all(streams)))
+}
+
+/// Polls the characteristic, and turns the first value read and each change after it into a
+/// notification.
+fn emulated_notifications<P: Peripheral + 'static>(
+    peripheral: P,
+    characteristic: Characteristic,
+    interval: Duration,
+) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
+    let uuid = characteristic.uuid;
+    let mut previous = None;
+    Box::pin(
+        poll_characteristic(peripheral, characteristic, interval).filter_map(move |result| {
+            let notification = match result {
+                Ok(value) if previous.as_ref() != Some(&value) => {
+                    previous = Some(value.clone());
+                    Some(ValueNotification::new(uuid, value).with_emulated(true))
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    debug!("Polling {} failed: {}", uuid, e);
+                    None
+                }
+            };
+            ready(notification)
+        }),
+    )
+}
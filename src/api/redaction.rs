@@ -0,0 +1,88 @@
+//! Control over how characteristic and descriptor values appear in log output.
+//!
+//! Payloads frequently contain credentials, keys or health data, so by default btleplug only logs
+//! their length. Applications which need to debug the actual bytes can opt in with
+//! [`set_value_log_policy`].
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How values read from, written to or notified by a peripheral are included in log messages.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ValueLogPolicy {
+    /// Values are omitted entirely.
+    Off,
+    /// Only the length of each value is logged.
+    #[default]
+    LengthOnly,
+    /// Values are logged in full as hex.
+    FullHex,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(ValueLogPolicy::LengthOnly as u8);
+
+/// Sets the global policy for logging values. This applies to all adapters and peripherals.
+pub fn set_value_log_policy(policy: ValueLogPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns the current global policy for logging values.
+pub fn value_log_policy() -> ValueLogPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        0 => ValueLogPolicy::Off,
+        2 => ValueLogPolicy::FullHex,
+        _ => ValueLogPolicy::LengthOnly,
+    }
+}
+
+/// Formats a value for a log message according to the current [`ValueLogPolicy`].
+pub(crate) struct LoggedValue<'a>(pub &'a [u8]);
+
+impl Display for LoggedValue<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        format_value(self.0, value_log_policy(), f)
+    }
+}
+
+fn format_value(value: &[u8], policy: ValueLogPolicy, f: &mut Formatter) -> fmt::Result {
+    match policy {
+        ValueLogPolicy::Off => write!(f, "<redacted>"),
+        ValueLogPolicy::LengthOnly => write!(f, "<{} bytes>", value.len()),
+        ValueLogPolicy::FullHex => {
+            for byte in value {
+                write!(f, "{:02x}", byte)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct WithPolicy<'a>(&'a [u8], ValueLogPolicy);
+
+    impl Display for WithPolicy<'_> {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            format_value(self.0, self.1, f)
+        }
+    }
+
+    #[test]
+    fn formats_per_policy() {
+        let value = [0x01, 0xab, 0xff];
+        assert_eq!(
+            WithPolicy(&value, ValueLogPolicy::Off).to_string(),
+            "<redacted>"
+        );
+        assert_eq!(
+            WithPolicy(&value, ValueLogPolicy::LengthOnly).to_string(),
+            "<3 bytes>"
+        );
+        assert_eq!(
+            WithPolicy(&value, ValueLogPolicy::FullHex).to_string(),
+            "01abff"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Replay and gap detection for devices which embed a counter in their manufacturer data.
+//!
+//! Presence and access systems often rely on beacons which increment a counter with every new
+//! advertisement, so that a recorded advertisement can't simply be replayed. [`CounterTracker`]
+//! tracks the last counter seen from each device and classifies each new one.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Where a counter is found in manufacturer data.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct CounterLayout {
+    /// The company identifier the manufacturer data is keyed by.
+    pub company_id: u16,
+    /// Offset of the counter within the manufacturer data, in bytes.
+    pub offset: usize,
+    /// Size of the counter in bytes, between 1 and 8. The counter wraps around at the maximum
+    /// value this allows.
+    pub size: usize,
+    /// Whether the counter is little-endian rather than big-endian.
+    pub little_endian: bool,
+}
+
+impl CounterLayout {
+    /// Extracts the counter from the manufacturer data of an advertisement, if present.
+    pub fn extract(&self, manufacturer_data: &HashMap<u16, Vec<u8>>) -> Option<u64> {
+        if !(1..=8).contains(&self.size) {
+            return None;
+        }
+        let bytes = manufacturer_data
+            .get(&self.company_id)?
+            .get(self.offset..self.offset + self.size)?;
+        let fold = |value: u64, byte: &u8| value << 8 | u64::from(*byte);
+        Some(if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    }
+
+    fn modulus_bits(&self) -> u32 {
+        self.size as u32 * 8
+    }
+}
+
+/// How a counter compares to the last one seen from the same device.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CounterStatus {
+    /// This is the first counter seen from the device.
+    First,
+    /// The counter is the same as the last one. Devices usually repeat each advertisement many
+    /// times, so this is expected.
+    Repeated,
+    /// The counter follows on directly from the last one.
+    Next,
+    /// The counter skipped ahead, so advertisements were missed.
+    Gap { missed: u64 },
+    /// The counter went backwards, meaning that the advertisement is a replay or the device was
+    /// reset.
+    Replay,
+}
+
+/// Tracks the counters advertised by each device, keyed by e.g.
+/// [`PeripheralId`](crate::platform::PeripheralId).
+#[derive(Clone, Debug)]
+pub struct CounterTracker<K> {
+    layout: CounterLayout,
+    last: HashMap<K, u64>,
+}
+
+impl<K: Eq + Hash> CounterTracker<K> {
+    pub fn new(layout: CounterLayout) -> Self {
+        Self {
+            layout,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Checks the counter in an advertisement's manufacturer data, as found in
+    /// [`CentralEvent::ManufacturerDataAdvertisement`](super::CentralEvent::ManufacturerDataAdvertisement).
+    /// Returns `None` if the data doesn't contain a counter.
+    pub fn observe(
+        &mut self,
+        id: K,
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+    ) -> Option<CounterStatus> {
+        let counter = self.layout.extract(manufacturer_data)?;
+        Some(self.observe_counter(id, counter))
+    }
+
+    /// Checks a counter which has already been extracted.
+    ///
+    /// Counters wrap around, so one which is less than half the counter range ahead of the last is
+    /// taken to be ahead, and any other to be behind. A replay never replaces the last counter, so
+    /// that an attacker can't rewind it.
+    pub fn observe_counter(&mut self, id: K, counter: u64) -> CounterStatus {
+        let bits = self.layout.modulus_bits();
+        let mask = if bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << bits) - 1
+        };
+        let counter = counter & mask;
+        let last = match self.last.get_mut(&id) {
+            Some(last) => last,
+            None => {
+                self.last.insert(id, counter);
+                return CounterStatus::First;
+            }
+        };
+        let ahead = counter.wrapping_sub(*last) & mask;
+        let status = match ahead {
+            0 => CounterStatus::Repeated,
+            1 => CounterStatus::Next,
+            ahead if ahead <= mask / 2 => CounterStatus::Gap { missed: ahead - 1 },
+            _ => return CounterStatus::Replay,
+        };
+        *last = counter;
+        status
+    }
+
+    /// Forgets the last counter of a device, e.g. after it has been legitimately reset.
+    pub fn forget(&mut self, id: &K) {
+        self.last.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: CounterLayout = CounterLayout {
+        company_id: 0x1234,
+        offset: 1,
+        size: 2,
+        little_endian: true,
+    };
+
+    #[test]
+    fn extract() {
+        let data = HashMap::from([(0x1234, vec![0xaa, 0x01, 0x02, 0xbb])]);
+        assert_eq!(LAYOUT.extract(&data), Some(0x0201));
+        let big_endian = CounterLayout {
+            little_endian: false,
+            ..LAYOUT
+        };
+        assert_eq!(big_endian.extract(&data), Some(0x0102));
+        let short = HashMap::from([(0x1234, vec![0xaa, 0x01])]);
+        assert_eq!(LAYOUT.extract(&short), None);
+        assert_eq!(LAYOUT.extract(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn classifies_counters() {
+        let mut tracker = CounterTracker::new(LAYOUT);
+        assert_eq!(tracker.observe_counter(1, 10), CounterStatus::First);
+        assert_eq!(tracker.observe_counter(1, 10), CounterStatus::Repeated);
+        assert_eq!(tracker.observe_counter(1, 11), CounterStatus::Next);
+        assert_eq!(
+            tracker.observe_counter(1, 15),
+            CounterStatus::Gap { missed: 3 }
+        );
+        assert_eq!(tracker.observe_counter(1, 12), CounterStatus::Replay);
+        // The replay didn't rewind the counter.
+        assert_eq!(tracker.observe_counter(1, 16), CounterStatus::Next);
+        assert_eq!(tracker.observe_counter(2, 3), CounterStatus::First);
+
+        tracker.observe_counter(3, 0xffff);
+        assert_eq!(tracker.observe_counter(3, 0), CounterStatus::Next);
+        tracker.forget(&3);
+        assert_eq!(tracker.observe_counter(3, 0x8000), CounterStatus::First);
+    }
+}
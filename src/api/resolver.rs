@@ -0,0 +1,461 @@
+//! Tracking of devices across address rotation.
+//!
+//! Privacy-enabled accessories periodically change their random address, so each rotation shows up
+//! as a brand new peripheral. Bonded devices are usually resolved to their identity address by the
+//! operating system already, as reported by [`Peripheral::identity_address`]. For everything else,
+//! an [`AddressResolver`] groups advertisements into logical devices: resolvable private addresses
+//! are checked against the Identity Resolving Keys the application has added, and remaining random
+//! addresses are matched using stable payload features chosen by the application through a
+//! [`DeviceMatcher`].
+//!
+//! [`Peripheral::identity_address`]: super::Peripheral::identity_address
+
+use super::{AddressType, BDAddr, LeAddress, PeripheralProperties, RandomAddressKind};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Decides whether two advertisements come from the same physical device.
+pub trait DeviceMatcher: Send + Sync {
+    /// Returns true if `candidate`, seen under a new address, belongs to the same device as
+    /// `known`, which holds the most recent properties seen for an already tracked device.
+    fn is_same_device(
+        &self,
+        known: &PeripheralProperties,
+        candidate: &PeripheralProperties,
+    ) -> bool;
+}
+
+impl<F> DeviceMatcher for F
+where
+    F: Fn(&PeripheralProperties, &PeripheralProperties) -> bool + Send + Sync,
+{
+    fn is_same_device(
+        &self,
+        known: &PeripheralProperties,
+        candidate: &PeripheralProperties,
+    ) -> bool {
+        self(known, candidate)
+    }
+}
+
+/// The Identity Resolving Key a device distributed when it bonded, which resolves the private
+/// addresses it generates. The key is written most significant byte first, as in the Bluetooth
+/// specification.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct IdentityResolvingKey(pub u128);
+
+impl IdentityResolvingKey {
+    /// Returns true if the address is a resolvable private address generated with this key, i.e.
+    /// its lower 24 bits are the hash `ah()` of its upper 24 bits under the key.
+    pub fn resolves(&self, address: &BDAddr) -> bool {
+        if address.random_kind() != Some(RandomAddressKind::ResolvablePrivate) {
+            return false;
+        }
+        let address = address.into_inner();
+        let mut prand = [0; 16];
+        prand[13..].copy_from_slice(&address[..3]);
+        aes128(&self.0.to_be_bytes(), &prand)[13..] == address[3..]
+    }
+}
+
+/// Identifier of a logical device, stable across address changes for as long as the
+/// [`AddressResolver`] that assigned it is alive.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LogicalDeviceId(u64);
+
+#[derive(Debug)]
+struct LogicalDevice {
+    /// Oldest first, with when each was last seen.
+    addresses: Vec<(LeAddress, Instant)>,
+    latest: Option<PeripheralProperties>,
+    irk: Option<IdentityResolvingKey>,
+}
+
+/// Groups advertisements from rotating random addresses into logical devices.
+///
+/// Devices with a public address are never merged with others. Resolvable private addresses which
+/// resolve against an added [`IdentityResolvingKey`] belong to that key's device. Other random (or
+/// unknown type) addresses are matched against the devices without a key with the given
+/// [`DeviceMatcher`], and if several match, the one tracked first is picked.
+///
+/// Addresses are remembered until [`expire`](Self::expire) or [`remove`](Self::remove) forgets
+/// them, so long-running applications should expire them regularly.
+#[derive(Debug)]
+pub struct AddressResolver<M> {
+    matcher: M,
+    next_id: u64,
+    by_address: HashMap<LeAddress, LogicalDeviceId>,
+    devices: BTreeMap<LogicalDeviceId, LogicalDevice>,
+}
+
+impl<M: DeviceMatcher> AddressResolver<M> {
+    pub fn new(matcher: M) -> Self {
+        Self {
+            matcher,
+            next_id: 0,
+            by_address: HashMap::new(),
+            devices: BTreeMap::new(),
+        }
+    }
+
+    fn new_device(&mut self, irk: Option<IdentityResolvingKey>) -> LogicalDeviceId {
+        let id = LogicalDeviceId(self.next_id);
+        self.next_id += 1;
+        self.devices.insert(
+            id,
+            LogicalDevice {
+                addresses: Vec::new(),
+                latest: None,
+                irk,
+            },
+        );
+        id
+    }
+
+    /// Adds the Identity Resolving Key of a bonded device, and returns the logical device which
+    /// the addresses it resolves will belong to. Adding the same key again returns the same
+    /// device.
+    pub fn add_identity_resolving_key(&mut self, irk: IdentityResolvingKey) -> LogicalDeviceId {
+        match self
+            .devices
+            .iter()
+            .find(|(_, device)| device.irk == Some(irk))
+        {
+            Some((id, _)) => *id,
+            None => self.new_device(Some(irk)),
+        }
+    }
+
+    /// Records the given advertisement properties and returns the logical device they belong to.
+    pub fn resolve(&mut self, properties: &PeripheralProperties) -> LogicalDeviceId {
+        let address = LeAddress::new(
+            properties.address,
+            properties.address_type.unwrap_or_default(),
+        );
+        let id = match self.by_address.get(&address) {
+            Some(id) => *id,
+            None => {
+                let id = if properties.address_type == Some(AddressType::Public) {
+                    None
+                } else {
+                    self.find_device(properties)
+                }
+                .unwrap_or_else(|| self.new_device(None));
+                self.by_address.insert(address, id);
+                id
+            }
+        };
+        let device = self.devices.get_mut(&id).unwrap();
+        let now = Instant::now();
+        match device
+            .addresses
+            .iter_mut()
+            .find(|(known, _)| *known == address)
+        {
+            Some((_, last_seen)) => *last_seen = now,
+            None => device.addresses.push((address, now)),
+        }
+        device.latest = Some(properties.clone());
+        id
+    }
+
+    /// Finds the device a new random address belongs to, by its key if it resolves against one,
+    /// or else with the matcher.
+    fn find_device(&self, properties: &PeripheralProperties) -> Option<LogicalDeviceId> {
+        let by_key = self.devices.iter().find(|(_, device)| {
+            device
+                .irk
+                .is_some_and(|irk| irk.resolves(&properties.address))
+        });
+        let by_matcher = || {
+            self.devices.iter().find(|(_, device)| {
+                device.irk.is_none()
+                    && device.latest.as_ref().is_some_and(|latest| {
+                        latest.address_type != Some(AddressType::Public)
+                            && self.matcher.is_same_device(latest, properties)
+                    })
+            })
+        };
+        by_key.or_else(by_matcher).map(|(id, _)| *id)
+    }
+
+    /// Returns the logical device previously resolved for the given address, if any.
+    pub fn logical_device(&self, address: &LeAddress) -> Option<LogicalDeviceId> {
+        self.by_address.get(address).copied()
+    }
+
+    /// Returns all addresses seen for the given logical device, oldest first.
+    pub fn addresses(&self, id: LogicalDeviceId) -> Vec<LeAddress> {
+        self.devices
+            .get(&id)
+            .map(|device| {
+                device
+                    .addresses
+                    .iter()
+                    .map(|(address, _)| *address)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the most recent properties seen for the given logical device.
+    pub fn latest_properties(&self, id: LogicalDeviceId) -> Option<&PeripheralProperties> {
+        self.devices
+            .get(&id)
+            .and_then(|device| device.latest.as_ref())
+    }
+
+    /// Forgets the addresses which haven't been seen for longer than `max_age`, and the logical
+    /// devices left without any, except for those with an Identity Resolving Key.
+    pub fn expire(&mut self, max_age: Duration) {
+        let Some(cutoff) = Instant::now().checked_sub(max_age) else {
+            return;
+        };
+        let by_address = &mut self.by_address;
+        self.devices.retain(|_, device| {
+            device.addresses.retain(|(address, last_seen)| {
+                let keep = *last_seen > cutoff;
+                if !keep {
+                    by_address.remove(address);
+                }
+                keep
+            });
+            if device.addresses.is_empty() {
+                device.latest = None;
+            }
+            !device.addresses.is_empty() || device.irk.is_some()
+        });
+    }
+
+    /// Forgets a logical device and all of its addresses, along with its key if it has one.
+    pub fn remove(&mut self, id: LogicalDeviceId) {
+        if let Some(device) = self.devices.remove(&id) {
+            for (address, _) in device.addresses {
+                self.by_address.remove(&address);
+            }
+        }
+    }
+}
+
+/// The AES S-box, computed as the multiplicative inverse in GF(2^8) followed by the affine
+/// transformation.
+const SBOX: [u8; 256] = {
+    let mut sbox = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        // The inverse is x^254, or 0 for 0.
+        let mut inverse = 1u8;
+        let mut exponent = 0;
+        while exponent < 254 {
+            inverse = gf_mul(inverse, i as u8);
+            exponent += 1;
+        }
+        if i == 0 {
+            inverse = 0;
+        }
+        sbox[i] = inverse
+            ^ inverse.rotate_left(1)
+            ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3)
+            ^ inverse.rotate_left(4)
+            ^ 0x63;
+        i += 1;
+    }
+    sbox
+};
+
+const fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+/// Encrypts a single block with AES-128, which is the security function `e` of the Bluetooth
+/// specification. The block is laid out column by column, as in FIPS-197.
+fn aes128(key: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+    let mut round_keys = [*key; 11];
+    let mut rcon = 1;
+    for round in 1..11 {
+        let previous = round_keys[round - 1];
+        let mut word =
+            [previous[13], previous[14], previous[15], previous[12]].map(|b| SBOX[b as usize]);
+        word[0] ^= rcon;
+        rcon = xtime(rcon);
+        let key = &mut round_keys[round];
+        for i in 0..16 {
+            word[i % 4] ^= previous[i];
+            key[i] = word[i % 4];
+        }
+    }
+
+    let add_round_key = |state: &mut [u8; 16], key: &[u8; 16]| {
+        for (b, k) in state.iter_mut().zip(key) {
+            *b ^= k;
+        }
+    };
+    let mut state = *block;
+    add_round_key(&mut state, &round_keys[0]);
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        let substituted = state.map(|b| SBOX[b as usize]);
+        // ShiftRows moves row r of each column c from column c + r.
+        for (i, b) in state.iter_mut().enumerate() {
+            let (column, row) = (i / 4, i % 4);
+            *b = substituted[row + 4 * ((column + row) % 4)];
+        }
+        if round != 10 {
+            for column in state.chunks_exact_mut(4) {
+                let original = [column[0], column[1], column[2], column[3]];
+                let all = original[0] ^ original[1] ^ original[2] ^ original[3];
+                for row in 0..4 {
+                    column[row] ^= all ^ xtime(original[row] ^ original[(row + 1) % 4]);
+                }
+            }
+        }
+        add_round_key(&mut state, round_key);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(address: [u8; 6], address_type: AddressType, data: u8) -> PeripheralProperties {
+        PeripheralProperties {
+            address: address.into(),
+            address_type: Some(address_type),
+            manufacturer_data: [(0x004c, vec![data])].into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn random(address: [u8; 6]) -> LeAddress {
+        LeAddress::random(address.into())
+    }
+
+    fn same_manufacturer_data(a: &PeripheralProperties, b: &PeripheralProperties) -> bool {
+        a.manufacturer_data == b.manufacturer_data
+    }
+
+    #[test]
+    fn aes128_fips_197() {
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let block = 0x00112233445566778899aabbccddeeffu128.to_be_bytes();
+        assert_eq!(
+            u128::from_be_bytes(aes128(&key, &block)),
+            0x69c4e0d86a7b0430d8cdb78070b4c55a
+        );
+    }
+
+    #[test]
+    fn resolves_private_address() {
+        // The sample data for ah() from the Core specification, Vol 3, Part H, D.7.
+        let irk = IdentityResolvingKey(0xec0234a357c8ad05341010a60a397d9b);
+        assert!(irk.resolves(&[0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa].into()));
+        assert!(!irk.resolves(&[0x70, 0x81, 0x94, 0x0d, 0xfb, 0xab].into()));
+        // The same hash in a static random address isn't resolvable.
+        assert!(!irk.resolves(&[0xf0, 0x81, 0x94, 0x0d, 0xfb, 0xaa].into()));
+    }
+
+    #[test]
+    fn groups_by_identity_resolving_key() {
+        let mut resolver = AddressResolver::new(|_: &_, _: &_| true);
+        let irk = resolver
+            .add_identity_resolving_key(IdentityResolvingKey(0xec0234a357c8ad05341010a60a397d9b));
+        let other = resolver.resolve(&properties([0x41; 6], AddressType::Random, 7));
+        let resolved = resolver.resolve(&properties(
+            [0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa],
+            AddressType::Random,
+            7,
+        ));
+        assert_eq!(resolved, irk);
+        assert_ne!(other, irk);
+    }
+
+    #[test]
+    fn groups_rotated_random_addresses() {
+        let mut resolver = AddressResolver::new(same_manufacturer_data);
+        let first = resolver.resolve(&properties([1; 6], AddressType::Random, 7));
+        let second = resolver.resolve(&properties([2; 6], AddressType::Random, 7));
+        let other = resolver.resolve(&properties([3; 6], AddressType::Random, 8));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_eq!(
+            resolver.addresses(first),
+            vec![random([1; 6]), random([2; 6])]
+        );
+        assert_eq!(resolver.logical_device(&random([2; 6])), Some(first));
+    }
+
+    #[test]
+    fn picks_the_oldest_of_several_matches() {
+        // Payload 9 matches every device.
+        let mut resolver = AddressResolver::new(|known: &_, candidate: &PeripheralProperties| {
+            candidate.manufacturer_data[&0x004c] == [9] || same_manufacturer_data(known, candidate)
+        });
+        let first = resolver.resolve(&properties([1; 6], AddressType::Random, 7));
+        let second = resolver.resolve(&properties([2; 6], AddressType::Random, 8));
+        assert_ne!(first, second);
+        assert_eq!(
+            resolver.resolve(&properties([3; 6], AddressType::Random, 9)),
+            first
+        );
+    }
+
+    #[test]
+    fn never_merges_public_addresses() {
+        let mut resolver = AddressResolver::new(same_manufacturer_data);
+        let public = resolver.resolve(&properties([1; 6], AddressType::Public, 7));
+        let random = resolver.resolve(&properties([2; 6], AddressType::Random, 7));
+        assert_ne!(public, random);
+    }
+
+    #[test]
+    fn keys_on_address_type() {
+        let mut resolver = AddressResolver::new(|_: &_, _: &_| false);
+        let public = resolver.resolve(&properties([1; 6], AddressType::Public, 7));
+        let random = resolver.resolve(&properties([1; 6], AddressType::Random, 7));
+        assert_ne!(public, random);
+    }
+
+    #[test]
+    fn expire_forgets_old_addresses() {
+        let mut resolver = AddressResolver::new(same_manufacturer_data);
+        let irk = resolver
+            .add_identity_resolving_key(IdentityResolvingKey(0xec0234a357c8ad05341010a60a397d9b));
+        let id = resolver.resolve(&properties([1; 6], AddressType::Random, 7));
+        resolver.expire(Duration::from_secs(60));
+        assert_eq!(resolver.logical_device(&random([1; 6])), Some(id));
+
+        resolver.expire(Duration::ZERO);
+        assert_eq!(resolver.logical_device(&random([1; 6])), None);
+        assert!(resolver.latest_properties(id).is_none());
+        // Devices with a key are kept for their next address.
+        assert_eq!(
+            resolver.add_identity_resolving_key(IdentityResolvingKey(
+                0xec0234a357c8ad05341010a60a397d9b
+            )),
+            irk
+        );
+    }
+
+    #[test]
+    fn remove_forgets_addresses() {
+        let mut resolver = AddressResolver::new(same_manufacturer_data);
+        let id = resolver.resolve(&properties([1; 6], AddressType::Random, 7));
+        resolver.remove(id);
+        assert_eq!(resolver.logical_device(&random([1; 6])), None);
+        assert!(resolver.latest_properties(id).is_none());
+    }
+}
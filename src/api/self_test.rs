@@ -0,0 +1,113 @@
+//! Preflight checks of the local Bluetooth stack.
+//!
+//! [`Central::self_test`] checks that the adapter is present and usable, so that applications can
+//! tell users what to fix in their setup before anything else goes wrong in a more confusing way.
+
+use super::{Central, ScanFilter};
+use crate::Result;
+use std::fmt::{self, Display, Formatter};
+
+/// The outcome of one check.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CheckOutcome {
+    Passed,
+    /// The check failed, for the given reason.
+    Failed(String),
+    /// The check couldn't be performed, e.g. because an earlier check it depends on failed.
+    Skipped,
+}
+
+/// One check made by a self-test.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SelfTestCheck {
+    /// What was checked, e.g. `powered`.
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+/// The results of a self-test, in the order the checks were made.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    /// Records the outcome of a check, returning whether it passed.
+    pub(crate) fn record(&mut self, name: &'static str, result: Result<()>) -> bool {
+        let outcome = match result {
+            Ok(()) => CheckOutcome::Passed,
+            Err(e) => CheckOutcome::Failed(e.to_string()),
+        };
+        let passed = outcome == CheckOutcome::Passed;
+        self.checks.push(SelfTestCheck { name, outcome });
+        passed
+    }
+
+    pub(crate) fn skip(&mut self, name: &'static str) {
+        self.checks.push(SelfTestCheck {
+            name,
+            outcome: CheckOutcome::Skipped,
+        });
+    }
+
+    /// Returns true if every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.outcome == CheckOutcome::Passed)
+    }
+
+    /// Returns the checks which failed.
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestCheck> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+}
+
+/// Checks that the central can start and stop scanning.
+pub(crate) async fn check_scanning<C: Central>(central: &C, report: &mut SelfTestReport) {
+    if report.record(
+        "start_scan",
+        central.start_scan(ScanFilter::default()).await,
+    ) {
+        report.record("stop_scan", central.stop_scan().await);
+    } else {
+        report.skip("stop_scan");
+    }
+}
+
+impl Display for SelfTestReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for check in &self.checks {
+            match &check.outcome {
+                CheckOutcome::Passed => writeln!(f, "{}: ok", check.name)?,
+                CheckOutcome::Failed(reason) => writeln!(f, "{}: failed: {}", check.name, reason)?,
+                CheckOutcome::Skipped => writeln!(f, "{}: skipped", check.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn report() {
+        let mut report = SelfTestReport::default();
+        assert!(report.record("adapter", Ok(())));
+        assert!(report.passed());
+        assert!(!report.record("powered", Err(Error::NotConnected)));
+        report.skip("start_scan");
+        assert!(!report.passed());
+        let failures: Vec<_> = report.failures().map(|check| check.name).collect();
+        assert_eq!(failures, ["powered"]);
+        assert_eq!(
+            report.to_string(),
+            "adapter: ok\npowered: failed: Not connected\nstart_scan: skipped\n"
+        );
+    }
+}
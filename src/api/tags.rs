@@ -0,0 +1,42 @@
+//! In-memory tags for peripherals; see [`Central::add_tag`](super::Central::add_tag).
+
+use crate::platform::PeripheralId;
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+
+/// The tags an adapter has assigned to its peripherals, for [`Central`](super::Central)
+/// implementations to return from [`Central::peripheral_tags`](super::Central::peripheral_tags).
+#[derive(Debug, Default)]
+pub struct PeripheralTags {
+    tags: DashMap<PeripheralId, BTreeSet<String>>,
+}
+
+impl PeripheralTags {
+    /// Assigns a tag to a peripheral.
+    pub fn add(&self, id: &PeripheralId, tag: &str) {
+        self.tags
+            .entry(id.clone())
+            .or_default()
+            .insert(tag.to_owned());
+    }
+
+    /// Removes a tag from a peripheral, if it had it.
+    pub fn remove(&self, id: &PeripheralId, tag: &str) {
+        self.tags
+            .remove_if_mut(id, |_, tags| tags.remove(tag) && tags.is_empty());
+    }
+
+    /// Returns the tags currently assigned to a peripheral.
+    pub fn get(&self, id: &PeripheralId) -> BTreeSet<String> {
+        self.tags
+            .get(id)
+            .map(|tags| tags.clone())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the tags of all peripherals for which the predicate returns false, e.g. when the
+    /// platform forgets the peripherals.
+    pub fn retain(&self, mut keep: impl FnMut(&PeripheralId) -> bool) {
+        self.tags.retain(|id, _| keep(id));
+    }
+}
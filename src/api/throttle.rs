@@ -0,0 +1,151 @@
+//! Rate limiting of [`CentralEvent::DeviceUpdated`] events.
+//!
+//! In crowded environments devices can report updates many times a second each, which is more
+//! than most consumers need or can keep up with. See [`Central::throttled_events`].
+
+#[cfg(doc)]
+use super::Central;
+use super::CentralEvent;
+use crate::platform::PeripheralId;
+use futures::future::{self, Either};
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{self as tokio_time, Instant};
+
+/// Once this many devices have been seen, devices which haven't been updated recently are
+/// forgotten.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Tracks when updates were last delivered for each device.
+struct Throttle<K> {
+    min_interval: Duration,
+    last_delivered: HashMap<K, Instant>,
+    /// Devices with a suppressed update, and when it may be delivered.
+    pending: HashMap<K, Instant>,
+}
+
+impl<K: Clone + Eq + Hash> Throttle<K> {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_delivered: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.pending.values().min().copied()
+    }
+
+    /// Removes and returns a pending update which is due, if any.
+    fn take_due(&mut self, now: Instant) -> Option<K> {
+        let id = self
+            .pending
+            .iter()
+            .find(|(_, due)| **due <= now)
+            .map(|(id, _)| id.clone())?;
+        self.pending.remove(&id);
+        self.last_delivered.insert(id.clone(), now);
+        Some(id)
+    }
+
+    /// Returns true if an update for the device can be delivered now, and records it if so.
+    fn admit(&mut self, id: &K, now: Instant) -> bool {
+        match self.last_delivered.get(id) {
+            Some(last) if now < *last + self.min_interval => {
+                let due = *last + self.min_interval;
+                self.pending.entry(id.clone()).or_insert(due);
+                false
+            }
+            _ => {
+                if self.last_delivered.len() >= PRUNE_THRESHOLD {
+                    let min_interval = self.min_interval;
+                    self.last_delivered
+                        .retain(|_, last| now < *last + min_interval);
+                }
+                self.last_delivered.insert(id.clone(), now);
+                true
+            }
+        }
+    }
+}
+
+struct State {
+    events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    throttle: Throttle<PeripheralId>,
+    finished: bool,
+}
+
+/// Limits `DeviceUpdated` events to at most one per `min_interval` for each device. An update
+/// arriving too soon isn't dropped but delayed until the interval has passed, so the last update
+/// of a burst is always delivered and consumers see the latest properties. All other events are
+/// passed through unchanged. This must be polled from within a Tokio runtime.
+pub fn throttle_device_updates(
+    events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    min_interval: Duration,
+) -> Pin<Box<dyn Stream<Item = CentralEvent> + Send>> {
+    let state = State {
+        events,
+        throttle: Throttle::new(min_interval),
+        finished: false,
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(id) = state.throttle.take_due(Instant::now()) {
+                return Some((CentralEvent::DeviceUpdated(id), state));
+            }
+            let event = match (state.throttle.next_due(), state.finished) {
+                (None, true) => return None,
+                (Some(due), true) => {
+                    tokio_time::sleep_until(due).await;
+                    continue;
+                }
+                (Some(due), false) => {
+                    let sleep = Box::pin(tokio_time::sleep_until(due));
+                    match future::select(state.events.next(), sleep).await {
+                        Either::Left((event, _)) => event,
+                        Either::Right(_) => continue,
+                    }
+                }
+                (None, false) => state.events.next().await,
+            };
+            match event {
+                Some(CentralEvent::DeviceUpdated(id)) => {
+                    if state.throttle.admit(&id, Instant::now()) {
+                        return Some((CentralEvent::DeviceUpdated(id), state));
+                    }
+                }
+                Some(event) => return Some((event, state)),
+                None => state.finished = true,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_updates_within_interval() {
+        let min_interval = Duration::from_millis(100);
+        let mut throttle = Throttle::new(min_interval);
+        let start = Instant::now();
+
+        assert!(throttle.admit(&1, start));
+        assert!(throttle.admit(&2, start));
+        assert!(!throttle.admit(&1, start + Duration::from_millis(10)));
+        assert!(!throttle.admit(&1, start + Duration::from_millis(20)));
+        assert_eq!(throttle.next_due(), Some(start + min_interval));
+
+        assert_eq!(throttle.take_due(start + Duration::from_millis(50)), None);
+        assert_eq!(throttle.take_due(start + min_interval), Some(1));
+        // Only one delayed update is delivered per burst.
+        assert_eq!(throttle.take_due(start + min_interval), None);
+        assert!(!throttle.admit(&1, start + Duration::from_millis(150)));
+        assert!(throttle.admit(&2, start + Duration::from_millis(150)));
+    }
+}
@@ -1,24 +1,67 @@
+use super::advertising;
+use super::agent::register_agent;
+use super::connection::{specific_error, BluezConnection, ADAPTER_INTERFACE};
+use super::gatt_server::GattApplication;
+use super::mgmt;
 use super::peripheral::{Peripheral, PeripheralId};
-use crate::api::{Central, CentralEvent, ScanFilter};
+use crate::api::advertising::Advertisement;
+use crate::api::gatt_server::{LocalService, ServerEvent};
+use crate::api::pairing::PairingAgent;
+use crate::api::self_test::{check_scanning, SelfTestReport};
+use crate::api::tags::PeripheralTags;
+use crate::api::{
+    AdapterInfo, AdapterRoles, AdapterState, Capabilities, Central, CentralEvent, Peripheral as _,
+    Phy, ScanFilter, ScanMode, ScanOptions, ScanTransport,
+};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use bluez_async::{
     AdapterId, BluetoothError, BluetoothEvent, BluetoothSession, DeviceEvent, DiscoveryFilter,
     Transport,
 };
+use dbus::Path;
+use futures::future::ready;
 use futures::stream::{self, Stream, StreamExt};
+use std::collections::{BTreeSet, HashMap};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
     session: BluetoothSession,
+    connection: BluezConnection,
     adapter: AdapterId,
 }
 
 impl Adapter {
-    pub(crate) fn new(session: BluetoothSession, adapter: AdapterId) -> Self {
-        Self { session, adapter }
+    pub(crate) fn new(
+        session: BluetoothSession,
+        connection: BluezConnection,
+        adapter: AdapterId,
+    ) -> Self {
+        Self {
+            session,
+            connection,
+            adapter,
+        }
+    }
+}
+
+/// Unstable access to the BlueZ objects behind the adapter, for operations btleplug doesn't cover
+/// yet.
+#[cfg(feature = "unstable-raw-handles")]
+impl Adapter {
+    /// Returns the D-Bus object path of the `org.bluez.Adapter1` object.
+    pub fn raw_path(&self) -> dbus::Path<'static> {
+        self.adapter.clone().into()
+    }
+
+    /// Returns the system bus connection btleplug uses to talk to BlueZ.
+    pub fn raw_connection(&self) -> std::sync::Arc<dbus::nonblock::SyncConnection> {
+        self.connection.raw_connection()
     }
 }
 
@@ -31,6 +74,8 @@ impl Central for Adapter {
         // Get the stream first, on the basis that it's better to have a duplicate DeviceDiscovered
         // event than to miss one. It's unlikely to happen in any case.
         let events = self.session.adapter_event_stream(&self.adapter).await?;
+        let removed_devices = self.connection.removed_devices().await?;
+        let battery_changes = self.connection.battery_changes().await?;
 
         // Synthesise `DeviceDiscovered' and `DeviceConnected` events for existing peripherals.
         let devices = self.session.get_devices().await?;
@@ -49,18 +94,85 @@ impl Central for Adapter {
         );
 
         let session = self.session.clone();
+        let connection = self.connection.clone();
         let adapter_id = self.adapter.clone();
         let events = events
-            .filter_map(move |event| central_event(event, session.clone(), adapter_id.clone()));
+            .then(move |event| {
+                central_events(
+                    event,
+                    session.clone(),
+                    connection.clone(),
+                    adapter_id.clone(),
+                )
+            })
+            .flat_map(stream::iter);
 
-        Ok(Box::pin(initial_events.chain(events)))
+        // BlueZ only reports the object path of a removed device, so remember the IDs of the
+        // devices this stream has reported to map it back.
+        let known_devices = Arc::new(Mutex::new(HashMap::new()));
+        let remember_devices = |known_devices: Arc<Mutex<HashMap<String, PeripheralId>>>| {
+            move |event: &CentralEvent| {
+                if let CentralEvent::DeviceDiscovered(id)
+                | CentralEvent::CachedDeviceDiscovered(id) = event
+                {
+                    let path = Path::from(id.0.clone()).to_string();
+                    known_devices.lock().unwrap().insert(path, id.clone());
+                }
+            }
+        };
+        let events = initial_events
+            .chain(events)
+            .inspect(remember_devices(known_devices.clone()));
+        let battery_devices = known_devices.clone();
+        let emitted_devices = known_devices.clone();
+        let battery_levels = battery_changes.filter_map(move |(path, level)| {
+            let id = battery_devices.lock().unwrap().get(&path).cloned();
+            ready(id.map(|id| CentralEvent::BatteryLevelChanged { id, level }))
+        });
+        let connection = self.connection.clone();
+        let lost_devices = removed_devices.filter_map(move |path| {
+            let id: Option<PeripheralId> = known_devices.lock().unwrap().remove(&path);
+            if let Some(id) = &id {
+                connection.set_connected(&id.0, false);
+            }
+            ready(id.map(CentralEvent::DeviceLost))
+        });
+
+        let adapter_id = self.adapter.clone();
+        let emitted = self
+            .connection
+            .emitted_events()
+            .filter(move |event| ready(event.peripheral_id().0.adapter() == adapter_id))
+            .inspect(remember_devices(emitted_devices));
+
+        Ok(Box::pin(stream::select(
+            stream::select(events, lost_devices),
+            stream::select(battery_levels, emitted),
+        )))
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.start_scan_with_options(ScanOptions::default().with_filter(filter))
+            .await
+    }
+
+    async fn start_scan_with_options(&self, options: ScanOptions) -> Result<()> {
+        if options.rssi_threshold.is_some() && options.pathloss_threshold.is_some() {
+            return Err(Error::NotSupported(
+                "BlueZ can't filter by both RSSI and path loss".to_string(),
+            ));
+        }
+        if options.mode == ScanMode::Passive {
+            return Err(Error::NotSupported(
+                "BlueZ discovery always scans actively".to_string(),
+            ));
+        }
         let filter = DiscoveryFilter {
-            service_uuids: filter.services,
-            duplicate_data: Some(true),
-            transport: Some(Transport::Auto),
+            service_uuids: options.filter.services,
+            rssi_threshold: options.rssi_threshold,
+            pathloss_threshold: options.pathloss_threshold,
+            duplicate_data: Some(!options.filter_duplicates),
+            transport: Some(options.transport.into()),
             ..Default::default()
         };
         self.session
@@ -80,7 +192,7 @@ impl Central for Adapter {
         let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
         Ok(devices
             .into_iter()
-            .map(|device| Peripheral::new(self.session.clone(), device))
+            .map(|device| Peripheral::new(self.session.clone(), self.connection.clone(), device))
             .collect())
     }
 
@@ -92,7 +204,42 @@ impl Central for Adapter {
                 e.into()
             }
         })?;
-        Ok(Peripheral::new(self.session.clone(), device))
+        Ok(Peripheral::new(
+            self.session.clone(),
+            self.connection.clone(),
+            device,
+        ))
+    }
+
+    async fn connected_peripherals(&self) -> Result<Vec<Peripheral>> {
+        let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
+        let mut connected = vec![];
+        for device in devices.into_iter().filter(|device| device.connected) {
+            // BlueZ keeps the link up after the process which connected exits, so this may be the
+            // first we hear of the connection.
+            if self.connection.connected_since(&device.id).is_none() {
+                self.connection.set_connected(&device.id, true);
+                self.connection
+                    .activity_log(&device.id)
+                    .transition("attached");
+            }
+            let services_resolved = device.services_resolved;
+            let peripheral = Peripheral::new(self.session.clone(), self.connection.clone(), device);
+            if services_resolved {
+                peripheral.load_services().await?;
+            }
+            connected.push(peripheral);
+        }
+        Ok(connected)
+    }
+
+    async fn load_cached_peripherals(&self) -> Result<Vec<Peripheral>> {
+        let peripherals = self.peripherals().await?;
+        for peripheral in &peripherals {
+            self.connection
+                .emit(CentralEvent::CachedDeviceDiscovered(peripheral.id()));
+        }
+        Ok(peripherals)
     }
 
     async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
@@ -101,67 +248,268 @@ impl Central for Adapter {
         ))
     }
 
+    async fn supported_phys(&self) -> Result<BTreeSet<Phy>> {
+        mgmt::supported_phys(mgmt::adapter_index(&self.adapter.to_string())?).await
+    }
+
+    async fn set_preferred_phys(&self, phys: BTreeSet<Phy>) -> Result<()> {
+        mgmt::set_preferred_phys(mgmt::adapter_index(&self.adapter.to_string())?, &phys).await
+    }
+
     async fn adapter_info(&self) -> Result<String> {
         let adapter_info = self.session.get_adapter_info(&self.adapter).await?;
         Ok(format!("{} ({})", adapter_info.id, adapter_info.modalias))
     }
+
+    async fn adapter_details(&self) -> Result<AdapterInfo> {
+        let adapter_info = self.session.get_adapter_info(&self.adapter).await?;
+        Ok(AdapterInfo {
+            id: adapter_info.id.to_string(),
+            address: adapter_info.mac_address.into(),
+            alias: Some(adapter_info.alias),
+            modalias: Some(adapter_info.modalias.to_string()),
+            roles: self.connection.adapter_roles(&self.adapter).await?,
+        })
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        let property = |name| {
+            self.connection
+                .get_property::<bool>(self.adapter.clone(), ADAPTER_INTERFACE, name)
+        };
+        // PowerState is only provided by BlueZ 5.66 and later.
+        let power_state: Option<String> = self
+            .connection
+            .get_property(self.adapter.clone(), ADAPTER_INTERFACE, "PowerState")
+            .await?;
+        Ok(AdapterState {
+            powered: property("Powered").await?.unwrap_or_default(),
+            blocked: power_state.as_deref() == Some("off-blocked"),
+            discovering: property("Discovering").await?.unwrap_or_default(),
+            discoverable: property("Discoverable").await?.unwrap_or_default(),
+        })
+    }
+
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        let result = self
+            .connection
+            .set_property(self.adapter.clone(), ADAPTER_INTERFACE, "Powered", powered)
+            .await;
+        match result {
+            // Older versions of BlueZ don't fail with `Blocked`, so ask the adapter whether rfkill
+            // is why it failed.
+            Err(Error::Other(e)) if powered => match self.adapter_state().await {
+                Ok(state) if state.blocked => Err(Error::AdapterBlocked),
+                _ => Err(Error::Other(e)),
+            },
+            result => result,
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            advertising: true,
+            gatt_server: true,
+            concurrent_roles: true,
+            l2cap: true,
+            phy_control: true,
+            pairing_agent: true,
+            ..Default::default()
+        }
+    }
+
+    async fn self_test(&self) -> SelfTestReport {
+        let mut report = SelfTestReport::default();
+        // This fails if BlueZ isn't running or D-Bus policy doesn't allow access to it.
+        let adapter_info = self.session.get_adapter_info(&self.adapter).await;
+        let powered = match adapter_info {
+            Ok(adapter_info) => {
+                report.record("adapter", Ok(()));
+                let powered = report.record(
+                    "powered",
+                    if adapter_info.powered {
+                        Ok(())
+                    } else {
+                        Err(Error::AdapterPoweredOff)
+                    },
+                );
+                // Don't interfere with a scan which is already in progress.
+                powered && !adapter_info.discovering
+            }
+            Err(e) => {
+                report.record("adapter", Err(e.into()));
+                report.skip("powered");
+                false
+            }
+        };
+        if powered {
+            check_scanning(self, &mut report).await;
+        } else {
+            report.skip("start_scan");
+            report.skip("stop_scan");
+        }
+        report
+    }
+
+    async fn start_advertising(&self, advertisement: Advertisement) -> Result<()> {
+        self.connection
+            .check_role(&self.adapter, AdapterRoles::PERIPHERAL)
+            .await?;
+        self.connection
+            .register_advertisement(&self.adapter, move |message| {
+                advertising::handle_call(&advertisement, message)
+            })
+            .await
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        self.connection
+            .unregister_advertisement(&self.adapter)
+            .await
+    }
+
+    async fn register_gatt_services(&self, services: Vec<LocalService>) -> Result<()> {
+        self.connection
+            .check_role(&self.adapter, AdapterRoles::PERIPHERAL)
+            .await?;
+        let root = Path::from(format!("/org/btleplug/gatt/{}", self.adapter));
+        let events = self.connection.server_events(&self.adapter);
+        let application = Arc::new(GattApplication::new(root, services, events));
+        self.connection
+            .register_gatt_application(&self.adapter, application)
+            .await
+    }
+
+    async fn unregister_gatt_services(&self) -> Result<()> {
+        self.connection
+            .unregister_gatt_application(&self.adapter)
+            .await
+    }
+
+    async fn notify_local_characteristic(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        let application = self
+            .connection
+            .gatt_application(&self.adapter)
+            .ok_or(Error::NoSuchCharacteristic)?;
+        if let Some(signal) = application.notification(service, characteristic, value)? {
+            self.connection.send_signal(signal)?;
+        }
+        Ok(())
+    }
+
+    async fn gatt_server_events(&self) -> Result<Pin<Box<dyn Stream<Item = ServerEvent> + Send>>> {
+        let events = self.connection.server_events(&self.adapter).subscribe();
+        Ok(Box::pin(
+            BroadcastStream::new(events).filter_map(|event| ready(event.ok())),
+        ))
+    }
+
+    async fn register_pairing_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()> {
+        register_agent(self.session.clone(), &self.connection, agent).await
+    }
+
+    fn peripheral_tags(&self) -> Option<&PeripheralTags> {
+        Some(self.connection.peripheral_tags())
+    }
+}
+
+impl From<ScanTransport> for Transport {
+    fn from(transport: ScanTransport) -> Self {
+        match transport {
+            ScanTransport::Auto => Transport::Auto,
+            ScanTransport::Le => Transport::Le,
+            ScanTransport::BrEdr => Transport::BrEdr,
+        }
+    }
 }
 
 impl From<BluetoothError> for Error {
     fn from(error: BluetoothError) -> Self {
-        Error::Other(Box::new(error))
+        match &error {
+            BluetoothError::DbusError(dbus_error) => specific_error(dbus_error),
+            _ => None,
+        }
+        .unwrap_or_else(|| Error::Other(Box::new(error)))
     }
 }
 
-async fn central_event(
+/// Converts a BlueZ event into the `CentralEvent`s it should be reported as.
+///
+/// Changes to advertisement-derived data are reported both as the specific advertisement event
+/// carrying the new payload and as a `DeviceUpdated`, since BlueZ stops sending RSSI updates while
+/// a device is connected and those would otherwise go unnoticed by `DeviceUpdated` subscribers.
+async fn central_events(
     event: BluetoothEvent,
     session: BluetoothSession,
+    connection: BluezConnection,
     adapter_id: AdapterId,
-) -> Option<CentralEvent> {
+) -> Vec<CentralEvent> {
     match event {
         BluetoothEvent::Device {
             id,
             event: device_event,
-        } if id.adapter() == adapter_id => match device_event {
-            DeviceEvent::Discovered => {
-                let device = session.get_device_info(&id).await.ok()?;
-                Some(CentralEvent::DeviceDiscovered(device.id.into()))
+        } if id.adapter() == adapter_id => {
+            // Only look the device up for the events reported below, rather than for every
+            // characteristic value change.
+            if !matches!(
+                device_event,
+                DeviceEvent::Discovered
+                    | DeviceEvent::Connected { .. }
+                    | DeviceEvent::Rssi { .. }
+                    | DeviceEvent::ManufacturerData { .. }
+                    | DeviceEvent::ServiceData { .. }
+                    | DeviceEvent::Services { .. }
+            ) {
+                return vec![];
             }
-            DeviceEvent::Connected { connected } => {
-                let device = session.get_device_info(&id).await.ok()?;
-                if connected {
-                    Some(CentralEvent::DeviceConnected(device.id.into()))
-                } else {
-                    Some(CentralEvent::DeviceDisconnected(device.id.into()))
+            let device = match session.get_device_info(&id).await {
+                Ok(device) => device,
+                Err(_) => return vec![],
+            };
+            let id: PeripheralId = device.id.into();
+            match device_event {
+                DeviceEvent::Discovered => vec![CentralEvent::DeviceDiscovered(id)],
+                DeviceEvent::Connected { connected } => {
+                    let activity_log = connection.activity_log(&id.0);
+                    connection.set_connected(&id.0, connected);
+                    if connected {
+                        activity_log.transition("connected");
+                        vec![CentralEvent::DeviceConnected(id)]
+                    } else {
+                        activity_log.transition("disconnected");
+                        vec![CentralEvent::DeviceDisconnected(id)]
+                    }
                 }
+                DeviceEvent::Rssi { rssi: _ } => vec![CentralEvent::DeviceUpdated(id)],
+                DeviceEvent::ManufacturerData { manufacturer_data } => vec![
+                    CentralEvent::ManufacturerDataAdvertisement {
+                        id: id.clone(),
+                        manufacturer_data,
+                    },
+                    CentralEvent::DeviceUpdated(id),
+                ],
+                DeviceEvent::ServiceData { service_data } => vec![
+                    CentralEvent::ServiceDataAdvertisement {
+                        id: id.clone(),
+                        service_data,
+                    },
+                    CentralEvent::DeviceUpdated(id),
+                ],
+                DeviceEvent::Services { services } => vec![
+                    CentralEvent::ServicesAdvertisement {
+                        id: id.clone(),
+                        services,
+                    },
+                    CentralEvent::DeviceUpdated(id),
+                ],
+                _ => vec![],
             }
-            DeviceEvent::Rssi { rssi: _ } => {
-                let device = session.get_device_info(&id).await.ok()?;
-                Some(CentralEvent::DeviceUpdated(device.id.into()))
-            }
-            DeviceEvent::ManufacturerData { manufacturer_data } => {
-                let device = session.get_device_info(&id).await.ok()?;
-                Some(CentralEvent::ManufacturerDataAdvertisement {
-                    id: device.id.into(),
-                    manufacturer_data,
-                })
-            }
-            DeviceEvent::ServiceData { service_data } => {
-                let device = session.get_device_info(&id).await.ok()?;
-                Some(CentralEvent::ServiceDataAdvertisement {
-                    id: device.id.into(),
-                    service_data,
-                })
-            }
-            DeviceEvent::Services { services } => {
-                let device = session.get_device_info(&id).await.ok()?;
-                Some(CentralEvent::ServicesAdvertisement {
-                    id: device.id.into(),
-                    services,
-                })
-            }
-            _ => None,
-        },
-        _ => None,
+        }
+        _ => vec![],
     }
 }
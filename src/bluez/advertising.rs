@@ -0,0 +1,82 @@
+//! An `org.bluez.LEAdvertisement1` object describing an [`Advertisement`], for BlueZ's
+//! `LEAdvertisingManager1` to advertise.
+
+use super::connection::{variant, UNKNOWN_METHOD_ERROR, UNKNOWN_PROPERTY_ERROR};
+use crate::api::advertising::Advertisement;
+use dbus::arg::{PropMap, Variant};
+use dbus::Message;
+use std::collections::HashMap;
+use std::ffi::CString;
+
+const ADVERTISEMENT_INTERFACE: &str = "org.bluez.LEAdvertisement1";
+
+/// Returns the properties of the `LEAdvertisement1` object for the advertisement.
+fn properties(advertisement: &Advertisement) -> PropMap {
+    let mut properties = PropMap::new();
+    let advertisement_type = if advertisement.connectable {
+        "peripheral"
+    } else {
+        "broadcast"
+    };
+    properties.insert("Type".to_string(), variant(advertisement_type.to_string()));
+    if let Some(local_name) = &advertisement.local_name {
+        properties.insert("LocalName".to_string(), variant(local_name.clone()));
+    }
+    if !advertisement.services.is_empty() {
+        let services: Vec<String> = advertisement
+            .services
+            .iter()
+            .map(|uuid| uuid.to_string())
+            .collect();
+        properties.insert("ServiceUUIDs".to_string(), variant(services));
+    }
+    if !advertisement.manufacturer_data.is_empty() {
+        let manufacturer_data: HashMap<u16, Variant<Vec<u8>>> = advertisement
+            .manufacturer_data
+            .iter()
+            .map(|(company_id, data)| (*company_id, Variant(data.clone())))
+            .collect();
+        properties.insert("ManufacturerData".to_string(), variant(manufacturer_data));
+    }
+    if !advertisement.service_data.is_empty() {
+        let service_data: HashMap<String, Variant<Vec<u8>>> = advertisement
+            .service_data
+            .iter()
+            .map(|(uuid, data)| (uuid.to_string(), Variant(data.clone())))
+            .collect();
+        properties.insert("ServiceData".to_string(), variant(service_data));
+    }
+    if advertisement.include_tx_power {
+        properties.insert(
+            "Includes".to_string(),
+            variant(vec!["tx-power".to_string()]),
+        );
+    }
+    properties
+}
+
+/// Answers a method call from BlueZ to the advertisement object. BlueZ reads the properties once
+/// when the advertisement is registered, and calls `Release` when it stops advertising it.
+pub(crate) fn handle_call(advertisement: &Advertisement, message: &Message) -> Message {
+    let error = |name: &str, text: &str| message.error(&name.into(), &CString::new(text).unwrap());
+    match message.member().as_deref() {
+        Some("GetAll") => match message.read1::<&str>() {
+            Ok(ADVERTISEMENT_INTERFACE) => {
+                message.method_return().append1(properties(advertisement))
+            }
+            _ => message.method_return().append1(PropMap::new()),
+        },
+        Some("Get") => {
+            let property = match message.read2::<&str, &str>() {
+                Ok((ADVERTISEMENT_INTERFACE, name)) => properties(advertisement).remove(name),
+                _ => None,
+            };
+            match property {
+                Some(value) => message.method_return().append1(value),
+                None => error(UNKNOWN_PROPERTY_ERROR, "No such property"),
+            }
+        }
+        Some("Release") => message.method_return(),
+        _ => error(UNKNOWN_METHOD_ERROR, "No such method"),
+    }
+}
@@ -0,0 +1,142 @@
+//! An `org.bluez.Agent1` which forwards BlueZ's pairing requests to a [`PairingAgent`].
+
+use super::connection::BluezConnection;
+use super::peripheral::PeripheralId;
+use crate::api::pairing::{IoCapability, PairingAgent};
+use crate::Result;
+use bluez_async::BluetoothSession;
+use dbus::{Message, Path};
+use std::ffi::CString;
+use std::sync::Arc;
+
+const REJECTED_ERROR: &str = "org.bluez.Error.Rejected";
+
+/// A method call from BlueZ, parsed out of the method call so that the message isn't borrowed while
+/// waiting for the agent.
+enum AgentCall {
+    Release,
+    Cancel,
+    DisplayPasskey { device: String, passkey: u32 },
+    RequestPasskey { device: String },
+    RequestConfirmation { device: String, passkey: u32 },
+    RequestAuthorization { device: String },
+}
+
+enum Reply {
+    Empty,
+    Passkey(u32),
+}
+
+fn capability_name(capability: IoCapability) -> &'static str {
+    match capability {
+        IoCapability::DisplayOnly => "DisplayOnly",
+        IoCapability::DisplayYesNo => "DisplayYesNo",
+        IoCapability::KeyboardOnly => "KeyboardOnly",
+        IoCapability::NoInputNoOutput => "NoInputNoOutput",
+        IoCapability::KeyboardDisplay => "KeyboardDisplay",
+    }
+}
+
+/// Registers the agent as BlueZ's default agent, replacing any agent registered before.
+pub(crate) async fn register_agent(
+    session: BluetoothSession,
+    connection: &BluezConnection,
+    agent: Arc<dyn PairingAgent>,
+) -> Result<()> {
+    let capability = capability_name(agent.io_capability());
+    connection
+        .register_agent(capability, move |message| {
+            handle_call(session.clone(), agent.clone(), message)
+        })
+        .await
+}
+
+/// Parses a method call, returning `None` for those which are always rejected. PIN codes are only
+/// used by BR/EDR legacy pairing, and BlueZ's own policy decides which services to authorise.
+fn parse_call(message: &Message) -> Option<AgentCall> {
+    let device = || message.read1::<Path>().ok().map(|path| path.to_string());
+    Some(match &*message.member()? {
+        "Release" => AgentCall::Release,
+        "Cancel" => AgentCall::Cancel,
+        "DisplayPasskey" => {
+            let (device, passkey, _entered) = message.read3::<Path, u32, u16>().ok()?;
+            AgentCall::DisplayPasskey {
+                device: device.to_string(),
+                passkey,
+            }
+        }
+        "RequestPasskey" => AgentCall::RequestPasskey { device: device()? },
+        "RequestConfirmation" => {
+            let (device, passkey) = message.read2::<Path, u32>().ok()?;
+            AgentCall::RequestConfirmation {
+                device: device.to_string(),
+                passkey,
+            }
+        }
+        "RequestAuthorization" => AgentCall::RequestAuthorization { device: device()? },
+        _ => return None,
+    })
+}
+
+async fn peripheral_id(session: &BluetoothSession, path: &str) -> Option<PeripheralId> {
+    let devices = session.get_devices().await.ok()?;
+    devices
+        .into_iter()
+        .find(|device| &*Path::from(device.id.clone()) == path)
+        .map(|device| device.id.into())
+}
+
+/// Asks the agent to respond to a call, returning `None` if it rejects it.
+async fn respond(
+    session: &BluetoothSession,
+    agent: &dyn PairingAgent,
+    call: AgentCall,
+) -> Option<Reply> {
+    match call {
+        AgentCall::Release => Some(Reply::Empty),
+        AgentCall::Cancel => {
+            agent.cancel().await;
+            Some(Reply::Empty)
+        }
+        AgentCall::DisplayPasskey { device, passkey } => {
+            let id = peripheral_id(session, &device).await?;
+            agent.display_passkey(id, passkey).await;
+            Some(Reply::Empty)
+        }
+        AgentCall::RequestPasskey { device } => {
+            let id = peripheral_id(session, &device).await?;
+            agent.request_passkey(id).await.map(Reply::Passkey)
+        }
+        AgentCall::RequestConfirmation { device, passkey } => {
+            let id = peripheral_id(session, &device).await?;
+            agent
+                .confirm_passkey(id, passkey)
+                .await
+                .then_some(Reply::Empty)
+        }
+        AgentCall::RequestAuthorization { device } => {
+            let id = peripheral_id(session, &device).await?;
+            agent.authorize(id).await.then_some(Reply::Empty)
+        }
+    }
+}
+
+async fn handle_call(
+    session: BluetoothSession,
+    agent: Arc<dyn PairingAgent>,
+    message: Message,
+) -> Message {
+    let call = parse_call(&message);
+    let reply = match call {
+        Some(call) => respond(&session, agent.as_ref(), call).await,
+        None => None,
+    };
+    match reply {
+        Some(Reply::Empty) => message.method_return(),
+        Some(Reply::Passkey(passkey)) => message.method_return().append1(passkey),
+        None => message.error(
+            &REJECTED_ERROR.into(),
+            &CString::new("Rejected by the pairing agent").unwrap(),
+        ),
+    }
+}
@@ -0,0 +1,1084 @@
+use super::gatt_server::GattApplication;
+use crate::api::activity::ActivityLog;
+use crate::api::concurrency::{
+    max_concurrent_operations, max_concurrent_operations_per_peripheral, Limiter, Permit,
+};
+use crate::api::diagnostics::LatencyHistogram;
+use crate::api::gatt_server::ServerEvent;
+use crate::api::tags::PeripheralTags;
+use crate::api::{AdapterRoles, AdvertisingFlags, CentralEvent, Characteristic};
+use crate::{Error, Result};
+use bluez_async::{AdapterId, DeviceId};
+use dashmap::{DashMap, DashSet};
+use dbus::arg::{prop_cast, Append, Arg, Get, PropMap, RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender, Token};
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{MsgMatch, Proxy, SyncConnection};
+use dbus::{Message, Path};
+use futures::future::{self, ready, Either};
+use futures::stream::{Stream, StreamExt};
+use log::{debug, error};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, OwnedRwLockReadGuard, RwLock};
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
+
+const BLUEZ_SERVICE: &str = "org.bluez";
+const DBUS_METHOD_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+const INVALID_ARGS_ERROR: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+const NOT_PERMITTED_ERROR: &str = "org.bluez.Error.NotPermitted";
+const NOT_READY_ERROR: &str = "org.bluez.Error.NotReady";
+const BLOCKED_ERROR: &str = "org.bluez.Error.Blocked";
+const OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+const AGENT_MANAGER_INTERFACE: &str = "org.bluez.AgentManager1";
+const AGENT_INTERFACE: &str = "org.bluez.Agent1";
+const AGENT_PATH: &str = "/org/btleplug/agent";
+const ADVERTISING_MANAGER_INTERFACE: &str = "org.bluez.LEAdvertisingManager1";
+const GATT_MANAGER_INTERFACE: &str = "org.bluez.GattManager1";
+
+pub(crate) const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+pub(crate) const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+pub(crate) const SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+pub(crate) const CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+pub(crate) const DESCRIPTOR_INTERFACE: &str = "org.bluez.GattDescriptor1";
+pub(crate) const BATTERY_INTERFACE: &str = "org.bluez.Battery1";
+pub(crate) const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+pub(crate) const UNKNOWN_OBJECT_ERROR: &str = "org.freedesktop.DBus.Error.UnknownObject";
+pub(crate) const UNKNOWN_PROPERTY_ERROR: &str = "org.freedesktop.DBus.Error.UnknownProperty";
+pub(crate) const UNKNOWN_METHOD_ERROR: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+/// Wraps a value for a property map of an object exported to BlueZ.
+pub(crate) fn variant(value: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+/// An adapter appearing or disappearing, identified by its object path.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AdapterChange {
+    Added(String),
+    Removed(String),
+}
+
+/// A GATT application exported on behalf of an adapter, the filter receiving method calls to it,
+/// and the match telling it when devices disconnect.
+type RegisteredApplication = (Token, MsgMatch, Arc<GattApplication>);
+
+tokio::task_local! {
+    /// The device the current task is running a batch of operations on, whose operations mustn't
+    /// wait for the batch to finish.
+    static BATCH_DEVICE: DeviceId;
+}
+
+/// A D-Bus connection to BlueZ, for the parts of its API which `bluez_async` doesn't cover, along
+/// with the little state which BlueZ doesn't keep for us.
+#[derive(Clone)]
+pub(crate) struct BluezConnection {
+    connection: Arc<SyncConnection>,
+    connected_since: Arc<DashMap<DeviceId, SystemTime>>,
+    /// The devices this process connected to in the central role, as opposed to centrals which
+    /// connected to one of its advertisements.
+    central_connections: Arc<DashSet<DeviceId>>,
+    tags: Arc<PeripheralTags>,
+    activity: Arc<DashMap<DeviceId, Arc<ActivityLog>>>,
+    /// Keyed by device, service UUID and characteristic UUID.
+    write_latencies: Arc<DashMap<(DeviceId, Uuid, Uuid), LatencyHistogram>>,
+    operations: Arc<Limiter>,
+    device_operations: Arc<DashMap<DeviceId, Arc<Limiter>>>,
+    /// Held for reading by each operation on a device, and for writing by a batch of them.
+    device_batches: Arc<DashMap<DeviceId, Arc<RwLock<()>>>>,
+    lost: Arc<LostDevices>,
+    /// The flags of each device's last advertisement, by object path, as BlueZ reported them when
+    /// adding the device or changing its properties. `None` if BlueZ didn't report any.
+    advertising_flags: Arc<DashMap<String, Option<AdvertisingFlags>>>,
+    /// The filter receiving method calls to the pairing agent, if one is registered.
+    agent: Arc<Mutex<Option<Token>>>,
+    /// The filters receiving method calls to each adapter's advertisement, if it has one.
+    advertisements: Arc<Mutex<HashMap<AdapterId, Token>>>,
+    /// The GATT application registered on each adapter, if it has one.
+    gatt_applications: Arc<Mutex<HashMap<AdapterId, RegisteredApplication>>>,
+    /// What centrals do to each adapter's GATT application, across registrations.
+    server_events: Arc<DashMap<AdapterId, broadcast::Sender<ServerEvent>>>,
+    /// Events which don't come straight from a BlueZ signal, for the adapters' event streams.
+    events: broadcast::Sender<CentralEvent>,
+}
+
+impl Debug for BluezConnection {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "BluezConnection")
+    }
+}
+
+impl BluezConnection {
+    #[cfg(feature = "unstable-raw-handles")]
+    pub(crate) fn raw_connection(&self) -> Arc<SyncConnection> {
+        self.connection.clone()
+    }
+
+    /// Connects to the system bus. This calls tokio::task::spawn, so it must be called from the
+    /// context of a Tokio Runtime.
+    pub(crate) fn new() -> Result<Self> {
+        let (resource, connection) = dbus_tokio::connection::new_system_sync()?;
+        tokio::spawn(async move {
+            let err = resource.await;
+            error!("Lost connection to D-Bus: {}", err);
+        });
+        let (events, _) = broadcast::channel(16);
+        let bluez_connection = Self {
+            connection,
+            connected_since: Arc::new(DashMap::new()),
+            central_connections: Arc::new(DashSet::new()),
+            tags: Arc::new(PeripheralTags::default()),
+            activity: Arc::new(DashMap::new()),
+            write_latencies: Arc::new(DashMap::new()),
+            operations: Arc::default(),
+            device_operations: Arc::new(DashMap::new()),
+            device_batches: Arc::new(DashMap::new()),
+            lost: Arc::default(),
+            advertising_flags: Arc::new(DashMap::new()),
+            agent: Arc::default(),
+            advertisements: Arc::default(),
+            gatt_applications: Arc::default(),
+            server_events: Arc::new(DashMap::new()),
+            events,
+        };
+        let watcher = bluez_connection.clone();
+        tokio::spawn(async move {
+            if let Err(e) = watcher.watch_lost_devices().await {
+                error!("Failed to watch for removed devices: {}", e);
+            }
+        });
+        let watcher = bluez_connection.clone();
+        tokio::spawn(async move {
+            if let Err(e) = watcher.watch_advertising_flags().await {
+                error!("Failed to watch for advertising flags: {}", e);
+            }
+        });
+        Ok(bluez_connection)
+    }
+
+    /// Keeps track of which devices BlueZ has removed, so that operations on them fail with
+    /// [`Error::DeviceLost`] rather than whatever D-Bus error BlueZ happens to give.
+    async fn watch_lost_devices(&self) -> Result<()> {
+        let rule = MatchRule::new_signal(OBJECT_MANAGER_INTERFACE, "InterfacesAdded")
+            .with_sender(BLUEZ_SERVICE);
+        let (_added_signal, added) = self.connection.add_match(rule).await?.msg_stream();
+        let added = added.filter_map(|message| {
+            let added = match message.read2::<Path, HashMap<String, PropMap>>() {
+                Ok((path, interfaces)) if interfaces.contains_key(DEVICE_INTERFACE) => {
+                    Some(path.to_string())
+                }
+                _ => None,
+            };
+            ready(added)
+        });
+        let removed = self.removed_devices().await?;
+        let mut changes =
+            futures::stream::select(added.map(Either::Left), removed.map(Either::Right));
+        while let Some(change) = changes.next().await {
+            match change {
+                Either::Left(path) => self.lost.added(&path),
+                Either::Right(path) => {
+                    debug!("BlueZ removed {}", path);
+                    // Forget what was kept about the device, as it will have a new history if BlueZ
+                    // finds it again.
+                    self.tags
+                        .retain(|id| Path::from(id.0.clone()).to_string() != path);
+                    self.activity
+                        .retain(|device, _| Path::from(device.clone()).to_string() != path);
+                    self.advertising_flags.remove(&path);
+                    self.lost.removed(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the advertising flags of each device up to date, as `bluez_async` doesn't include
+    /// them in its device info.
+    async fn watch_advertising_flags(&self) -> Result<()> {
+        let added_rule = MatchRule::new_signal(OBJECT_MANAGER_INTERFACE, "InterfacesAdded")
+            .with_sender(BLUEZ_SERVICE);
+        let changed_rule = MatchRule::new_signal(PROPERTIES_INTERFACE, "PropertiesChanged")
+            .with_sender(BLUEZ_SERVICE)
+            .with_namespaced_path("/org/bluez");
+        let (_added_signal, added) = self.connection.add_match(added_rule).await?.msg_stream();
+        let (_changed_signal, changed) =
+            self.connection.add_match(changed_rule).await?.msg_stream();
+        let added = added.filter_map(|message| {
+            let added = match message.read2::<Path, HashMap<String, PropMap>>() {
+                Ok((path, interfaces)) => interfaces
+                    .get(DEVICE_INTERFACE)
+                    .map(|properties| (path.to_string(), advertising_flags(properties))),
+                _ => None,
+            };
+            ready(added)
+        });
+        let changed = changed.filter_map(|message| {
+            let changed = match (message.path(), message.read2::<String, PropMap>()) {
+                (Some(path), Ok((interface, changed)))
+                    if interface == DEVICE_INTERFACE
+                        && changed.contains_key("AdvertisingFlags") =>
+                {
+                    Some((path.to_string(), advertising_flags(&changed)))
+                }
+                _ => None,
+            };
+            ready(changed)
+        });
+        let mut updates = futures::stream::select(added, changed);
+        while let Some((path, flags)) = updates.next().await {
+            self.advertising_flags.insert(path, flags);
+        }
+        Ok(())
+    }
+
+    /// Returns the flags of the device's last advertisement, if BlueZ reported any.
+    pub(crate) async fn advertising_flags(
+        &self,
+        device: &DeviceId,
+    ) -> Result<Option<AdvertisingFlags>> {
+        let path = Path::from(device.to_owned());
+        if let Some(flags) = self.advertising_flags.get(&path.to_string()) {
+            return Ok(*flags);
+        }
+        // BlueZ added the device before the watcher started, and hasn't changed its flags since.
+        // AdvertisingFlags is still experimental in BlueZ, so this is often unavailable.
+        let flags = self
+            .get_property::<Vec<u8>>(path.clone(), DEVICE_INTERFACE, "AdvertisingFlags")
+            .await?
+            .and_then(|flags| flags.first().copied())
+            .map(AdvertisingFlags::from_bits_truncate);
+        self.advertising_flags
+            .entry(path.to_string())
+            .or_insert(flags);
+        Ok(flags)
+    }
+
+    /// Runs an operation on a device, failing it with [`Error::DeviceLost`] if BlueZ has removed
+    /// the device or does so before it finishes.
+    pub(crate) async fn unless_lost<T>(
+        &self,
+        device: &DeviceId,
+        operation: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let path = Path::from(device.to_owned()).to_string();
+        match self.lost.unless_lost(path, operation).await {
+            // The signal that BlueZ removed the device may not have arrived yet, so make sure it
+            // was the device rather than e.g. a characteristic of it which went away.
+            Err(e) if is_unknown_object(&e) && !self.device_exists(device).await => {
+                Err(Error::DeviceLost)
+            }
+            result => result,
+        }
+    }
+
+    /// Returns false if BlueZ no longer has an object for the device.
+    async fn device_exists(&self, device: &DeviceId) -> bool {
+        let address: std::result::Result<String, dbus::Error> = self
+            .proxy(device.clone())
+            .get(DEVICE_INTERFACE, "Address")
+            .await;
+        !matches!(address, Err(e) if e.name() == Some(UNKNOWN_OBJECT_ERROR))
+    }
+
+    /// Exports a pairing agent with the given capability, whose method calls are answered by
+    /// `handler`, and makes it BlueZ's default agent.
+    pub(crate) async fn register_agent<F>(
+        &self,
+        capability: &str,
+        handler: impl Fn(Message) -> F + Send + 'static,
+    ) -> Result<()>
+    where
+        F: Future<Output = Message> + Send + 'static,
+    {
+        let agent_manager = self.proxy("/org/bluez");
+        let previous = self.agent.lock().unwrap().take();
+        if let Some(token) = previous {
+            self.connection.stop_receive(token);
+            // BlueZ forgets agents when it restarts, so this may fail harmlessly.
+            let unregistered: std::result::Result<(), _> = agent_manager
+                .method_call(
+                    AGENT_MANAGER_INTERFACE,
+                    "UnregisterAgent",
+                    (Path::from(AGENT_PATH),),
+                )
+                .await;
+            if let Err(e) = unregistered {
+                debug!("Failed to unregister previous agent: {}", e);
+            }
+        }
+
+        let connection = self.connection.clone();
+        let rule = MatchRule::new_method_call()
+            .with_path(AGENT_PATH)
+            .with_interface(AGENT_INTERFACE);
+        let token = self.connection.start_receive(
+            rule,
+            Box::new(move |message, _| {
+                // Requests wait for the user, so answer them without blocking the connection.
+                let reply = handler(message);
+                let connection = connection.clone();
+                tokio::spawn(async move {
+                    if connection.send(reply.await).is_err() {
+                        error!("Failed to reply to pairing agent request");
+                    }
+                });
+                true
+            }),
+        );
+        *self.agent.lock().unwrap() = Some(token);
+
+        let () = agent_manager
+            .method_call(
+                AGENT_MANAGER_INTERFACE,
+                "RegisterAgent",
+                (Path::from(AGENT_PATH), capability),
+            )
+            .await?;
+        let () = agent_manager
+            .method_call(
+                AGENT_MANAGER_INTERFACE,
+                "RequestDefaultAgent",
+                (Path::from(AGENT_PATH),),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Exports an advertisement for the adapter, whose method calls are answered by `handler`,
+    /// and registers it with BlueZ, replacing the adapter's previous advertisement.
+    pub(crate) async fn register_advertisement(
+        &self,
+        adapter: &AdapterId,
+        handler: impl Fn(&Message) -> Message + Send + Sync + 'static,
+    ) -> Result<()> {
+        // BlueZ may have dropped the previous advertisement already, e.g. if it restarted.
+        if let Err(e) = self.unregister_advertisement(adapter).await {
+            debug!("Failed to unregister previous advertisement: {}", e);
+        }
+
+        let path = advertisement_path(adapter);
+        let rule = MatchRule::new_method_call().with_path(path.clone());
+        let token = self.connection.start_receive(
+            rule,
+            Box::new(move |message, connection| {
+                if connection.send(handler(&message)).is_err() {
+                    error!("Failed to reply to advertisement request");
+                }
+                true
+            }),
+        );
+        self.advertisements
+            .lock()
+            .unwrap()
+            .insert(adapter.to_owned(), token);
+
+        let registered: std::result::Result<(), dbus::Error> = self
+            .proxy(adapter.to_owned())
+            .method_call(
+                ADVERTISING_MANAGER_INTERFACE,
+                "RegisterAdvertisement",
+                (path, PropMap::new()),
+            )
+            .await;
+        if let Err(e) = registered {
+            if let Some(token) = self.advertisements.lock().unwrap().remove(adapter) {
+                self.connection.stop_receive(token);
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Stops the adapter's advertisement, if it has one.
+    pub(crate) async fn unregister_advertisement(&self, adapter: &AdapterId) -> Result<()> {
+        let token = self.advertisements.lock().unwrap().remove(adapter);
+        if let Some(token) = token {
+            let unregistered = self
+                .proxy(adapter.to_owned())
+                .method_call(
+                    ADVERTISING_MANAGER_INTERFACE,
+                    "UnregisterAdvertisement",
+                    (advertisement_path(adapter),),
+                )
+                .await;
+            // BlueZ calls `Release` while unregistering, so only stop answering afterwards.
+            self.connection.stop_receive(token);
+            let () = unregistered?;
+        }
+        Ok(())
+    }
+
+    /// Exports a GATT application and registers it with BlueZ on the adapter, replacing the
+    /// adapter's previous application.
+    pub(crate) async fn register_gatt_application(
+        &self,
+        adapter: &AdapterId,
+        application: Arc<GattApplication>,
+    ) -> Result<()> {
+        // BlueZ may have dropped the previous application already, e.g. if it restarted.
+        if let Err(e) = self.unregister_gatt_application(adapter).await {
+            debug!("Failed to unregister previous GATT application: {}", e);
+        }
+
+        let root = application.root().clone();
+        let rule = MatchRule::new_method_call().with_namespaced_path(root.clone());
+        let handler = application.clone();
+        let connection = self.connection.clone();
+        let token = self.connection.start_receive(
+            rule,
+            Box::new(move |message, _| {
+                // Characteristic handlers may take a while, so answer without blocking the
+                // connection.
+                let application = handler.clone();
+                let connection = connection.clone();
+                tokio::spawn(async move {
+                    if connection
+                        .send(application.handle_call(message).await)
+                        .is_err()
+                    {
+                        error!("Failed to reply to GATT application request");
+                    }
+                });
+                true
+            }),
+        );
+        let rule = MatchRule::new_signal(PROPERTIES_INTERFACE, "PropertiesChanged")
+            .with_sender(BLUEZ_SERVICE)
+            .with_namespaced_path(Path::from(adapter.to_owned()));
+        let disconnected_application = application.clone();
+        let disconnections = match self.connection.add_match(rule).await {
+            Ok(disconnections) => disconnections.cb(
+                move |message: Message, (interface, changed): (String, PropMap)| {
+                    if interface == DEVICE_INTERFACE
+                        && prop_cast::<bool>(&changed, "Connected") == Some(&false)
+                    {
+                        if let Some(path) = message.path() {
+                            disconnected_application.device_disconnected(&path);
+                        }
+                    }
+                    true
+                },
+            ),
+            Err(e) => {
+                self.connection.stop_receive(token);
+                return Err(e.into());
+            }
+        };
+        self.gatt_applications
+            .lock()
+            .unwrap()
+            .insert(adapter.to_owned(), (token, disconnections, application));
+
+        let registered: std::result::Result<(), dbus::Error> = self
+            .proxy(adapter.to_owned())
+            .method_call(
+                GATT_MANAGER_INTERFACE,
+                "RegisterApplication",
+                (root, PropMap::new()),
+            )
+            .await;
+        if let Err(e) = registered {
+            let registered = self.gatt_applications.lock().unwrap().remove(adapter);
+            if let Some((token, disconnections, _)) = registered {
+                self.stop_application(token, disconnections).await;
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Unregisters the adapter's GATT application, if it has one.
+    pub(crate) async fn unregister_gatt_application(&self, adapter: &AdapterId) -> Result<()> {
+        let registered = self.gatt_applications.lock().unwrap().remove(adapter);
+        if let Some((token, disconnections, application)) = registered {
+            let unregistered = self
+                .proxy(adapter.to_owned())
+                .method_call(
+                    GATT_MANAGER_INTERFACE,
+                    "UnregisterApplication",
+                    (application.root().clone(),),
+                )
+                .await;
+            self.stop_application(token, disconnections).await;
+            let () = unregistered?;
+        }
+        Ok(())
+    }
+
+    /// Stops answering method calls to an application and watching for disconnections on its
+    /// behalf.
+    async fn stop_application(&self, token: Token, disconnections: MsgMatch) {
+        self.connection.stop_receive(token);
+        if let Err(e) = self.connection.remove_match(disconnections.token()).await {
+            debug!("Failed to stop watching for disconnections: {}", e);
+        }
+    }
+
+    pub(crate) fn gatt_application(&self, adapter: &AdapterId) -> Option<Arc<GattApplication>> {
+        self.gatt_applications
+            .lock()
+            .unwrap()
+            .get(adapter)
+            .map(|(_, _, application)| application.clone())
+    }
+
+    /// Returns the sender of the adapter's [`ServerEvent`]s, which outlives any one GATT
+    /// application so that streams of them can be taken before registering.
+    pub(crate) fn server_events(&self, adapter: &AdapterId) -> broadcast::Sender<ServerEvent> {
+        self.server_events
+            .entry(adapter.to_owned())
+            .or_insert_with(|| broadcast::channel(16).0)
+            .clone()
+    }
+
+    /// Sends a signal from one of the objects btleplug exports.
+    pub(crate) fn send_signal(&self, signal: Message) -> Result<()> {
+        self.connection
+            .send(signal)
+            .map_err(|()| Error::RuntimeError("Failed to send D-Bus signal".to_string()))?;
+        Ok(())
+    }
+
+    /// Records when this process connected to the given device.
+    pub(crate) fn set_connected(&self, device: &DeviceId, connected: bool) {
+        if connected {
+            self.connected_since
+                .insert(device.to_owned(), SystemTime::now());
+        } else {
+            self.connected_since.remove(device);
+            self.central_connections.remove(device);
+        }
+    }
+
+    /// Records that this process connected to the given device in the central role.
+    pub(crate) fn set_connected_as_central(&self, device: &DeviceId) {
+        self.set_connected(device, true);
+        self.central_connections.insert(device.to_owned());
+    }
+
+    /// Returns the roles the adapter supports, or none if BlueZ doesn't say.
+    pub(crate) async fn adapter_roles(&self, adapter: &AdapterId) -> Result<AdapterRoles> {
+        // Roles is only provided by BlueZ 5.56 and later.
+        let roles: Vec<String> = self
+            .get_property(adapter.to_owned(), ADAPTER_INTERFACE, "Roles")
+            .await?
+            .unwrap_or_default();
+        Ok(roles
+            .iter()
+            .map(|role| match role.as_str() {
+                "central" => AdapterRoles::CENTRAL,
+                "peripheral" => AdapterRoles::PERIPHERAL,
+                "central-peripheral" => AdapterRoles::CENTRAL_PERIPHERAL,
+                _ => AdapterRoles::empty(),
+            })
+            .collect())
+    }
+
+    /// Checks that the adapter can take the given role, either [`AdapterRoles::CENTRAL`] to
+    /// connect to a device or [`AdapterRoles::PERIPHERAL`] to advertise or host GATT services,
+    /// alongside whatever it is already doing in the other role.
+    pub(crate) async fn check_role(&self, adapter: &AdapterId, role: AdapterRoles) -> Result<()> {
+        let other_role_active = if role == AdapterRoles::PERIPHERAL {
+            self.central_connections
+                .iter()
+                .any(|device| device.adapter() == *adapter)
+        } else {
+            self.advertisements.lock().unwrap().contains_key(adapter)
+                || self.gatt_applications.lock().unwrap().contains_key(adapter)
+        };
+        // Every adapter can be a central, so only look up its roles if there might be a conflict.
+        if role == AdapterRoles::CENTRAL && !other_role_active {
+            return Ok(());
+        }
+        let roles = self.adapter_roles(adapter).await?;
+        match role_conflict(roles, role, other_role_active) {
+            Some(message) => Err(Error::NotSupported(message.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn connected_since(&self, device: &DeviceId) -> Option<SystemTime> {
+        self.connected_since.get(device).map(|since| *since)
+    }
+
+    pub(crate) fn peripheral_tags(&self) -> &PeripheralTags {
+        &self.tags
+    }
+
+    pub(crate) fn activity_log(&self, device: &DeviceId) -> Arc<ActivityLog> {
+        self.activity.entry(device.to_owned()).or_default().clone()
+    }
+
+    fn device_batch(&self, device: &DeviceId) -> Arc<RwLock<()>> {
+        self.device_batches
+            .entry(device.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    fn in_batch(device: &DeviceId) -> bool {
+        BATCH_DEVICE
+            .try_with(|batch_device| batch_device == device)
+            .unwrap_or(false)
+    }
+
+    /// Runs a batch of operations on the given device, which operations from other tasks wait
+    /// for.
+    pub(crate) async fn run_batch<T>(
+        &self,
+        device: &DeviceId,
+        batch: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if Self::in_batch(device) {
+            return batch.await;
+        }
+        let _batch = self.device_batch(device).write_owned().await;
+        BATCH_DEVICE.scope(device.to_owned(), batch).await
+    }
+
+    /// Waits until no other task is running a batch on the given device and the limits on
+    /// outstanding operations allow another on it. The operation counts against them until the
+    /// permits are dropped.
+    pub(crate) async fn acquire_operation(
+        &self,
+        device: &DeviceId,
+    ) -> (Option<OwnedRwLockReadGuard<()>>, Permit, Permit) {
+        let batch = if Self::in_batch(device) {
+            None
+        } else {
+            Some(self.device_batch(device).read_owned().await)
+        };
+        let device_operations = self
+            .device_operations
+            .entry(device.to_owned())
+            .or_default()
+            .clone();
+        // Take the per-device permit first, so that operations queued on one busy device don't
+        // hold up others.
+        let device_permit = device_operations
+            .acquire(max_concurrent_operations_per_peripheral())
+            .await;
+        let permit = self.operations.acquire(max_concurrent_operations()).await;
+        (batch, device_permit, permit)
+    }
+
+    pub(crate) fn record_write_latency(
+        &self,
+        device: &DeviceId,
+        characteristic: &Characteristic,
+        latency: Duration,
+    ) {
+        self.write_latencies
+            .entry((
+                device.to_owned(),
+                characteristic.service_uuid,
+                characteristic.uuid,
+            ))
+            .or_default()
+            .record(latency);
+    }
+
+    pub(crate) fn write_latency(
+        &self,
+        device: &DeviceId,
+        characteristic: &Characteristic,
+    ) -> Option<LatencyHistogram> {
+        self.write_latencies
+            .get(&(
+                device.to_owned(),
+                characteristic.service_uuid,
+                characteristic.uuid,
+            ))
+            .map(|histogram| histogram.clone())
+    }
+
+    /// Returns a stream of the object paths of devices which BlueZ removes from now on.
+    pub(crate) async fn removed_devices(&self) -> Result<impl Stream<Item = String>> {
+        let rule = MatchRule::new_signal(OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")
+            .with_sender(BLUEZ_SERVICE);
+        let (signal, messages) = self.connection.add_match(rule).await?.msg_stream();
+        Ok(messages.filter_map(move |message| {
+            // Messages are only delivered while the signal is alive, so keep it with the stream.
+            let _ = &signal;
+            let removed = match message.read2::<Path, Vec<String>>() {
+                Ok((path, interfaces)) if interfaces.iter().any(|i| i == DEVICE_INTERFACE) => {
+                    Some(path.to_string())
+                }
+                _ => None,
+            };
+            ready(removed)
+        }))
+    }
+
+    /// Returns a stream of the object paths of devices whose battery level BlueZ reports a change
+    /// of from now on, along with the new level.
+    pub(crate) async fn battery_changes(&self) -> Result<impl Stream<Item = (String, u8)>> {
+        let rule = MatchRule::new_signal(PROPERTIES_INTERFACE, "PropertiesChanged")
+            .with_sender(BLUEZ_SERVICE)
+            .with_namespaced_path("/org/bluez");
+        let (signal, messages) = self.connection.add_match(rule).await?.msg_stream();
+        Ok(messages.filter_map(move |message| {
+            // Messages are only delivered while the signal is alive, so keep it with the stream.
+            let _ = &signal;
+            let changed = match (message.path(), message.read2::<String, PropMap>()) {
+                (Some(path), Ok((interface, changed))) if interface == BATTERY_INTERFACE => changed
+                    .get("Percentage")
+                    .and_then(|percentage| percentage.0.as_u64())
+                    .map(|percentage| (path.to_string(), percentage as u8)),
+                _ => None,
+            };
+            ready(changed)
+        }))
+    }
+
+    /// Returns a stream of adapters being added and removed, by object path.
+    pub(crate) async fn adapter_changes(&self) -> Result<impl Stream<Item = AdapterChange>> {
+        let added_rule = MatchRule::new_signal(OBJECT_MANAGER_INTERFACE, "InterfacesAdded")
+            .with_sender(BLUEZ_SERVICE);
+        let removed_rule = MatchRule::new_signal(OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")
+            .with_sender(BLUEZ_SERVICE);
+        let (added_signal, added) = self.connection.add_match(added_rule).await?.msg_stream();
+        let (removed_signal, removed) = self.connection.add_match(removed_rule).await?.msg_stream();
+        let added = added.filter_map(move |message| {
+            // Messages are only delivered while the signals are alive, so keep them with the
+            // streams.
+            let _ = &added_signal;
+            let added = match message.read2::<Path, HashMap<String, PropMap>>() {
+                Ok((path, interfaces)) if interfaces.contains_key(ADAPTER_INTERFACE) => {
+                    Some(AdapterChange::Added(path.to_string()))
+                }
+                _ => None,
+            };
+            ready(added)
+        });
+        let removed = removed.filter_map(move |message| {
+            let _ = &removed_signal;
+            let removed = match message.read2::<Path, Vec<String>>() {
+                Ok((path, interfaces)) if interfaces.iter().any(|i| i == ADAPTER_INTERFACE) => {
+                    Some(AdapterChange::Removed(path.to_string()))
+                }
+                _ => None,
+            };
+            ready(removed)
+        });
+        Ok(futures::stream::select(added, removed))
+    }
+
+    pub(crate) fn emit(&self, event: CentralEvent) {
+        // There may be no event streams to receive this.
+        let _ = self.events.send(event);
+    }
+
+    /// Returns a stream of the events passed to [`emit`](Self::emit) from now on.
+    pub(crate) fn emitted_events(&self) -> impl Stream<Item = CentralEvent> {
+        BroadcastStream::new(self.events.subscribe()).filter_map(|event| ready(event.ok()))
+    }
+
+    /// Waits until BlueZ knows the name of the device, which it reads from the GAP service by
+    /// itself after connecting.
+    pub(crate) async fn wait_for_name(&self, device: &DeviceId) -> Result<String> {
+        let path = Path::from(device.to_owned());
+        let rule = MatchRule::new_signal(PROPERTIES_INTERFACE, "PropertiesChanged")
+            .with_sender(BLUEZ_SERVICE)
+            .with_path(path.clone());
+        // Subscribe before checking, so that a change in between isn't missed.
+        let (_signal, mut messages) = self.connection.add_match(rule).await?.msg_stream();
+        if let Some(name) = self
+            .get_property::<String>(path, DEVICE_INTERFACE, "Name")
+            .await?
+        {
+            return Ok(name);
+        }
+        while let Some(message) = messages.next().await {
+            let name = match message.read2::<String, PropMap>() {
+                Ok((interface, changed)) if interface == DEVICE_INTERFACE => changed
+                    .get("Name")
+                    .and_then(|name| name.0.as_str().map(str::to_owned)),
+                _ => None,
+            };
+            if let Some(name) = name {
+                return Ok(name);
+            }
+        }
+        Err(Error::RuntimeError("Lost connection to D-Bus".to_string()))
+    }
+
+    pub(crate) fn proxy<'a>(&self, path: impl Into<Path<'a>>) -> Proxy<'a, Arc<SyncConnection>> {
+        Proxy::new(
+            BLUEZ_SERVICE,
+            path,
+            DBUS_METHOD_CALL_TIMEOUT,
+            self.connection.clone(),
+        )
+    }
+
+    /// Gets a property of the given object, returning `None` if BlueZ doesn't provide it, which is
+    /// the case for optional and experimental properties.
+    pub(crate) async fn get_property<T>(
+        &self,
+        path: impl Into<Path<'static>>,
+        interface: &str,
+        name: &str,
+    ) -> Result<Option<T>>
+    where
+        T: for<'b> Get<'b> + Arg + 'static,
+    {
+        match self.proxy(path).get(interface, name).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.name() == Some(INVALID_ARGS_ERROR) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn set_property<T>(
+        &self,
+        path: impl Into<Path<'static>>,
+        interface: &str,
+        name: &str,
+        value: T,
+    ) -> Result<()>
+    where
+        T: Arg + Append,
+    {
+        let () = self.proxy(path).set(interface, name, value).await?;
+        Ok(())
+    }
+}
+
+/// Returns why an adapter with the given roles can't take `role`, if it can't. Adapters which don't
+/// report their roles are assumed to support both at once.
+fn role_conflict(
+    roles: AdapterRoles,
+    role: AdapterRoles,
+    other_role_active: bool,
+) -> Option<&'static str> {
+    if roles.is_empty() {
+        None
+    } else if !roles.contains(role) {
+        Some(if role == AdapterRoles::PERIPHERAL {
+            "The adapter doesn't support the peripheral role"
+        } else {
+            "The adapter doesn't support the central role"
+        })
+    } else if other_role_active && !roles.contains(AdapterRoles::CENTRAL_PERIPHERAL) {
+        Some("The adapter can't be in the central and peripheral roles at the same time")
+    } else {
+        None
+    }
+}
+
+/// Returns the advertising flags among a device's properties, if there are any.
+fn advertising_flags(properties: &PropMap) -> Option<AdvertisingFlags> {
+    prop_cast::<Vec<u8>>(properties, "AdvertisingFlags")
+        .and_then(|flags| flags.first().copied())
+        .map(AdvertisingFlags::from_bits_truncate)
+}
+
+fn advertisement_path(adapter: &AdapterId) -> Path<'static> {
+    Path::from(format!("/org/btleplug/advertisement/{}", adapter))
+}
+
+/// Maps the D-Bus errors which have a more specific btleplug error.
+///
+/// BlueZ refuses to write to or subscribe to a characteristic with `NotPermitted` and a message
+/// such as "Write acquired" or "Notify acquired" while another client holds a file descriptor for
+/// it from `AcquireWrite` or `AcquireNotify`. Operations which need the radio fail with `NotReady`
+/// while the adapter is powered off, and powering it on fails with `Blocked` while rfkill blocks
+/// it. Older versions fail with a generic `Failed` instead, which the caller has to tell apart by
+/// checking the adapter's power state.
+pub(crate) fn specific_error(error: &dbus::Error) -> Option<Error> {
+    match (error.name(), error.message()) {
+        (Some(NOT_PERMITTED_ERROR), Some(message)) if message.ends_with("acquired") => {
+            Some(Error::InUse(message.to_owned()))
+        }
+        (Some(NOT_READY_ERROR), _) => Some(Error::AdapterPoweredOff),
+        (Some(BLOCKED_ERROR), _) => Some(Error::AdapterBlocked),
+        _ => None,
+    }
+}
+
+/// Returns true if the error is D-Bus's for a method call to an object which doesn't exist, which
+/// for an operation on a device may mean that BlueZ has removed it.
+fn is_unknown_object(error: &Error) -> bool {
+    let Error::Other(error) = error else {
+        return false;
+    };
+    let dbus_error = error.downcast_ref::<dbus::Error>().or_else(|| {
+        match error.downcast_ref::<bluez_async::BluetoothError>() {
+            Some(bluez_async::BluetoothError::DbusError(e)) => Some(e),
+            _ => None,
+        }
+    });
+    dbus_error.is_some_and(|e| e.name() == Some(UNKNOWN_OBJECT_ERROR))
+}
+
+/// The object paths of devices which BlueZ has removed, until it adds them again, and the
+/// operations waiting to hear of their removal.
+#[derive(Debug)]
+struct LostDevices {
+    lost: DashSet<String>,
+    removed: broadcast::Sender<String>,
+}
+
+impl Default for LostDevices {
+    fn default() -> Self {
+        Self {
+            lost: DashSet::new(),
+            removed: broadcast::channel(16).0,
+        }
+    }
+}
+
+impl LostDevices {
+    fn added(&self, path: &str) {
+        self.lost.remove(path);
+    }
+
+    fn removed(&self, path: String) {
+        self.lost.insert(path.clone());
+        // There may be no pending operations to receive this.
+        let _ = self.removed.send(path);
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.lost.contains(path)
+    }
+
+    /// Runs the operation unless the device with the given path is lost, failing it if the device
+    /// is lost before it finishes.
+    async fn unless_lost<T>(
+        &self,
+        path: String,
+        operation: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let mut removed = self.removed.subscribe();
+        if self.contains(&path) {
+            return Err(Error::DeviceLost);
+        }
+        let lost = Box::pin(async move {
+            loop {
+                match removed.recv().await {
+                    Ok(removed_path) if removed_path == path => return,
+                    // Removals may have been missed, so check whether this device's was.
+                    Err(broadcast::error::RecvError::Lagged(_)) if self.contains(&path) => return,
+                    Err(broadcast::error::RecvError::Closed) => future::pending().await,
+                    _ => {}
+                }
+            }
+        });
+        match future::select(Box::pin(operation), lost).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::DeviceLost),
+        }
+    }
+}
+
+impl From<dbus::Error> for Error {
+    fn from(error: dbus::Error) -> Self {
+        specific_error(&error).unwrap_or_else(|| Error::Other(Box::new(error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55";
+
+    #[test]
+    fn role_conflicts() {
+        let peripheral = AdapterRoles::PERIPHERAL;
+        let both = AdapterRoles::CENTRAL | AdapterRoles::PERIPHERAL;
+        assert_eq!(role_conflict(AdapterRoles::empty(), peripheral, true), None);
+        assert!(role_conflict(AdapterRoles::CENTRAL, peripheral, false).is_some());
+        assert_eq!(role_conflict(both, peripheral, false), None);
+        assert!(role_conflict(both, peripheral, true).is_some());
+        assert!(role_conflict(both, AdapterRoles::CENTRAL, true).is_some());
+        assert_eq!(
+            role_conflict(
+                both | AdapterRoles::CENTRAL_PERIPHERAL,
+                AdapterRoles::CENTRAL,
+                true
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn lost_until_added_again() {
+        let lost = LostDevices::default();
+        assert!(lost
+            .unless_lost(PATH.to_owned(), async { Ok(()) })
+            .await
+            .is_ok());
+        lost.removed(PATH.to_owned());
+        assert!(matches!(
+            lost.unless_lost(PATH.to_owned(), async { Ok(()) }).await,
+            Err(Error::DeviceLost)
+        ));
+        lost.added(PATH);
+        assert!(lost
+            .unless_lost(PATH.to_owned(), async { Ok(()) })
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn lost_during_operation() {
+        let lost = LostDevices::default();
+        let operation = lost.unless_lost(PATH.to_owned(), future::pending::<Result<()>>());
+        let remove = async {
+            // Let the operation start waiting first.
+            tokio::task::yield_now().await;
+            lost.removed("/org/bluez/hci0/dev_66_77_88_99_AA_BB".to_owned());
+            lost.removed(PATH.to_owned());
+        };
+        let (result, ()) = futures::join!(operation, remove);
+        assert!(matches!(result, Err(Error::DeviceLost)));
+    }
+
+    #[test]
+    fn advertising_flags_property() {
+        let mut properties = PropMap::new();
+        assert_eq!(advertising_flags(&properties), None);
+        properties.insert(
+            "AdvertisingFlags".to_owned(),
+            Variant(Box::new(vec![0x06u8])),
+        );
+        assert_eq!(
+            advertising_flags(&properties),
+            Some(
+                AdvertisingFlags::LE_GENERAL_DISCOVERABLE | AdvertisingFlags::BR_EDR_NOT_SUPPORTED
+            )
+        );
+    }
+
+    #[test]
+    fn blocked_by_error_name() {
+        let blocked = dbus::Error::new_custom(BLOCKED_ERROR, "Blocked through rfkill");
+        assert!(matches!(
+            specific_error(&blocked),
+            Some(Error::AdapterBlocked)
+        ));
+        let failed = dbus::Error::new_custom("org.bluez.Error.Failed", "Blocked through rfkill");
+        assert!(specific_error(&failed).is_none());
+    }
+
+    #[test]
+    fn unknown_object() {
+        let error = |name| {
+            Error::from(bluez_async::BluetoothError::DbusError(
+                dbus::Error::new_custom(name, ""),
+            ))
+        };
+        assert!(is_unknown_object(&error(UNKNOWN_OBJECT_ERROR)));
+        assert!(!is_unknown_object(&error(INVALID_ARGS_ERROR)));
+        assert!(!is_unknown_object(&Error::DeviceLost));
+    }
+}
@@ -0,0 +1,716 @@
+//! A GATT application of [`LocalService`]s, exported as the object tree BlueZ's `GattManager1`
+//! expects: an `ObjectManager` at the root, with `GattService1` and `GattCharacteristic1`
+//! objects beneath it.
+
+use super::connection::{
+    variant, CHARACTERISTIC_INTERFACE, PROPERTIES_INTERFACE, SERVICE_INTERFACE,
+    UNKNOWN_METHOD_ERROR, UNKNOWN_OBJECT_ERROR, UNKNOWN_PROPERTY_ERROR,
+};
+use crate::api::gatt_server::{LocalCharacteristic, LocalService, ServerEvent};
+use crate::api::{AttError, BDAddr, CharPropFlags};
+use crate::{Error, Result};
+use dbus::arg::{prop_cast, PropMap};
+use dbus::{Message, Path};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// The names BlueZ uses for characteristic properties.
+fn flags(properties: CharPropFlags) -> Vec<String> {
+    [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write-without-response",
+        ),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            "authenticated-signed-writes",
+        ),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| properties.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// Returns the error BlueZ translates back into the given ATT error. BlueZ reads the code of any
+/// other error from the message of a `Failed` error.
+fn att_error(message: &Message, error: AttError) -> Message {
+    let name = match error {
+        AttError::READ_NOT_PERMITTED | AttError::WRITE_NOT_PERMITTED => {
+            "org.bluez.Error.NotPermitted"
+        }
+        AttError::INSUFFICIENT_AUTHORIZATION => "org.bluez.Error.NotAuthorized",
+        AttError::INVALID_OFFSET => "org.bluez.Error.InvalidOffset",
+        AttError::INVALID_ATTRIBUTE_VALUE_LENGTH => "org.bluez.Error.InvalidValueLength",
+        AttError::REQUEST_NOT_SUPPORTED => "org.bluez.Error.NotSupported",
+        _ => "org.bluez.Error.Failed",
+    };
+    message.error(
+        &name.into(),
+        &CString::new(format!("{:#04x}", error.0)).unwrap(),
+    )
+}
+
+fn error(message: &Message, name: &str, text: &str) -> Message {
+    message.error(&name.into(), &CString::new(text).unwrap())
+}
+
+/// Which object of the application a method call was made on.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Object {
+    Root,
+    Service(usize),
+    Characteristic(usize, usize),
+}
+
+/// The central making a read or write, as far as BlueZ says.
+struct Requester {
+    /// The object path of the central's `Device1` object.
+    device: String,
+    mtu: Option<u16>,
+}
+
+/// A method call to a characteristic, parsed out of the message so that it isn't borrowed while
+/// waiting for the handler.
+enum CharacteristicCall {
+    Read {
+        offset: usize,
+        requester: Option<Requester>,
+    },
+    Write {
+        value: Vec<u8>,
+        offset: usize,
+        requester: Option<Requester>,
+    },
+    StartNotify,
+    StopNotify,
+}
+
+fn offset(options: &PropMap) -> usize {
+    prop_cast::<u16>(options, "offset").map_or(0, |offset| usize::from(*offset))
+}
+
+fn requester(options: &PropMap) -> Option<Requester> {
+    Some(Requester {
+        device: prop_cast::<Path>(options, "device")?.to_string(),
+        mtu: prop_cast::<u16>(options, "mtu").copied(),
+    })
+}
+
+/// Returns the address of the device with the given object path, which BlueZ names after it.
+fn device_address(path: &str) -> Option<BDAddr> {
+    path.rsplit('/')
+        .next()?
+        .strip_prefix("dev_")?
+        .replace('_', ":")
+        .parse()
+        .ok()
+}
+
+fn parse_characteristic_call(message: &Message) -> Option<CharacteristicCall> {
+    Some(match &*message.member()? {
+        "ReadValue" => {
+            let options: PropMap = message.read1().ok()?;
+            CharacteristicCall::Read {
+                offset: offset(&options),
+                requester: requester(&options),
+            }
+        }
+        "WriteValue" => {
+            let (value, options): (Vec<u8>, PropMap) = message.read2().ok()?;
+            CharacteristicCall::Write {
+                value,
+                offset: offset(&options),
+                requester: requester(&options),
+            }
+        }
+        "StartNotify" => CharacteristicCall::StartNotify,
+        "StopNotify" => CharacteristicCall::StopNotify,
+        _ => return None,
+    })
+}
+
+/// The services registered on one adapter, which of their characteristics are notifying, and
+/// which centrals have used them.
+#[derive(Debug)]
+pub(crate) struct GattApplication {
+    root: Path<'static>,
+    services: Vec<LocalService>,
+    notifying: Mutex<HashSet<(usize, usize)>>,
+    /// The MTU last reported for each central, keyed by the object path of its device.
+    centrals: Mutex<HashMap<String, Option<u16>>>,
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl GattApplication {
+    pub(crate) fn new(
+        root: Path<'static>,
+        services: Vec<LocalService>,
+        events: broadcast::Sender<ServerEvent>,
+    ) -> Self {
+        Self {
+            root,
+            services,
+            notifying: Mutex::default(),
+            centrals: Mutex::default(),
+            events,
+        }
+    }
+
+    pub(crate) fn root(&self) -> &Path<'static> {
+        &self.root
+    }
+
+    fn service_path(&self, service: usize) -> Path<'static> {
+        Path::from(format!("{}/service{}", self.root, service))
+    }
+
+    fn characteristic_path(&self, service: usize, characteristic: usize) -> Path<'static> {
+        Path::from(format!(
+            "{}/service{}/char{}",
+            self.root, service, characteristic
+        ))
+    }
+
+    fn object(&self, path: &str) -> Option<Object> {
+        if path == &*self.root {
+            return Some(Object::Root);
+        }
+        let mut parts = path
+            .strip_prefix(&*self.root)?
+            .strip_prefix("/service")?
+            .split("/char");
+        let service = parts.next()?.parse().ok()?;
+        let service_info = self.services.get(service)?;
+        match parts.next() {
+            None => Some(Object::Service(service)),
+            Some(characteristic) => {
+                let characteristic = characteristic.parse().ok()?;
+                service_info.characteristics.get(characteristic)?;
+                parts
+                    .next()
+                    .is_none()
+                    .then_some(Object::Characteristic(service, characteristic))
+            }
+        }
+    }
+
+    fn characteristic(&self, service: usize, characteristic: usize) -> &LocalCharacteristic {
+        &self.services[service].characteristics[characteristic]
+    }
+
+    fn emit(&self, event: ServerEvent) {
+        // Nobody may be listening.
+        let _ = self.events.send(event);
+    }
+
+    /// Reports a central the first time it makes a request, and whenever its MTU changes.
+    fn central_seen(&self, requester: &Requester) {
+        let Some(central) = device_address(&requester.device) else {
+            return;
+        };
+        let mut centrals = self.centrals.lock().unwrap();
+        let previous = centrals.insert(requester.device.clone(), requester.mtu);
+        drop(centrals);
+        if previous.is_none() {
+            self.emit(ServerEvent::CentralConnected(central));
+        }
+        match (previous.flatten(), requester.mtu) {
+            (previous, Some(mtu)) if previous != Some(mtu) => {
+                self.emit(ServerEvent::MtuChanged { central, mtu })
+            }
+            _ => {}
+        }
+    }
+
+    /// Reports that the device with the given object path disconnected, if it had used the
+    /// application.
+    pub(crate) fn device_disconnected(&self, device: &str) {
+        let removed = self.centrals.lock().unwrap().remove(device).is_some();
+        if let (true, Some(central)) = (removed, device_address(device)) {
+            self.emit(ServerEvent::CentralDisconnected(central));
+        }
+    }
+
+    fn subscription_event(
+        &self,
+        service: usize,
+        characteristic: usize,
+        added: bool,
+    ) -> ServerEvent {
+        let service_uuid = self.services[service].uuid;
+        let characteristic_uuid = self.characteristic(service, characteristic).uuid;
+        if added {
+            ServerEvent::SubscriptionAdded {
+                central: None,
+                service: service_uuid,
+                characteristic: characteristic_uuid,
+            }
+        } else {
+            ServerEvent::SubscriptionRemoved {
+                central: None,
+                service: service_uuid,
+                characteristic: characteristic_uuid,
+            }
+        }
+    }
+
+    /// Returns the interfaces and properties of an object.
+    fn interfaces(&self, object: Object) -> HashMap<String, PropMap> {
+        let mut properties = PropMap::new();
+        let interface = match object {
+            Object::Root => return HashMap::new(),
+            Object::Service(service) => {
+                let info = &self.services[service];
+                properties.insert("UUID".to_string(), variant(info.uuid.to_string()));
+                properties.insert("Primary".to_string(), variant(info.primary));
+                SERVICE_INTERFACE
+            }
+            Object::Characteristic(service, characteristic) => {
+                let info = self.characteristic(service, characteristic);
+                properties.insert("UUID".to_string(), variant(info.uuid.to_string()));
+                properties.insert("Service".to_string(), variant(self.service_path(service)));
+                properties.insert("Flags".to_string(), variant(flags(info.properties)));
+                let notifying = self
+                    .notifying
+                    .lock()
+                    .unwrap()
+                    .contains(&(service, characteristic));
+                properties.insert("Notifying".to_string(), variant(notifying));
+                CHARACTERISTIC_INTERFACE
+            }
+        };
+        HashMap::from([(interface.to_string(), properties)])
+    }
+
+    fn managed_objects(&self) -> HashMap<Path<'static>, HashMap<String, PropMap>> {
+        let mut objects = HashMap::new();
+        for (service, info) in self.services.iter().enumerate() {
+            objects.insert(
+                self.service_path(service),
+                self.interfaces(Object::Service(service)),
+            );
+            for characteristic in 0..info.characteristics.len() {
+                objects.insert(
+                    self.characteristic_path(service, characteristic),
+                    self.interfaces(Object::Characteristic(service, characteristic)),
+                );
+            }
+        }
+        objects
+    }
+
+    fn handle_properties_call(&self, object: Object, message: &Message) -> Message {
+        let mut interfaces = self.interfaces(object);
+        match message.member().as_deref() {
+            Some("GetAll") => {
+                let properties = message
+                    .read1::<&str>()
+                    .ok()
+                    .and_then(|interface| interfaces.remove(interface))
+                    .unwrap_or_default();
+                message.method_return().append1(properties)
+            }
+            Some("Get") => {
+                let property = message
+                    .read2::<&str, &str>()
+                    .ok()
+                    .and_then(|(interface, name)| interfaces.remove(interface)?.remove(name));
+                match property {
+                    Some(value) => message.method_return().append1(value),
+                    None => error(message, UNKNOWN_PROPERTY_ERROR, "No such property"),
+                }
+            }
+            _ => error(message, UNKNOWN_METHOD_ERROR, "No such method"),
+        }
+    }
+
+    async fn handle_characteristic_call(
+        &self,
+        service: usize,
+        characteristic: usize,
+        message: Message,
+    ) -> Message {
+        let info = self.characteristic(service, characteristic);
+        let handler = info.handler.clone();
+        let call = parse_characteristic_call(&message);
+        let requester = match &call {
+            Some(CharacteristicCall::Read { requester, .. })
+            | Some(CharacteristicCall::Write { requester, .. }) => requester.as_ref(),
+            _ => None,
+        };
+        if let Some(requester) = requester {
+            self.central_seen(requester);
+        }
+        match call {
+            Some(CharacteristicCall::Read { offset, .. }) => match handler.read(offset).await {
+                Ok(value) => message.method_return().append1(value),
+                Err(e) => att_error(&message, e),
+            },
+            Some(CharacteristicCall::Write { value, offset, .. }) => {
+                if let Err(e) = info.check_write(&value, offset) {
+                    return att_error(&message, e);
+                }
+                match handler.write(value, offset).await {
+                    Ok(()) => message.method_return(),
+                    Err(e) => att_error(&message, e),
+                }
+            }
+            Some(CharacteristicCall::StartNotify) => {
+                let started = self
+                    .notifying
+                    .lock()
+                    .unwrap()
+                    .insert((service, characteristic));
+                if started {
+                    self.emit(self.subscription_event(service, characteristic, true));
+                    handler.notifying_changed(true).await;
+                }
+                message.method_return()
+            }
+            Some(CharacteristicCall::StopNotify) => {
+                let stopped = self
+                    .notifying
+                    .lock()
+                    .unwrap()
+                    .remove(&(service, characteristic));
+                if stopped {
+                    self.emit(self.subscription_event(service, characteristic, false));
+                    handler.notifying_changed(false).await;
+                }
+                message.method_return()
+            }
+            None => error(&message, UNKNOWN_METHOD_ERROR, "No such method"),
+        }
+    }
+
+    /// Answers a method call from BlueZ to one of the application's objects.
+    pub(crate) async fn handle_call(&self, message: Message) -> Message {
+        let object = message.path().and_then(|path| self.object(&path));
+        let is_properties_call = message.interface().as_deref() == Some(PROPERTIES_INTERFACE);
+        let is_get_managed_objects = message.member().as_deref() == Some("GetManagedObjects");
+        match object {
+            None => error(&message, UNKNOWN_OBJECT_ERROR, "No such object"),
+            Some(object) if is_properties_call => self.handle_properties_call(object, &message),
+            Some(Object::Root) if is_get_managed_objects => {
+                message.method_return().append1(self.managed_objects())
+            }
+            Some(Object::Characteristic(service, characteristic)) => {
+                self.handle_characteristic_call(service, characteristic, message)
+                    .await
+            }
+            _ => error(&message, UNKNOWN_METHOD_ERROR, "No such method"),
+        }
+    }
+
+    /// Returns the signal which sends a new value of a characteristic to the centrals subscribed
+    /// to it, or `None` if none are.
+    pub(crate) fn notification(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        value: &[u8],
+    ) -> Result<Option<Message>> {
+        let (service, characteristic) = self
+            .services
+            .iter()
+            .enumerate()
+            .filter(|(_, service)| service.uuid == service_uuid)
+            .find_map(|(service, info)| {
+                info.characteristics
+                    .iter()
+                    .position(|characteristic| characteristic.uuid == characteristic_uuid)
+                    .map(|characteristic| (service, characteristic))
+            })
+            .ok_or(Error::NoSuchCharacteristic)?;
+        if !self
+            .notifying
+            .lock()
+            .unwrap()
+            .contains(&(service, characteristic))
+        {
+            return Ok(None);
+        }
+        let mut changed = PropMap::new();
+        changed.insert("Value".to_string(), variant(value.to_vec()));
+        let signal = Message::signal(
+            &self.characteristic_path(service, characteristic),
+            &PROPERTIES_INTERFACE.into(),
+            &"PropertiesChanged".into(),
+        )
+        .append3(CHARACTERISTIC_INTERFACE, changed, Vec::<String>::new());
+        Ok(Some(signal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::gatt_server::CharacteristicHandler;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+    const OTHER_SERVICE: Uuid = Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb);
+    const LEVEL: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+    const STATUS: Uuid = Uuid::from_u128(0x00002bed_0000_1000_8000_00805f9b34fb);
+
+    #[derive(Debug)]
+    struct Refuse;
+
+    impl CharacteristicHandler for Refuse {}
+
+    /// An application with a service of two characteristics, and one with none.
+    fn application() -> GattApplication {
+        let services = vec![
+            LocalService::new(SERVICE)
+                .with_characteristic(LocalCharacteristic::new(
+                    STATUS,
+                    CharPropFlags::READ,
+                    Refuse,
+                ))
+                .with_characteristic(LocalCharacteristic::new(
+                    LEVEL,
+                    CharPropFlags::READ | CharPropFlags::NOTIFY,
+                    Refuse,
+                )),
+            LocalService::new(OTHER_SERVICE),
+        ];
+        GattApplication::new(
+            Path::from("/org/btleplug/app0"),
+            services,
+            broadcast::channel(16).0,
+        )
+    }
+
+    fn method_call(path: &str, interface: &str, member: &str) -> Message {
+        let mut message =
+            Message::new_method_call("org.btleplug", path, interface, member).unwrap();
+        message.set_serial(1);
+        message
+    }
+
+    fn error_of(mut reply: Message) -> (String, String) {
+        let error = reply.as_result().unwrap_err();
+        (
+            error.name().unwrap().to_string(),
+            error.message().unwrap().to_string(),
+        )
+    }
+
+    #[test]
+    fn object() {
+        let app = application();
+        assert_eq!(app.object("/org/btleplug/app0"), Some(Object::Root));
+        assert_eq!(
+            app.object("/org/btleplug/app0/service0"),
+            Some(Object::Service(0))
+        );
+        assert_eq!(
+            app.object("/org/btleplug/app0/service1"),
+            Some(Object::Service(1))
+        );
+        assert_eq!(
+            app.object("/org/btleplug/app0/service0/char1"),
+            Some(Object::Characteristic(0, 1))
+        );
+        for path in [
+            "/org/btleplug",
+            "/org/btleplug/app01/service0",
+            "/org/btleplug/app0/service2",
+            "/org/btleplug/app0/service1/char0",
+            "/org/btleplug/app0/service0/char2",
+            "/org/btleplug/app0/service0/char0/char0",
+            "/org/btleplug/app0/service0/char0/desc0",
+            "/org/btleplug/app0/service",
+            "/org/btleplug/app0/servicex",
+            "/org/btleplug/app0/service0/charx",
+        ] {
+            assert_eq!(app.object(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn flag_names() {
+        assert!(flags(CharPropFlags::empty()).is_empty());
+        assert_eq!(
+            flags(
+                CharPropFlags::WRITE_WITHOUT_RESPONSE
+                    | CharPropFlags::NOTIFY
+                    | CharPropFlags::EXTENDED_PROPERTIES
+            ),
+            ["write-without-response", "notify", "extended-properties"]
+        );
+        assert_eq!(flags(CharPropFlags::all()).len(), 8);
+    }
+
+    #[test]
+    fn att_errors() {
+        let call = method_call(
+            "/org/btleplug/app0/service0/char0",
+            CHARACTERISTIC_INTERFACE,
+            "ReadValue",
+        );
+        let error = |error| error_of(att_error(&call, error));
+        assert_eq!(
+            error(AttError::READ_NOT_PERMITTED).0,
+            "org.bluez.Error.NotPermitted"
+        );
+        assert_eq!(
+            error(AttError::INVALID_OFFSET).0,
+            "org.bluez.Error.InvalidOffset"
+        );
+        // BlueZ parses the code of other errors, including application errors, from the message.
+        for code in [0x0e, 0x80, 0x9f] {
+            assert_eq!(
+                error(AttError(code)),
+                (
+                    "org.bluez.Error.Failed".to_string(),
+                    format!("0x{:02x}", code)
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn notification() {
+        let app = application();
+        // Nothing is subscribed yet.
+        assert!(matches!(app.notification(SERVICE, LEVEL, &[]), Ok(None)));
+        assert!(matches!(
+            app.notification(OTHER_SERVICE, LEVEL, &[]),
+            Err(Error::NoSuchCharacteristic)
+        ));
+
+        let mut reply = app
+            .handle_call(method_call(
+                "/org/btleplug/app0/service0/char1",
+                CHARACTERISTIC_INTERFACE,
+                "StartNotify",
+            ))
+            .await;
+        assert!(reply.as_result().is_ok());
+        let signal = app.notification(SERVICE, LEVEL, &[42]).unwrap().unwrap();
+        assert_eq!(
+            signal.path().unwrap().to_string(),
+            "/org/btleplug/app0/service0/char1"
+        );
+        assert_eq!(signal.member().unwrap().to_string(), "PropertiesChanged");
+        let (interface, changed, invalidated): (String, PropMap, Vec<String>) =
+            signal.read3().unwrap();
+        assert_eq!(interface, CHARACTERISTIC_INTERFACE);
+        assert_eq!(prop_cast::<Vec<u8>>(&changed, "Value"), Some(&vec![42]));
+        assert!(invalidated.is_empty());
+    }
+
+    fn read_call(device: &str, mtu: u16) -> Message {
+        let options = PropMap::from([
+            (
+                "device".to_string(),
+                variant(Path::from(device.to_string())),
+            ),
+            ("mtu".to_string(), variant(mtu)),
+        ]);
+        method_call(
+            "/org/btleplug/app0/service0/char0",
+            CHARACTERISTIC_INTERFACE,
+            "ReadValue",
+        )
+        .append1(options)
+    }
+
+    #[tokio::test]
+    async fn write_constraints() {
+        #[derive(Debug)]
+        struct Accept;
+
+        #[async_trait::async_trait]
+        impl CharacteristicHandler for Accept {
+            async fn write(
+                &self,
+                _value: Vec<u8>,
+                _offset: usize,
+            ) -> std::result::Result<(), AttError> {
+                Ok(())
+            }
+        }
+
+        let app = GattApplication::new(
+            Path::from("/org/btleplug/app0"),
+            vec![LocalService::new(SERVICE).with_characteristic(
+                LocalCharacteristic::new(STATUS, CharPropFlags::WRITE, Accept).with_max_length(2),
+            )],
+            broadcast::channel(16).0,
+        );
+        let write = |value: Vec<u8>| {
+            method_call(
+                "/org/btleplug/app0/service0/char0",
+                CHARACTERISTIC_INTERFACE,
+                "WriteValue",
+            )
+            .append2(value, PropMap::new())
+        };
+        let mut reply = app.handle_call(write(vec![1, 2])).await;
+        assert!(reply.as_result().is_ok());
+        assert_eq!(
+            error_of(app.handle_call(write(vec![1, 2, 3])).await).0,
+            "org.bluez.Error.InvalidValueLength"
+        );
+    }
+
+    #[tokio::test]
+    async fn server_events() {
+        const DEVICE: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55";
+        let central = BDAddr::from([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let app = application();
+        let mut events = app.events.subscribe();
+
+        app.handle_call(read_call(DEVICE, 185)).await;
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::CentralConnected(central)
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::MtuChanged { central, mtu: 185 }
+        );
+        // Only changes to the MTU are reported.
+        app.handle_call(read_call(DEVICE, 185)).await;
+        app.handle_call(read_call(DEVICE, 247)).await;
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::MtuChanged { central, mtu: 247 }
+        );
+
+        app.handle_call(method_call(
+            "/org/btleplug/app0/service0/char1",
+            CHARACTERISTIC_INTERFACE,
+            "StartNotify",
+        ))
+        .await;
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::SubscriptionAdded {
+                central: None,
+                service: SERVICE,
+                characteristic: LEVEL
+            }
+        );
+
+        app.device_disconnected("/org/bluez/hci0/dev_66_77_88_99_AA_BB");
+        app.device_disconnected(DEVICE);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::CentralDisconnected(central)
+        );
+        app.device_disconnected(DEVICE);
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! L2CAP connection-oriented channels, which BlueZ doesn't offer over D-Bus, so these use the
+//! kernel's Bluetooth sockets directly.
+
+use crate::api::{AddressType, BDAddr};
+use crate::Error;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const BTPROTO_L2CAP: libc::c_int = 0;
+const BDADDR_LE_PUBLIC: u8 = 1;
+const BDADDR_LE_RANDOM: u8 = 2;
+
+/// `struct sockaddr_l2` from BlueZ's `l2cap.h`.
+#[repr(C)]
+struct SockaddrL2 {
+    l2_family: libc::sa_family_t,
+    l2_psm: u16,
+    /// The address in little-endian order, i.e. reversed from how it is displayed.
+    l2_bdaddr: [u8; 6],
+    l2_cid: u16,
+    l2_bdaddr_type: u8,
+}
+
+impl SockaddrL2 {
+    fn new(address: BDAddr, address_type: AddressType, psm: u16) -> Self {
+        let mut l2_bdaddr = address.into_inner();
+        l2_bdaddr.reverse();
+        Self {
+            l2_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            l2_psm: psm.to_le(),
+            l2_bdaddr,
+            l2_cid: 0,
+            l2_bdaddr_type: match address_type {
+                AddressType::Public => BDADDR_LE_PUBLIC,
+                AddressType::Random => BDADDR_LE_RANDOM,
+            },
+        }
+    }
+}
+
+pub(crate) fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+pub(crate) fn check_size(result: libc::ssize_t) -> io::Result<usize> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as usize)
+    }
+}
+
+pub(crate) fn socket_error(error: io::Error) -> Error {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+        _ => Error::Other(Box::new(error)),
+    }
+}
+
+/// A connected L2CAP socket in `SOCK_SEQPACKET` mode.
+#[derive(Debug)]
+pub(crate) struct L2capSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl L2capSocket {
+    /// Connects from the adapter with the given address to the PSM on the device.
+    pub(crate) async fn connect(
+        adapter: BDAddr,
+        device: BDAddr,
+        device_type: AddressType,
+        psm: u16,
+    ) -> io::Result<Self> {
+        // SAFETY: socket has no memory safety requirements, and the descriptor it returns isn't
+        // owned by anything else.
+        let fd = unsafe {
+            let fd = check(libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                BTPROTO_L2CAP,
+            ))?;
+            OwnedFd::from_raw_fd(fd)
+        };
+        // Bind to the adapter, as otherwise the kernel picks whichever comes first.
+        let local = SockaddrL2::new(adapter, AddressType::Public, 0);
+        let remote = SockaddrL2::new(device, device_type, psm);
+        // SAFETY: The addresses are valid `sockaddr_l2`s for the lengths given.
+        unsafe {
+            check(libc::bind(
+                fd.as_raw_fd(),
+                &local as *const SockaddrL2 as *const libc::sockaddr,
+                mem::size_of::<SockaddrL2>() as libc::socklen_t,
+            ))?;
+            let connected = check(libc::connect(
+                fd.as_raw_fd(),
+                &remote as *const SockaddrL2 as *const libc::sockaddr,
+                mem::size_of::<SockaddrL2>() as libc::socklen_t,
+            ));
+            match connected {
+                Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+                connected => {
+                    connected?;
+                }
+            }
+        }
+        let fd = AsyncFd::new(fd)?;
+        // The socket becomes writable once the connection is established or has failed.
+        fd.writable().await?.retain_ready();
+        let mut error: libc::c_int = 0;
+        let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `error` and `length` are valid for writing an int.
+        check(unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ERROR,
+                &mut error as *mut libc::c_int as *mut libc::c_void,
+                &mut length,
+            )
+        })?;
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+        Ok(Self { fd })
+    }
+}
+
+impl AsyncRead for L2capSocket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            // SAFETY: `unfilled` is valid for writing its length.
+            let read = guard.try_io(|fd| {
+                check_size(unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        unfilled.as_mut_ptr() as *mut libc::c_void,
+                        unfilled.len(),
+                    )
+                })
+            });
+            if let Ok(read) = read {
+                buf.advance(read?);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl AsyncWrite for L2capSocket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+            // SAFETY: `buf` is valid for reading its length.
+            let written = guard.try_io(|fd| {
+                check_size(unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void,
+                        buf.len(),
+                    )
+                })
+            });
+            if let Ok(written) = written {
+                return Poll::Ready(written);
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes go straight to the kernel.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // SAFETY: shutdown has no memory safety requirements.
+        Poll::Ready(check(unsafe { libc::shutdown(self.fd.as_raw_fd(), libc::SHUT_WR) }).map(drop))
+    }
+}
@@ -1,18 +1,32 @@
-use super::adapter::Adapter;
-use crate::{api, Result};
+use super::{
+    adapter::Adapter,
+    connection::{AdapterChange, BluezConnection},
+};
+use crate::{
+    api::{self, ManagerEvent},
+    Result,
+};
 use async_trait::async_trait;
 use bluez_async::BluetoothSession;
+use dbus::Path;
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
 
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
 pub struct Manager {
     session: BluetoothSession,
+    connection: BluezConnection,
 }
 
 impl Manager {
     pub async fn new() -> Result<Self> {
         let (_, session) = BluetoothSession::new().await?;
-        Ok(Self { session })
+        let connection = BluezConnection::new()?;
+        Ok(Self {
+            session,
+            connection,
+        })
     }
 }
 
@@ -24,7 +38,34 @@ impl api::Manager for Manager {
         let adapters = self.session.get_adapters().await?;
         Ok(adapters
             .into_iter()
-            .map(|adapter| Adapter::new(self.session.clone(), adapter.id))
+            .map(|adapter| Adapter::new(self.session.clone(), self.connection.clone(), adapter.id))
             .collect())
     }
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = ManagerEvent<Adapter>> + Send>>> {
+        let session = self.session.clone();
+        let connection = self.connection.clone();
+        let changes = self.connection.adapter_changes().await?;
+        Ok(Box::pin(changes.filter_map(move |change| {
+            let session = session.clone();
+            let connection = connection.clone();
+            async move {
+                match change {
+                    AdapterChange::Added(path) => {
+                        let adapters = session.get_adapters().await.ok()?;
+                        let adapter = adapters
+                            .into_iter()
+                            .find(|adapter| Path::from(adapter.id.clone()).to_string() == path)?;
+                        Some(ManagerEvent::AdapterAdded(Adapter::new(
+                            session, connection, adapter.id,
+                        )))
+                    }
+                    // Adapter IDs are the last component of the path, e.g. hci0.
+                    AdapterChange::Removed(path) => Some(ManagerEvent::AdapterRemoved {
+                        id: path.rsplit('/').next().unwrap_or(&path).to_string(),
+                    }),
+                }
+            }
+        })))
+    }
 }
@@ -0,0 +1,422 @@
+//! The kernel's Bluetooth management interface, for the few connection settings BlueZ doesn't
+//! offer over D-Bus. Most of its commands need the `CAP_NET_ADMIN` capability.
+
+use super::l2cap::{check, check_size, socket_error};
+use crate::api::{AddressType, BDAddr, ConnectionParameters, Phy};
+use crate::{Error, Result};
+use std::collections::BTreeSet;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+const BTPROTO_HCI: libc::c_int = 1;
+const HCI_DEV_NONE: u16 = 0xffff;
+const HCI_CHANNEL_CONTROL: u16 = 3;
+
+const MGMT_OP_LOAD_CONN_PARAM: u16 = 0x0035;
+const MGMT_OP_GET_PHY_CONFIGURATION: u16 = 0x0044;
+const MGMT_OP_SET_PHY_CONFIGURATION: u16 = 0x0045;
+const MGMT_EV_CMD_COMPLETE: u16 = 0x0001;
+const MGMT_EV_CMD_STATUS: u16 = 0x0002;
+
+const MGMT_STATUS_SUCCESS: u8 = 0x00;
+const MGMT_STATUS_UNKNOWN_COMMAND: u8 = 0x01;
+const MGMT_STATUS_NOT_SUPPORTED: u8 = 0x0c;
+const MGMT_STATUS_PERMISSION_DENIED: u8 = 0x14;
+
+const MGMT_ADDR_LE_PUBLIC: u8 = 0x01;
+const MGMT_ADDR_LE_RANDOM: u8 = 0x02;
+
+const MGMT_PHY_LE_1M: u32 = 0b11 << 9;
+const MGMT_PHY_LE_2M: u32 = 0b11 << 11;
+const MGMT_PHY_LE_CODED: u32 = 0b11 << 13;
+const MGMT_PHY_LE: u32 = MGMT_PHY_LE_1M | MGMT_PHY_LE_2M | MGMT_PHY_LE_CODED;
+
+/// The transmit and receive bits of an LE PHY.
+fn phy_bits(phy: Phy) -> u32 {
+    match phy {
+        Phy::Le1M => MGMT_PHY_LE_1M,
+        Phy::Le2M => MGMT_PHY_LE_2M,
+        Phy::LeCoded => MGMT_PHY_LE_CODED,
+    }
+}
+
+/// Encodes a command for the adapter with the given index.
+fn command_packet(index: u16, opcode: u16, parameters: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(6 + parameters.len());
+    packet.extend_from_slice(&opcode.to_le_bytes());
+    packet.extend_from_slice(&index.to_le_bytes());
+    packet.extend_from_slice(&(parameters.len() as u16).to_le_bytes());
+    packet.extend_from_slice(parameters);
+    packet
+}
+
+/// Returns the status and parameters of the reply to the given command, if the event is one.
+fn command_reply(event: &[u8], index: u16, opcode: u16) -> Option<(u8, Vec<u8>)> {
+    let [code_0, code_1, index_0, index_1, _, _, opcode_0, opcode_1, status, ref reply @ ..] =
+        *event
+    else {
+        return None;
+    };
+    let code = u16::from_le_bytes([code_0, code_1]);
+    ((code == MGMT_EV_CMD_COMPLETE || code == MGMT_EV_CMD_STATUS)
+        && u16::from_le_bytes([index_0, index_1]) == index
+        && u16::from_le_bytes([opcode_0, opcode_1]) == opcode)
+        .then(|| (status, reply.to_vec()))
+}
+
+/// `struct sockaddr_hci` from BlueZ's `hci.h`.
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+/// A socket on the management channel.
+#[derive(Debug)]
+struct MgmtSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl MgmtSocket {
+    fn open() -> io::Result<Self> {
+        // SAFETY: socket has no memory safety requirements, and the descriptor it returns isn't
+        // owned by anything else.
+        let fd = unsafe {
+            let fd = check(libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                BTPROTO_HCI,
+            ))?;
+            OwnedFd::from_raw_fd(fd)
+        };
+        let address = SockaddrHci {
+            hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            hci_dev: HCI_DEV_NONE,
+            hci_channel: HCI_CHANNEL_CONTROL,
+        };
+        // SAFETY: The address is a valid `sockaddr_hci` for the length given.
+        check(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const SockaddrHci as *const libc::sockaddr,
+                mem::size_of::<SockaddrHci>() as libc::socklen_t,
+            )
+        })?;
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Sends a command for the adapter with the given index, and returns the status and parameters
+    /// of its reply.
+    async fn command(
+        &self,
+        index: u16,
+        opcode: u16,
+        parameters: &[u8],
+    ) -> io::Result<(u8, Vec<u8>)> {
+        let packet = command_packet(index, opcode, parameters);
+        loop {
+            let mut guard = self.fd.writable().await?;
+            // SAFETY: `packet` is valid for reading its length.
+            let written = guard.try_io(|fd| {
+                check_size(unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                    )
+                })
+            });
+            if let Ok(written) = written {
+                written?;
+                break;
+            }
+        }
+        // Other events are broadcast to every management socket, so skip them until the reply.
+        let mut event = [0; 1024];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            // SAFETY: `event` is valid for writing its length.
+            let read = guard.try_io(|fd| {
+                check_size(unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        event.as_mut_ptr() as *mut libc::c_void,
+                        event.len(),
+                    )
+                })
+            });
+            let Ok(read) = read else {
+                continue;
+            };
+            if let Some(reply) = command_reply(&event[..read?], index, opcode) {
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+fn status_error(status: u8) -> Result<()> {
+    match status {
+        MGMT_STATUS_SUCCESS => Ok(()),
+        MGMT_STATUS_PERMISSION_DENIED => Err(Error::PermissionDenied),
+        MGMT_STATUS_UNKNOWN_COMMAND | MGMT_STATUS_NOT_SUPPORTED => Err(Error::NotSupported(
+            "The kernel doesn't support this management command".to_string(),
+        )),
+        status => Err(Error::Other(
+            format!("Management command failed with status {:#04x}", status).into(),
+        )),
+    }
+}
+
+/// Returns the index of the adapter with the given BlueZ id, such as `hci0`.
+pub(crate) fn adapter_index(adapter_id: &str) -> Result<u16> {
+    adapter_id
+        .strip_prefix("hci")
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| Error::Other(format!("Unexpected adapter id {}", adapter_id).into()))
+}
+
+/// Loads connection parameters for a device into the kernel, which uses them for its next
+/// connection and, since Linux 6.11, updates the current connection to them.
+///
+/// Before Linux 6.11 the kernel treats each load as replacing the whole list, so this also clears
+/// the parameters stored for other devices which aren't set to connect automatically, such as
+/// those loaded here earlier.
+pub(crate) async fn load_connection_parameters(
+    adapter_index: u16,
+    address: BDAddr,
+    address_type: AddressType,
+    parameters: &ConnectionParameters,
+) -> Result<()> {
+    let request = load_connection_parameters_request(address, address_type, parameters);
+    command(adapter_index, MGMT_OP_LOAD_CONN_PARAM, &request)
+        .await
+        .map(drop)
+}
+
+/// Encodes the parameters of a `Load Connection Parameters` command with a single entry.
+fn load_connection_parameters_request(
+    address: BDAddr,
+    address_type: AddressType,
+    parameters: &ConnectionParameters,
+) -> Vec<u8> {
+    let mut address = address.into_inner();
+    address.reverse();
+    let units = |duration: std::time::Duration, unit_micros: u128| {
+        ((duration.as_micros() / unit_micros) as u16).to_le_bytes()
+    };
+    let mut request = vec![];
+    // One entry: address, address type, minimum and maximum interval in 1.25 ms units, latency
+    // and supervision timeout in 10 ms units.
+    request.extend_from_slice(&1u16.to_le_bytes());
+    request.extend_from_slice(&address);
+    request.push(match address_type {
+        AddressType::Public => MGMT_ADDR_LE_PUBLIC,
+        AddressType::Random => MGMT_ADDR_LE_RANDOM,
+    });
+    request.extend_from_slice(&units(parameters.min_interval, 1250));
+    request.extend_from_slice(&units(parameters.max_interval, 1250));
+    request.extend_from_slice(&parameters.peripheral_latency.to_le_bytes());
+    request.extend_from_slice(&units(parameters.supervision_timeout, 10_000));
+    request
+}
+
+/// Sends a command on a new management socket, and returns the parameters of its reply if it
+/// succeeded.
+async fn command(adapter_index: u16, opcode: u16, parameters: &[u8]) -> Result<Vec<u8>> {
+    let socket = MgmtSocket::open().map_err(socket_error)?;
+    let (status, reply) = socket
+        .command(adapter_index, opcode, parameters)
+        .await
+        .map_err(socket_error)?;
+    status_error(status)?;
+    Ok(reply)
+}
+
+/// The PHYs an adapter supports, which of them can be turned on or off, and which are on.
+struct PhyConfiguration {
+    supported: u32,
+    configurable: u32,
+    selected: u32,
+}
+
+impl PhyConfiguration {
+    /// Decodes the reply to a `Get PHY Configuration` command.
+    fn parse(reply: &[u8]) -> Result<Self> {
+        let field = |index: usize| {
+            reply
+                .get(index * 4..index * 4 + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| Error::Other("Truncated PHY configuration".into()))
+        };
+        Ok(Self {
+            supported: field(0)?,
+            configurable: field(1)?,
+            selected: field(2)?,
+        })
+    }
+
+    /// Returns the LE PHYs which are supported.
+    fn supported_phys(&self) -> BTreeSet<Phy> {
+        [Phy::Le1M, Phy::Le2M, Phy::LeCoded]
+            .into_iter()
+            .filter(|phy| self.supported & phy_bits(*phy) == phy_bits(*phy))
+            .collect()
+    }
+
+    /// Encodes the parameters of a `Set PHY Configuration` command selecting the given LE PHYs,
+    /// keeping the BR/EDR PHYs and those which can't be turned off alone.
+    fn set_request(&self, phys: &BTreeSet<Phy>) -> Result<[u8; 4]> {
+        let wanted = phys.iter().fold(0, |bits, phy| bits | phy_bits(*phy));
+        if wanted & !self.supported != 0 {
+            return Err(Error::NotSupported(
+                "The adapter doesn't support all of these PHYs".to_string(),
+            ));
+        }
+        let configurable = self.configurable & MGMT_PHY_LE;
+        let selected = (self.selected & !configurable) | (wanted & configurable);
+        Ok(selected.to_le_bytes())
+    }
+}
+
+async fn phy_configuration(adapter_index: u16) -> Result<PhyConfiguration> {
+    let reply = command(adapter_index, MGMT_OP_GET_PHY_CONFIGURATION, &[]).await?;
+    PhyConfiguration::parse(&reply)
+}
+
+/// Returns the LE PHYs the adapter supports.
+pub(crate) async fn supported_phys(adapter_index: u16) -> Result<BTreeSet<Phy>> {
+    Ok(phy_configuration(adapter_index).await?.supported_phys())
+}
+
+/// Selects the given LE PHYs on the adapter, leaving its BR/EDR PHYs and those which can't be
+/// turned off alone.
+pub(crate) async fn set_preferred_phys(adapter_index: u16, phys: &BTreeSet<Phy>) -> Result<()> {
+    let request = phy_configuration(adapter_index).await?.set_request(phys)?;
+    command(adapter_index, MGMT_OP_SET_PHY_CONFIGURATION, &request)
+        .await
+        .map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn encodes_command_packet() {
+        assert_eq!(
+            command_packet(1, MGMT_OP_SET_PHY_CONFIGURATION, &[0xaa, 0xbb]),
+            [0x45, 0x00, 0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb]
+        );
+    }
+
+    #[test]
+    fn matches_command_reply() {
+        // Command Complete for Get PHY Configuration on hci1, with status 0 and one byte.
+        let event = [0x01, 0x00, 0x01, 0x00, 0x04, 0x00, 0x44, 0x00, 0x00, 0x7f];
+        assert_eq!(
+            command_reply(&event, 1, MGMT_OP_GET_PHY_CONFIGURATION),
+            Some((0x00, vec![0x7f]))
+        );
+        assert_eq!(
+            command_reply(&event, 0, MGMT_OP_GET_PHY_CONFIGURATION),
+            None
+        );
+        assert_eq!(
+            command_reply(&event, 1, MGMT_OP_SET_PHY_CONFIGURATION),
+            None
+        );
+        // Command Status, as sent for failures.
+        let status = [0x02, 0x00, 0x01, 0x00, 0x03, 0x00, 0x45, 0x00, 0x14];
+        assert_eq!(
+            command_reply(&status, 1, MGMT_OP_SET_PHY_CONFIGURATION),
+            Some((MGMT_STATUS_PERMISSION_DENIED, vec![]))
+        );
+        // Other events, such as Device Connected, and truncated ones are skipped.
+        assert_eq!(
+            command_reply(
+                &[0x0b, 0x00, 0x01, 0x00, 0x03, 0x00, 0x45, 0x00, 0x00],
+                1,
+                MGMT_OP_SET_PHY_CONFIGURATION
+            ),
+            None
+        );
+        assert_eq!(
+            command_reply(&event[..8], 1, MGMT_OP_GET_PHY_CONFIGURATION),
+            None
+        );
+    }
+
+    #[test]
+    fn encodes_load_connection_parameters() {
+        let parameters = ConnectionParameters {
+            min_interval: Duration::from_micros(7500),
+            max_interval: Duration::from_millis(15),
+            peripheral_latency: 4,
+            supervision_timeout: Duration::from_secs(2),
+        };
+        assert_eq!(
+            load_connection_parameters_request(
+                BDAddr::from([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+                AddressType::Random,
+                &parameters
+            ),
+            [
+                0x01, 0x00, // One entry
+                0x55, 0x44, 0x33, 0x22, 0x11, 0x00, // Address, little-endian
+                0x02, // LE random
+                0x06, 0x00, // 7.5 ms
+                0x0c, 0x00, // 15 ms
+                0x04, 0x00, // Latency
+                0xc8, 0x00, // 2 s
+            ]
+        );
+        let request =
+            load_connection_parameters_request(BDAddr::default(), AddressType::Public, &parameters);
+        assert_eq!(request[8], MGMT_ADDR_LE_PUBLIC);
+    }
+
+    #[test]
+    fn phy_configuration() {
+        // Supports BR/EDR 1M slot 1 (bit 0) and all LE PHYs, of which LE 1M can't be turned off,
+        // with BR/EDR 1M slot 1 and LE 1M selected.
+        let reply = [
+            0x01, 0x7e, 0x00, 0x00, // Supported
+            0x00, 0x78, 0x00, 0x00, // Configurable
+            0x01, 0x06, 0x00, 0x00, // Selected
+        ];
+        let configuration = PhyConfiguration::parse(&reply).unwrap();
+        assert_eq!(
+            configuration.supported_phys(),
+            BTreeSet::from([Phy::Le1M, Phy::Le2M, Phy::LeCoded])
+        );
+        assert_eq!(
+            configuration
+                .set_request(&BTreeSet::from([Phy::Le1M, Phy::Le2M]))
+                .unwrap(),
+            [0x01, 0x1e, 0x00, 0x00]
+        );
+        // LE 1M stays selected as it isn't configurable.
+        assert_eq!(
+            configuration
+                .set_request(&BTreeSet::from([Phy::LeCoded]))
+                .unwrap(),
+            [0x01, 0x66, 0x00, 0x00]
+        );
+        assert!(PhyConfiguration::parse(&reply[..11]).is_err());
+
+        let le_1m_only =
+            PhyConfiguration::parse(&[0, 0x06, 0, 0, 0, 0, 0, 0, 0, 0x06, 0, 0]).unwrap();
+        assert_eq!(le_1m_only.supported_phys(), BTreeSet::from([Phy::Le1M]));
+        assert!(matches!(
+            le_1m_only.set_request(&BTreeSet::from([Phy::Le2M])),
+            Err(Error::NotSupported(_))
+        ));
+    }
+}
@@ -1,3 +1,9 @@
 pub mod adapter;
+mod advertising;
+mod agent;
+mod connection;
+mod gatt_server;
+mod l2cap;
 pub mod manager;
+mod mgmt;
 pub mod peripheral;
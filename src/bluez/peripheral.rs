@@ -4,33 +4,57 @@ use bluez_async::{
     CharacteristicInfo, DescriptorInfo, DeviceId, DeviceInfo, MacAddress, ServiceInfo,
     WriteOptions,
 };
+use dbus::arg::{prop_cast, PropMap, RefArg};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::ObjectManager;
+use dbus::Path;
 use futures::future::{join_all, ready};
 use futures::stream::{Stream, StreamExt};
+use log::{debug, trace};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use serde_cr as serde;
 use std::collections::{BTreeSet, HashMap};
 use std::fmt::{self, Display, Formatter};
+use std::future::Future;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use super::connection::{
+    BluezConnection, ADAPTER_INTERFACE, BATTERY_INTERFACE, CHARACTERISTIC_INTERFACE,
+    DESCRIPTOR_INTERFACE, DEVICE_INTERFACE, SERVICE_INTERFACE,
+};
+use super::l2cap::{socket_error, L2capSocket};
+use super::mgmt;
+use crate::api::activity::{ActivityLog, ActivityRecord};
+use crate::api::batch::{BatchResult, OperationBatch};
+use crate::api::battery::read_battery_service;
+use crate::api::diagnostics::LatencyHistogram;
+use crate::api::l2cap::L2capChannel;
+use crate::api::redaction::LoggedValue;
 use crate::api::{
-    self, AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor, PeripheralProperties,
-    Service, ValueNotification, WriteType,
+    self, AdapterRoles, AddressType, BDAddr, CentralEvent, CharPropFlags, Characteristic,
+    ConnectOptions, ConnectionInfo, ConnectionParameters, Descriptor, HandleRange, LeAddress,
+    PeripheralProperties, Service, ValueNotification, WriteType,
 };
 use crate::{Error, Result};
 
+/// The infos are kept behind `Arc`s so that looking up the D-Bus object for every read, write or
+/// notify only needs a reference count bump rather than copying the object path.
 #[derive(Clone, Debug)]
 struct CharacteristicInternal {
-    info: CharacteristicInfo,
-    descriptors: HashMap<Uuid, DescriptorInfo>,
+    info: Arc<CharacteristicInfo>,
+    descriptors: HashMap<Uuid, Arc<DescriptorInfo>>,
 }
 
 impl CharacteristicInternal {
-    fn new(info: CharacteristicInfo, descriptors: HashMap<Uuid, DescriptorInfo>) -> Self {
-        Self { info, descriptors }
+    fn new(info: CharacteristicInfo, descriptors: HashMap<Uuid, Arc<DescriptorInfo>>) -> Self {
+        Self {
+            info: Arc::new(info),
+            descriptors,
+        }
     }
 }
 
@@ -38,6 +62,56 @@ impl CharacteristicInternal {
 struct ServiceInternal {
     info: ServiceInfo,
     characteristics: HashMap<Uuid, CharacteristicInternal>,
+    handles: Option<HandleRange>,
+    included_services: Vec<Uuid>,
+}
+
+/// The objects BlueZ exports, with the properties of each of their interfaces, as returned by
+/// `GetManagedObjects`.
+type ManagedObjects = HashMap<Path<'static>, HashMap<String, PropMap>>;
+
+/// Returns the `Handle` property of the given interface of an object, if BlueZ reports it.
+fn object_handle(object: &HashMap<String, PropMap>, interface: &str) -> Option<u16> {
+    prop_cast::<u16>(object.get(interface)?, "Handle").copied()
+}
+
+/// Works out the handles a service occupies from the `Handle` properties of its objects, where
+/// BlueZ reports them, which older versions don't. The handle of a characteristic is that of its
+/// declaration, which its value follows.
+fn service_handles(objects: &ManagedObjects, service: &Path) -> Option<HandleRange> {
+    let start = object_handle(objects.get(service)?, SERVICE_INTERFACE).filter(|&h| h != 0)?;
+    let prefix = format!("{}/", service);
+    let end = objects
+        .iter()
+        .filter(|(path, _)| path.starts_with(&prefix))
+        .filter_map(|(_, object)| {
+            object_handle(object, CHARACTERISTIC_INTERFACE)
+                .map(|handle| handle.saturating_add(1))
+                .or_else(|| object_handle(object, DESCRIPTOR_INTERFACE))
+        })
+        .fold(start, u16::max);
+    Some(HandleRange { start, end })
+}
+
+/// Returns the object paths of the services a service includes.
+fn included_service_paths<'a>(
+    objects: &'a ManagedObjects,
+    service: &Path<'static>,
+) -> impl Iterator<Item = &'a str> {
+    objects
+        .get(service)
+        .and_then(|object| object.get(SERVICE_INTERFACE)?.get("Includes")?.0.as_iter())
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.as_str())
+}
+
+/// An immutable snapshot of the GATT database of a device, as of the last service discovery. It is
+/// shared between all clones of a `Peripheral`, and replaced as a whole when services are
+/// rediscovered, so operations never need to hold a lock while talking to BlueZ.
+#[derive(Debug, Default)]
+struct GattDb {
+    services: HashMap<Uuid, ServiceInternal>,
 }
 
 #[cfg_attr(
@@ -54,13 +128,30 @@ impl Display for PeripheralId {
     }
 }
 
+#[cfg(test)]
+impl PeripheralId {
+    /// Makes up the ID BlueZ would give the device with the given address on `hci0`.
+    pub(crate) fn for_test(address: BDAddr) -> Self {
+        let path = format!(
+            "/org/bluez/hci0/dev_{}",
+            address.to_string().replace(':', "_")
+        );
+        PeripheralId(serde_json::from_value(serde_json::json!({ "object_path": path })).unwrap())
+    }
+}
+
+/// How long to wait for BlueZ to read the name of a device after connecting.
+const NAME_RESOLUTION_TIMEOUT: Duration = Duration::from_secs(30);
+const ALREADY_EXISTS_ERROR: &str = "org.bluez.Error.AlreadyExists";
+
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone, Debug)]
 pub struct Peripheral {
     session: BluetoothSession,
+    connection: BluezConnection,
     device: DeviceId,
     mac_address: BDAddr,
-    services: Arc<Mutex<HashMap<Uuid, ServiceInternal>>>,
+    gatt_db: Arc<RwLock<Arc<GattDb>>>,
 }
 
 fn get_characteristic<'a>(
@@ -87,19 +178,27 @@ fn get_characteristic<'a>(
 }
 
 impl Peripheral {
-    pub(crate) fn new(session: BluetoothSession, device: DeviceInfo) -> Self {
+    pub(crate) fn new(
+        session: BluetoothSession,
+        connection: BluezConnection,
+        device: DeviceInfo,
+    ) -> Self {
         Peripheral {
             session,
+            connection,
             device: device.id,
             mac_address: device.mac_address.into(),
-            services: Arc::new(Mutex::new(HashMap::new())),
+            gatt_db: Arc::default(),
         }
     }
 
-    fn characteristic_info(&self, characteristic: &Characteristic) -> Result<CharacteristicInfo> {
-        let services = self.services.lock().unwrap();
+    fn characteristic_info(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Arc<CharacteristicInfo>> {
+        let gatt_db = self.gatt_db();
         get_characteristic(
-            &services,
+            &gatt_db.services,
             &characteristic.service_uuid,
             &characteristic.uuid,
         )
@@ -107,10 +206,10 @@ impl Peripheral {
         .cloned()
     }
 
-    fn descriptor_info(&self, descriptor: &Descriptor) -> Result<DescriptorInfo> {
-        let services = self.services.lock().unwrap();
+    fn descriptor_info(&self, descriptor: &Descriptor) -> Result<Arc<DescriptorInfo>> {
+        let gatt_db = self.gatt_db();
         let characteristic = get_characteristic(
-            &services,
+            &gatt_db.services,
             &descriptor.service_uuid,
             &descriptor.characteristic_uuid,
         )?;
@@ -123,11 +222,154 @@ impl Peripheral {
             .cloned()
     }
 
+    fn activity_log(&self) -> Arc<ActivityLog> {
+        self.connection.activity_log(&self.device)
+    }
+
+    /// Runs an operation once the concurrency limits allow, recording it in the activity log.
+    async fn operation<T>(
+        &self,
+        operation: &'static str,
+        details: impl FnOnce() -> String,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let _permits = self.connection.acquire_operation(&self.device).await;
+        let future = self.connection.unless_lost(&self.device, future);
+        self.activity_log()
+            .operation(operation, details, future)
+            .await
+    }
+
+    /// Asks BlueZ to remove the device, which also removes its bond and cached services.
+    async fn remove_device(&self, operation: &'static str) -> Result<()> {
+        // Not self.operation(), as this removes the device, which would fail it as lost.
+        let _permits = self.connection.acquire_operation(&self.device).await;
+        self.activity_log()
+            .operation(operation, String::new, async {
+                let device = Path::from(self.device.clone());
+                let () = self
+                    .connection
+                    .proxy(self.device.adapter())
+                    .method_call(ADAPTER_INTERFACE, "RemoveDevice", (device,))
+                    .await?;
+                Ok(())
+            })
+            .await
+    }
+
+    fn gatt_db(&self) -> Arc<GattDb> {
+        self.gatt_db.read().unwrap().clone()
+    }
+
+    pub(crate) async fn load_services(&self) -> Result<()> {
+        let mut services_internal = HashMap::new();
+        let services = self.session.get_services(&self.device).await?;
+        for service in services {
+            let characteristics = self.session.get_characteristics(&service.id).await?;
+            let characteristics =
+                join_all(characteristics.into_iter().map(|characteristic| async {
+                    let descriptors = self
+                        .session
+                        .get_descriptors(&characteristic.id)
+                        .await
+                        .unwrap_or(Vec::new())
+                        .into_iter()
+                        .map(|descriptor| (descriptor.uuid, Arc::new(descriptor)))
+                        .collect();
+                    CharacteristicInternal::new(characteristic, descriptors)
+                }))
+                .await;
+            services_internal.insert(
+                service.uuid,
+                ServiceInternal {
+                    info: service,
+                    characteristics: characteristics
+                        .into_iter()
+                        .map(|characteristic| (characteristic.info.uuid, characteristic))
+                        .collect(),
+                    handles: None,
+                    included_services: vec![],
+                },
+            );
+        }
+        // bluez-async doesn't expose handles or included services, so read them from all the
+        // objects at once.
+        let objects = self.connection.proxy("/").get_managed_objects().await?;
+        let uuids: HashMap<String, Uuid> = services_internal
+            .values()
+            .map(|service| {
+                (
+                    Path::from(service.info.id.clone()).to_string(),
+                    service.info.uuid,
+                )
+            })
+            .collect();
+        for service in services_internal.values_mut() {
+            let path = Path::from(service.info.id.clone());
+            service.handles = service_handles(&objects, &path);
+            service.included_services = included_service_paths(&objects, &path)
+                .filter_map(|path| uuids.get(path).copied())
+                .collect();
+        }
+        *self.gatt_db.write().unwrap() = Arc::new(GattDb {
+            services: services_internal,
+        });
+        Ok(())
+    }
+
+    /// Returns BlueZ's view of whether notifications or indications are enabled.
+    async fn notifying(&self, characteristic: &CharacteristicInfo) -> Result<bool> {
+        Ok(self
+            .connection
+            .get_property::<bool>(
+                characteristic.id.clone(),
+                CHARACTERISTIC_INTERFACE,
+                "Notifying",
+            )
+            .await?
+            .unwrap_or(false))
+    }
+
+    /// Emits `NameResolved` once BlueZ has read the device's name, which it does in the
+    /// background after connecting.
+    fn resolve_name(&self) {
+        let connection = self.connection.clone();
+        let device = self.device.clone();
+        tokio::spawn(async move {
+            let name =
+                tokio::time::timeout(NAME_RESOLUTION_TIMEOUT, connection.wait_for_name(&device))
+                    .await;
+            match name {
+                Ok(Ok(name)) => connection.emit(CentralEvent::NameResolved {
+                    id: device.into(),
+                    name,
+                }),
+                Ok(Err(e)) => debug!("Failed to resolve the name of {:?}: {}", device, e),
+                Err(_) => debug!("{:?} has no name", device),
+            }
+        });
+    }
+
     async fn device_info(&self) -> Result<DeviceInfo> {
         Ok(self.session.get_device_info(&self.device).await?)
     }
 }
 
+/// Unstable access to the BlueZ objects behind the peripheral, for operations btleplug doesn't
+/// cover yet.
+#[cfg(feature = "unstable-raw-handles")]
+impl Peripheral {
+    /// Returns the D-Bus object path of the `org.bluez.Device1` object.
+    pub fn raw_path(&self) -> dbus::Path<'static> {
+        self.device.clone().into()
+    }
+
+    /// Returns the system bus connection btleplug uses to talk to BlueZ.
+    pub fn raw_connection(&self) -> Arc<dbus::nonblock::SyncConnection> {
+        self.connection.raw_connection()
+    }
+}
+
 #[async_trait]
 impl api::Peripheral for Peripheral {
     fn id(&self) -> PeripheralId {
@@ -138,8 +380,19 @@ impl api::Peripheral for Peripheral {
         self.mac_address
     }
 
+    async fn le_address(&self) -> Result<LeAddress> {
+        // BlueZ replaces the address and its type with the identity address after bonding, so
+        // both need to come from the current properties rather than the address at creation.
+        let device_info = self.device_info().await?;
+        Ok(LeAddress::new(
+            device_info.mac_address.into(),
+            device_info.address_type.into(),
+        ))
+    }
+
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
         let device_info = self.device_info().await?;
+        let advertising_flags = self.connection.advertising_flags(&self.device).await?;
         Ok(Some(PeripheralProperties {
             address: device_info.mac_address.into(),
             address_type: Some(device_info.address_type.into()),
@@ -149,65 +402,191 @@ impl api::Peripheral for Peripheral {
             manufacturer_data: device_info.manufacturer_data,
             service_data: device_info.service_data,
             services: device_info.services,
+            appearance: device_info.appearance,
             class: device_info.class,
+            advertising_flags,
+            // BlueZ only reports advertisements whose contents changed, not every packet.
+            advertising_interval: None,
+            last_advertisement: None,
         }))
     }
 
     fn services(&self) -> BTreeSet<Service> {
-        self.services
-            .lock()
-            .unwrap()
+        self.gatt_db()
+            .services
             .values()
             .map(|service| service.into())
             .collect()
     }
 
+    async fn battery_level(&self) -> Result<Option<u8>> {
+        // BlueZ's battery plugin reads the Battery Service itself and hides it from clients, so
+        // the service is only there to read if the plugin is disabled.
+        let percentage = self
+            .connection
+            .get_property::<u8>(self.device.clone(), BATTERY_INTERFACE, "Percentage")
+            .await?;
+        match percentage {
+            Some(percentage) => Ok(Some(percentage)),
+            None => read_battery_service(self).await,
+        }
+    }
+
     async fn is_connected(&self) -> Result<bool> {
         let device_info = self.device_info().await?;
         Ok(device_info.connected)
     }
 
+    async fn connection_info(&self) -> Result<ConnectionInfo> {
+        if !self.is_connected().await? {
+            return Err(Error::NotConnected);
+        }
+        // BlueZ 5.62 and later expose the negotiated MTU on every characteristic. Older versions
+        // only report it when a file descriptor is acquired, which would write the device's CCCDs
+        // and lock out other clients, so `mtu()` returns the default there instead.
+        let characteristic = self.gatt_db().services.values().find_map(|service| {
+            service
+                .characteristics
+                .values()
+                .next()
+                .map(|characteristic| characteristic.info.id.clone())
+        });
+        let mtu = match characteristic {
+            Some(id) => {
+                self.connection
+                    .get_property::<u16>(id, CHARACTERISTIC_INTERFACE, "MTU")
+                    .await?
+            }
+            None => None,
+        };
+        Ok(ConnectionInfo {
+            mtu,
+            connected_since: self.connection.connected_since(&self.device),
+            ..Default::default()
+        })
+    }
+
+    async fn request_connection_params(&self, parameters: ConnectionParameters) -> Result<()> {
+        parameters.validate()?;
+        let details = || format!("{:?}", parameters);
+        self.operation("request_connection_params", details, async {
+            let device = self.device_info().await?;
+            if !device.connected {
+                return Err(Error::NotConnected);
+            }
+            mgmt::load_connection_parameters(
+                mgmt::adapter_index(&self.device.adapter().to_string())?,
+                device.mac_address.into(),
+                device.address_type.into(),
+                &parameters,
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn batch<F>(&self, build: F) -> Result<Vec<BatchResult>>
+    where
+        F: FnOnce(&mut OperationBatch) + Send,
+    {
+        let mut operations = OperationBatch::default();
+        build(&mut operations);
+        self.connection
+            .run_batch(&self.device, operations.run(self))
+            .await
+    }
+
     async fn connect(&self) -> Result<()> {
-        self.session.connect(&self.device).await?;
+        self.operation("connect", String::new, async {
+            // The device may still be connected from before this process started, in which
+            // case there's no need to go through BlueZ's connection procedure again.
+            let device_info = self.device_info().await?;
+            if !(device_info.connected && device_info.services_resolved) {
+                self.connection
+                    .check_role(&self.device.adapter(), AdapterRoles::CENTRAL)
+                    .await?;
+                self.session.connect(&self.device).await?;
+            }
+            self.connection.set_connected_as_central(&self.device);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        let named = self.device_info().await?.name.is_some();
+        match options.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.connect()).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    // BlueZ keeps connecting after the future is dropped unless told otherwise.
+                    if let Err(e) = self.cancel_connect().await {
+                        debug!("Failed to cancel connection to {}: {}", self.device, e);
+                    }
+                    return Err(Error::TimedOut(timeout));
+                }
+            },
+            None => self.connect().await?,
+        }
+        if options.resolve_name && !named {
+            self.resolve_name();
+        }
         Ok(())
     }
 
     async fn disconnect(&self) -> Result<()> {
-        self.session.disconnect(&self.device).await?;
-        Ok(())
+        self.operation("disconnect", String::new, async {
+            self.session.disconnect(&self.device).await?;
+            self.connection.set_connected(&self.device, false);
+            Ok(())
+        })
+        .await
     }
 
-    async fn discover_services(&self) -> Result<()> {
-        let mut services_internal = HashMap::new();
-        let services = self.session.get_services(&self.device).await?;
-        for service in services {
-            let characteristics = self.session.get_characteristics(&service.id).await?;
-            let characteristics =
-                join_all(characteristics.into_iter().map(|characteristic| async {
-                    let descriptors = self
-                        .session
-                        .get_descriptors(&characteristic.id)
-                        .await
-                        .unwrap_or(Vec::new())
-                        .into_iter()
-                        .map(|descriptor| (descriptor.uuid, descriptor))
-                        .collect();
-                    CharacteristicInternal::new(characteristic, descriptors)
-                }))
+    async fn cancel_connect(&self) -> Result<()> {
+        // Not self.operation(), which would wait for the connection attempt to finish.
+        self.activity_log()
+            .operation("cancel_connect", String::new, async {
+                // This fails if no pairing is in progress, which is fine.
+                let _: std::result::Result<(), dbus::Error> = self
+                    .connection
+                    .proxy(self.device.clone())
+                    .method_call(DEVICE_INTERFACE, "CancelPairing", ())
+                    .await;
+                // Disconnecting also aborts a connection BlueZ is still trying to make.
+                self.session.disconnect(&self.device).await?;
+                self.connection.set_connected(&self.device, false);
+                Ok(())
+            })
+            .await
+    }
+
+    async fn pair(&self) -> Result<()> {
+        self.operation("pair", String::new, async {
+            let paired: std::result::Result<(), dbus::Error> = self
+                .connection
+                .proxy(self.device.clone())
+                .method_call(DEVICE_INTERFACE, "Pair", ())
                 .await;
-            services_internal.insert(
-                service.uuid,
-                ServiceInternal {
-                    info: service,
-                    characteristics: characteristics
-                        .into_iter()
-                        .map(|characteristic| (characteristic.info.uuid, characteristic))
-                        .collect(),
-                },
-            );
-        }
-        *self.services.lock().unwrap() = services_internal;
-        Ok(())
+            match paired {
+                Err(e) if e.name() == Some(ALREADY_EXISTS_ERROR) => Ok(()),
+                paired => Ok(paired?),
+            }
+        })
+        .await
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        self.remove_device("unpair").await
+    }
+
+    async fn forget(&self) -> Result<()> {
+        self.remove_device("forget").await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.operation("discover_services", String::new, self.load_services())
+            .await
     }
 
     async fn write(
@@ -216,74 +595,252 @@ impl api::Peripheral for Peripheral {
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        let options = WriteOptions {
-            write_type: Some(write_type.into()),
-            ..Default::default()
+        let details = || {
+            format!(
+                "{} {} ({:?})",
+                characteristic.uuid,
+                LoggedValue(data),
+                write_type
+            )
         };
-        Ok(self
-            .session
-            .write_characteristic_value_with_options(&characteristic_info.id, data, options)
-            .await?)
+        self.operation("write", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            trace!(
+                "Writing {} to {} ({:?})",
+                LoggedValue(data),
+                characteristic.uuid,
+                write_type
+            );
+            let options = WriteOptions {
+                write_type: Some(write_type.into()),
+                ..Default::default()
+            };
+            let start = Instant::now();
+            self.session
+                .write_characteristic_value_with_options(&characteristic_info.id, data, options)
+                .await?;
+            if write_type == WriteType::WithResponse {
+                self.connection
+                    .record_write_latency(&self.device, characteristic, start.elapsed());
+            }
+            Ok(())
+        })
+        .await
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self
-            .session
-            .read_characteristic_value(&characteristic_info.id)
-            .await?)
+        let details = || characteristic.uuid.to_string();
+        self.operation("read", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            Ok(self
+                .session
+                .read_characteristic_value(&characteristic_info.id)
+                .await?)
+        })
+        .await
+    }
+
+    async fn open_l2cap_channel(&self, psm: u16) -> Result<L2capChannel> {
+        self.operation("open_l2cap_channel", || psm.to_string(), async {
+            let adapter = self
+                .session
+                .get_adapter_info(&self.device.adapter())
+                .await?;
+            let device = self.device_info().await?;
+            let socket = L2capSocket::connect(
+                adapter.mac_address.into(),
+                device.mac_address.into(),
+                device.address_type.into(),
+                psm,
+            )
+            .await
+            .map_err(socket_error)?;
+            Ok(L2capChannel::new(psm, socket))
+        })
+        .await
+    }
+
+    async fn reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        // BlueZ queues the value as prepared writes and executes them once it has checked that the
+        // device echoed each one back correctly, but has no way to combine several values.
+        let (characteristic, data) = match writes {
+            [] => return Ok(()),
+            [write] => write,
+            _ => {
+                return Err(Error::NotSupported(
+                    "BlueZ can only write one characteristic reliably at a time".to_string(),
+                ))
+            }
+        };
+        let details = || format!("{} {}", characteristic.uuid, LoggedValue(data));
+        self.operation("reliable_write", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            let options = WriteOptions {
+                write_type: Some(bluez_async::WriteType::Reliable),
+                ..Default::default()
+            };
+            Ok(self
+                .session
+                .write_characteristic_value_with_options(
+                    &characteristic_info.id,
+                    data.as_slice(),
+                    options,
+                )
+                .await?)
+        })
+        .await
+    }
+
+    async fn read_with_offset(
+        &self,
+        characteristic: &Characteristic,
+        offset: usize,
+    ) -> Result<Vec<u8>> {
+        let details = || format!("{} at offset {}", characteristic.uuid, offset);
+        self.operation("read", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            Ok(self
+                .session
+                .read_characteristic_value_with_offset(&characteristic_info.id, offset)
+                .await?)
+        })
+        .await
+    }
+
+    async fn write_with_offset(
+        &self,
+        characteristic: &Characteristic,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let details = || {
+            format!(
+                "{} {} at offset {}",
+                characteristic.uuid,
+                LoggedValue(data),
+                offset
+            )
+        };
+        self.operation("write", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            // BlueZ makes a long write whenever there is an offset.
+            let options = WriteOptions {
+                offset,
+                write_type: Some(bluez_async::WriteType::WithResponse),
+            };
+            Ok(self
+                .session
+                .write_characteristic_value_with_options(&characteristic_info.id, data, options)
+                .await?)
+        })
+        .await
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self.session.start_notify(&characteristic_info.id).await?)
+        let details = || characteristic.uuid.to_string();
+        self.operation("subscribe", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            Ok(self.session.start_notify(&characteristic_info.id).await?)
+        })
+        .await
     }
 
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let details = || characteristic.uuid.to_string();
+        self.operation("unsubscribe", details, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            Ok(self.session.stop_notify(&characteristic_info.id).await?)
+        })
+        .await
+    }
+
+    async fn subscriptions(&self) -> Result<BTreeSet<Characteristic>> {
+        let gatt_db = self.gatt_db();
+        let notifiable = gatt_db.services.values().flat_map(|service| {
+            service
+                .characteristics
+                .values()
+                .filter(|characteristic| {
+                    characteristic
+                        .info
+                        .flags
+                        .intersects(CharacteristicFlags::NOTIFY | CharacteristicFlags::INDICATE)
+                })
+                .map(move |characteristic| (&service.info, characteristic))
+        });
+        let notifying = join_all(notifiable.map(|(service, characteristic)| async move {
+            let notifying = self.notifying(&characteristic.info).await?;
+            Ok(notifying.then(|| make_characteristic(characteristic, service)))
+        }))
+        .await;
+        notifying
+            .into_iter()
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    async fn is_notifying(&self, characteristic: &Characteristic) -> Result<bool> {
         let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self.session.stop_notify(&characteristic_info.id).await?)
+        self.notifying(&characteristic_info).await
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         let device_id = self.device.clone();
         let events = self.session.device_event_stream(&device_id).await?;
-        let services = self.services.clone();
+        let gatt_db = self.gatt_db.clone();
         Ok(Box::pin(events.filter_map(move |event| {
-            ready(value_notification(event, &device_id, services.clone()))
+            ready(value_notification(event, &device_id, &gatt_db))
         })))
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let descriptor_info = self.descriptor_info(descriptor)?;
-        Ok(self
-            .session
-            .write_descriptor_value(&descriptor_info.id, data)
-            .await?)
+        let details = || format!("{} {}", descriptor.uuid, LoggedValue(data));
+        self.operation("write_descriptor", details, async {
+            let descriptor_info = self.descriptor_info(descriptor)?;
+            Ok(self
+                .session
+                .write_descriptor_value(&descriptor_info.id, data)
+                .await?)
+        })
+        .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let descriptor_info = self.descriptor_info(descriptor)?;
-        Ok(self
-            .session
-            .read_descriptor_value(&descriptor_info.id)
-            .await?)
+        let details = || descriptor.uuid.to_string();
+        self.operation("read_descriptor", details, async {
+            let descriptor_info = self.descriptor_info(descriptor)?;
+            Ok(self
+                .session
+                .read_descriptor_value(&descriptor_info.id)
+                .await?)
+        })
+        .await
+    }
+
+    fn write_latency(&self, characteristic: &Characteristic) -> Option<LatencyHistogram> {
+        self.connection.write_latency(&self.device, characteristic)
+    }
+
+    fn recent_activity(&self) -> Vec<ActivityRecord> {
+        self.activity_log().records()
     }
 }
 
 fn value_notification(
     event: BluetoothEvent,
     device_id: &DeviceId,
-    services: Arc<Mutex<HashMap<Uuid, ServiceInternal>>>,
+    gatt_db: &RwLock<Arc<GattDb>>,
 ) -> Option<ValueNotification> {
     match event {
         BluetoothEvent::Characteristic {
             id,
             event: CharacteristicEvent::Value { value },
         } if id.service().device() == *device_id => {
-            let services = services.lock().unwrap();
-            let uuid = find_characteristic_by_id(&services, id)?.uuid;
-            Some(ValueNotification { uuid, value })
+            let received_at = Instant::now();
+            let gatt_db = gatt_db.read().unwrap().clone();
+            let uuid = find_characteristic_by_id(&gatt_db.services, id)?.uuid;
+            trace!("Notification from {}: {}", uuid, LoggedValue(&value));
+            Some(ValueNotification::new(uuid, value).with_received_at(received_at))
         }
         _ => None,
     }
@@ -347,17 +904,19 @@ fn make_descriptor(
 
 fn make_characteristic(
     characteristic: &CharacteristicInternal,
-    service_uuid: Uuid,
+    service: &ServiceInfo,
 ) -> Characteristic {
     let CharacteristicInternal { info, descriptors } = characteristic;
+    let service_uuid = service.uuid;
     Characteristic {
         uuid: info.uuid,
         properties: info.flags.into(),
         descriptors: descriptors
-            .iter()
-            .map(|(_, descriptor)| make_descriptor(descriptor, info.uuid, service_uuid))
+            .values()
+            .map(|descriptor| make_descriptor(descriptor, info.uuid, service_uuid))
             .collect(),
         service_uuid,
+        service_primary: service.primary,
     }
 }
 
@@ -368,9 +927,11 @@ impl From<&ServiceInternal> for Service {
             primary: service.info.primary,
             characteristics: service
                 .characteristics
-                .iter()
-                .map(|(_, characteristic)| make_characteristic(characteristic, service.info.uuid))
+                .values()
+                .map(|characteristic| make_characteristic(characteristic, &service.info))
                 .collect(),
+            handles: service.handles,
+            included_services: service.included_services.clone(),
         }
     }
 }
@@ -405,3 +966,72 @@ impl From<CharacteristicFlags> for CharPropFlags {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::Variant;
+
+    fn object(
+        interface: &str,
+        properties: Vec<(&str, Box<dyn RefArg>)>,
+    ) -> HashMap<String, PropMap> {
+        let properties = properties
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), Variant(value)))
+            .collect();
+        HashMap::from([(interface.to_string(), properties)])
+    }
+
+    #[test]
+    fn handles_and_included_services() {
+        let service = Path::from("/org/bluez/hci0/dev_00_00_00_00_00_01/service0010");
+        let included = Path::from("/org/bluez/hci0/dev_00_00_00_00_00_01/service0020");
+        let objects: ManagedObjects = HashMap::from([
+            (
+                service.clone(),
+                object(
+                    SERVICE_INTERFACE,
+                    vec![
+                        ("Handle", Box::new(0x10u16)),
+                        ("Includes", Box::new(vec![included.clone()])),
+                    ],
+                ),
+            ),
+            (
+                Path::from(format!("{}/char0011", service)),
+                object(
+                    CHARACTERISTIC_INTERFACE,
+                    vec![("Handle", Box::new(0x11u16))],
+                ),
+            ),
+            (
+                Path::from(format!("{}/char0014", service)),
+                object(
+                    CHARACTERISTIC_INTERFACE,
+                    vec![("Handle", Box::new(0x14u16))],
+                ),
+            ),
+            (
+                Path::from(format!("{}/char0011/desc0013", service)),
+                object(DESCRIPTOR_INTERFACE, vec![("Handle", Box::new(0x13u16))]),
+            ),
+            // Older versions of BlueZ don't report handles.
+            (included.clone(), object(SERVICE_INTERFACE, vec![])),
+        ]);
+
+        assert_eq!(
+            service_handles(&objects, &service),
+            Some(HandleRange {
+                start: 0x10,
+                end: 0x15
+            })
+        );
+        assert_eq!(service_handles(&objects, &included), None);
+        assert_eq!(
+            included_service_paths(&objects, &service).collect::<Vec<_>>(),
+            [&*included]
+        );
+        assert_eq!(included_service_paths(&objects, &included).count(), 0);
+    }
+}
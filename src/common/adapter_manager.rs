@@ -11,12 +11,13 @@
 // following copyright:
 //
 // Copyright (c) 2014 The Rust Project Developers
-use crate::api::{CentralEvent, Peripheral};
+use crate::api::{pairing::PairingAgent, tags::PeripheralTags, CentralEvent, Peripheral};
 use crate::platform::PeripheralId;
 use dashmap::{mapref::one::RefMut, DashMap};
 use futures::stream::{Stream, StreamExt};
 use log::trace;
 use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 use tokio::sync::broadcast;
 use tokio_stream::wrappers::BroadcastStream;
 
@@ -27,6 +28,8 @@ where
 {
     peripherals: DashMap<PeripheralId, PeripheralType>,
     events_channel: broadcast::Sender<CentralEvent>,
+    tags: PeripheralTags,
+    pairing_agent: RwLock<Option<Arc<dyn PairingAgent>>>,
 }
 
 impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<PeripheralType> {
@@ -35,6 +38,8 @@ impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<Peripheral
         AdapterManager {
             peripherals: DashMap::new(),
             events_channel: broadcast_sender,
+            tags: PeripheralTags::default(),
+            pairing_agent: RwLock::new(None),
         }
     }
 }
@@ -45,7 +50,7 @@ where
 {
     pub fn emit(&self, event: CentralEvent) {
         match event {
-            CentralEvent::DeviceDisconnected(ref id) => {
+            CentralEvent::DeviceDisconnected(ref id) | CentralEvent::DeviceLost(ref id) => {
                 self.peripherals.remove(id);
             }
             _ => {}
@@ -92,4 +97,17 @@ where
     pub fn peripheral(&self, id: &PeripheralId) -> Option<PeripheralType> {
         self.peripherals.get(id).map(|val| val.value().clone())
     }
+
+    pub fn peripheral_tags(&self) -> &PeripheralTags {
+        &self.tags
+    }
+
+    pub fn set_pairing_agent(&self, agent: Arc<dyn PairingAgent>) {
+        *self.pairing_agent.write().unwrap() = Some(agent);
+    }
+
+    /// Returns the agent registered to respond to pairing requests, if any.
+    pub fn pairing_agent(&self) -> Option<Arc<dyn PairingAgent>> {
+        self.pairing_agent.read().unwrap().clone()
+    }
 }
@@ -1,22 +1,81 @@
-use super::internal::{run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage};
+use super::internal::{
+    run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage, CoreBluetoothReply,
+    CoreBluetoothReplyFuture,
+};
 use super::peripheral::{Peripheral, PeripheralId};
-use crate::api::{Central, CentralEvent, ScanFilter};
+use super::peripheral_manager_delegate::LocalWrite;
+use crate::api::advertising::Advertisement;
+use crate::api::gatt_server::{LocalCharacteristic, LocalService, ServerEvent};
+use crate::api::{tags::PeripheralTags, AttError, Capabilities, Central, CentralEvent, ScanFilter};
 use crate::common::adapter_manager::AdapterManager;
 use crate::{Error, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::{self, Sender};
+use futures::future::ready;
 use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 use log::*;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 use tokio::task;
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
     manager: Arc<AdapterManager<Peripheral>>,
     sender: Sender<CoreBluetoothMessage>,
+    /// The services registered on the peripheral manager, whose handlers answer requests.
+    local_services: Arc<Mutex<Vec<LocalService>>>,
+    server_events: broadcast::Sender<ServerEvent>,
+}
+
+/// Returns the registered characteristic with the given UUIDs, if any.
+fn local_characteristic(
+    local_services: &Mutex<Vec<LocalService>>,
+    service_uuid: Uuid,
+    characteristic_uuid: Uuid,
+) -> Option<LocalCharacteristic> {
+    local_services
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|service| service.uuid == service_uuid)
+        .flat_map(|service| &service.characteristics)
+        .find(|characteristic| characteristic.uuid == characteristic_uuid)
+        .cloned()
+}
+
+/// Makes writes which a central made together, so that the first which fails fails them all.
+async fn write_local(
+    local_services: &Mutex<Vec<LocalService>>,
+    writes: Vec<LocalWrite>,
+) -> std::result::Result<(), AttError> {
+    for write in writes {
+        let characteristic = local_characteristic(
+            local_services,
+            write.service_uuid,
+            write.characteristic_uuid,
+        )
+        .ok_or(AttError::ATTRIBUTE_NOT_FOUND)?;
+        characteristic.check_write(&write.value, write.offset)?;
+        characteristic
+            .handler
+            .write(write.value, write.offset)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Waits for the reply to a message which either succeeds or fails with a description.
+async fn reply_result(fut: CoreBluetoothReplyFuture) -> Result<()> {
+    match fut.await {
+        CoreBluetoothReply::Ok => Ok(()),
+        CoreBluetoothReply::Err(msg) => Err(Error::RuntimeError(msg)),
+        _ => panic!("Shouldn't get anything but Ok or Err!"),
+    }
 }
 
 impl Adapter {
@@ -37,9 +96,13 @@ impl Adapter {
         }
         debug!("Adapter connected");
         let manager = Arc::new(AdapterManager::default());
+        let local_services = Arc::new(Mutex::new(Vec::new()));
+        let (server_events, _) = broadcast::channel(16);
 
         let manager_clone = manager.clone();
         let adapter_sender_clone = adapter_sender.clone();
+        let local_services_clone = local_services.clone();
+        let server_events_clone = server_events.clone();
         task::spawn(async move {
             while let Some(msg) = receiver.next().await {
                 match msg {
@@ -67,6 +130,87 @@ impl Adapter {
                     CoreBluetoothEvent::DeviceDisconnected { uuid } => {
                         manager_clone.emit(CentralEvent::DeviceDisconnected(uuid.into()));
                     }
+                    CoreBluetoothEvent::ReadRequest {
+                        request_id,
+                        service_uuid,
+                        characteristic_uuid,
+                        offset,
+                    } => {
+                        let characteristic = local_characteristic(
+                            &local_services_clone,
+                            service_uuid,
+                            characteristic_uuid,
+                        );
+                        let mut sender = adapter_sender_clone.clone();
+                        // Handlers may take a while, so don't hold up other events for them.
+                        task::spawn(async move {
+                            let result = match characteristic {
+                                Some(characteristic) => characteristic.handler.read(offset).await,
+                                None => Err(AttError::ATTRIBUTE_NOT_FOUND),
+                            };
+                            let message =
+                                CoreBluetoothMessage::RespondToRead { request_id, result };
+                            if let Err(e) = sender.send(message).await {
+                                error!("Error responding to read request: {}", e);
+                            }
+                        });
+                    }
+                    CoreBluetoothEvent::WriteRequest { request_id, writes } => {
+                        let local_services = local_services_clone.clone();
+                        let mut sender = adapter_sender_clone.clone();
+                        task::spawn(async move {
+                            let result = write_local(&local_services, writes).await;
+                            let message =
+                                CoreBluetoothMessage::RespondToWrite { request_id, result };
+                            if let Err(e) = sender.send(message).await {
+                                error!("Error responding to write request: {}", e);
+                            }
+                        });
+                    }
+                    CoreBluetoothEvent::CentralSubscribed {
+                        service_uuid,
+                        characteristic_uuid,
+                        first,
+                    } => {
+                        let _ = server_events_clone.send(ServerEvent::SubscriptionAdded {
+                            central: None,
+                            service: service_uuid,
+                            characteristic: characteristic_uuid,
+                        });
+                        if let Some(characteristic) = local_characteristic(
+                            &local_services_clone,
+                            service_uuid,
+                            characteristic_uuid,
+                        )
+                        .filter(|_| first)
+                        {
+                            task::spawn(async move {
+                                characteristic.handler.notifying_changed(true).await
+                            });
+                        }
+                    }
+                    CoreBluetoothEvent::CentralUnsubscribed {
+                        service_uuid,
+                        characteristic_uuid,
+                        last,
+                    } => {
+                        let _ = server_events_clone.send(ServerEvent::SubscriptionRemoved {
+                            central: None,
+                            service: service_uuid,
+                            characteristic: characteristic_uuid,
+                        });
+                        if let Some(characteristic) = local_characteristic(
+                            &local_services_clone,
+                            service_uuid,
+                            characteristic_uuid,
+                        )
+                        .filter(|_| last)
+                        {
+                            task::spawn(async move {
+                                characteristic.handler.notifying_changed(false).await
+                            });
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -75,6 +219,8 @@ impl Adapter {
         Ok(Adapter {
             manager,
             sender: adapter_sender,
+            local_services,
+            server_events,
         })
     }
 }
@@ -121,4 +267,109 @@ impl Central for Adapter {
         // TODO: Get information about the adapter.
         Ok("CoreBluetooth".to_string())
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            advertising: true,
+            gatt_server: true,
+            concurrent_roles: true,
+            ..Default::default()
+        }
+    }
+
+    /// CoreBluetooth only advertises the local name and service UUIDs, and its advertisements are
+    /// always connectable, so other advertisements fail with [`Error::NotSupported`].
+    async fn start_advertising(&self, advertisement: Advertisement) -> Result<()> {
+        if !advertisement.manufacturer_data.is_empty()
+            || !advertisement.service_data.is_empty()
+            || advertisement.include_tx_power
+        {
+            return Err(Error::NotSupported(
+                "CoreBluetooth can only advertise the local name and service UUIDs".to_string(),
+            ));
+        }
+        if !advertisement.connectable {
+            return Err(Error::NotSupported(
+                "CoreBluetooth advertisements are always connectable".to_string(),
+            ));
+        }
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::StartAdvertising {
+                advertisement,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        reply_result(fut).await
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::StopAdvertising)
+            .await?;
+        Ok(())
+    }
+
+    async fn register_gatt_services(&self, services: Vec<LocalService>) -> Result<()> {
+        *self.local_services.lock().unwrap() = services.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::RegisterServices {
+                services,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        let result = reply_result(fut).await;
+        if result.is_err() {
+            self.local_services.lock().unwrap().clear();
+        }
+        result
+    }
+
+    async fn unregister_gatt_services(&self) -> Result<()> {
+        self.local_services.lock().unwrap().clear();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::UnregisterServices)
+            .await?;
+        Ok(())
+    }
+
+    async fn notify_local_characteristic(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        if local_characteristic(&self.local_services, service, characteristic).is_none() {
+            return Err(Error::NoSuchCharacteristic);
+        }
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::NotifyLocalCharacteristic {
+                service_uuid: service,
+                characteristic_uuid: characteristic,
+                value: value.to_vec(),
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        reply_result(fut).await
+    }
+
+    /// CoreBluetooth doesn't tell centrals' addresses, so only subscriptions are reported, without
+    /// saying which central made them.
+    async fn gatt_server_events(&self) -> Result<Pin<Box<dyn Stream<Item = ServerEvent> + Send>>> {
+        let events = self.server_events.subscribe();
+        Ok(Box::pin(
+            BroadcastStream::new(events).filter_map(|event| ready(event.ok())),
+        ))
+    }
+
+    fn peripheral_tags(&self) -> Option<&PeripheralTags> {
+        Some(self.manager.peripheral_tags())
+    }
 }
@@ -16,6 +16,7 @@
 // This file may not be copied, modified, or distributed except
 // according to those terms.
 
+use super::internal::CoreBluetoothReply;
 use super::{
     framework::{cb, ns},
     utils::{
@@ -25,7 +26,9 @@ use super::{
         nsuuid_to_uuid,
     },
 };
+use crate::api::{redaction::LoggedValue, AttError};
 use cocoa::base::{id, nil};
+use cocoa::foundation::NSInteger;
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::sink::SinkExt;
 use libc::c_void;
@@ -44,6 +47,7 @@ use std::{
     ops::Deref,
     slice,
     sync::Once,
+    time::Instant,
 };
 use uuid::Uuid;
 
@@ -110,12 +114,27 @@ pub enum CentralDelegateEvent {
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
         data: Vec<u8>,
+        received_at: Instant,
     },
     CharacteristicWritten {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
     },
+    /// A read of the characteristic failed; `reply` is the error to fail the read with.
+    CharacteristicReadFailed {
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        reply: CoreBluetoothReply,
+    },
+    /// A write to the characteristic failed; `reply` is the error to fail the write with.
+    CharacteristicWriteFailed {
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        reply: CoreBluetoothReply,
+    },
     DescriptorNotified {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
@@ -209,12 +228,14 @@ impl Debug for CentralDelegateEvent {
                 service_uuid,
                 characteristic_uuid,
                 data,
+                received_at,
             } => f
                 .debug_struct("CharacteristicNotified")
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
                 .field("data", data)
+                .field("received_at", received_at)
                 .finish(),
             CentralDelegateEvent::CharacteristicWritten {
                 peripheral_uuid,
@@ -226,6 +247,30 @@ impl Debug for CentralDelegateEvent {
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
                 .finish(),
+            CentralDelegateEvent::CharacteristicReadFailed {
+                peripheral_uuid,
+                service_uuid,
+                characteristic_uuid,
+                reply,
+            } => f
+                .debug_struct("CharacteristicReadFailed")
+                .field("peripheral_uuid", peripheral_uuid)
+                .field("service_uuid", service_uuid)
+                .field("characteristic_uuid", characteristic_uuid)
+                .field("reply", reply)
+                .finish(),
+            CentralDelegateEvent::CharacteristicWriteFailed {
+                peripheral_uuid,
+                service_uuid,
+                characteristic_uuid,
+                reply,
+            } => f
+                .debug_struct("CharacteristicWriteFailed")
+                .field("peripheral_uuid", peripheral_uuid)
+                .field("service_uuid", service_uuid)
+                .field("characteristic_uuid", characteristic_uuid)
+                .field("reply", reply)
+                .finish(),
             CentralDelegateEvent::ManufacturerData {
                 peripheral_uuid,
                 manufacturer_id,
@@ -383,6 +428,20 @@ pub mod CentralDelegate {
         }
     }
 
+    /// Converts an `NSError` from a GATT operation into the reply to fail the operation with.
+    /// Errors in `CBATTErrorDomain` carry the ATT error code sent by the device, which is how
+    /// insufficient authentication or encryption is reported after the OS tried to pair.
+    fn error_reply(error: id) -> CoreBluetoothReply {
+        let domain: id = unsafe { msg_send![error, domain] };
+        if nsstring_to_string(domain).as_deref() == Some("CBATTErrorDomain") {
+            let code: NSInteger = unsafe { msg_send![error, code] };
+            if let Ok(code) = u8::try_from(code) {
+                return CoreBluetoothReply::AttError(AttError(code));
+            }
+        }
+        CoreBluetoothReply::Err(localized_description(error))
+    }
+
     ////////////////////////////////////////////////////////////////
     //
     // Utility functions
@@ -421,7 +480,10 @@ pub mod CentralDelegate {
         trace!("Getting data!");
         let value = cb::characteristic_value(characteristic);
         let v = nsdata_to_vec(value);
-        trace!("BluetoothGATTCharacteristic::get_value -> {:?}", v);
+        trace!(
+            "BluetoothGATTCharacteristic::get_value -> {}",
+            LoggedValue(&v)
+        );
         v
     }
 
@@ -748,6 +810,7 @@ pub mod CentralDelegate {
             localized_description(error)
         );
         if error == nil {
+            let received_at = Instant::now();
             let service = cb::characteristic_service(characteristic);
             send_delegate_event(
                 delegate,
@@ -756,9 +819,21 @@ pub mod CentralDelegate {
                     service_uuid: cbuuid_to_uuid(cb::attribute_uuid(service)),
                     characteristic_uuid: cbuuid_to_uuid(cb::attribute_uuid(characteristic)),
                     data: get_characteristic_value(characteristic),
+                    received_at,
                 },
             );
             // Notify BluetoothGATTCharacteristic::read_value that read was successful.
+        } else {
+            let service = cb::characteristic_service(characteristic);
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::CharacteristicReadFailed {
+                    peripheral_uuid: nsuuid_to_uuid(cb::peer_identifier(peripheral)),
+                    service_uuid: cbuuid_to_uuid(cb::attribute_uuid(service)),
+                    characteristic_uuid: cbuuid_to_uuid(cb::attribute_uuid(characteristic)),
+                    reply: error_reply(error),
+                },
+            );
         }
     }
 
@@ -785,6 +860,17 @@ pub mod CentralDelegate {
                     characteristic_uuid: cbuuid_to_uuid(cb::attribute_uuid(characteristic)),
                 },
             );
+        } else {
+            let service = cb::characteristic_service(characteristic);
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::CharacteristicWriteFailed {
+                    peripheral_uuid: nsuuid_to_uuid(cb::peer_identifier(peripheral)),
+                    service_uuid: cbuuid_to_uuid(cb::attribute_uuid(service)),
+                    characteristic_uuid: cbuuid_to_uuid(cb::attribute_uuid(characteristic)),
+                    reply: error_reply(error),
+                },
+            );
         }
     }
 
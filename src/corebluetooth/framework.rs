@@ -18,7 +18,7 @@
 
 use cocoa::{
     base::{id, nil},
-    foundation::{NSArray, NSData, NSDictionary, NSString, NSUInteger},
+    foundation::{NSArray, NSData, NSDictionary, NSInteger, NSString, NSUInteger},
 };
 use objc::runtime::BOOL;
 use objc::{class, msg_send, sel, sel_impl};
@@ -123,6 +123,7 @@ pub mod cb {
 
         #[link(name = "CoreBluetooth", kind = "framework")]
         extern "C" {
+            pub static CBAdvertisementDataLocalNameKey: id;
             pub static CBAdvertisementDataManufacturerDataKey: id;
             pub static CBAdvertisementDataServiceDataKey: id;
             pub static CBAdvertisementDataServiceUUIDsKey: id;
@@ -172,6 +173,65 @@ pub mod cb {
         unsafe { msg_send![cbcentralmanager, cancelPeripheralConnection: peripheral] }
     }
 
+    // CBPeripheralManager
+
+    pub fn peripheralmanager(delegate: id /*CBPeripheralManagerDelegate* */) -> id /*CBPeripheralManager* */
+    {
+        let label = CString::new("CBPeripheralQueue").unwrap();
+        unsafe {
+            let cbperipheralmanager: id = msg_send![class!(CBPeripheralManager), alloc];
+            let queue = dispatch_queue_create(label.as_ptr(), DISPATCH_QUEUE_SERIAL);
+
+            msg_send![cbperipheralmanager, initWithDelegate:delegate queue:queue options:nil]
+        }
+    }
+
+    pub fn peripheralmanager_state(cbperipheralmanager: id) -> CBManagerState {
+        unsafe { msg_send![cbperipheralmanager, state] }
+    }
+
+    pub fn peripheralmanager_addservice(
+        cbperipheralmanager: id,
+        service: id, /* CBMutableService* */
+    ) {
+        unsafe { msg_send![cbperipheralmanager, addService: service] }
+    }
+
+    pub fn peripheralmanager_removeallservices(cbperipheralmanager: id) {
+        unsafe { msg_send![cbperipheralmanager, removeAllServices] }
+    }
+
+    pub fn peripheralmanager_startadvertising(
+        cbperipheralmanager: id,
+        advertisement_data: id, /* NSDictionary<NSString*,id> */
+    ) {
+        unsafe { msg_send![cbperipheralmanager, startAdvertising: advertisement_data] }
+    }
+
+    pub fn peripheralmanager_stopadvertising(cbperipheralmanager: id) {
+        unsafe { msg_send![cbperipheralmanager, stopAdvertising] }
+    }
+
+    /// Returns `NO` if the transmit queue is full, in which case
+    /// `peripheralManagerIsReadyToUpdateSubscribers:` is called once there is room again.
+    pub fn peripheralmanager_updatevalue_forcharacteristic(
+        cbperipheralmanager: id,
+        value: id,          /* NSData* */
+        characteristic: id, /* CBMutableCharacteristic* */
+    ) -> BOOL {
+        unsafe {
+            msg_send![cbperipheralmanager, updateValue:value forCharacteristic:characteristic onSubscribedCentrals:nil]
+        }
+    }
+
+    pub fn peripheralmanager_respondtorequest_withresult(
+        cbperipheralmanager: id,
+        request: id, /* CBATTRequest* */
+        result: NSInteger,
+    ) {
+        unsafe { msg_send![cbperipheralmanager, respondToRequest:request withResult:result] }
+    }
+
     // CBManager
     pub fn manager_authorization() -> CBManagerAuthorization {
         unsafe { msg_send![class!(CBManager), authorization] }
@@ -186,6 +246,17 @@ pub mod cb {
         AllowedAlways = 3,
     }
 
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    #[repr(i64)]
+    pub enum CBManagerState {
+        Unknown = 0,
+        Resetting = 1,
+        Unsupported = 2,
+        Unauthorized = 3,
+        PoweredOff = 4,
+        PoweredOn = 5,
+    }
+
     // CBPeer
 
     pub fn peer_identifier(cbpeer: id) -> id /* NSUUID* */ {
@@ -315,6 +386,22 @@ pub mod cb {
         unsafe { msg_send![cbservice, characteristics] }
     }
 
+    // CBMutableService : CBService
+
+    pub fn mutableservice(uuid: id /* CBUUID* */, primary: BOOL) -> id /* CBMutableService* */ {
+        unsafe {
+            let cbservice: id = msg_send![class!(CBMutableService), alloc];
+            msg_send![cbservice, initWithType:uuid primary:primary]
+        }
+    }
+
+    pub fn mutableservice_setcharacteristics(
+        cbservice: id,
+        characteristics: id, /* NSArray<CBMutableCharacteristic*>* */
+    ) {
+        unsafe { msg_send![cbservice, setCharacteristics: characteristics] }
+    }
+
     // CBCharacteristic : CBAttribute
 
     pub fn characteristic_isnotifying(cbcharacteristic: id) -> BOOL {
@@ -337,6 +424,52 @@ pub mod cb {
         unsafe { msg_send![cbcharacteristic, descriptors] }
     }
 
+    // CBMutableCharacteristic : CBCharacteristic
+
+    /// Creates a characteristic whose value is given in response to each request, rather than
+    /// cached by CoreBluetooth.
+    pub fn mutablecharacteristic(
+        uuid: id, /* CBUUID* */
+        properties: c_uint,
+        permissions: c_uint,
+    ) -> id /* CBMutableCharacteristic* */ {
+        unsafe {
+            let cbcharacteristic: id = msg_send![class!(CBMutableCharacteristic), alloc];
+            msg_send![cbcharacteristic, initWithType:uuid properties:properties value:nil permissions:permissions]
+        }
+    }
+
+    // CBAttributePermissions = NSUInteger from CBCharacteristic.h
+
+    pub const ATTRIBUTEPERMISSION_READABLE: c_uint = 0x01; // CBAttributePermissionsReadable
+    pub const ATTRIBUTEPERMISSION_WRITEABLE: c_uint = 0x02; // CBAttributePermissionsWriteable
+
+    // CBATTRequest
+
+    pub fn attrequest_central(cbattrequest: id) -> id /* CBCentral* */ {
+        unsafe { msg_send![cbattrequest, central] }
+    }
+
+    pub fn attrequest_characteristic(cbattrequest: id) -> id /* CBCharacteristic* */ {
+        unsafe { msg_send![cbattrequest, characteristic] }
+    }
+
+    pub fn attrequest_offset(cbattrequest: id) -> NSUInteger {
+        unsafe { msg_send![cbattrequest, offset] }
+    }
+
+    pub fn attrequest_value(cbattrequest: id) -> id /* NSData* */ {
+        unsafe { msg_send![cbattrequest, value] }
+    }
+
+    pub fn attrequest_setvalue(cbattrequest: id, value: id /* NSData* */) {
+        unsafe { msg_send![cbattrequest, setValue: value] }
+    }
+
+    // CBATTError = NSInteger from CBError.h, which uses the ATT error codes.
+
+    pub const ATTERROR_SUCCESS: NSInteger = 0; // CBATTErrorSuccess
+
     // CBDescriptor : CBAttribute
 
     pub fn descriptor_characteristic(cbdescriptor: id) -> id /* CBCharacteristic* */ {
@@ -369,6 +502,7 @@ pub mod cb {
 
     // CBAdvertisementData...Key
 
+    pub use self::link::CBAdvertisementDataLocalNameKey as ADVERTISEMENT_DATA_LOCAL_NAME_KEY;
     pub use self::link::CBAdvertisementDataManufacturerDataKey as ADVERTISEMENT_DATA_MANUFACTURER_DATA_KEY;
     pub use self::link::CBAdvertisementDataServiceDataKey as ADVERTISEMENT_DATA_SERVICE_DATA_KEY;
     pub use self::link::CBAdvertisementDataServiceUUIDsKey as ADVERTISEMENT_DATA_SERVICE_UUIDS_KEY;
@@ -8,22 +8,27 @@
 // For more info on handling CoreBluetooth Managers (and possibly having
 // multiple), see https://forums.developer.apple.com/thread/20810
 
+#[cfg(feature = "unstable-raw-handles")]
+use super::peripheral::RawPeripheral;
 use super::{
     central_delegate::{CentralDelegate, CentralDelegateEvent},
     framework::{
-        cb::{self, CBManagerAuthorization, CBPeripheralState},
+        cb::{self, CBManagerAuthorization, CBManagerState, CBPeripheralState},
         ns,
     },
     future::{BtlePlugFuture, BtlePlugFutureStateShared},
+    peripheral_manager_delegate::{
+        LocalWrite, PeripheralManagerDelegate, PeripheralManagerDelegateEvent,
+    },
     utils::{
         core_bluetooth::{cbuuid_to_uuid, uuid_to_cbuuid},
-        nsstring::nsstring_to_string,
+        nsstring::{nsstring_to_string, str_to_nsstring},
         nsuuid_to_uuid,
     },
 };
 use crate::api::{
-    bleuuid::uuid_from_u16, CharPropFlags, Characteristic, Descriptor, ScanFilter, Service,
-    WriteType,
+    advertising::Advertisement, bleuuid::uuid_from_u16, gatt_server::LocalService, AttError,
+    CharPropFlags, Characteristic, Descriptor, ScanFilter, Service, WriteType,
 };
 use crate::Error;
 use cocoa::{
@@ -35,12 +40,17 @@ use futures::select;
 use futures::sink::SinkExt;
 use futures::stream::{Fuse, StreamExt};
 use log::{error, trace, warn};
-use objc::{rc::StrongPtr, runtime::YES};
+use objc::{
+    rc::StrongPtr,
+    runtime::{NO, YES},
+};
+use std::os::raw::c_uint;
 use std::{
-    collections::{BTreeSet, HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     fmt::{self, Debug, Formatter},
     ops::Deref,
     thread,
+    time::Instant,
 };
 use tokio::runtime;
 use uuid::Uuid;
@@ -146,16 +156,19 @@ impl CBCharacteristic {
 #[derive(Clone, Debug)]
 pub enum CoreBluetoothReply {
     ReadResult(Vec<u8>),
+    #[cfg(feature = "unstable-raw-handles")]
+    Peripheral(RawPeripheral),
     Connected(BTreeSet<Service>),
     State(CBPeripheralState),
     Ok,
+    AttError(AttError),
     Err(String),
 }
 
 #[derive(Debug)]
 pub enum CBPeripheralEvent {
     Disconnected,
-    Notification(Uuid, Vec<u8>),
+    Notification(Uuid, Vec<u8>, Instant),
     ManufacturerData(u16, Vec<u8>, i16),
     ServiceData(HashMap<Uuid, Vec<u8>>, i16),
     Services(Vec<Uuid>, i16),
@@ -276,30 +289,44 @@ impl CBPeripheral {
             let services = self
                 .services
                 .iter()
-                .map(|(&service_uuid, service)| Service {
-                    uuid: service_uuid,
-                    primary: cb::service_isprimary(*service.cbservice) != objc::runtime::NO,
-                    characteristics: service
-                        .characteristics
-                        .iter()
-                        .map(|(&characteristic_uuid, characteristic)| {
-                            let descriptors = characteristic
-                                .descriptors
-                                .iter()
-                                .map(|(&descriptor_uuid, _)| Descriptor {
-                                    uuid: descriptor_uuid,
-                                    service_uuid,
-                                    characteristic_uuid,
-                                })
-                                .collect();
-                            Characteristic {
-                                uuid: characteristic_uuid,
-                                service_uuid,
-                                descriptors,
-                                properties: characteristic.properties,
-                            }
+                .map(|(&service_uuid, service)| {
+                    let primary = cb::service_isprimary(*service.cbservice) != objc::runtime::NO;
+                    // Included services were discovered before the descriptors of any
+                    // characteristic, as CoreBluetooth makes the requests in order.
+                    let includes = cb::service_includedservices(*service.cbservice);
+                    let included_services = (0..ns::array_count(includes))
+                        .map(|i| {
+                            cbuuid_to_uuid(cb::attribute_uuid(ns::array_objectatindex(includes, i)))
                         })
-                        .collect(),
+                        .collect();
+                    Service {
+                        uuid: service_uuid,
+                        primary,
+                        characteristics: service
+                            .characteristics
+                            .iter()
+                            .map(|(&characteristic_uuid, characteristic)| {
+                                let descriptors = characteristic
+                                    .descriptors
+                                    .iter()
+                                    .map(|(&descriptor_uuid, _)| Descriptor {
+                                        uuid: descriptor_uuid,
+                                        service_uuid,
+                                        characteristic_uuid,
+                                    })
+                                    .collect();
+                                Characteristic {
+                                    uuid: characteristic_uuid,
+                                    service_uuid,
+                                    service_primary: primary,
+                                    descriptors,
+                                    properties: characteristic.properties,
+                                }
+                            })
+                            .collect(),
+                        handles: None,
+                        included_services,
+                    }
                 })
                 .collect();
             self.connected_future_state
@@ -334,6 +361,25 @@ struct CoreBluetoothInternal {
     // task::block this when sending even though it'll never actually block.
     event_sender: Sender<CoreBluetoothEvent>,
     message_receiver: Fuse<Receiver<CoreBluetoothMessage>>,
+    // The peripheral role has a manager of its own, which can only be used once it is powered on.
+    peripheral_manager: StrongPtr,
+    peripheral_delegate: StrongPtr,
+    peripheral_delegate_receiver: Fuse<Receiver<PeripheralManagerDelegateEvent>>,
+    peripheral_manager_state: CBManagerState,
+    // Messages for the peripheral manager which arrived before it reported its state.
+    pending_peripheral_messages: Vec<CoreBluetoothMessage>,
+    // Map of (service, characteristic) UUIDs to the CBMutableCharacteristics published.
+    local_characteristics: HashMap<(Uuid, Uuid), StrongPtr>,
+    // The identifiers of the centrals subscribed to each local characteristic.
+    subscribers: HashMap<(Uuid, Uuid), HashSet<Uuid>>,
+    // The number of services still being added, and the future to fulfill once they all are.
+    services_future_state: Option<(usize, CoreBluetoothReplyStateShared)>,
+    advertising_future_state: Option<CoreBluetoothReplyStateShared>,
+    // Requests from centrals waiting for the adapter to respond to them.
+    requests: HashMap<u64, StrongPtr>,
+    next_request_id: u64,
+    // Notifications which didn't fit in the transmit queue, to send once there is room.
+    pending_notifications: VecDeque<(StrongPtr, Vec<u8>, CoreBluetoothReplyStateShared)>,
 }
 
 impl Debug for CoreBluetoothInternal {
@@ -345,7 +391,15 @@ impl Debug for CoreBluetoothInternal {
             .field("delegate_receiver", &self.delegate_receiver)
             .field("event_sender", &self.event_sender)
             .field("message_receiver", &self.message_receiver)
-            .finish()
+            .field("peripheral_manager", self.peripheral_manager.deref())
+            .field("peripheral_manager_state", &self.peripheral_manager_state)
+            .field(
+                "local_characteristics",
+                &self.local_characteristics.keys().collect::<Vec<_>>(),
+            )
+            .field("subscribers", &self.subscribers)
+            .field("requests", &self.requests.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
     }
 }
 
@@ -393,6 +447,11 @@ pub enum CoreBluetoothMessage {
         peripheral_uuid: Uuid,
         future: CoreBluetoothReplyStateShared,
     },
+    #[cfg(feature = "unstable-raw-handles")]
+    RawPeripheral {
+        peripheral_uuid: Uuid,
+        future: CoreBluetoothReplyStateShared,
+    },
     ReadDescriptorValue {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
@@ -408,6 +467,30 @@ pub enum CoreBluetoothMessage {
         data: Vec<u8>,
         future: CoreBluetoothReplyStateShared,
     },
+    StartAdvertising {
+        advertisement: Advertisement,
+        future: CoreBluetoothReplyStateShared,
+    },
+    StopAdvertising,
+    RegisterServices {
+        services: Vec<LocalService>,
+        future: CoreBluetoothReplyStateShared,
+    },
+    UnregisterServices,
+    NotifyLocalCharacteristic {
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        value: Vec<u8>,
+        future: CoreBluetoothReplyStateShared,
+    },
+    RespondToRead {
+        request_id: u64,
+        result: Result<Vec<u8>, AttError>,
+    },
+    RespondToWrite {
+        request_id: u64,
+        result: Result<(), AttError>,
+    },
 }
 
 #[derive(Debug)]
@@ -425,6 +508,32 @@ pub enum CoreBluetoothEvent {
     DeviceDisconnected {
         uuid: Uuid,
     },
+    /// A central read a local characteristic; the adapter responds with
+    /// [`CoreBluetoothMessage::RespondToRead`].
+    ReadRequest {
+        request_id: u64,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        offset: usize,
+    },
+    /// A central wrote to local characteristics; the adapter responds with
+    /// [`CoreBluetoothMessage::RespondToWrite`] once all the writes are made, or one fails.
+    WriteRequest {
+        request_id: u64,
+        writes: Vec<LocalWrite>,
+    },
+    /// `first` is true if no other central was subscribed to the characteristic.
+    CentralSubscribed {
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        first: bool,
+    },
+    /// `last` is true if no other central is still subscribed to the characteristic.
+    CentralUnsubscribed {
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        last: bool,
+    },
 }
 
 impl CoreBluetoothInternal {
@@ -436,6 +545,9 @@ impl CoreBluetoothInternal {
         unsafe {
             let (delegate, delegate_receiver) = CentralDelegate::delegate();
             let delegate = StrongPtr::new(delegate);
+            let (peripheral_delegate, peripheral_delegate_receiver) =
+                PeripheralManagerDelegate::delegate();
+            let peripheral_delegate = StrongPtr::new(peripheral_delegate);
             Self {
                 manager: StrongPtr::new(cb::centralmanager(*delegate)),
                 peripherals: HashMap::new(),
@@ -443,6 +555,18 @@ impl CoreBluetoothInternal {
                 event_sender,
                 message_receiver: message_receiver.fuse(),
                 delegate,
+                peripheral_manager: StrongPtr::new(cb::peripheralmanager(*peripheral_delegate)),
+                peripheral_delegate,
+                peripheral_delegate_receiver: peripheral_delegate_receiver.fuse(),
+                peripheral_manager_state: CBManagerState::Unknown,
+                pending_peripheral_messages: Vec::new(),
+                local_characteristics: HashMap::new(),
+                subscribers: HashMap::new(),
+                services_future_state: None,
+                advertising_future_state: None,
+                requests: HashMap::new(),
+                next_request_id: 0,
+                pending_notifications: VecDeque::new(),
             }
         }
     }
@@ -714,6 +838,7 @@ impl CoreBluetoothInternal {
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
         data: Vec<u8>,
+        received_at: Instant,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
             if let Some(service) = peripheral.services.get_mut(&service_uuid) {
@@ -737,7 +862,11 @@ impl CoreBluetoothInternal {
                             .set_reply(CoreBluetoothReply::ReadResult(data_clone));
                     } else if let Err(e) = peripheral
                         .event_sender
-                        .send(CBPeripheralEvent::Notification(characteristic_uuid, data))
+                        .send(CBPeripheralEvent::Notification(
+                            characteristic_uuid,
+                            data,
+                            received_at,
+                        ))
                         .await
                     {
                         error!("Error sending notification event: {}", e);
@@ -762,6 +891,41 @@ impl CoreBluetoothInternal {
         }
     }
 
+    /// Fails the oldest pending read of the characteristic, if any.
+    fn on_characteristic_read_failed(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        reply: CoreBluetoothReply,
+    ) {
+        if let Some(characteristic) =
+            self.get_characteristic(peripheral_uuid, service_uuid, characteristic_uuid)
+        {
+            trace!("Got read failed event! {:?}", reply);
+            if let Some(state) = characteristic.read_future_state.pop_back() {
+                state.lock().unwrap().set_reply(reply);
+            }
+        }
+    }
+
+    fn on_characteristic_write_failed(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        reply: CoreBluetoothReply,
+    ) {
+        if let Some(characteristic) =
+            self.get_characteristic(peripheral_uuid, service_uuid, characteristic_uuid)
+        {
+            trace!("Got write failed event! {:?}", reply);
+            if let Some(state) = characteristic.write_future_state.pop_back() {
+                state.lock().unwrap().set_reply(reply);
+            }
+        }
+    }
+
     fn connect_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
         trace!("Trying to connect peripheral!");
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
@@ -790,6 +954,17 @@ impl CoreBluetoothInternal {
         }
     }
 
+    #[cfg(feature = "unstable-raw-handles")]
+    fn raw_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        if let Some(p) = self.peripherals.get(&peripheral_uuid) {
+            fut.lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Peripheral(RawPeripheral::new(
+                    p.peripheral.clone(),
+                )));
+        }
+    }
+
     fn write_value(
         &mut self,
         peripheral_uuid: Uuid,
@@ -994,6 +1169,323 @@ impl CoreBluetoothInternal {
         }
     }
 
+    fn on_peripheral_manager_state(&mut self, state: CBManagerState) {
+        trace!("Peripheral manager state {:?}", state);
+        self.peripheral_manager_state = state;
+        if !matches!(state, CBManagerState::Unknown | CBManagerState::Resetting) {
+            for message in std::mem::take(&mut self.pending_peripheral_messages) {
+                self.handle_peripheral_message(message);
+            }
+        }
+    }
+
+    /// Handles a message which needs the peripheral manager to be powered on, holding it back
+    /// until the manager has reported its state, and failing it if the manager can't be used.
+    fn handle_peripheral_message(&mut self, message: CoreBluetoothMessage) {
+        match self.peripheral_manager_state {
+            CBManagerState::Unknown | CBManagerState::Resetting => {
+                self.pending_peripheral_messages.push(message);
+                return;
+            }
+            CBManagerState::PoweredOn => {}
+            state => {
+                let reply = CoreBluetoothReply::Err(format!(
+                    "The peripheral manager can't be used in state {:?}",
+                    state
+                ));
+                match message {
+                    CoreBluetoothMessage::StartAdvertising { future, .. }
+                    | CoreBluetoothMessage::RegisterServices { future, .. }
+                    | CoreBluetoothMessage::NotifyLocalCharacteristic { future, .. } => {
+                        future.lock().unwrap().set_reply(reply)
+                    }
+                    _ => {}
+                }
+                return;
+            }
+        }
+        match message {
+            CoreBluetoothMessage::StartAdvertising {
+                advertisement,
+                future,
+            } => self.start_advertising(advertisement, future),
+            CoreBluetoothMessage::StopAdvertising => {
+                cb::peripheralmanager_stopadvertising(*self.peripheral_manager)
+            }
+            CoreBluetoothMessage::RegisterServices { services, future } => {
+                self.register_services(services, future)
+            }
+            CoreBluetoothMessage::UnregisterServices => self.unregister_services(),
+            CoreBluetoothMessage::NotifyLocalCharacteristic {
+                service_uuid,
+                characteristic_uuid,
+                value,
+                future,
+            } => self.notify_local_characteristic(service_uuid, characteristic_uuid, value, future),
+            message => error!("Not a peripheral manager message: {:?}", message),
+        }
+    }
+
+    fn start_advertising(
+        &mut self,
+        advertisement: Advertisement,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        trace!("Starting advertising {:?}", advertisement);
+        let data = ns::mutabledictionary();
+        if let Some(local_name) = &advertisement.local_name {
+            ns::mutabledictionary_setobject_forkey(data, str_to_nsstring(local_name), unsafe {
+                cb::ADVERTISEMENT_DATA_LOCAL_NAME_KEY
+            });
+        }
+        if !advertisement.services.is_empty() {
+            let service_uuids = advertisement
+                .services
+                .iter()
+                .copied()
+                .map(uuid_to_cbuuid)
+                .collect::<Vec<_>>();
+            ns::mutabledictionary_setobject_forkey(
+                data,
+                unsafe { NSArray::arrayWithObjects(nil, &service_uuids) },
+                unsafe { cb::ADVERTISEMENT_DATA_SERVICE_UUIDS_KEY },
+            );
+        }
+        // Starting again replaces the advertisement, as on the other platforms.
+        cb::peripheralmanager_stopadvertising(*self.peripheral_manager);
+        if let Some(previous) = self.advertising_future_state.replace(fut) {
+            previous.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                "Replaced by another advertisement".to_string(),
+            ));
+        }
+        cb::peripheralmanager_startadvertising(*self.peripheral_manager, data);
+    }
+
+    fn on_advertising_started(&mut self, error: Option<String>) {
+        trace!("Advertising started {:?}", error);
+        if let Some(state) = self.advertising_future_state.take() {
+            state.lock().unwrap().set_reply(match error {
+                Some(error) => CoreBluetoothReply::Err(error),
+                None => CoreBluetoothReply::Ok,
+            });
+        }
+    }
+
+    fn register_services(
+        &mut self,
+        services: Vec<LocalService>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        self.unregister_services();
+        if services.is_empty() {
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+            return;
+        }
+        self.services_future_state = Some((services.len(), fut));
+        for service in services {
+            trace!("Adding service {}", service.uuid);
+            let cbservice = unsafe {
+                StrongPtr::new(cb::mutableservice(
+                    uuid_to_cbuuid(service.uuid),
+                    if service.primary { YES } else { NO },
+                ))
+            };
+            let characteristics = service
+                .characteristics
+                .iter()
+                .map(|characteristic| {
+                    let (properties, permissions) = local_properties(characteristic.properties);
+                    let cbcharacteristic = unsafe {
+                        StrongPtr::new(cb::mutablecharacteristic(
+                            uuid_to_cbuuid(characteristic.uuid),
+                            properties,
+                            permissions,
+                        ))
+                    };
+                    let ptr = *cbcharacteristic;
+                    self.local_characteristics
+                        .insert((service.uuid, characteristic.uuid), cbcharacteristic);
+                    ptr
+                })
+                .collect::<Vec<_>>();
+            cb::mutableservice_setcharacteristics(*cbservice, unsafe {
+                NSArray::arrayWithObjects(nil, &characteristics)
+            });
+            cb::peripheralmanager_addservice(*self.peripheral_manager, *cbservice);
+        }
+    }
+
+    fn on_service_added(&mut self, service_uuid: Uuid, error: Option<String>) {
+        trace!("Service {} added {:?}", service_uuid, error);
+        let Some((remaining, state)) = self.services_future_state.take() else {
+            return;
+        };
+        if let Some(error) = error {
+            // Don't leave the services which were added published.
+            self.unregister_services();
+            state
+                .lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err(error));
+        } else if remaining == 1 {
+            state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+        } else {
+            self.services_future_state = Some((remaining - 1, state));
+        }
+    }
+
+    fn unregister_services(&mut self) {
+        cb::peripheralmanager_removeallservices(*self.peripheral_manager);
+        self.local_characteristics.clear();
+        self.subscribers.clear();
+        let pending = self
+            .pending_notifications
+            .drain(..)
+            .map(|(_, _, state)| state)
+            .chain(self.services_future_state.take().map(|(_, state)| state));
+        for state in pending {
+            state.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                "Services were unregistered".to_string(),
+            ));
+        }
+    }
+
+    fn notify_local_characteristic(
+        &mut self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        value: Vec<u8>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        let key = (service_uuid, characteristic_uuid);
+        let Some(characteristic) = self.local_characteristics.get(&key) else {
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                "No such local characteristic".to_string(),
+            ));
+            return;
+        };
+        if self.subscribers.get(&key).map_or(true, HashSet::is_empty) {
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+            return;
+        }
+        // Keep notifications in order behind those waiting for room in the transmit queue.
+        self.pending_notifications
+            .push_back((characteristic.clone(), value, fut));
+        self.send_pending_notifications();
+    }
+
+    fn send_pending_notifications(&mut self) {
+        while let Some((characteristic, value, state)) = self.pending_notifications.pop_front() {
+            if cb::peripheralmanager_updatevalue_forcharacteristic(
+                *self.peripheral_manager,
+                ns::data(&value),
+                *characteristic,
+            ) == NO
+            {
+                trace!("Transmit queue full, waiting to notify");
+                self.pending_notifications
+                    .push_front((characteristic, value, state));
+                return;
+            }
+            state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+        }
+    }
+
+    fn add_request(&mut self, request: StrongPtr) -> u64 {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.requests.insert(request_id, request);
+        request_id
+    }
+
+    async fn on_read_request(
+        &mut self,
+        request: StrongPtr,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        offset: usize,
+    ) {
+        let request_id = self.add_request(request);
+        self.dispatch_event(CoreBluetoothEvent::ReadRequest {
+            request_id,
+            service_uuid,
+            characteristic_uuid,
+            offset,
+        })
+        .await;
+    }
+
+    async fn on_write_requests(&mut self, request: StrongPtr, writes: Vec<LocalWrite>) {
+        let request_id = self.add_request(request);
+        self.dispatch_event(CoreBluetoothEvent::WriteRequest { request_id, writes })
+            .await;
+    }
+
+    fn respond_to_request(&mut self, request_id: u64, result: Result<Option<Vec<u8>>, AttError>) {
+        let Some(request) = self.requests.remove(&request_id) else {
+            warn!("Response to unknown request {}", request_id);
+            return;
+        };
+        let result = match result {
+            Ok(value) => {
+                if let Some(value) = value {
+                    cb::attrequest_setvalue(*request, ns::data(&value));
+                }
+                cb::ATTERROR_SUCCESS
+            }
+            Err(error) => error.0.into(),
+        };
+        cb::peripheralmanager_respondtorequest_withresult(
+            *self.peripheral_manager,
+            *request,
+            result,
+        );
+    }
+
+    async fn on_central_subscribed(
+        &mut self,
+        central_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) {
+        let subscribers = self
+            .subscribers
+            .entry((service_uuid, characteristic_uuid))
+            .or_default();
+        let first = subscribers.is_empty();
+        if subscribers.insert(central_uuid) {
+            self.dispatch_event(CoreBluetoothEvent::CentralSubscribed {
+                service_uuid,
+                characteristic_uuid,
+                first,
+            })
+            .await;
+        }
+    }
+
+    async fn on_central_unsubscribed(
+        &mut self,
+        central_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) {
+        let Some(subscribers) = self
+            .subscribers
+            .get_mut(&(service_uuid, characteristic_uuid))
+        else {
+            return;
+        };
+        if subscribers.remove(&central_uuid) {
+            let last = subscribers.is_empty();
+            self.dispatch_event(CoreBluetoothEvent::CentralUnsubscribed {
+                service_uuid,
+                characteristic_uuid,
+                last,
+            })
+            .await;
+        }
+    }
+
     async fn wait_for_message(&mut self) {
         select! {
             delegate_msg = self.delegate_receiver.select_next_some() => {
@@ -1043,12 +1535,25 @@ impl CoreBluetoothInternal {
                         service_uuid,
                         characteristic_uuid,
                         data,
-                     } => self.on_characteristic_read(peripheral_uuid, service_uuid,characteristic_uuid, data).await,
+                        received_at,
+                     } => self.on_characteristic_read(peripheral_uuid, service_uuid,characteristic_uuid, data, received_at).await,
                     CentralDelegateEvent::CharacteristicWritten{
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
                     } => self.on_characteristic_written(peripheral_uuid, service_uuid, characteristic_uuid),
+                    CentralDelegateEvent::CharacteristicReadFailed{
+                        peripheral_uuid,
+                        service_uuid,
+                        characteristic_uuid,
+                        reply,
+                    } => self.on_characteristic_read_failed(peripheral_uuid, service_uuid, characteristic_uuid, reply),
+                    CentralDelegateEvent::CharacteristicWriteFailed{
+                        peripheral_uuid,
+                        service_uuid,
+                        characteristic_uuid,
+                        reply,
+                    } => self.on_characteristic_write_failed(peripheral_uuid, service_uuid, characteristic_uuid, reply),
                     CentralDelegateEvent::ManufacturerData{peripheral_uuid, manufacturer_id, data, rssi} => {
                         self.on_manufacturer_data(peripheral_uuid, manufacturer_id, data, rssi).await
                     },
@@ -1073,6 +1578,34 @@ impl CoreBluetoothInternal {
                     } => self.on_descriptor_written(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid),
                 };
             }
+            peripheral_delegate_msg = self.peripheral_delegate_receiver.select_next_some() => {
+                match peripheral_delegate_msg {
+                    PeripheralManagerDelegateEvent::DidUpdateState{state} => {
+                        self.on_peripheral_manager_state(state)
+                    }
+                    PeripheralManagerDelegateEvent::AddedService{service_uuid, error} => {
+                        self.on_service_added(service_uuid, error)
+                    }
+                    PeripheralManagerDelegateEvent::StartedAdvertising{error} => {
+                        self.on_advertising_started(error)
+                    }
+                    PeripheralManagerDelegateEvent::ReadRequest{request, service_uuid, characteristic_uuid, offset} => {
+                        self.on_read_request(request, service_uuid, characteristic_uuid, offset).await
+                    }
+                    PeripheralManagerDelegateEvent::WriteRequests{request, writes} => {
+                        self.on_write_requests(request, writes).await
+                    }
+                    PeripheralManagerDelegateEvent::CentralSubscribed{central_uuid, service_uuid, characteristic_uuid} => {
+                        self.on_central_subscribed(central_uuid, service_uuid, characteristic_uuid).await
+                    }
+                    PeripheralManagerDelegateEvent::CentralUnsubscribed{central_uuid, service_uuid, characteristic_uuid} => {
+                        self.on_central_unsubscribed(central_uuid, service_uuid, characteristic_uuid).await
+                    }
+                    PeripheralManagerDelegateEvent::ReadyToUpdateSubscribers => {
+                        self.send_pending_notifications()
+                    }
+                };
+            }
             adapter_msg = self.message_receiver.select_next_some() => {
                 trace!("Adapter message!");
                 match adapter_msg {
@@ -1104,6 +1637,10 @@ impl CoreBluetoothInternal {
                     CoreBluetoothMessage::IsConnected{peripheral_uuid, future} => {
                         self.is_connected(peripheral_uuid, future);
                     },
+                    #[cfg(feature = "unstable-raw-handles")]
+                    CoreBluetoothMessage::RawPeripheral{peripheral_uuid, future} => {
+                        self.raw_peripheral(peripheral_uuid, future);
+                    },
                     CoreBluetoothMessage::ReadDescriptorValue{peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, future} => {
                         self.read_descriptor_value(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, future)
                     }
@@ -1114,6 +1651,13 @@ impl CoreBluetoothInternal {
                         data,
                         future,
                     } => self.write_descriptor_value(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, data, future),
+                    CoreBluetoothMessage::RespondToRead{request_id, result} => {
+                        self.respond_to_request(request_id, result.map(Some))
+                    }
+                    CoreBluetoothMessage::RespondToWrite{request_id, result} => {
+                        self.respond_to_request(request_id, result.map(|()| None))
+                    }
+                    message => self.handle_peripheral_message(message),
                 };
             }
         }
@@ -1141,6 +1685,33 @@ impl CoreBluetoothInternal {
     }
 }
 
+/// Returns the `CBCharacteristicProperties` and `CBAttributePermissions` to publish a local
+/// characteristic with. CoreBluetooth doesn't allow broadcast or extended properties on published
+/// characteristics.
+fn local_properties(flags: CharPropFlags) -> (c_uint, c_uint) {
+    let mut properties = 0;
+    let mut permissions = 0;
+    if flags.contains(CharPropFlags::READ) {
+        properties |= cb::CHARACTERISTICPROPERTY_READ;
+        permissions |= cb::ATTRIBUTEPERMISSION_READABLE;
+    }
+    if flags.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+        properties |= cb::CHARACTERISTICPROPERTY_WRITEWITHOUTRESPONSE;
+        permissions |= cb::ATTRIBUTEPERMISSION_WRITEABLE;
+    }
+    if flags.contains(CharPropFlags::WRITE) {
+        properties |= cb::CHARACTERISTICPROPERTY_WRITE;
+        permissions |= cb::ATTRIBUTEPERMISSION_WRITEABLE;
+    }
+    if flags.contains(CharPropFlags::NOTIFY) {
+        properties |= cb::CHARACTERISTICPROPERTY_NOTIFY;
+    }
+    if flags.contains(CharPropFlags::INDICATE) {
+        properties |= cb::CHARACTERISTICPROPERTY_INDICATE;
+    }
+    (properties, permissions)
+}
+
 /// Convert a `ScanFilter` to the appropriate `NSArray<CBUUID *> *` to use for discovery. If the
 /// filter has an empty list of services then this will return `nil`, to discover all devices.
 fn scan_filter_to_service_uuids(filter: ScanFilter) -> id {
@@ -1162,6 +1733,11 @@ impl Drop for CoreBluetoothInternal {
         // NOTE: stop discovery only here instead of in BluetoothDiscoverySession
         self.stop_discovery();
         CentralDelegate::delegate_drop_channel(*self.delegate);
+        if self.peripheral_manager_state == CBManagerState::PoweredOn {
+            cb::peripheralmanager_stopadvertising(*self.peripheral_manager);
+            cb::peripheralmanager_removeallservices(*self.peripheral_manager);
+        }
+        PeripheralManagerDelegate::delegate_drop_channel(*self.peripheral_delegate);
     }
 }
 
@@ -12,4 +12,5 @@ mod future;
 mod internal;
 pub mod manager;
 pub mod peripheral;
+mod peripheral_manager_delegate;
 mod utils;
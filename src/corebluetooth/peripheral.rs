@@ -24,6 +24,8 @@ use futures::channel::mpsc::{Receiver, SendError, Sender};
 use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 use log::*;
+#[cfg(feature = "unstable-raw-handles")]
+use objc::rc::StrongPtr;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
@@ -53,6 +55,14 @@ impl Display for PeripheralId {
     }
 }
 
+#[cfg(test)]
+impl PeripheralId {
+    /// Makes up an ID for the device with the given address, which CoreBluetooth doesn't expose.
+    pub(crate) fn for_test(address: BDAddr) -> Self {
+        PeripheralId(Uuid::from_u128(u64::from(address).into()))
+    }
+}
+
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone)]
 pub struct Peripheral {
@@ -101,7 +111,13 @@ impl Peripheral {
             manufacturer_data: HashMap::new(),
             service_data: HashMap::new(),
             services: Vec::new(),
+            // CoreBluetooth doesn't expose the appearance or class of a device.
+            appearance: None,
             class: None,
+            // CoreBluetooth doesn't expose the advertisement flags.
+            advertising_flags: None,
+            advertising_interval: None,
+            last_advertisement: None,
         });
         let (notifications_channel, _) = broadcast::channel(16);
 
@@ -120,8 +136,9 @@ impl Peripheral {
 
             loop {
                 match event_receiver.next().await {
-                    Some(CBPeripheralEvent::Notification(uuid, data)) => {
-                        let notification = ValueNotification { uuid, value: data };
+                    Some(CBPeripheralEvent::Notification(uuid, data, received_at)) => {
+                        let notification =
+                            ValueNotification::new(uuid, data).with_received_at(received_at);
 
                         // Note: we ignore send errors here which may happen while there are no
                         // receivers...
@@ -130,23 +147,34 @@ impl Peripheral {
                     Some(CBPeripheralEvent::ManufacturerData(manufacturer_id, data, rssi)) => {
                         let mut properties = shared.properties.lock().unwrap();
                         properties.rssi = Some(rssi);
-                        properties
+                        let previous = properties
                             .manufacturer_data
                             .insert(manufacturer_id, data.clone());
                         shared.emit_event(CentralEvent::ManufacturerDataAdvertisement {
                             id: shared.uuid.into(),
                             manufacturer_data: properties.manufacturer_data.clone(),
                         });
+                        // Devices may change their advertised state while connected, so make sure
+                        // DeviceUpdated subscribers hear about it too.
+                        if previous.as_ref() != Some(&data) {
+                            shared.emit_event(CentralEvent::DeviceUpdated(shared.uuid.into()));
+                        }
                     }
                     Some(CBPeripheralEvent::ServiceData(service_data, rssi)) => {
                         let mut properties = shared.properties.lock().unwrap();
                         properties.rssi = Some(rssi);
+                        let changed = service_data
+                            .iter()
+                            .any(|(uuid, data)| properties.service_data.get(uuid) != Some(data));
                         properties.service_data.extend(service_data.clone());
 
                         shared.emit_event(CentralEvent::ServiceDataAdvertisement {
                             id: shared.uuid.into(),
                             service_data,
                         });
+                        if changed {
+                            shared.emit_event(CentralEvent::DeviceUpdated(shared.uuid.into()));
+                        }
                     }
                     Some(CBPeripheralEvent::Services(services, rssi)) => {
                         let mut properties = shared.properties.lock().unwrap();
@@ -195,6 +223,62 @@ impl Debug for Peripheral {
     }
 }
 
+/// A retained `CBPeripheral *`.
+///
+/// CoreBluetooth delivers the peripheral's delegate callbacks to btleplug, so don't replace its
+/// delegate, and only call its methods on the queue btleplug's central manager runs on.
+#[cfg(feature = "unstable-raw-handles")]
+#[derive(Clone)]
+pub struct RawPeripheral(StrongPtr);
+
+// SAFETY: Retaining and releasing Objective-C objects is thread-safe, and the pointer itself is
+// only dereferenced by the caller of `as_ptr`.
+#[cfg(feature = "unstable-raw-handles")]
+unsafe impl Send for RawPeripheral {}
+#[cfg(feature = "unstable-raw-handles")]
+unsafe impl Sync for RawPeripheral {}
+
+#[cfg(feature = "unstable-raw-handles")]
+impl RawPeripheral {
+    pub(crate) fn new(peripheral: StrongPtr) -> Self {
+        Self(peripheral)
+    }
+
+    /// Returns the `CBPeripheral *`, which stays valid as long as this is alive.
+    pub fn as_ptr(&self) -> cocoa::base::id {
+        *self.0
+    }
+}
+
+#[cfg(feature = "unstable-raw-handles")]
+impl Debug for RawPeripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("RawPeripheral").field(&*self.0).finish()
+    }
+}
+
+/// Unstable access to the CoreBluetooth objects behind the peripheral, for operations btleplug
+/// doesn't cover yet.
+#[cfg(feature = "unstable-raw-handles")]
+impl Peripheral {
+    /// Returns the `CBPeripheral`.
+    pub async fn raw_peripheral(&self) -> Result<RawPeripheral> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.shared
+            .message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::RawPeripheral {
+                peripheral_uuid: self.shared.uuid,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Peripheral(peripheral) => Ok(peripheral),
+            _ => panic!("Shouldn't get anything but a Peripheral!"),
+        }
+    }
+}
+
 #[async_trait]
 impl api::Peripheral for Peripheral {
     fn id(&self) -> PeripheralId {
@@ -311,6 +395,8 @@ impl api::Peripheral for Peripheral {
             .await?;
         match fut.await {
             CoreBluetoothReply::Ok => {}
+            CoreBluetoothReply::AttError(error) => return Err(Error::Att(error)),
+            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
             reply => panic!("Unexpected reply: {:?}", reply),
         }
         Ok(())
@@ -330,6 +416,8 @@ impl api::Peripheral for Peripheral {
             .await?;
         match fut.await {
             CoreBluetoothReply::ReadResult(chars) => Ok(chars),
+            CoreBluetoothReply::AttError(error) => Err(Error::Att(error)),
+            CoreBluetoothReply::Err(msg) => Err(Error::RuntimeError(msg)),
             _ => {
                 panic!("Shouldn't get anything but read result!");
             }
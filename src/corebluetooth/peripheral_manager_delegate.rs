@@ -0,0 +1,417 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+    framework::{cb, ns},
+    utils::{
+        core_bluetooth::{cbuuid_to_uuid, characteristic_debug},
+        nsdata_to_vec,
+        nsstring::nsstring_to_string,
+        nsuuid_to_uuid,
+    },
+};
+use cocoa::base::{id, nil};
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::sink::SinkExt;
+use libc::c_void;
+use log::{error, trace};
+use objc::{
+    class,
+    declare::ClassDecl,
+    rc::StrongPtr,
+    runtime::{Class, Object, Protocol, Sel},
+};
+use objc::{msg_send, sel, sel_impl};
+use std::{
+    fmt::{self, Debug, Formatter},
+    ops::Deref,
+    sync::Once,
+};
+use uuid::Uuid;
+
+/// A write to a local characteristic, one of those a central may make together.
+#[derive(Debug)]
+pub struct LocalWrite {
+    pub service_uuid: Uuid,
+    pub characteristic_uuid: Uuid,
+    pub offset: usize,
+    pub value: Vec<u8>,
+}
+
+pub enum PeripheralManagerDelegateEvent {
+    DidUpdateState {
+        state: cb::CBManagerState,
+    },
+    AddedService {
+        service_uuid: Uuid,
+        error: Option<String>,
+    },
+    StartedAdvertising {
+        error: Option<String>,
+    },
+    ReadRequest {
+        request: StrongPtr,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        offset: usize,
+    },
+    /// `request` is the first of the requests, which is the one to respond to for all of them.
+    WriteRequests {
+        request: StrongPtr,
+        writes: Vec<LocalWrite>,
+    },
+    CentralSubscribed {
+        central_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    },
+    CentralUnsubscribed {
+        central_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    },
+    ReadyToUpdateSubscribers,
+}
+
+impl Debug for PeripheralManagerDelegateEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            PeripheralManagerDelegateEvent::DidUpdateState { state } => f
+                .debug_struct("DidUpdateState")
+                .field("state", state)
+                .finish(),
+            PeripheralManagerDelegateEvent::AddedService {
+                service_uuid,
+                error,
+            } => f
+                .debug_struct("AddedService")
+                .field("service_uuid", service_uuid)
+                .field("error", error)
+                .finish(),
+            PeripheralManagerDelegateEvent::StartedAdvertising { error } => f
+                .debug_struct("StartedAdvertising")
+                .field("error", error)
+                .finish(),
+            PeripheralManagerDelegateEvent::ReadRequest {
+                request,
+                service_uuid,
+                characteristic_uuid,
+                offset,
+            } => f
+                .debug_struct("ReadRequest")
+                .field("request", request.deref())
+                .field("service_uuid", service_uuid)
+                .field("characteristic_uuid", characteristic_uuid)
+                .field("offset", offset)
+                .finish(),
+            PeripheralManagerDelegateEvent::WriteRequests { request, writes } => f
+                .debug_struct("WriteRequests")
+                .field("request", request.deref())
+                .field("writes", writes)
+                .finish(),
+            PeripheralManagerDelegateEvent::CentralSubscribed {
+                central_uuid,
+                service_uuid,
+                characteristic_uuid,
+            } => f
+                .debug_struct("CentralSubscribed")
+                .field("central_uuid", central_uuid)
+                .field("service_uuid", service_uuid)
+                .field("characteristic_uuid", characteristic_uuid)
+                .finish(),
+            PeripheralManagerDelegateEvent::CentralUnsubscribed {
+                central_uuid,
+                service_uuid,
+                characteristic_uuid,
+            } => f
+                .debug_struct("CentralUnsubscribed")
+                .field("central_uuid", central_uuid)
+                .field("service_uuid", service_uuid)
+                .field("characteristic_uuid", characteristic_uuid)
+                .finish(),
+            PeripheralManagerDelegateEvent::ReadyToUpdateSubscribers => {
+                f.debug_tuple("ReadyToUpdateSubscribers").finish()
+            }
+        }
+    }
+}
+
+pub mod PeripheralManagerDelegate {
+    use super::*;
+
+    pub fn delegate() -> (id, Receiver<PeripheralManagerDelegateEvent>) {
+        let (sender, receiver) = mpsc::channel::<PeripheralManagerDelegateEvent>(256);
+        let sendbox = Box::new(sender);
+        let delegate = unsafe {
+            let mut delegate: id = msg_send![delegate_class(), alloc];
+            delegate = msg_send![
+                delegate,
+                initWithSender: Box::into_raw(sendbox) as *mut c_void
+            ];
+            delegate
+        };
+        (delegate, receiver)
+    }
+
+    pub fn delegate_drop_channel(delegate: id) {
+        unsafe {
+            let _ = Box::from_raw(*(&*delegate).get_ivar::<*mut c_void>(DELEGATE_SENDER_IVAR)
+                as *mut Sender<PeripheralManagerDelegateEvent>);
+        }
+    }
+
+    const DELEGATE_SENDER_IVAR: &str = "_sender";
+
+    fn delegate_class() -> &'static Class {
+        trace!("delegate_class");
+        static REGISTER_DELEGATE_CLASS: Once = Once::new();
+        REGISTER_DELEGATE_CLASS.call_once(|| {
+            let mut decl = ClassDecl::new("BtlePlugPeripheralManagerDelegate", class!(NSObject)).unwrap();
+            decl.add_protocol(Protocol::get("CBPeripheralManagerDelegate").unwrap());
+
+            decl.add_ivar::<*mut c_void>(DELEGATE_SENDER_IVAR); /* futures::channel::mpsc::Sender<PeripheralManagerDelegateEvent>* */
+            unsafe {
+                // Initialization
+                decl.add_method(sel!(initWithSender:),
+                                delegate_init as extern fn(&mut Object, Sel, *mut c_void) -> id);
+
+                // PeripheralManager Events
+                decl.add_method(sel!(peripheralManagerDidUpdateState:),
+                                delegate_peripheralmanagerdidupdatestate as extern fn(&mut Object, Sel, id));
+                decl.add_method(sel!(peripheralManager:didAddService:error:),
+                                delegate_peripheralmanager_didaddservice_error as extern fn(&mut Object, Sel, id, id, id));
+                decl.add_method(sel!(peripheralManagerDidStartAdvertising:error:),
+                                delegate_peripheralmanagerdidstartadvertising_error as extern fn(&mut Object, Sel, id, id));
+                decl.add_method(sel!(peripheralManager:didReceiveReadRequest:),
+                                delegate_peripheralmanager_didreceivereadrequest as extern fn(&mut Object, Sel, id, id));
+                decl.add_method(sel!(peripheralManager:didReceiveWriteRequests:),
+                                delegate_peripheralmanager_didreceivewriterequests as extern fn(&mut Object, Sel, id, id));
+                decl.add_method(sel!(peripheralManager:central:didSubscribeToCharacteristic:),
+                                delegate_peripheralmanager_central_didsubscribetocharacteristic as extern fn(&mut Object, Sel, id, id, id));
+                decl.add_method(sel!(peripheralManager:central:didUnsubscribeFromCharacteristic:),
+                                delegate_peripheralmanager_central_didunsubscribefromcharacteristic as extern fn(&mut Object, Sel, id, id, id));
+                decl.add_method(sel!(peripheralManagerIsReadyToUpdateSubscribers:),
+                                delegate_peripheralmanagerisreadytoupdatesubscribers as extern fn(&mut Object, Sel, id));
+            }
+
+            decl.register();
+        });
+
+        class!(BtlePlugPeripheralManagerDelegate)
+    }
+
+    fn localized_description(error: id) -> Option<String> {
+        if error == nil {
+            None
+        } else {
+            let nsstring = unsafe { msg_send![error, localizedDescription] };
+            Some(nsstring_to_string(nsstring).unwrap_or_else(|| "".to_string()))
+        }
+    }
+
+    /// Returns the UUIDs of a local characteristic and the service it belongs to.
+    fn characteristic_uuids(characteristic: id) -> (Uuid, Uuid) {
+        let service = cb::characteristic_service(characteristic);
+        (
+            cbuuid_to_uuid(cb::attribute_uuid(service)),
+            cbuuid_to_uuid(cb::attribute_uuid(characteristic)),
+        )
+    }
+
+    ////////////////////////////////////////////////////////////////
+    //
+    // Utility functions
+    //
+    ////////////////////////////////////////////////////////////////
+
+    fn delegate_get_sender_clone(delegate: &mut Object) -> Sender<PeripheralManagerDelegateEvent> {
+        unsafe {
+            (*(*(&*delegate).get_ivar::<*mut c_void>(DELEGATE_SENDER_IVAR)
+                as *mut Sender<PeripheralManagerDelegateEvent>))
+                .clone()
+        }
+    }
+
+    fn send_delegate_event(delegate: &mut Object, event: PeripheralManagerDelegateEvent) {
+        let mut sender = delegate_get_sender_clone(delegate);
+        futures::executor::block_on(async {
+            if let Err(e) = sender.send(event).await {
+                error!("Error sending delegate event: {}", e);
+            }
+        });
+    }
+
+    extern "C" fn delegate_init(delegate: &mut Object, _cmd: Sel, sender: *mut c_void) -> id {
+        trace!("delegate_init");
+        unsafe {
+            trace!("Storing off ivars!");
+            delegate.set_ivar(DELEGATE_SENDER_IVAR, sender);
+        }
+        delegate
+    }
+
+    ////////////////////////////////////////////////////////////////
+    //
+    // PeripheralManager Handlers
+    //
+    ////////////////////////////////////////////////////////////////
+
+    extern "C" fn delegate_peripheralmanagerdidupdatestate(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral_manager: id,
+    ) {
+        trace!("delegate_peripheralmanagerdidupdatestate");
+        let state = cb::peripheralmanager_state(peripheral_manager);
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::DidUpdateState { state },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanager_didaddservice_error(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+        service: id,
+        error: id,
+    ) {
+        trace!("delegate_peripheralmanager_didaddservice_error");
+        let service_uuid = cbuuid_to_uuid(cb::attribute_uuid(service));
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::AddedService {
+                service_uuid,
+                error: localized_description(error),
+            },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanagerdidstartadvertising_error(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+        error: id,
+    ) {
+        trace!("delegate_peripheralmanagerdidstartadvertising_error");
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::StartedAdvertising {
+                error: localized_description(error),
+            },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanager_didreceivereadrequest(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+        request: id,
+    ) {
+        let characteristic = cb::attrequest_characteristic(request);
+        trace!(
+            "delegate_peripheralmanager_didreceivereadrequest {}",
+            characteristic_debug(characteristic)
+        );
+        let (service_uuid, characteristic_uuid) = characteristic_uuids(characteristic);
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::ReadRequest {
+                request: unsafe { StrongPtr::retain(request) },
+                service_uuid,
+                characteristic_uuid,
+                offset: cb::attrequest_offset(request) as usize,
+            },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanager_didreceivewriterequests(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+        requests: id, /* NSArray<CBATTRequest*>* */
+    ) {
+        trace!("delegate_peripheralmanager_didreceivewriterequests");
+        let writes = (0..ns::array_count(requests))
+            .map(|i| {
+                let request = ns::array_objectatindex(requests, i);
+                let (service_uuid, characteristic_uuid) =
+                    characteristic_uuids(cb::attrequest_characteristic(request));
+                LocalWrite {
+                    service_uuid,
+                    characteristic_uuid,
+                    offset: cb::attrequest_offset(request) as usize,
+                    value: nsdata_to_vec(cb::attrequest_value(request)),
+                }
+            })
+            .collect::<Vec<_>>();
+        if writes.is_empty() {
+            return;
+        }
+        let request = unsafe { StrongPtr::retain(ns::array_objectatindex(requests, 0)) };
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::WriteRequests { request, writes },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanager_central_didsubscribetocharacteristic(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+        central: id,
+        characteristic: id,
+    ) {
+        trace!(
+            "delegate_peripheralmanager_central_didsubscribetocharacteristic {}",
+            characteristic_debug(characteristic)
+        );
+        let (service_uuid, characteristic_uuid) = characteristic_uuids(characteristic);
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::CentralSubscribed {
+                central_uuid: nsuuid_to_uuid(cb::peer_identifier(central)),
+                service_uuid,
+                characteristic_uuid,
+            },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanager_central_didunsubscribefromcharacteristic(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+        central: id,
+        characteristic: id,
+    ) {
+        trace!(
+            "delegate_peripheralmanager_central_didunsubscribefromcharacteristic {}",
+            characteristic_debug(characteristic)
+        );
+        let (service_uuid, characteristic_uuid) = characteristic_uuids(characteristic);
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::CentralUnsubscribed {
+                central_uuid: nsuuid_to_uuid(cb::peer_identifier(central)),
+                service_uuid,
+                characteristic_uuid,
+            },
+        );
+    }
+
+    extern "C" fn delegate_peripheralmanagerisreadytoupdatesubscribers(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _peripheral_manager: id,
+    ) {
+        trace!("delegate_peripheralmanagerisreadytoupdatesubscribers");
+        send_delegate_event(
+            delegate,
+            PeripheralManagerDelegateEvent::ReadyToUpdateSubscribers,
+        );
+    }
+}
@@ -6,7 +6,7 @@ use super::{
     peripheral::{Peripheral, PeripheralId},
 };
 use crate::{
-    api::{BDAddr, Central, CentralEvent, PeripheralProperties, ScanFilter},
+    api::{tags::PeripheralTags, BDAddr, Central, CentralEvent, PeripheralProperties, ScanFilter},
     common::adapter_manager::AdapterManager,
     Error, Result,
 };
@@ -132,6 +132,10 @@ impl Central for Adapter {
         Ok("Android".to_string())
     }
 
+    fn peripheral_tags(&self) -> Option<&PeripheralTags> {
+        Some(self.manager.peripheral_tags())
+    }
+
     async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
         Ok(self.manager.event_stream())
     }
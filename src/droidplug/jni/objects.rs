@@ -10,7 +10,7 @@ use jni_utils::{future::JFuture, stream::JStream, uuid::JUuid};
 use std::{collections::HashMap, convert::TryFrom, iter::Iterator};
 use uuid::Uuid;
 
-use crate::api::{BDAddr, CharPropFlags, PeripheralProperties, ScanFilter};
+use crate::api::{AdvertisingFlags, BDAddr, CharPropFlags, PeripheralProperties, ScanFilter};
 
 pub struct JPeripheral<'a: 'b, 'b> {
     internal: JObject<'a>,
@@ -724,6 +724,12 @@ impl<'a: 'b, 'b> TryFrom<JScanResult<'a, 'b>> for (BDAddr, Option<PeripheralProp
                 }
             }
 
+            // getAdvertiseFlags returns -1 if the advertisement had no Flags field.
+            let advertising_flags = match record.get_advertise_flags()? {
+                -1 => None,
+                flags => Some(AdvertisingFlags::from_bits_truncate(flags as u8)),
+            };
+
             Some(PeripheralProperties {
                 address: addr,
                 address_type: None,
@@ -733,7 +739,11 @@ impl<'a: 'b, 'b> TryFrom<JScanResult<'a, 'b>> for (BDAddr, Option<PeripheralProp
                 service_data,
                 services,
                 rssi,
+                appearance: None,
                 class: None,
+                advertising_flags,
+                advertising_interval: None,
+                last_advertisement: None,
             })
         };
         Ok((addr, properties))
@@ -747,6 +757,7 @@ pub struct JScanRecord<'a: 'b, 'b> {
     get_manufacturer_specific_data: JMethodID<'a>,
     get_service_data: JMethodID<'a>,
     get_service_uuids: JMethodID<'a>,
+    get_advertise_flags: JMethodID<'a>,
     env: &'b JNIEnv<'a>,
 }
 
@@ -778,6 +789,7 @@ impl<'a: 'b, 'b> JScanRecord<'a, 'b> {
         let get_service_data = env.get_method_id(&class, "getServiceData", "()Ljava/util/Map;")?;
         let get_service_uuids =
             env.get_method_id(&class, "getServiceUuids", "()Ljava/util/List;")?;
+        let get_advertise_flags = env.get_method_id(&class, "getAdvertiseFlags", "()I")?;
         Ok(Self {
             internal: obj,
             get_device_name,
@@ -785,6 +797,7 @@ impl<'a: 'b, 'b> JScanRecord<'a, 'b> {
             get_manufacturer_specific_data,
             get_service_data,
             get_service_uuids,
+            get_advertise_flags,
             env,
         })
     }
@@ -813,6 +826,17 @@ impl<'a: 'b, 'b> JScanRecord<'a, 'b> {
             .i()
     }
 
+    pub fn get_advertise_flags(&self) -> Result<jint> {
+        self.env
+            .call_method_unchecked(
+                self.internal,
+                self.get_advertise_flags,
+                JavaType::Primitive(Primitive::Int),
+                &[],
+            )?
+            .i()
+    }
+
     pub fn get_manufacturer_specific_data(&self) -> Result<JSparseArray<'a, 'b>> {
         let obj = self
             .env
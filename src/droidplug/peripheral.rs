@@ -26,6 +26,7 @@ use std::{
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use super::jni::{
@@ -46,6 +47,14 @@ impl Display for PeripheralId {
     }
 }
 
+#[cfg(test)]
+impl PeripheralId {
+    /// Makes up the ID of the device with the given address.
+    pub(crate) fn for_test(address: BDAddr) -> Self {
+        PeripheralId(address)
+    }
+}
+
 fn get_poll_result<'a: 'b, 'b>(
     env: &'b JNIEnv<'a>,
     result: JPollResult<'a, 'b>,
@@ -140,7 +149,6 @@ fn get_poll_result<'a: 'b, 'b>(
 
 struct PeripheralShared {
     services: BTreeSet<Service>,
-    characteristics: BTreeSet<Characteristic>,
     properties: Option<PeripheralProperties>,
 }
 
@@ -159,7 +167,6 @@ impl Peripheral {
             internal: env.new_global_ref(obj)?,
             shared: Arc::new(Mutex::new(PeripheralShared {
                 services: BTreeSet::new(),
-                characteristics: BTreeSet::new(),
                 properties: None,
             })),
         })
@@ -222,11 +229,6 @@ impl api::Peripheral for Peripheral {
         Ok((&guard.properties).clone())
     }
 
-    fn characteristics(&self) -> BTreeSet<Characteristic> {
-        let guard = self.shared.lock().unwrap();
-        (&guard.characteristics).clone()
-    }
-
     async fn is_connected(&self) -> Result<bool> {
         self.with_obj(|_env, obj| Ok(obj.is_connected()?))
     }
@@ -266,10 +268,10 @@ impl api::Peripheral for Peripheral {
             let obj = get_poll_result(env, result)?;
             let list = JList::from_env(env, obj)?;
             let mut peripheral_services = Vec::new();
-            let mut peripheral_characteristics = Vec::new();
 
             for service in list.iter()? {
                 let service = JBluetoothGattService::from_env(env, service)?;
+                let primary = service.is_primary()?;
                 let mut characteristics = BTreeSet::new();
                 for characteristic in service.get_characteristics()? {
                     let mut descriptors = BTreeSet::new();
@@ -282,26 +284,20 @@ impl api::Peripheral for Peripheral {
                     }
                     characteristics.insert(Characteristic {
                         service_uuid: service.get_uuid()?,
+                        service_primary: primary,
                         uuid: characteristic.get_uuid()?,
                         properties: characteristic.get_properties()?,
-                        descriptors: descriptors.clone(),
-                    });
-                    peripheral_characteristics.push(Characteristic {
-                        service_uuid: service.get_uuid()?,
-                        uuid: characteristic.get_uuid()?,
-                        properties: characteristic.get_properties()?,
-                        descriptors: descriptors,
+                        descriptors,
                     });
                 }
-                peripheral_services.push(Service {
-                    uuid: service.get_uuid()?,
-                    primary: service.is_primary()?,
+                peripheral_services.push(Service::new(
+                    service.get_uuid()?,
+                    primary,
                     characteristics,
-                })
+                ))
             }
             let mut guard = self.shared.lock().unwrap();
-            guard.services = BTreeSet::from_iter(peripheral_services.clone());
-            guard.characteristics = BTreeSet::from_iter(peripheral_characteristics.clone());
+            guard.services = BTreeSet::from_iter(peripheral_services);
             Ok(())
         })
     }
@@ -357,12 +353,13 @@ impl api::Peripheral for Peripheral {
         let stream = stream
             .map(|item| match item {
                 Ok(item) => {
+                    let received_at = Instant::now();
                     let env = global_jvm().get_env()?;
                     let item = item.as_obj();
                     let characteristic = JBluetoothGattCharacteristic::from_env(&env, item)?;
                     let uuid = characteristic.get_uuid()?;
                     let value = characteristic.get_value()?;
-                    Ok(ValueNotification { uuid, value })
+                    Ok(ValueNotification::new(uuid, value).with_received_at(received_at))
                 }
                 Err(err) => Err(err),
             })
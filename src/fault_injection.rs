@@ -0,0 +1,983 @@
+//! A decorator backend which injects faults into another backend, for chaos testing applications
+//! against the kinds of misbehaviour real BLE devices and stacks exhibit.
+//!
+//! Wrap an adapter in a [`FaultyCentral`] and use it in place of the original; peripherals obtained
+//! from it are wrapped in [`FaultyPeripheral`]s which fail, delay or disconnect according to a
+//! [`FaultConfig`]. Faults are drawn from a seeded pseudo-random generator, so a failing run can be
+//! reproduced with the same seed.
+//!
+//! An [`RfModel`] adds a simulated radio environment on top, in which peripherals move closer and
+//! further away along a scripted timeline. This makes proximity classifiers and reconnect policies
+//! testable against weak signals, lost packets and devices going out of range.
+
+use crate::api::activity::ActivityRecord;
+use crate::api::advertising::Advertisement;
+use crate::api::diagnostics::LatencyHistogram;
+use crate::api::energy::ScanDutyCycle;
+use crate::api::gatt_server::{LocalService, ServerEvent};
+use crate::api::l2cap::L2capChannel;
+use crate::api::pairing::PairingAgent;
+use crate::api::self_test::SelfTestReport;
+use crate::api::tags::PeripheralTags;
+use crate::api::{
+    AdapterInfo, AdapterState, AttError, BDAddr, CacheMode, Capabilities, Central, CentralEvent,
+    Characteristic, ConnectOptions, ConnectionInfo, ConnectionParameters, Descriptor, LeAddress,
+    Peripheral, PeripheralProperties, Phy, RawService, ScanFilter, ScanOptions, SecurityLevel,
+    Service, SubscribeOptions, ValueNotification, WakeTrigger, WriteType,
+};
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt};
+use log::debug;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+/// Which faults to inject, and how often.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultConfig {
+    /// Seed for the pseudo-random generator deciding when faults occur.
+    pub seed: u64,
+    /// Probability between 0 and 1 that a GATT operation fails with an injected ATT error.
+    pub error_probability: f64,
+    /// ATT errors to pick from for injected errors. Defaults to [`AttError::UNLIKELY_ERROR`].
+    pub att_errors: Vec<AttError>,
+    /// Probability between 0 and 1 that a GATT operation instead causes a spurious disconnection.
+    pub disconnect_probability: f64,
+    /// If set, every connection is dropped this long after it is established.
+    pub disconnect_after: Option<Duration>,
+    /// If set, every notification is delivered this much later than it was received.
+    pub notification_delay: Option<Duration>,
+    /// Scripted outcomes of successive connection attempts to particular peripherals, e.g.
+    /// `[Fail, Fail]` to succeed on the third attempt. Once a peripheral's script runs out,
+    /// attempts go through to the wrapped backend.
+    pub connect_script: HashMap<PeripheralId, Vec<ConnectFault>>,
+    /// If set, the radio environment to simulate.
+    pub rf_model: Option<RfModel>,
+}
+
+/// The scripted outcome of a connection attempt.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ConnectFault {
+    /// The attempt goes through to the wrapped backend.
+    Pass,
+    /// The attempt fails straight away.
+    Fail,
+    /// The attempt fails with [`Error::TimedOut`] after the given time.
+    TimeOut(Duration),
+    /// The attempt succeeds without connecting, as some stacks report when the link is already
+    /// up.
+    AlreadyConnected,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0x5eed,
+            error_probability: 0.0,
+            att_errors: vec![AttError::UNLIKELY_ERROR],
+            disconnect_probability: 0.0,
+            disconnect_after: None,
+            notification_delay: None,
+            connect_script: HashMap::new(),
+            rf_model: None,
+        }
+    }
+}
+
+/// A simulated radio environment, in which the distance of each peripheral from the adapter
+/// follows a scripted timeline. The distance determines the RSSI by a log-distance path loss
+/// model, and the RSSI how many packets are lost: GATT operations fail with one of the
+/// [`att_errors`](FaultConfig::att_errors) and notifications are dropped. Connections drop once a
+/// device is out of range, and connection attempts to it fail.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RfModel {
+    /// The RSSI in dBm of a device 1 m away. Defaults to -59.
+    pub rssi_at_1m: f64,
+    /// How quickly the signal fades with distance, 2 in free space and 2.7 to 4 indoors. Defaults
+    /// to 2.
+    pub path_loss_exponent: f64,
+    /// How many dB either way reported RSSIs vary at random. Defaults to 0.
+    pub rssi_jitter: f64,
+    /// The weakest RSSI in dBm at which packets still get through. Defaults to -95.
+    pub sensitivity: f64,
+    /// How many dB above the sensitivity packets start being lost. The probability of losing one
+    /// rises linearly from 0 there to 1 at the sensitivity. Defaults to 10.
+    pub loss_margin: f64,
+    /// The distance of each peripheral in metres, as points in time since the central was wrapped
+    /// between which it changes linearly. A peripheral is at its first distance before the first
+    /// point and its last distance after the last one. Peripherals without a timeline are always in
+    /// range, with their RSSI as reported by the wrapped backend.
+    pub distances: HashMap<PeripheralId, Vec<(Duration, f64)>>,
+    /// Times since the central was wrapped at which each peripheral's connection drops, whatever
+    /// its distance.
+    pub drops: HashMap<PeripheralId, Vec<Duration>>,
+}
+
+impl Default for RfModel {
+    fn default() -> Self {
+        Self {
+            rssi_at_1m: -59.0,
+            path_loss_exponent: 2.0,
+            rssi_jitter: 0.0,
+            sensitivity: -95.0,
+            loss_margin: 10.0,
+            distances: HashMap::new(),
+            drops: HashMap::new(),
+        }
+    }
+}
+
+impl RfModel {
+    /// Returns the RSSI in dBm of a device the given number of metres away, before jitter.
+    /// Distances under 10 cm count as 10 cm.
+    pub fn rssi_at(&self, distance: f64) -> f64 {
+        self.rssi_at_1m - 10.0 * self.path_loss_exponent * distance.max(0.1).log10()
+    }
+
+    /// Returns the probability between 0 and 1 that a packet received with the given RSSI is lost.
+    pub fn loss_probability(&self, rssi: f64) -> f64 {
+        if rssi < self.sensitivity {
+            1.0
+        } else if self.loss_margin <= 0.0 {
+            0.0
+        } else {
+            ((self.sensitivity + self.loss_margin - rssi) / self.loss_margin).clamp(0.0, 1.0)
+        }
+    }
+
+    /// Returns the distance in metres beyond which devices are out of range.
+    pub fn range(&self) -> f64 {
+        10f64.powf((self.rssi_at_1m - self.sensitivity) / (10.0 * self.path_loss_exponent))
+    }
+
+    /// Returns the distance of the peripheral at the given time since the central was wrapped, if
+    /// it has a timeline.
+    pub fn distance(&self, id: &PeripheralId, elapsed: Duration) -> Option<f64> {
+        let timeline = self.distances.get(id)?;
+        let (first, last) = (timeline.first()?, timeline.last()?);
+        if elapsed <= first.0 {
+            return Some(first.1);
+        }
+        let distance = timeline
+            .windows(2)
+            .find(|segment| elapsed <= segment[1].0)
+            .map_or(last.1, |segment| {
+                let (start, end) = (segment[0], segment[1]);
+                let progress = (elapsed - start.0).as_secs_f64() / (end.0 - start.0).as_secs_f64();
+                start.1 + (end.1 - start.1) * progress
+            });
+        Some(distance)
+    }
+
+    /// Returns the first time from `after` at which the peripheral is out of range, if it ever
+    /// is.
+    fn out_of_range_at(&self, id: &PeripheralId, after: Duration) -> Option<Duration> {
+        let range = self.range();
+        if self.distance(id, after)? > range {
+            return Some(after);
+        }
+        self.distances[id]
+            .windows(2)
+            .find(|segment| segment[1].0 > after && segment[1].1 > range)
+            .map(|segment| {
+                let (start, end) = (segment[0], segment[1]);
+                // The distance is in range at `after`, so a segment ending out of range crosses it.
+                let progress = ((range - start.1) / (end.1 - start.1)).max(0.0);
+                let at = start.0 + (end.0 - start.0).mul_f64(progress);
+                at.max(after)
+            })
+    }
+
+    /// Returns the first scripted drop of the peripheral's connection after `after`.
+    fn next_drop(&self, id: &PeripheralId, after: Duration) -> Option<Duration> {
+        self.drops
+            .get(id)?
+            .iter()
+            .copied()
+            .filter(|&at| at > after)
+            .min()
+    }
+}
+
+#[derive(Debug)]
+struct FaultState {
+    config: FaultConfig,
+    rng: Mutex<u64>,
+    connect_script: Mutex<HashMap<PeripheralId, VecDeque<ConnectFault>>>,
+    /// When the central was wrapped, which the timelines of the RF model start from.
+    start: Instant,
+}
+
+impl FaultState {
+    fn new(config: FaultConfig) -> Self {
+        // xorshift gets stuck at zero.
+        let rng = Mutex::new(config.seed.max(1));
+        let connect_script = config
+            .connect_script
+            .iter()
+            .map(|(id, script)| (id.clone(), script.iter().copied().collect()))
+            .collect();
+        Self {
+            config,
+            rng,
+            connect_script: Mutex::new(connect_script),
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the peripheral's RSSI in the RF model before jitter, if it has a distance timeline.
+    fn rf_rssi(&self, id: &PeripheralId) -> Option<f64> {
+        let model = self.config.rf_model.as_ref()?;
+        Some(model.rssi_at(model.distance(id, self.start.elapsed())?))
+    }
+
+    fn in_range(&self, id: &PeripheralId) -> bool {
+        match (&self.config.rf_model, self.rf_rssi(id)) {
+            (Some(model), Some(rssi)) => rssi >= model.sensitivity,
+            _ => true,
+        }
+    }
+
+    /// Decides whether the next packet to or from the peripheral is lost in the RF model.
+    fn packet_lost(&self, id: &PeripheralId) -> bool {
+        match (&self.config.rf_model, self.rf_rssi(id)) {
+            (Some(model), Some(rssi)) => self.roll(model.loss_probability(rssi)),
+            _ => false,
+        }
+    }
+
+    /// Takes the scripted outcome of the next connection attempt to the given peripheral.
+    fn next_connect_fault(&self, id: &PeripheralId) -> ConnectFault {
+        self.connect_script
+            .lock()
+            .unwrap()
+            .get_mut(id)
+            .and_then(|script| script.pop_front())
+            .unwrap_or(ConnectFault::Pass)
+    }
+
+    /// Returns a pseudo-random number in `[0, 1)`, using xorshift64*.
+    fn next_f64(&self) -> f64 {
+        let mut state = self.rng.lock().unwrap();
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545f4914f6cdd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+
+    fn att_error(&self) -> AttError {
+        let errors = &self.config.att_errors;
+        if errors.is_empty() {
+            return AttError::UNLIKELY_ERROR;
+        }
+        errors[(self.next_f64() * errors.len() as f64) as usize % errors.len()]
+    }
+}
+
+/// Wraps a [`Central`], injecting faults into the peripherals it returns.
+#[derive(Clone, Debug)]
+pub struct FaultyCentral<C> {
+    inner: C,
+    state: Arc<FaultState>,
+}
+
+impl<C: Central> FaultyCentral<C> {
+    pub fn new(inner: C, config: FaultConfig) -> Self {
+        Self {
+            inner,
+            state: Arc::new(FaultState::new(config)),
+        }
+    }
+
+    /// Returns the wrapped central.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn wrap(&self, peripheral: C::Peripheral) -> FaultyPeripheral<C::Peripheral> {
+        FaultyPeripheral {
+            inner: peripheral,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<C> Central for FaultyCentral<C>
+where
+    C: Central + 'static,
+    C::Peripheral: 'static,
+{
+    type Peripheral = FaultyPeripheral<C::Peripheral>;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        self.inner.events().await
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.inner.start_scan(filter).await
+    }
+
+    async fn start_scan_with_options(&self, options: ScanOptions) -> Result<()> {
+        self.inner.start_scan_with_options(options).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.inner.stop_scan().await
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        Ok(self
+            .inner
+            .peripherals()
+            .await?
+            .into_iter()
+            .map(|peripheral| self.wrap(peripheral))
+            .collect())
+    }
+
+    async fn peripheral(&self, id: &PeripheralId) -> Result<Self::Peripheral> {
+        Ok(self.wrap(self.inner.peripheral(id).await?))
+    }
+
+    async fn connected_peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        Ok(self
+            .inner
+            .connected_peripherals()
+            .await?
+            .into_iter()
+            .map(|peripheral| self.wrap(peripheral))
+            .collect())
+    }
+
+    async fn remove_peripheral(&self, id: &PeripheralId) -> Result<()> {
+        self.inner.remove_peripheral(id).await
+    }
+
+    async fn load_cached_peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        Ok(self
+            .inner
+            .load_cached_peripherals()
+            .await?
+            .into_iter()
+            .map(|peripheral| self.wrap(peripheral))
+            .collect())
+    }
+
+    async fn add_peripheral(&self, address: &PeripheralId) -> Result<Self::Peripheral> {
+        Ok(self.wrap(self.inner.add_peripheral(address).await?))
+    }
+
+    async fn supported_phys(&self) -> Result<BTreeSet<Phy>> {
+        self.inner.supported_phys().await
+    }
+
+    async fn set_preferred_phys(&self, phys: BTreeSet<Phy>) -> Result<()> {
+        self.inner.set_preferred_phys(phys).await
+    }
+
+    async fn adapter_info(&self) -> Result<String> {
+        self.inner.adapter_info().await
+    }
+
+    async fn adapter_details(&self) -> Result<AdapterInfo> {
+        self.inner.adapter_details().await
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        self.inner.adapter_state().await
+    }
+
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        self.inner.set_powered(powered).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn scan_duty_cycle(&self) -> Result<ScanDutyCycle> {
+        self.inner.scan_duty_cycle().await
+    }
+
+    async fn self_test(&self) -> SelfTestReport {
+        self.inner.self_test().await
+    }
+
+    async fn start_advertising(&self, advertisement: Advertisement) -> Result<()> {
+        self.inner.start_advertising(advertisement).await
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        self.inner.stop_advertising().await
+    }
+
+    async fn register_gatt_services(&self, services: Vec<LocalService>) -> Result<()> {
+        self.inner.register_gatt_services(services).await
+    }
+
+    async fn unregister_gatt_services(&self) -> Result<()> {
+        self.inner.unregister_gatt_services().await
+    }
+
+    async fn notify_local_characteristic(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        self.inner
+            .notify_local_characteristic(service, characteristic, value)
+            .await
+    }
+
+    async fn gatt_server_events(&self) -> Result<Pin<Box<dyn Stream<Item = ServerEvent> + Send>>> {
+        self.inner.gatt_server_events().await
+    }
+
+    async fn register_pairing_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()> {
+        self.inner.register_pairing_agent(agent).await
+    }
+
+    async fn register_wake_trigger(&self, trigger: WakeTrigger) -> Result<()> {
+        self.inner.register_wake_trigger(trigger).await
+    }
+
+    fn peripheral_tags(&self) -> Option<&PeripheralTags> {
+        self.inner.peripheral_tags()
+    }
+}
+
+/// Wraps a [`Peripheral`], injecting faults into its GATT operations according to the
+/// [`FaultConfig`] of the [`FaultyCentral`] it came from.
+#[derive(Clone, Debug)]
+pub struct FaultyPeripheral<P> {
+    inner: P,
+    state: Arc<FaultState>,
+}
+
+impl<P: Peripheral + 'static> FaultyPeripheral<P> {
+    /// Returns the wrapped peripheral.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Decides whether the next GATT operation should fail, and if so performs the fault.
+    async fn inject(&self) -> Result<()> {
+        if self.state.roll(self.state.config.disconnect_probability) {
+            debug!("Injecting disconnection from {}", self.inner.id());
+            self.inner.disconnect().await?;
+            return Err(Error::NotConnected);
+        }
+        if self.state.packet_lost(&self.inner.id()) {
+            let att_error = self.state.att_error();
+            debug!(
+                "Simulating lost packet to {}: {}",
+                self.inner.id(),
+                att_error
+            );
+            return Err(Error::Att(att_error));
+        }
+        if self.state.roll(self.state.config.error_probability) {
+            let att_error = self.state.att_error();
+            debug!("Injecting ATT error {} on {}", att_error, self.inner.id());
+            return Err(Error::Att(att_error));
+        }
+        Ok(())
+    }
+
+    /// Drops the connection which was just made when the RF model says it next drops, if it
+    /// does.
+    fn schedule_rf_drop(&self) {
+        let Some(model) = &self.state.config.rf_model else {
+            return;
+        };
+        let id = self.inner.id();
+        let elapsed = self.state.start.elapsed();
+        let Some(at) = [
+            model.next_drop(&id, elapsed),
+            model.out_of_range_at(&id, elapsed),
+        ]
+        .into_iter()
+        .flatten()
+        .min() else {
+            return;
+        };
+        let inner = self.inner.clone();
+        let deadline = self.state.start + at;
+        tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            if inner.is_connected().await.unwrap_or(false) {
+                debug!("Simulating connection drop from {}", inner.id());
+                if let Err(e) = inner.disconnect().await {
+                    debug!("Simulated connection drop failed: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl<P: Peripheral + 'static> Peripheral for FaultyPeripheral<P> {
+    fn id(&self) -> PeripheralId {
+        self.inner.id()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.inner.address()
+    }
+
+    async fn le_address(&self) -> Result<LeAddress> {
+        self.inner.le_address().await
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        let mut properties = self.inner.properties().await?;
+        if let (Some(properties), Some(rssi)) = (&mut properties, self.state.rf_rssi(&self.id())) {
+            let jitter = self
+                .state
+                .config
+                .rf_model
+                .as_ref()
+                .map_or(0.0, |model| model.rssi_jitter);
+            let rssi = rssi + jitter * (2.0 * self.state.next_f64() - 1.0);
+            properties.rssi = Some(rssi.round() as i16);
+        }
+        Ok(properties)
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.inner.services()
+    }
+
+    async fn services_raw(&self) -> Result<Vec<RawService>> {
+        self.inner.services_raw().await
+    }
+
+    fn notification_latency(&self) -> Option<Duration> {
+        self.inner.notification_latency()
+    }
+
+    async fn pair(&self) -> Result<()> {
+        self.inner.pair().await
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        self.inner.unpair().await
+    }
+
+    async fn forget(&self) -> Result<()> {
+        self.inner.forget().await
+    }
+
+    async fn request_exclusive_access(&self) -> Result<()> {
+        self.inner.request_exclusive_access().await
+    }
+
+    fn write_latency(&self, characteristic: &Characteristic) -> Option<LatencyHistogram> {
+        self.inner.write_latency(characteristic)
+    }
+
+    fn recent_activity(&self) -> Vec<ActivityRecord> {
+        self.inner.recent_activity()
+    }
+
+    async fn battery_level(&self) -> Result<Option<u8>> {
+        self.inner.battery_level().await
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.inner.is_connected().await
+    }
+
+    async fn connection_info(&self) -> Result<ConnectionInfo> {
+        self.inner.connection_info().await
+    }
+
+    async fn mtu(&self) -> Result<u16> {
+        self.inner.mtu().await
+    }
+
+    async fn request_mtu(&self, mtu: u16) -> Result<u16> {
+        self.inner.request_mtu(mtu).await
+    }
+
+    async fn optimize_throughput(&self) -> Result<ConnectionInfo> {
+        self.inner.optimize_throughput().await
+    }
+
+    async fn request_connection_params(&self, parameters: ConnectionParameters) -> Result<()> {
+        self.inner.request_connection_params(parameters).await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.connect_with_options(ConnectOptions::default()).await
+    }
+
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        let id = self.inner.id();
+        match self.state.next_connect_fault(&id) {
+            ConnectFault::Pass => {}
+            ConnectFault::Fail => {
+                debug!("Injecting connection failure to {}", id);
+                return Err(Error::Other("Injected connection failure".into()));
+            }
+            ConnectFault::TimeOut(duration) => {
+                debug!("Injecting connection timeout to {}", id);
+                tokio::time::sleep(duration).await;
+                return Err(Error::TimedOut(duration));
+            }
+            ConnectFault::AlreadyConnected => {
+                debug!("Injecting already connected to {}", id);
+                return Ok(());
+            }
+        }
+        if !self.state.in_range(&id) {
+            debug!("Simulating {} out of range", id);
+            return Err(Error::Other(
+                "Simulated connection failure out of range".into(),
+            ));
+        }
+        self.inner.connect_with_options(options).await?;
+        self.schedule_rf_drop();
+        if let Some(delay) = self.state.config.disconnect_after {
+            let inner = self.inner.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                debug!("Injecting scheduled disconnection from {}", inner.id());
+                if let Err(e) = inner.disconnect().await {
+                    debug!("Injected disconnection failed: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn cancel_connect(&self) -> Result<()> {
+        self.inner.cancel_connect().await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.inner.disconnect().await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.inject().await?;
+        self.inner.discover_services().await
+    }
+
+    async fn discover_services_with_cache_mode(&self, cache_mode: CacheMode) -> Result<()> {
+        self.inject().await?;
+        self.inner
+            .discover_services_with_cache_mode(cache_mode)
+            .await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.inject().await?;
+        self.inner.write(characteristic, data, write_type).await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.inject().await?;
+        self.inner.read(characteristic).await
+    }
+
+    async fn open_l2cap_channel(&self, psm: u16) -> Result<L2capChannel> {
+        self.inject().await?;
+        self.inner.open_l2cap_channel(psm).await
+    }
+
+    async fn reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        self.inject().await?;
+        self.inner.reliable_write(writes).await
+    }
+
+    async fn read_with_offset(
+        &self,
+        characteristic: &Characteristic,
+        offset: usize,
+    ) -> Result<Vec<u8>> {
+        self.inject().await?;
+        self.inner.read_with_offset(characteristic, offset).await
+    }
+
+    async fn write_with_offset(
+        &self,
+        characteristic: &Characteristic,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.inject().await?;
+        self.inner
+            .write_with_offset(characteristic, offset, data)
+            .await
+    }
+
+    async fn read_with_cache_mode(
+        &self,
+        characteristic: &Characteristic,
+        cache_mode: CacheMode,
+    ) -> Result<Vec<u8>> {
+        self.inject().await?;
+        self.inner
+            .read_with_cache_mode(characteristic, cache_mode)
+            .await
+    }
+
+    async fn set_protection_level(
+        &self,
+        characteristic: &Characteristic,
+        level: SecurityLevel,
+    ) -> Result<()> {
+        self.inner.set_protection_level(characteristic, level).await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.inject().await?;
+        self.inner.subscribe(characteristic).await
+    }
+
+    async fn subscribe_with_options(
+        &self,
+        characteristic: &Characteristic,
+        options: SubscribeOptions,
+    ) -> Result<()> {
+        self.inject().await?;
+        self.inner
+            .subscribe_with_options(characteristic, options)
+            .await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.inject().await?;
+        self.inner.unsubscribe(characteristic).await
+    }
+
+    async fn subscriptions(&self) -> Result<BTreeSet<Characteristic>> {
+        self.inner.subscriptions().await
+    }
+
+    async fn is_notifying(&self, characteristic: &Characteristic) -> Result<bool> {
+        self.inner.is_notifying(characteristic).await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let state = self.state.clone();
+        let id = self.inner.id();
+        let notifications = Box::pin(
+            self.inner
+                .notifications()
+                .await?
+                .filter(move |_| ready(!state.packet_lost(&id))),
+        );
+        match self.state.config.notification_delay {
+            Some(delay) => Ok(Box::pin(notifications.then(
+                move |notification| async move {
+                    tokio::time::sleep(delay).await;
+                    notification
+                },
+            ))),
+            None => Ok(notifications),
+        }
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.inject().await?;
+        self.inner.write_descriptor(descriptor, data).await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.inject().await?;
+        self.inner.read_descriptor(descriptor).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn probabilities() {
+        let state = FaultState::new(FaultConfig::default());
+        assert!((0..1000).all(|_| !state.roll(0.0)));
+        assert!((0..1000).all(|_| state.roll(1.0)));
+        let hits = (0..10000).filter(|_| state.roll(0.25)).count();
+        assert!((2000..3000).contains(&hits), "{} hits", hits);
+    }
+
+    #[test]
+    fn connect_script() {
+        let id = PeripheralId::for_test(BDAddr::from([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        let other = PeripheralId::for_test(BDAddr::from([0x00, 0x11, 0x22, 0x33, 0x44, 0x66]));
+        let timeout = ConnectFault::TimeOut(Duration::from_secs(1));
+        let state = FaultState::new(FaultConfig {
+            connect_script: HashMap::from([(
+                id.clone(),
+                vec![ConnectFault::Fail, timeout, ConnectFault::AlreadyConnected],
+            )]),
+            ..Default::default()
+        });
+        assert_eq!(state.next_connect_fault(&other), ConnectFault::Pass);
+        assert_eq!(state.next_connect_fault(&id), ConnectFault::Fail);
+        assert_eq!(state.next_connect_fault(&id), timeout);
+        assert_eq!(
+            state.next_connect_fault(&id),
+            ConnectFault::AlreadyConnected
+        );
+        assert_eq!(state.next_connect_fault(&id), ConnectFault::Pass);
+        assert_eq!(state.next_connect_fault(&id), ConnectFault::Pass);
+    }
+
+    #[tokio::test]
+    async fn scripted_connection_attempts() {
+        let mock = mock::Central::new();
+        let device = mock.add(BDAddr::default(), &[]);
+        let timeout = Duration::from_millis(1);
+        let config = FaultConfig {
+            connect_script: HashMap::from([(
+                device.id(),
+                vec![
+                    ConnectFault::Fail,
+                    ConnectFault::TimeOut(timeout),
+                    ConnectFault::AlreadyConnected,
+                ],
+            )]),
+            ..Default::default()
+        };
+        let central = FaultyCentral::new(mock, config);
+        let peripheral = central.peripheral(&device.id()).await.unwrap();
+        assert!(matches!(peripheral.connect().await, Err(Error::Other(_))));
+        assert!(matches!(
+            peripheral.connect().await,
+            Err(Error::TimedOut(t)) if t == timeout
+        ));
+        // Claims to succeed without reaching the device.
+        peripheral.connect().await.unwrap();
+        assert_eq!(device.connections(), 0);
+        // The script has run out, so attempts go through.
+        peripheral.connect().await.unwrap();
+        assert_eq!(device.connections(), 1);
+        assert!(device.is_connected().await.unwrap());
+    }
+
+    #[test]
+    fn rf_signal() {
+        let model = RfModel::default();
+        assert_eq!(model.rssi_at(1.0), -59.0);
+        assert_eq!(model.rssi_at(10.0), -79.0);
+        assert_eq!(model.rssi_at(0.0), model.rssi_at(0.1));
+        assert_eq!(model.loss_probability(-80.0), 0.0);
+        assert_eq!(model.loss_probability(-90.0), 0.5);
+        assert_eq!(model.loss_probability(-96.0), 1.0);
+        assert!((model.rssi_at(model.range()) - model.sensitivity).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rf_timeline() {
+        let id = PeripheralId::for_test(BDAddr::default());
+        let model = RfModel {
+            distances: HashMap::from([(
+                id.clone(),
+                vec![
+                    (Duration::from_secs(1), 10.0),
+                    (Duration::from_secs(3), 110.0),
+                    (Duration::from_secs(5), 10.0),
+                ],
+            )]),
+            drops: HashMap::from([(id.clone(), vec![Duration::from_secs(4)])]),
+            ..Default::default()
+        };
+        let other = PeripheralId::for_test(BDAddr::from([1, 2, 3, 4, 5, 6]));
+        assert_eq!(model.distance(&other, Duration::ZERO), None);
+        assert_eq!(model.distance(&id, Duration::ZERO), Some(10.0));
+        assert_eq!(model.distance(&id, Duration::from_secs(2)), Some(60.0));
+        assert_eq!(model.distance(&id, Duration::from_secs(4)), Some(60.0));
+        assert_eq!(model.distance(&id, Duration::from_secs(9)), Some(10.0));
+
+        // The range is about 63 m, which the device passes just after 2 s.
+        let out = model.out_of_range_at(&id, Duration::ZERO).unwrap();
+        assert!(out > Duration::from_secs(2) && out < Duration::from_millis(2100));
+        assert_eq!(
+            model.out_of_range_at(&id, Duration::from_secs(3)),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(model.out_of_range_at(&id, Duration::from_secs(5)), None);
+        assert_eq!(
+            model.next_drop(&id, Duration::ZERO),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(model.next_drop(&id, Duration::from_secs(4)), None);
+    }
+
+    #[tokio::test]
+    async fn rf_environment() {
+        let mock = mock::Central::new();
+        let device = mock.add(BDAddr::default(), &[]);
+        let far = mock.add(BDAddr::from([1, 2, 3, 4, 5, 6]), &[]);
+        let model = RfModel {
+            // Nearly every packet is lost at 10 m, though the device is still just in range.
+            sensitivity: -79.001,
+            distances: HashMap::from([
+                (device.id(), vec![(Duration::ZERO, 10.0)]),
+                (far.id(), vec![(Duration::ZERO, 100.0)]),
+            ]),
+            drops: HashMap::from([(device.id(), vec![Duration::from_millis(20)])]),
+            ..Default::default()
+        };
+        let central = FaultyCentral::new(
+            mock,
+            FaultConfig {
+                rf_model: Some(model),
+                ..Default::default()
+            },
+        );
+
+        let far = central.peripheral(&far.id()).await.unwrap();
+        assert!(matches!(far.connect().await, Err(Error::Other(_))));
+
+        let peripheral = central.peripheral(&device.id()).await.unwrap();
+        let properties = peripheral.properties().await.unwrap().unwrap();
+        assert_eq!(properties.rssi, Some(-79));
+        peripheral.connect().await.unwrap();
+        assert!(matches!(
+            peripheral.discover_services().await,
+            Err(Error::Att(AttError::UNLIKELY_ERROR))
+        ));
+        // Until the scripted drop.
+        assert!(device.is_connected().await.unwrap());
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while device.is_connected().await.unwrap() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn same_seed_same_faults() {
+        let a = FaultState::new(FaultConfig::default());
+        let b = FaultState::new(FaultConfig::default());
+        let a: Vec<bool> = (0..100).map(|_| a.roll(0.5)).collect();
+        let b: Vec<bool> = (0..100).map(|_| b.roll(0.5)).collect();
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,282 @@
+//! Mirroring the services of a remote peripheral onto the local GATT server.
+//!
+//! A [`GattProxy`] registers local copies of some of a connected peripheral's services, and
+//! forwards what centrals do to them: reads and writes go to the peripheral, subscriptions
+//! subscribe to it, and its notifications are sent on to the subscribed centrals. This is enough
+//! to build a range extender, or a gateway which logs the traffic between a device and its app.
+//!
+//! ```no_run
+//! use btleplug::api::{Central, Manager as _, Peripheral as _};
+//! use btleplug::gatt_proxy::GattProxy;
+//! use btleplug::platform::Manager;
+//! use uuid::Uuid;
+//! # use std::error::Error;
+//!
+//! # async fn example() -> Result<(), Box<dyn Error>> {
+//! const HEART_RATE: Uuid = Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb);
+//!
+//! let manager = Manager::new().await?;
+//! let central = manager.adapters().await?.remove(0);
+//! let sensor = central.peripherals().await?.remove(0);
+//! sensor.connect().await?;
+//! sensor.discover_services().await?;
+//! let proxy = GattProxy::start(&central, sensor, &[HEART_RATE]).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::api::gatt_server::{CharacteristicHandler, LocalCharacteristic, LocalService};
+use crate::api::{AttError, Central, CharPropFlags, Characteristic, Peripheral, WriteType};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use log::debug;
+use std::fmt::{self, Debug, Formatter};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// The properties of a remote characteristic which are forwarded.
+const FORWARDED_PROPERTIES: CharPropFlags = CharPropFlags::READ
+    .union(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+    .union(CharPropFlags::WRITE)
+    .union(CharPropFlags::NOTIFY)
+    .union(CharPropFlags::INDICATE);
+
+/// Returns the ATT error to give a central for an error from the peripheral.
+fn att_error(error: Error) -> AttError {
+    debug!("Proxied request failed: {}", error);
+    match error {
+        Error::Att(error) => error,
+        _ => AttError::UNLIKELY_ERROR,
+    }
+}
+
+/// Forwards requests of a local characteristic to the remote one it mirrors.
+#[derive(Debug)]
+struct Forward<P> {
+    peripheral: P,
+    characteristic: Characteristic,
+}
+
+#[async_trait]
+impl<P: Peripheral + 'static> CharacteristicHandler for Forward<P> {
+    async fn read(&self, offset: usize) -> std::result::Result<Vec<u8>, AttError> {
+        let value = self
+            .peripheral
+            .read(&self.characteristic)
+            .await
+            .map_err(att_error)?;
+        value
+            .get(offset..)
+            .map(<[u8]>::to_vec)
+            .ok_or(AttError::INVALID_OFFSET)
+    }
+
+    async fn write(&self, value: Vec<u8>, offset: usize) -> std::result::Result<(), AttError> {
+        // Peripheral::write can't write at an offset, so long writes can't be forwarded.
+        if offset != 0 {
+            return Err(AttError::REQUEST_NOT_SUPPORTED);
+        }
+        let write_type = if self
+            .characteristic
+            .properties
+            .contains(CharPropFlags::WRITE)
+        {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+        self.peripheral
+            .write(&self.characteristic, &value, write_type)
+            .await
+            .map_err(att_error)
+    }
+
+    async fn notifying_changed(&self, notifying: bool) {
+        let result = if notifying {
+            self.peripheral.subscribe(&self.characteristic).await
+        } else {
+            self.peripheral.unsubscribe(&self.characteristic).await
+        };
+        if let Err(e) = result {
+            debug!("Failed to forward subscription change: {}", e);
+        }
+    }
+}
+
+/// Local copies of a peripheral's services, registered on an adapter. Dropping the proxy stops
+/// forwarding notifications, but leaves the services registered; use [`stop`](Self::stop) to
+/// unregister them too.
+pub struct GattProxy<C> {
+    /// Boxed so that the proxy doesn't need the central to be `Unpin`.
+    central: Box<C>,
+    forwarder: JoinHandle<()>,
+}
+
+impl<C: Debug> Debug for GattProxy<C> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("GattProxy")
+            .field("central", &self.central)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: Central + 'static> GattProxy<C> {
+    /// Mirrors the given services of a connected peripheral, whose services have been discovered,
+    /// onto the GATT server of `central`, or all of them if `services` is empty. This replaces any
+    /// services registered on the adapter before. Fails with [`Error::NoSuchCharacteristic`] if
+    /// the peripheral doesn't have one of the services.
+    ///
+    /// A characteristic which appears in several mirrored services only has its notifications
+    /// sent on from the first of them.
+    pub async fn start<P: Peripheral + 'static>(
+        central: &C,
+        peripheral: P,
+        services: &[Uuid],
+    ) -> Result<Self> {
+        let remote_services: Vec<_> = peripheral
+            .services()
+            .into_iter()
+            .filter(|service| services.is_empty() || services.contains(&service.uuid))
+            .collect();
+        if services
+            .iter()
+            .any(|uuid| !remote_services.iter().any(|service| service.uuid == *uuid))
+        {
+            return Err(Error::NoSuchCharacteristic);
+        }
+
+        let local_services = remote_services
+            .iter()
+            .map(|service| LocalService {
+                uuid: service.uuid,
+                primary: service.primary,
+                characteristics: service
+                    .characteristics
+                    .iter()
+                    .map(|characteristic| {
+                        LocalCharacteristic::new(
+                            characteristic.uuid,
+                            characteristic.properties & FORWARDED_PROPERTIES,
+                            Forward {
+                                peripheral: peripheral.clone(),
+                                characteristic: characteristic.clone(),
+                            },
+                        )
+                    })
+                    .collect(),
+            })
+            .collect();
+        // Subscribe first so that no notification is missed.
+        let mut notifications = peripheral.notifications().await?;
+        central.register_gatt_services(local_services).await?;
+
+        let server = central.clone();
+        let forwarder = tokio::spawn(async move {
+            while let Some(notification) = notifications.next().await {
+                let service = remote_services.iter().find(|service| {
+                    service
+                        .characteristics
+                        .iter()
+                        .any(|characteristic| characteristic.uuid == notification.uuid)
+                });
+                let Some(service) = service else {
+                    continue;
+                };
+                if let Err(e) = server
+                    .notify_local_characteristic(
+                        service.uuid,
+                        notification.uuid,
+                        &notification.value,
+                    )
+                    .await
+                {
+                    debug!("Failed to forward notification: {}", e);
+                }
+            }
+        });
+        Ok(Self {
+            central: Box::new(central.clone()),
+            forwarder,
+        })
+    }
+
+    /// Stops forwarding and unregisters the local services.
+    pub async fn stop(self) -> Result<()> {
+        self.forwarder.abort();
+        self.central.unregister_gatt_services().await
+    }
+}
+
+impl<C> Drop for GattProxy<C> {
+    fn drop(&mut self) {
+        self.forwarder.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::BDAddr;
+    use crate::mock;
+    use std::time::Duration;
+
+    const LEVEL: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+
+    #[tokio::test]
+    async fn missing_service() {
+        let central = mock::Central::new();
+        let device = central.add(BDAddr::default(), &[LEVEL]);
+        assert!(matches!(
+            GattProxy::start(&central, device, &[LEVEL]).await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+        assert!(central.registered_services().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forwards_requests_and_notifications() {
+        let central = mock::Central::new();
+        let device = central.add(BDAddr::default(), &[LEVEL]);
+        device.connect().await.unwrap();
+        let proxy = GattProxy::start(&central, device.clone(), &[])
+            .await
+            .unwrap();
+
+        let services = central.registered_services();
+        let [service] = services.as_slice() else {
+            panic!("Expected one service, got {:?}", services);
+        };
+        let remote = device.services().into_iter().next().unwrap();
+        assert_eq!(service.uuid, remote.uuid);
+        let characteristic = &service.characteristics[0];
+        assert_eq!(characteristic.uuid, LEVEL);
+        assert_eq!(characteristic.properties, CharPropFlags::NOTIFY);
+
+        let handler = &characteristic.handler;
+        assert_eq!(handler.read(0).await, Ok(vec![]));
+        assert_eq!(handler.read(1).await, Err(AttError::INVALID_OFFSET));
+        assert_eq!(
+            handler.write(vec![1], 1).await,
+            Err(AttError::REQUEST_NOT_SUPPORTED)
+        );
+        handler.notifying_changed(true).await;
+        assert_eq!(device.subscriptions().await.unwrap().len(), 1);
+        device.notify(LEVEL, &[42]);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while central.notified().is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(central.notified(), [(remote.uuid, LEVEL, vec![42])]);
+
+        // Requests fail once the device disconnects.
+        device.disconnect().await.unwrap();
+        assert_eq!(handler.read(0).await, Err(AttError::UNLIKELY_ERROR));
+
+        proxy.stop().await.unwrap();
+        assert!(central.registered_services().is_empty());
+    }
+}
@@ -91,7 +91,7 @@
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 extern crate cocoa;
 
-use crate::api::ParseBDAddrError;
+use crate::api::{AttError, ParseBDAddrError};
 use std::result;
 use std::time::Duration;
 
@@ -104,9 +104,14 @@ mod common;
 mod corebluetooth;
 #[cfg(target_os = "android")]
 mod droidplug;
+pub mod fault_injection;
+pub mod gatt_proxy;
+#[cfg(test)]
+mod mock;
 pub mod platform;
 #[cfg(feature = "serde")]
 pub mod serde;
+pub mod session;
 #[cfg(target_os = "windows")]
 mod winrtble;
 
@@ -122,6 +127,20 @@ pub enum Error {
     #[error("Not connected")]
     NotConnected,
 
+    /// The platform removed the device while the operation was pending or before it started,
+    /// typically because it hadn't been seen for a while. Scan to find it again.
+    #[error("Device was removed")]
+    DeviceLost,
+
+    /// The adapter is powered off. See [`Central::set_powered`](crate::api::Central::set_powered).
+    #[error("The adapter is powered off")]
+    AdapterPoweredOff,
+
+    /// The adapter is blocked, e.g. by rfkill or airplane mode, so it can't be powered on until it
+    /// is unblocked outside btleplug.
+    #[error("The adapter is blocked")]
+    AdapterBlocked,
+
     #[error("Unexpected callback")]
     UnexpectedCallback,
 
@@ -131,12 +150,35 @@ pub enum Error {
     #[error("No such characteristic")]
     NoSuchCharacteristic,
 
+    #[error("No such descriptor")]
+    NoSuchDescriptor,
+
     #[error("The operation is not supported: {}", _0)]
     NotSupported(String),
 
+    /// The device rejected the operation with the given ATT error. See
+    /// [`AttError::requires_pairing`] for errors caused by insufficient link security.
+    #[error("ATT error: {}", _0)]
+    Att(AttError),
+
+    /// Another application or profile has taken exclusive access to the characteristic or
+    /// service, e.g. by acquiring a file descriptor for it from BlueZ or opening it exclusively on
+    /// Windows. The message says what was taken.
+    #[error("In use by another application: {}", _0)]
+    InUse(String),
+
+    /// The characteristic didn't return a valid value in the given number of reads. See
+    /// [`api::Peripheral::read_verified`].
+    #[error("No valid value after {} reads", _0)]
+    InvalidValue(usize),
+
     #[error("Timed out after {:?}", _0)]
     TimedOut(Duration),
 
+    /// The parameters of the operation are out of range. The message says which.
+    #[error("Invalid parameters: {}", _0)]
+    InvalidParameters(String),
+
     #[error("Error parsing UUID: {0}")]
     Uuid(#[from] uuid::Error),
 
@@ -0,0 +1,337 @@
+//! An in-memory backend for testing the platform-independent parts of btleplug, such as sessions
+//! and fault injection, without a Bluetooth stack.
+
+use crate::api::advertising::Advertisement;
+use crate::api::gatt_server::LocalService;
+use crate::api::{
+    BDAddr, CentralEvent, CharPropFlags, Characteristic, Descriptor, PeripheralProperties,
+    ScanFilter, Service, ValueNotification, WriteType,
+};
+use crate::platform::PeripheralId;
+use crate::{api, Error, Result};
+use async_trait::async_trait;
+use futures::stream::{Stream, StreamExt};
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
+
+const SERVICE: Uuid = Uuid::from_u128(0x5e551000_0000_1000_8000_00805f9b34fb);
+
+fn broadcast_stream<T: Clone + Send + 'static>(
+    sender: &broadcast::Sender<T>,
+) -> Pin<Box<dyn Stream<Item = T> + Send>> {
+    Box::pin(BroadcastStream::new(sender.subscribe()).filter_map(|x| async move { x.ok() }))
+}
+
+/// The service, characteristic and value of a local characteristic notification.
+type Notification = (Uuid, Uuid, Vec<u8>);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Central {
+    events: broadcast::Sender<CentralEvent>,
+    peripherals: Arc<Mutex<Vec<Peripheral>>>,
+    notified: Arc<Mutex<Vec<Notification>>>,
+    advertising_starts: Arc<AtomicU32>,
+    registered_services: Arc<Mutex<Vec<LocalService>>>,
+}
+
+impl Central {
+    pub fn new() -> Self {
+        Self {
+            events: broadcast::channel(16).0,
+            peripherals: Default::default(),
+            notified: Default::default(),
+            advertising_starts: Default::default(),
+            registered_services: Default::default(),
+        }
+    }
+
+    /// Returns the services registered through
+    /// [`register_gatt_services`](api::Central::register_gatt_services), if any.
+    pub fn registered_services(&self) -> Vec<LocalService> {
+        self.registered_services.lock().unwrap().clone()
+    }
+
+    /// Returns how many times advertising has been started.
+    pub fn advertising_starts(&self) -> u32 {
+        self.advertising_starts.load(Ordering::SeqCst)
+    }
+
+    /// Returns the notifications sent through
+    /// [`notify_local_characteristic`](api::Central::notify_local_characteristic) so far.
+    pub fn notified(&self) -> Vec<Notification> {
+        self.notified.lock().unwrap().clone()
+    }
+
+    /// Adds a disconnected peripheral with a service containing the given notifiable
+    /// characteristics.
+    pub fn add(&self, address: BDAddr, characteristics: &[Uuid]) -> Peripheral {
+        let characteristics = characteristics
+            .iter()
+            .map(|&uuid| Characteristic {
+                uuid,
+                service_uuid: SERVICE,
+                service_primary: true,
+                properties: CharPropFlags::NOTIFY,
+                descriptors: BTreeSet::new(),
+            })
+            .collect();
+        let peripheral = Peripheral {
+            id: PeripheralId::for_test(address),
+            address,
+            service: Service::new(SERVICE, true, characteristics),
+            state: Arc::new(PeripheralState {
+                connected: AtomicBool::new(false),
+                connections: AtomicU32::new(0),
+                subscriptions: Mutex::new(BTreeSet::new()),
+                notifications: broadcast::channel(16).0,
+                central_events: self.events.clone(),
+            }),
+        };
+        self.peripherals.lock().unwrap().push(peripheral.clone());
+        let _ = self
+            .events
+            .send(CentralEvent::DeviceDiscovered(peripheral.id.clone()));
+        peripheral
+    }
+}
+
+#[async_trait]
+impl api::Central for Central {
+    type Peripheral = Peripheral;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(broadcast_stream(&self.events))
+    }
+
+    async fn start_scan(&self, _filter: ScanFilter) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Peripheral>> {
+        Ok(self.peripherals.lock().unwrap().clone())
+    }
+
+    async fn peripheral(&self, id: &PeripheralId) -> Result<Peripheral> {
+        self.peripherals
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|peripheral| peripheral.id == *id)
+            .cloned()
+            .ok_or(Error::DeviceNotFound)
+    }
+
+    async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
+        Err(Error::NotSupported(
+            "Can't add mock peripherals by ID".to_string(),
+        ))
+    }
+
+    async fn adapter_info(&self) -> Result<String> {
+        Ok("Mock adapter".to_string())
+    }
+
+    async fn start_advertising(&self, _advertisement: Advertisement) -> Result<()> {
+        self.advertising_starts.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn register_gatt_services(&self, services: Vec<LocalService>) -> Result<()> {
+        *self.registered_services.lock().unwrap() = services;
+        Ok(())
+    }
+
+    async fn unregister_gatt_services(&self) -> Result<()> {
+        self.registered_services.lock().unwrap().clear();
+        Ok(())
+    }
+
+    async fn notify_local_characteristic(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        self.notified
+            .lock()
+            .unwrap()
+            .push((service, characteristic, value.to_vec()));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Peripheral {
+    id: PeripheralId,
+    address: BDAddr,
+    service: Service,
+    state: Arc<PeripheralState>,
+}
+
+#[derive(Debug)]
+struct PeripheralState {
+    connected: AtomicBool,
+    connections: AtomicU32,
+    subscriptions: Mutex<BTreeSet<Characteristic>>,
+    notifications: broadcast::Sender<ValueNotification>,
+    central_events: broadcast::Sender<CentralEvent>,
+}
+
+impl Peripheral {
+    /// Returns how many times the peripheral has been connected.
+    pub fn connections(&self) -> u32 {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    /// Sends a notification from the given characteristic, if it is subscribed to.
+    pub fn notify(&self, uuid: Uuid, value: &[u8]) {
+        let subscribed = self
+            .state
+            .subscriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|characteristic| characteristic.uuid == uuid);
+        if subscribed {
+            let _ = self
+                .state
+                .notifications
+                .send(ValueNotification::new(uuid, value.to_vec()));
+        }
+    }
+
+    /// Drops the connection as if the device had gone out of range.
+    pub fn drop_connection(&self) {
+        if self.state.connected.swap(false, Ordering::SeqCst) {
+            self.state.subscriptions.lock().unwrap().clear();
+            let _ = self
+                .state
+                .central_events
+                .send(CentralEvent::DeviceDisconnected(self.id.clone()));
+        }
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.state.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+}
+
+#[async_trait]
+impl api::Peripheral for Peripheral {
+    fn id(&self) -> PeripheralId {
+        self.id.clone()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.address
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(Some(PeripheralProperties {
+            address: self.address,
+            services: vec![SERVICE],
+            ..Default::default()
+        }))
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        BTreeSet::from([self.service.clone()])
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.state.connected.load(Ordering::SeqCst))
+    }
+
+    async fn connect(&self) -> Result<()> {
+        if !self.state.connected.swap(true, Ordering::SeqCst) {
+            self.state.connections.fetch_add(1, Ordering::SeqCst);
+            let _ = self
+                .state
+                .central_events
+                .send(CentralEvent::DeviceConnected(self.id.clone()));
+        }
+        Ok(())
+    }
+
+    async fn cancel_connect(&self) -> Result<()> {
+        self.drop_connection();
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.drop_connection();
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.check_connected()
+    }
+
+    async fn write(
+        &self,
+        _characteristic: &Characteristic,
+        _data: &[u8],
+        _write_type: WriteType,
+    ) -> Result<()> {
+        self.check_connected()
+    }
+
+    async fn read(&self, _characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.check_connected()?;
+        Ok(vec![])
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.check_connected()?;
+        self.state
+            .subscriptions
+            .lock()
+            .unwrap()
+            .insert(characteristic.clone());
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.check_connected()?;
+        self.state
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(characteristic);
+        Ok(())
+    }
+
+    async fn subscriptions(&self) -> Result<BTreeSet<Characteristic>> {
+        Ok(self.state.subscriptions.lock().unwrap().clone())
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        Ok(broadcast_stream(&self.state.notifications))
+    }
+
+    async fn write_descriptor(&self, _descriptor: &Descriptor, _data: &[u8]) -> Result<()> {
+        self.check_connected()
+    }
+
+    async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.check_connected()?;
+        Ok(vec![])
+    }
+}
@@ -1,5 +1,9 @@
 //! The `platform` module contains the platform-specific implementations of the various [`api`]
 //! traits. Refer for the `api` module for how to use them.
+//!
+//! The types exported here are `Clone`, `Send` and `Sync` on every platform, as are the events
+//! and values they return, so they can be kept in shared state and moved between tasks in the
+//! same way everywhere. Clones refer to the same underlying object.
 
 #[cfg(target_os = "linux")]
 pub use crate::bluez::{
@@ -18,7 +22,13 @@ pub use crate::winrtble::{
     adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
 };
 
-use crate::api::{self, Central};
+use crate::api::{
+    self, Central, CentralEvent, Characteristic, ConnectionInfo, Descriptor, PeripheralProperties,
+    Service, ValueNotification,
+};
+use crate::fault_injection::{FaultyCentral, FaultyPeripheral};
+use crate::session::DeviceSession;
+use crate::Error;
 use static_assertions::assert_impl_all;
 use std::{
     fmt::{Debug, Display},
@@ -42,3 +52,18 @@ assert_impl_all!(
     Sized,
     Sync
 );
+
+// Ensure that the values passed between tasks are thread-safe whichever backend produced them.
+assert_impl_all!(CentralEvent: Clone, Send, Sync);
+assert_impl_all!(ValueNotification: Clone, Send, Sync);
+assert_impl_all!(PeripheralProperties: Clone, Send, Sync);
+assert_impl_all!(ConnectionInfo: Clone, Send, Sync);
+assert_impl_all!(Service: Clone, Send, Sync);
+assert_impl_all!(Characteristic: Clone, Send, Sync);
+assert_impl_all!(Descriptor: Clone, Send, Sync);
+assert_impl_all!(Error: Send, Sync);
+
+// And that the wrappers keep the guarantees of the types they wrap.
+assert_impl_all!(FaultyCentral<Adapter>: Central, Clone, Debug, Send, Sync);
+assert_impl_all!(FaultyPeripheral<Peripheral>: api::Peripheral, Clone, Debug, Send, Sync);
+assert_impl_all!(DeviceSession<Peripheral>: Send, Sync);
@@ -0,0 +1,787 @@
+//! A high-level pipeline for the common case of talking to a single device.
+//!
+//! Most applications find a device, connect to it, discover its services, subscribe to a few
+//! characteristics and then process notifications until the device goes away, reconnecting if it
+//! does. [`DeviceSession::open`] does all of this from a declarative [`SessionSpec`], and the
+//! session is then a single [`Stream`] of [`SessionEvent`]s. [`DeviceSession::attach`] does the
+//! same for a peripheral the application has already found, keeping it connected and subscribed,
+//! and [`DeviceSession::migrate`] moves a session to another adapter.
+//!
+//! ```no_run
+//! use btleplug::api::{Manager as _, ScanFilter};
+//! use btleplug::platform::Manager;
+//! use btleplug::session::{DeviceSession, ReconnectPolicy, SessionEvent, SessionSpec};
+//! use futures::stream::StreamExt;
+//! use std::time::Duration;
+//! use uuid::Uuid;
+//! # use std::error::Error;
+//!
+//! # async fn example() -> Result<(), Box<dyn Error>> {
+//! const HEART_RATE: Uuid = Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb);
+//! const MEASUREMENT: Uuid = Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb);
+//!
+//! let manager = Manager::new().await?;
+//! let central = manager.adapters().await?.remove(0);
+//! let spec = SessionSpec {
+//!     filter: ScanFilter::default().with_services([HEART_RATE]),
+//!     subscriptions: vec![MEASUREMENT],
+//!     reconnect: ReconnectPolicy::Retry { max_attempts: None, delay: Duration::from_secs(1) },
+//!     ..Default::default()
+//! };
+//! let mut session = DeviceSession::open(&central, spec).await?;
+//! while let Some(event) = session.next().await {
+//!     if let SessionEvent::Notification(notification) = event {
+//!         println!("Heart rate: {:?}", notification.value);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::api::{
+    Central, CentralEvent, Peripheral, PeripheralProperties, ScanFilter, ValueNotification,
+};
+use crate::{Error, Result};
+use futures::stream::{self, Stream, StreamExt};
+use log::debug;
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// What to do when the device of a [`DeviceSession`] disconnects.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum ReconnectPolicy {
+    /// End the session.
+    #[default]
+    Never,
+    /// Try to reconnect, waiting `delay` before each attempt, and end the session after
+    /// `max_attempts` consecutive failures if given.
+    Retry {
+        max_attempts: Option<u32>,
+        delay: Duration,
+    },
+    /// Try to reconnect, waiting `initial_delay` before the first attempt and doubling the delay
+    /// after each failure up to `max_delay`, and end the session after `max_attempts` consecutive
+    /// failures if given. This avoids keeping the radio busy while a device is out of range.
+    Backoff {
+        max_attempts: Option<u32>,
+        initial_delay: Duration,
+        max_delay: Duration,
+    },
+}
+
+impl ReconnectPolicy {
+    /// Returns how long to wait before the given reconnection attempt, counting from 1, or `None`
+    /// if no more attempts should be made.
+    fn delay(&self, attempt: u32) -> Option<Duration> {
+        match *self {
+            Self::Never => None,
+            Self::Retry {
+                max_attempts,
+                delay,
+            } => max_attempts
+                .is_none_or(|max| attempt <= max)
+                .then_some(delay),
+            Self::Backoff {
+                max_attempts,
+                initial_delay,
+                max_delay,
+            } => max_attempts.is_none_or(|max| attempt <= max).then(|| {
+                initial_delay
+                    .saturating_mul(2u32.saturating_pow(attempt - 1))
+                    .min(max_delay)
+            }),
+        }
+    }
+}
+
+/// Decides whether a discovered device is the one a session should connect to.
+pub type DeviceMatcher = Arc<dyn Fn(&PeripheralProperties) -> bool + Send + Sync>;
+
+/// Describes which device a [`DeviceSession`] is for and how to set it up.
+#[derive(Clone, Default)]
+pub struct SessionSpec {
+    /// The filter to scan with. The device must match it, including its service patterns.
+    pub filter: ScanFilter,
+    /// Further restricts which device to connect to, e.g. by name.
+    pub matcher: Option<DeviceMatcher>,
+    /// Fail with [`Error::TimedOut`] if no matching device is found within this time.
+    pub scan_timeout: Option<Duration>,
+    /// Characteristics which must be present after service discovery, or opening or reconnecting
+    /// fails with [`Error::NoSuchCharacteristic`].
+    pub required_characteristics: Vec<Uuid>,
+    /// Characteristics to subscribe to after every connection. These are implicitly required.
+    pub subscriptions: Vec<Uuid>,
+    /// What to do when the device disconnects.
+    pub reconnect: ReconnectPolicy,
+}
+
+impl Debug for SessionSpec {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("SessionSpec")
+            .field("filter", &self.filter)
+            .field("matcher", &self.matcher.as_ref().map(|_| ".."))
+            .field("scan_timeout", &self.scan_timeout)
+            .field("required_characteristics", &self.required_characteristics)
+            .field("subscriptions", &self.subscriptions)
+            .field("reconnect", &self.reconnect)
+            .finish()
+    }
+}
+
+impl SessionSpec {
+    fn matches(&self, properties: &PeripheralProperties) -> bool {
+        self.filter.matches(properties)
+            && self
+                .matcher
+                .as_ref()
+                .is_none_or(|matcher| matcher(properties))
+    }
+}
+
+/// Something that happened during a [`DeviceSession`].
+#[derive(Debug)]
+pub enum SessionEvent {
+    /// The device has been reconnected and set up again.
+    Reconnected,
+    /// A notification was received from one of the subscribed characteristics.
+    Notification(ValueNotification),
+    /// The device disconnected.
+    Disconnected,
+    /// A reconnection attempt is about to be made, counting from 1.
+    Reconnecting { attempt: u32 },
+    /// A reconnection attempt failed.
+    ReconnectFailed(Error),
+    /// [`DeviceSession::migrate`] has reached the given step.
+    Migration(MigrationStep),
+}
+
+/// The steps of moving a session to another adapter with [`DeviceSession::migrate`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MigrationStep {
+    /// Disconnecting from the device on the old adapter.
+    Disconnecting,
+    /// Looking for the device on the new adapter.
+    Finding,
+    /// Connecting to the device on the new adapter and setting it up again.
+    Connecting,
+    /// The session now runs on the new adapter.
+    Done,
+}
+
+/// A connected device set up according to a [`SessionSpec`], and the [`Stream`] of what happens to
+/// it. The stream ends when the session ends, either because the device disconnected and won't be
+/// reconnected, or because the underlying event streams ended.
+pub struct DeviceSession<P> {
+    /// Boxed so that the session can be polled without pinning the peripheral.
+    peripheral: Box<P>,
+    spec: SessionSpec,
+    /// Weak so that the stream still ends when the driver does.
+    sender: mpsc::WeakUnboundedSender<SessionEvent>,
+    events: mpsc::UnboundedReceiver<SessionEvent>,
+    /// Stops the driver from reconnecting while the session is being migrated.
+    paused: watch::Sender<bool>,
+    driver: JoinHandle<()>,
+}
+
+impl<P: Debug> Debug for DeviceSession<P> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DeviceSession")
+            .field("peripheral", &self.peripheral)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P: Peripheral + 'static> DeviceSession<P> {
+    /// Scans for a device matching the spec, connects to it and sets it up. This requires a Tokio
+    /// runtime, as the session is driven by a spawned task.
+    pub async fn open<C>(central: &C, spec: SessionSpec) -> Result<Self>
+    where
+        C: Central<Peripheral = P> + 'static,
+    {
+        let peripheral = find(central, &spec).await?;
+        set_up(&peripheral, &spec).await?;
+        Ok(Self::start(central, peripheral, spec))
+    }
+
+    /// Supervises a peripheral which has already been found, e.g. from [`Central::peripherals`],
+    /// without scanning for it. It is connected and set up according to the spec, ignoring its
+    /// filter, and reconnected according to its policy. As well as the spec's subscriptions, the
+    /// characteristics the peripheral is already subscribed to are subscribed to again after every
+    /// reconnection. This requires a Tokio runtime, as the session is driven by a spawned task.
+    pub async fn attach<C>(central: &C, peripheral: P, mut spec: SessionSpec) -> Result<Self>
+    where
+        C: Central<Peripheral = P> + 'static,
+    {
+        if peripheral.is_connected().await? {
+            for characteristic in peripheral.subscriptions().await? {
+                if !spec.subscriptions.contains(&characteristic.uuid) {
+                    spec.subscriptions.push(characteristic.uuid);
+                }
+            }
+        }
+        set_up(&peripheral, &spec).await?;
+        Ok(Self::start(central, peripheral, spec))
+    }
+
+    /// Starts driving a peripheral which has been set up.
+    fn start<C>(central: &C, peripheral: P, spec: SessionSpec) -> Self
+    where
+        C: Central<Peripheral = P> + 'static,
+    {
+        let (sender, events) = mpsc::unbounded_channel();
+        let (paused, paused_receiver) = watch::channel(false);
+        let driver = tokio::spawn(drive(
+            central.clone(),
+            peripheral.clone(),
+            spec.clone(),
+            sender.clone(),
+            paused_receiver,
+        ));
+        Self {
+            peripheral: Box::new(peripheral),
+            spec,
+            sender: sender.downgrade(),
+            events,
+            paused,
+            driver,
+        }
+    }
+
+    /// Moves the session to another adapter, e.g. to balance connections across several
+    /// dongles. The device is disconnected on the current adapter, found on the new one by its
+    /// address and the session's spec, connected and set up again, and the session then carries
+    /// on there, reporting each [`MigrationStep`] on its stream.
+    ///
+    /// If this fails, the device is left disconnected from the new adapter and the session
+    /// carries on reconnecting on the old one according to its policy. Devices using resolvable
+    /// private addresses can only be found again if the new adapter resolves them, which on
+    /// Linux means being bonded with it too.
+    pub async fn migrate<C>(&mut self, central: &C) -> Result<()>
+    where
+        C: Central<Peripheral = P> + 'static,
+    {
+        // The session has already ended if the driver has dropped its sender.
+        let sender = self.sender.upgrade().ok_or(Error::NotConnected)?;
+        self.paused.send_replace(true);
+        let peripheral = match self.move_to(central, &sender).await {
+            Ok(peripheral) => peripheral,
+            Err(e) => {
+                self.paused.send_replace(false);
+                return Err(e);
+            }
+        };
+        self.driver.abort();
+        let (paused, paused_receiver) = watch::channel(false);
+        self.driver = tokio::spawn(drive(
+            central.clone(),
+            peripheral.clone(),
+            self.spec.clone(),
+            sender.clone(),
+            paused_receiver,
+        ));
+        self.paused = paused;
+        *self.peripheral = peripheral;
+        let _ = sender.send(SessionEvent::Migration(MigrationStep::Done));
+        Ok(())
+    }
+
+    async fn move_to<C>(
+        &self,
+        central: &C,
+        sender: &mpsc::UnboundedSender<SessionEvent>,
+    ) -> Result<P>
+    where
+        C: Central<Peripheral = P>,
+    {
+        let address = self.peripheral.le_address().await?.address;
+        let _ = sender.send(SessionEvent::Migration(MigrationStep::Disconnecting));
+        if self.peripheral.is_connected().await? {
+            self.peripheral.disconnect().await?;
+        }
+
+        let _ = sender.send(SessionEvent::Migration(MigrationStep::Finding));
+        let mut spec = self.spec.clone();
+        let matcher = spec.matcher.take();
+        spec.matcher = Some(Arc::new(move |properties: &PeripheralProperties| {
+            properties.address == address
+                && matcher.as_ref().is_none_or(|matcher| matcher(properties))
+        }));
+        let peripheral = find(central, &spec).await?;
+
+        let _ = sender.send(SessionEvent::Migration(MigrationStep::Connecting));
+        if let Err(e) = set_up(&peripheral, &self.spec).await {
+            // Free the device to be reconnected on the old adapter.
+            let _ = peripheral.disconnect().await;
+            return Err(e);
+        }
+        Ok(peripheral)
+    }
+
+    /// Returns the peripheral of this session.
+    pub fn peripheral(&self) -> &P {
+        &self.peripheral
+    }
+
+    /// Ends the session and disconnects from the device.
+    pub async fn close(self) -> Result<()> {
+        self.driver.abort();
+        self.peripheral.disconnect().await
+    }
+}
+
+impl<P> Stream for DeviceSession<P> {
+    type Item = SessionEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SessionEvent>> {
+        self.get_mut().events.poll_recv(cx)
+    }
+}
+
+impl<P> Drop for DeviceSession<P> {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+async fn find<C: Central>(central: &C, spec: &SessionSpec) -> Result<C::Peripheral> {
+    // Subscribe before scanning so that no discovery is missed.
+    let mut events = central.events().await?;
+    central.start_scan(spec.filter.clone()).await?;
+    let search = async {
+        for peripheral in central.peripherals().await? {
+            if let Some(properties) = peripheral.properties().await? {
+                if spec.matches(&properties) {
+                    return Ok(peripheral);
+                }
+            }
+        }
+        while let Some(event) = events.next().await {
+            if let CentralEvent::DeviceDiscovered(id)
+            | CentralEvent::CachedDeviceDiscovered(id)
+            | CentralEvent::DeviceUpdated(id) = event
+            {
+                let peripheral = central.peripheral(&id).await?;
+                if let Some(properties) = peripheral.properties().await? {
+                    if spec.matches(&properties) {
+                        return Ok(peripheral);
+                    }
+                }
+            }
+        }
+        Err(Error::DeviceNotFound)
+    };
+    let result = match spec.scan_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, search)
+            .await
+            .unwrap_or(Err(Error::TimedOut(timeout))),
+        None => search.await,
+    };
+    central.stop_scan().await?;
+    result
+}
+
+async fn set_up<P: Peripheral>(peripheral: &P, spec: &SessionSpec) -> Result<()> {
+    if !peripheral.is_connected().await? {
+        peripheral.connect().await?;
+    }
+    peripheral.discover_services().await?;
+    let characteristics = peripheral.characteristics();
+    for uuid in spec
+        .required_characteristics
+        .iter()
+        .chain(&spec.subscriptions)
+    {
+        if !characteristics.iter().any(|c| c.uuid == *uuid) {
+            debug!("Required characteristic {} not found", uuid);
+            return Err(Error::NoSuchCharacteristic);
+        }
+    }
+    for characteristic in characteristics
+        .iter()
+        .filter(|c| spec.subscriptions.contains(&c.uuid))
+    {
+        peripheral.subscribe(characteristic).await?;
+    }
+    Ok(())
+}
+
+enum Input {
+    Notification(ValueNotification),
+    Disconnected,
+}
+
+/// Forwards notifications and reconnects according to the spec, until the session ends or the
+/// receiving side is dropped. Reconnecting waits while the session is paused for migration.
+async fn drive<C: Central>(
+    central: C,
+    peripheral: C::Peripheral,
+    spec: SessionSpec,
+    sender: mpsc::UnboundedSender<SessionEvent>,
+    mut paused: watch::Receiver<bool>,
+) {
+    let id = peripheral.id();
+    loop {
+        let (notifications, central_events) =
+            match (peripheral.notifications().await, central.events().await) {
+                (Ok(notifications), Ok(central_events)) => (notifications, central_events),
+                _ => return,
+            };
+        let id = id.clone();
+        let disconnections = central_events.filter_map(move |event| {
+            let disconnected = matches!(
+                event,
+                CentralEvent::DeviceDisconnected(ref d) | CentralEvent::DeviceLost(ref d) if *d == id
+            );
+            futures::future::ready(disconnected.then_some(Input::Disconnected))
+        });
+        let mut inputs = stream::select(notifications.map(Input::Notification), disconnections);
+
+        loop {
+            match inputs.next().await {
+                Some(Input::Notification(notification)) => {
+                    if sender
+                        .send(SessionEvent::Notification(notification))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                Some(Input::Disconnected) => break,
+                None => return,
+            }
+        }
+
+        // A migration disconnects on purpose, so only report it if the migration fails.
+        if paused.wait_for(|paused| !paused).await.is_err() {
+            return;
+        }
+        if sender.send(SessionEvent::Disconnected).is_err() {
+            return;
+        }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let Some(delay) = spec.reconnect.delay(attempt) else {
+                return;
+            };
+            if sender.send(SessionEvent::Reconnecting { attempt }).is_err() {
+                return;
+            }
+            tokio::time::sleep(delay).await;
+            if paused.wait_for(|paused| !paused).await.is_err() {
+                return;
+            }
+            match set_up(&peripheral, &spec).await {
+                Ok(()) => break,
+                Err(e) => {
+                    if sender.send(SessionEvent::ReconnectFailed(e)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        if sender.send(SessionEvent::Reconnected).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::BDAddr;
+    use crate::fault_injection::{ConnectFault, FaultConfig, FaultyCentral};
+    use crate::mock;
+    use std::collections::HashMap;
+
+    const MEASUREMENT: Uuid = Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb);
+
+    fn address() -> BDAddr {
+        BDAddr::from([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+    }
+
+    fn retry(max_attempts: Option<u32>) -> ReconnectPolicy {
+        ReconnectPolicy::Retry {
+            max_attempts,
+            delay: Duration::from_millis(1),
+        }
+    }
+
+    /// Returns the next event other than a notification.
+    async fn next_event<P>(session: &mut DeviceSession<P>) -> SessionEvent {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), session.next())
+                .await
+                .expect("Timed out waiting for a session event")
+                .expect("Session ended");
+            if !matches!(event, SessionEvent::Notification(_)) {
+                return event;
+            }
+        }
+    }
+
+    /// Notifies until the session forwards a notification, as its driver subscribes to them in
+    /// the background.
+    async fn notify_until_received<P>(
+        session: &mut DeviceSession<P>,
+        device: &mock::Peripheral,
+    ) -> ValueNotification {
+        for _ in 0..500 {
+            device.notify(MEASUREMENT, &[42]);
+            match tokio::time::timeout(Duration::from_millis(10), session.next()).await {
+                Ok(Some(SessionEvent::Notification(notification))) => return notification,
+                Ok(event) => panic!("Unexpected session event {:?}", event),
+                Err(_) => {}
+            }
+        }
+        panic!("Notification was never forwarded");
+    }
+
+    #[test]
+    fn never_reconnects() {
+        assert_eq!(ReconnectPolicy::Never.delay(1), None);
+    }
+
+    #[test]
+    fn retry_delay() {
+        let policy = ReconnectPolicy::Retry {
+            max_attempts: Some(2),
+            delay: Duration::from_secs(1),
+        };
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(3), None);
+        assert_eq!(retry(None).delay(u32::MAX), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn backoff_delay() {
+        let policy = ReconnectPolicy::Backoff {
+            max_attempts: None,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        let delays: Vec<_> = (1..=6).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(|ms| Some(Duration::from_millis(ms)))
+        );
+        // The doubling saturates rather than overflowing.
+        assert_eq!(policy.delay(64), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(u32::MAX), Some(Duration::from_secs(1)));
+
+        let limited = ReconnectPolicy::Backoff {
+            max_attempts: Some(3),
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        assert_eq!(limited.delay(3), Some(Duration::from_millis(400)));
+        assert_eq!(limited.delay(4), None);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_disconnection() {
+        let mock = mock::Central::new();
+        let device = mock.add(address(), &[MEASUREMENT]);
+        // The initial connection goes through, then the first reconnection attempt fails.
+        let config = FaultConfig {
+            connect_script: HashMap::from([(
+                device.id(),
+                vec![ConnectFault::Pass, ConnectFault::Fail],
+            )]),
+            ..Default::default()
+        };
+        let central = FaultyCentral::new(mock, config);
+        let spec = SessionSpec {
+            subscriptions: vec![MEASUREMENT],
+            reconnect: retry(Some(3)),
+            ..Default::default()
+        };
+        let peripheral = central.peripheral(&device.id()).await.unwrap();
+        let mut session = DeviceSession::attach(&central, peripheral, spec)
+            .await
+            .unwrap();
+        assert_eq!(
+            notify_until_received(&mut session, &device).await.value,
+            [42]
+        );
+
+        device.drop_connection();
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Disconnected
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnecting { attempt: 1 }
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::ReconnectFailed(Error::Other(_))
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnecting { attempt: 2 }
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnected
+        ));
+        assert_eq!(device.connections(), 2);
+        notify_until_received(&mut session, &device).await;
+    }
+
+    #[tokio::test]
+    async fn ends_after_max_attempts() {
+        let mock = mock::Central::new();
+        let device = mock.add(address(), &[MEASUREMENT]);
+        let config = FaultConfig {
+            connect_script: HashMap::from([(
+                device.id(),
+                vec![ConnectFault::Pass, ConnectFault::Fail, ConnectFault::Fail],
+            )]),
+            ..Default::default()
+        };
+        let central = FaultyCentral::new(mock, config);
+        let spec = SessionSpec {
+            subscriptions: vec![MEASUREMENT],
+            reconnect: retry(Some(2)),
+            ..Default::default()
+        };
+        let peripheral = central.peripheral(&device.id()).await.unwrap();
+        let mut session = DeviceSession::attach(&central, peripheral, spec)
+            .await
+            .unwrap();
+        notify_until_received(&mut session, &device).await;
+
+        device.drop_connection();
+        let mut failures = 0;
+        while let Some(event) = session.next().await {
+            if let SessionEvent::ReconnectFailed(_) = event {
+                failures += 1;
+            }
+        }
+        assert_eq!(failures, 2);
+        assert_eq!(device.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn attach_resubscribes_existing_subscriptions() {
+        let central = mock::Central::new();
+        let device = central.add(address(), &[MEASUREMENT]);
+        device.connect().await.unwrap();
+        let characteristic = device.characteristics().into_iter().next().unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+
+        // The spec doesn't ask for the subscription, but it is kept across reconnections.
+        let spec = SessionSpec {
+            reconnect: ReconnectPolicy::Backoff {
+                max_attempts: None,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+            },
+            ..Default::default()
+        };
+        let mut session = DeviceSession::attach(&central, device.clone(), spec)
+            .await
+            .unwrap();
+        notify_until_received(&mut session, &device).await;
+
+        device.drop_connection();
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Disconnected
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnecting { attempt: 1 }
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnected
+        ));
+        notify_until_received(&mut session, &device).await;
+    }
+
+    #[tokio::test]
+    async fn migrates_to_another_adapter() {
+        let old_central = mock::Central::new();
+        let old_device = old_central.add(address(), &[MEASUREMENT]);
+        let new_central = mock::Central::new();
+        let new_device = new_central.add(address(), &[MEASUREMENT]);
+        let spec = SessionSpec {
+            subscriptions: vec![MEASUREMENT],
+            reconnect: retry(None),
+            ..Default::default()
+        };
+        let mut session = DeviceSession::attach(&old_central, old_device.clone(), spec)
+            .await
+            .unwrap();
+        notify_until_received(&mut session, &old_device).await;
+
+        session.migrate(&new_central).await.unwrap();
+        for step in [
+            MigrationStep::Disconnecting,
+            MigrationStep::Finding,
+            MigrationStep::Connecting,
+            MigrationStep::Done,
+        ] {
+            assert!(matches!(
+                next_event(&mut session).await,
+                SessionEvent::Migration(s) if s == step
+            ));
+        }
+        assert_eq!(session.peripheral().id(), new_device.id());
+        notify_until_received(&mut session, &new_device).await;
+        assert!(!old_device.is_connected().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn resumes_on_the_old_adapter_when_migration_fails() {
+        let old_central = mock::Central::new();
+        let old_device = old_central.add(address(), &[MEASUREMENT]);
+        let spec = SessionSpec {
+            subscriptions: vec![MEASUREMENT],
+            scan_timeout: Some(Duration::from_millis(50)),
+            reconnect: retry(None),
+            ..Default::default()
+        };
+        let mut session = DeviceSession::attach(&old_central, old_device.clone(), spec)
+            .await
+            .unwrap();
+        notify_until_received(&mut session, &old_device).await;
+
+        // The device isn't on the new adapter, so finding it times out.
+        let result = session.migrate(&mock::Central::new()).await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Migration(MigrationStep::Disconnecting)
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Migration(MigrationStep::Finding)
+        ));
+        // The driver was paused during the migration, so only now reports the disconnection.
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Disconnected
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnecting { attempt: 1 }
+        ));
+        assert!(matches!(
+            next_event(&mut session).await,
+            SessionEvent::Reconnected
+        ));
+        assert_eq!(old_device.connections(), 2);
+        notify_until_received(&mut session, &old_device).await;
+    }
+}
@@ -11,31 +11,43 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::{ble::watcher::BLEWatcher, peripheral::Peripheral, peripheral::PeripheralId};
+use super::{ble::watcher::BLEWatcher, peripheral::Peripheral, peripheral::PeripheralId, utils};
 use crate::{
-    api::{BDAddr, Central, CentralEvent, ScanFilter},
+    api::{
+        pairing::PairingAgent, tags::PeripheralTags, AdapterInfo, AdapterRoles, AdapterState,
+        BDAddr, Capabilities, Central, CentralEvent, LeAddress, Phy, ScanFilter, ScanOptions,
+    },
     common::adapter_manager::AdapterManager,
     Error, Result,
 };
 use async_trait::async_trait;
 use futures::stream::Stream;
+use std::collections::BTreeSet;
 use std::convert::TryInto;
 use std::fmt::{self, Debug, Formatter};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use windows::Devices::Bluetooth::{BluetoothAdapter, BluetoothLEDevice};
+use windows::Devices::Enumeration::DeviceInformation;
+use windows::Devices::Radios::{Radio, RadioAccessStatus, RadioState};
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone)]
 pub struct Adapter {
     watcher: Arc<Mutex<BLEWatcher>>,
     manager: Arc<AdapterManager<Peripheral>>,
+    radio: Radio,
 }
 
 impl Adapter {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(radio: Radio) -> Self {
         let watcher = Arc::new(Mutex::new(BLEWatcher::new()));
         let manager = Arc::new(AdapterManager::default());
-        Adapter { watcher, manager }
+        Adapter {
+            watcher,
+            manager,
+            radio,
+        }
     }
 }
 
@@ -56,13 +68,24 @@ impl Central for Adapter {
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.start_scan_with_options(ScanOptions::default().with_filter(filter))
+            .await
+    }
+
+    async fn start_scan_with_options(&self, options: ScanOptions) -> Result<()> {
         let watcher = self.watcher.lock().unwrap();
         let manager = self.manager.clone();
         watcher.start(
-            filter,
+            options,
             Box::new(move |args| {
                 let bluetooth_address = args.BluetoothAddress().unwrap();
                 let address: BDAddr = bluetooth_address.try_into().unwrap();
+                let address = match args.BluetoothAddressType() {
+                    Ok(address_type) => {
+                        LeAddress::new(address, utils::to_address_type(address_type))
+                    }
+                    Err(_) => LeAddress::public(address),
+                };
                 if let Some(mut entry) = manager.peripheral_mut(&address.into()) {
                     entry.value_mut().update_properties(args);
                     manager.emit(CentralEvent::DeviceUpdated(address.into()));
@@ -90,6 +113,38 @@ impl Central for Adapter {
         self.manager.peripheral(id).ok_or(Error::DeviceNotFound)
     }
 
+    async fn load_cached_peripherals(&self) -> Result<Vec<Peripheral>> {
+        let selector = BluetoothLEDevice::GetDeviceSelectorFromPairingState(true)?;
+        let paired = DeviceInformation::FindAllAsyncAqsFilter(&selector)?.await?;
+        let mut peripherals = vec![];
+        for information in paired {
+            // Paired devices which are turned off still have a device object.
+            let device = BluetoothLEDevice::FromIdAsync(&information.Id()?)?.await?;
+            let Ok(address) = BDAddr::try_from(device.BluetoothAddress()?) else {
+                continue;
+            };
+            let address = LeAddress::new(
+                address,
+                utils::to_address_type(device.BluetoothAddressType()?),
+            );
+            let peripheral = match self.manager.peripheral(&address.into()) {
+                Some(peripheral) => peripheral,
+                None => {
+                    let peripheral = Peripheral::new(Arc::downgrade(&self.manager), address);
+                    self.manager.add_peripheral(peripheral.clone());
+                    peripheral
+                }
+            };
+            if let Ok(name) = information.Name() {
+                peripheral.set_cached_name(name.to_string());
+            }
+            self.manager
+                .emit(CentralEvent::CachedDeviceDiscovered(address.into()));
+            peripherals.push(peripheral);
+        }
+        Ok(peripherals)
+    }
+
     async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
         Err(Error::NotSupported(
             "Can't add a Peripheral from a BDAddr".to_string(),
@@ -100,4 +155,86 @@ impl Central for Adapter {
         // TODO: Get information about the adapter.
         Ok("WinRT".to_string())
     }
+
+    async fn supported_phys(&self) -> Result<BTreeSet<Phy>> {
+        let adapter = BluetoothAdapter::GetDefaultAsync()?.await?;
+        let not_supported = |_| Error::NotSupported("Querying PHYs needs Windows 11".to_string());
+        let mut phys = BTreeSet::from([Phy::Le1M]);
+        if adapter
+            .IsLowEnergyUncoded2MPhySupported()
+            .map_err(not_supported)?
+        {
+            phys.insert(Phy::Le2M);
+        }
+        if adapter
+            .IsLowEnergyCodedPhySupported()
+            .map_err(not_supported)?
+        {
+            phys.insert(Phy::LeCoded);
+        }
+        Ok(phys)
+    }
+
+    async fn adapter_details(&self) -> Result<AdapterInfo> {
+        // WinRT only exposes the details of the default adapter.
+        let adapter = BluetoothAdapter::GetDefaultAsync()?.await?;
+        let mut roles = AdapterRoles::empty();
+        roles.set(AdapterRoles::CENTRAL, adapter.IsCentralRoleSupported()?);
+        roles.set(
+            AdapterRoles::PERIPHERAL,
+            adapter.IsPeripheralRoleSupported()?,
+        );
+        Ok(AdapterInfo {
+            id: adapter.DeviceId()?.to_string(),
+            address: BDAddr::try_from(adapter.BluetoothAddress()?)?,
+            alias: self.radio.Name().ok().map(|name| name.to_string()),
+            modalias: None,
+            roles,
+        })
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        let state = self.radio.State()?;
+        Ok(AdapterState {
+            powered: state == RadioState::On,
+            // Airplane mode and the hardware switch disable the radio.
+            blocked: state == RadioState::Disabled,
+            discovering: self.watcher.lock().unwrap().is_started(),
+            discoverable: false,
+        })
+    }
+
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        let state = if powered {
+            RadioState::On
+        } else {
+            RadioState::Off
+        };
+        match self.radio.SetStateAsync(state)?.await? {
+            RadioAccessStatus::Allowed => Ok(()),
+            RadioAccessStatus::DeniedByUser => Err(Error::PermissionDenied),
+            RadioAccessStatus::DeniedBySystem => Err(Error::AdapterBlocked),
+            status => Err(Error::Other(
+                format!("Failed to power the radio: {:?}", status).into(),
+            )),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            protection_level: true,
+            passive_scan: true,
+            pairing_agent: true,
+            ..Default::default()
+        }
+    }
+
+    async fn register_pairing_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()> {
+        self.manager.set_pairing_agent(agent);
+        Ok(())
+    }
+
+    fn peripheral_tags(&self) -> Option<&PeripheralTags> {
+        Some(self.manager.peripheral_tags())
+    }
 }
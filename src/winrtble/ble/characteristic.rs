@@ -13,27 +13,29 @@
 
 use super::{super::utils::to_descriptor_value, descriptor::BLEDescriptor};
 use crate::{
-    api::{Characteristic, WriteType},
+    api::{redaction::LoggedValue, CacheMode, Characteristic, SecurityLevel, WriteType},
     winrtble::utils,
     Error, Result,
 };
 
 use log::{debug, trace};
 use std::collections::HashMap;
+use std::time::Instant;
 use uuid::Uuid;
 use windows::{
     Devices::Bluetooth::{
         BluetoothCacheMode,
         GenericAttributeProfile::{
             GattCharacteristic, GattClientCharacteristicConfigurationDescriptorValue,
-            GattCommunicationStatus, GattValueChangedEventArgs, GattWriteOption,
+            GattCommunicationStatus, GattProtectionLevel, GattReliableWriteTransaction,
+            GattValueChangedEventArgs, GattWriteOption,
         },
     },
     Foundation::{EventRegistrationToken, TypedEventHandler},
     Storage::Streams::{DataReader, DataWriter},
 };
 
-pub type NotifiyEventHandler = Box<dyn Fn(Vec<u8>) + Send>;
+pub type NotifiyEventHandler = Box<dyn Fn(Vec<u8>, Instant) + Send>;
 
 impl Into<GattWriteOption> for WriteType {
     fn into(self) -> GattWriteOption {
@@ -44,6 +46,27 @@ impl Into<GattWriteOption> for WriteType {
     }
 }
 
+impl From<CacheMode> for BluetoothCacheMode {
+    fn from(cache_mode: CacheMode) -> Self {
+        match cache_mode {
+            CacheMode::Cached => BluetoothCacheMode::Cached,
+            CacheMode::Uncached => BluetoothCacheMode::Uncached,
+        }
+    }
+}
+
+impl From<SecurityLevel> for GattProtectionLevel {
+    fn from(level: SecurityLevel) -> Self {
+        match level {
+            SecurityLevel::None => GattProtectionLevel::Plain,
+            SecurityLevel::Unauthenticated => GattProtectionLevel::EncryptionRequired,
+            SecurityLevel::Authenticated | SecurityLevel::AuthenticatedSecureConnections => {
+                GattProtectionLevel::EncryptionAndAuthenticationRequired
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BLECharacteristic {
     characteristic: GattCharacteristic,
@@ -79,10 +102,25 @@ impl BLECharacteristic {
         }
     }
 
-    pub async fn read_value(&self) -> Result<Vec<u8>> {
+    /// Queues a write of this characteristic in a reliable write transaction.
+    pub fn queue_reliable_write(
+        &self,
+        transaction: &GattReliableWriteTransaction,
+        data: &[u8],
+    ) -> Result<()> {
+        let writer = DataWriter::new()?;
+        writer.WriteBytes(data)?;
+        Ok(transaction.WriteValue(&self.characteristic, &writer.DetachBuffer()?)?)
+    }
+
+    pub fn set_protection_level(&self, level: SecurityLevel) -> Result<()> {
+        Ok(self.characteristic.SetProtectionLevel(level.into())?)
+    }
+
+    pub async fn read_value(&self, cache_mode: CacheMode) -> Result<Vec<u8>> {
         let result = self
             .characteristic
-            .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)?
+            .ReadValueWithCacheModeAsync(cache_mode.into())?
             .await?;
         if result.Status()? == GattCommunicationStatus::Success {
             let value = result.Value()?;
@@ -108,8 +146,12 @@ impl BLECharacteristic {
                         let len = reader.UnconsumedBufferLength()? as usize;
                         let mut input: Vec<u8> = vec![0u8; len];
                         reader.ReadBytes(&mut input[0..len])?;
-                        trace!("changed {:?}", input);
-                        on_value_changed(input);
+                        trace!("changed {}", LoggedValue(&input));
+                        let received_at = args
+                            .Timestamp()
+                            .map(|timestamp| utils::to_instant(utils::to_system_time(timestamp)))
+                            .unwrap_or_else(|_| Instant::now());
+                        on_value_changed(input, received_at);
                     }
                     Ok(())
                 },
@@ -156,6 +198,10 @@ impl BLECharacteristic {
         }
     }
 
+    pub fn is_subscribed(&self) -> bool {
+        self.notify_token.is_some()
+    }
+
     pub fn uuid(&self) -> Uuid {
         utils::to_uuid(&self.characteristic.Uuid().unwrap())
     }
@@ -172,6 +218,8 @@ impl BLECharacteristic {
         Characteristic {
             uuid,
             service_uuid,
+            // Only primary services are discovered.
+            service_primary: true,
             descriptors,
             properties,
         }
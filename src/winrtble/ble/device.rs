@@ -11,16 +11,31 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::BDAddr, winrtble::utils, Error, Result};
+use crate::{
+    api::{
+        pairing::{IoCapability, PairingAgent},
+        LeAddress, Phy, SecurityLevel,
+    },
+    platform::PeripheralId,
+    winrtble::utils,
+    Error, Result,
+};
 use log::{debug, trace};
+use std::sync::Arc;
 use windows::{
     Devices::Bluetooth::{
         BluetoothCacheMode, BluetoothConnectionStatus, BluetoothLEDevice,
+        BluetoothLEPreferredConnectionParameters, BluetoothLEPreferredConnectionParametersRequest,
+        BluetoothLEPreferredConnectionParametersRequestStatus,
         GenericAttributeProfile::{
             GattCharacteristic, GattCommunicationStatus, GattDescriptor, GattDeviceService,
-            GattDeviceServicesResult,
+            GattDeviceServicesResult, GattOpenStatus, GattSession, GattSharingMode,
         },
     },
+    Devices::Enumeration::{
+        DeviceInformationCustomPairing, DevicePairingKinds, DevicePairingProtectionLevel,
+        DevicePairingRequestedEventArgs, DevicePairingResultStatus, DeviceUnpairingResultStatus,
+    },
     Foundation::{EventRegistrationToken, TypedEventHandler},
 };
 
@@ -29,15 +44,77 @@ pub type ConnectedEventHandler = Box<dyn Fn(bool) + Send>;
 pub struct BLEDevice {
     device: BluetoothLEDevice,
     connection_token: EventRegistrationToken,
+    /// Preferred connection parameters only apply while the request is kept open.
+    connection_parameters_request: Option<BluetoothLEPreferredConnectionParametersRequest>,
+}
+
+/// Returns the pairing ceremonies an agent with the given capability can take part in.
+fn pairing_kinds(capability: IoCapability) -> DevicePairingKinds {
+    match capability {
+        IoCapability::NoInputNoOutput => DevicePairingKinds::ConfirmOnly,
+        IoCapability::DisplayOnly => {
+            DevicePairingKinds::ConfirmOnly | DevicePairingKinds::DisplayPin
+        }
+        IoCapability::KeyboardOnly => {
+            DevicePairingKinds::ConfirmOnly | DevicePairingKinds::ProvidePin
+        }
+        IoCapability::DisplayYesNo => {
+            DevicePairingKinds::ConfirmOnly
+                | DevicePairingKinds::DisplayPin
+                | DevicePairingKinds::ConfirmPinMatch
+        }
+        IoCapability::KeyboardDisplay => {
+            DevicePairingKinds::ConfirmOnly
+                | DevicePairingKinds::DisplayPin
+                | DevicePairingKinds::ProvidePin
+                | DevicePairingKinds::ConfirmPinMatch
+        }
+    }
+}
+
+/// Asks the agent to complete a pairing request, accepting it if the agent does. Requests which
+/// aren't accepted are rejected once the deferral completes.
+async fn respond(
+    agent: &dyn PairingAgent,
+    id: PeripheralId,
+    args: &DevicePairingRequestedEventArgs,
+) -> Result<()> {
+    let kind = args.PairingKind()?;
+    let passkey = || -> Result<u32> {
+        args.Pin()?
+            .to_string()
+            .parse()
+            .map_err(|_| Error::Other("Windows gave an invalid passkey".into()))
+    };
+    if kind == DevicePairingKinds::ConfirmOnly {
+        if agent.authorize(id).await {
+            args.Accept()?;
+        }
+    } else if kind == DevicePairingKinds::DisplayPin {
+        agent.display_passkey(id, passkey()?).await;
+        args.Accept()?;
+    } else if kind == DevicePairingKinds::ProvidePin {
+        if let Some(passkey) = agent.request_passkey(id).await {
+            args.AcceptWithPin(&format!("{:06}", passkey).into())?;
+        }
+    } else if kind == DevicePairingKinds::ConfirmPinMatch {
+        if agent.confirm_passkey(id, passkey()?).await {
+            args.Accept()?;
+        }
+    }
+    Ok(())
 }
 
 impl BLEDevice {
     pub async fn new(
-        address: BDAddr,
+        address: LeAddress,
         connection_status_changed: ConnectedEventHandler,
     ) -> Result<Self> {
-        let async_op = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())
-            .map_err(|_| Error::DeviceNotFound)?;
+        let async_op = BluetoothLEDevice::FromBluetoothAddressWithBluetoothAddressTypeAsync(
+            address.address.into(),
+            utils::to_bluetooth_address_type(address.address_type),
+        )
+        .map_err(|_| Error::DeviceNotFound)?;
         let device = async_op.await.map_err(|_| Error::DeviceNotFound)?;
         let connection_status_handler =
             TypedEventHandler::new(move |sender: &Option<BluetoothLEDevice>, _| {
@@ -59,6 +136,7 @@ impl BLEDevice {
         Ok(BLEDevice {
             device,
             connection_token,
+            connection_parameters_request: None,
         })
     }
 
@@ -85,6 +163,143 @@ impl BLEDevice {
         utils::to_error(status)
     }
 
+    /// Requests throughput-optimized connection parameters, which needs Windows 11.
+    pub fn request_throughput_optimized(&mut self) -> Result<()> {
+        let not_supported =
+            |_| Error::NotSupported("Preferred connection parameters need Windows 11".to_string());
+        let parameters = BluetoothLEPreferredConnectionParameters::ThroughputOptimized()
+            .map_err(not_supported)?;
+        let request = self
+            .device
+            .RequestPreferredConnectionParameters(&parameters)
+            .map_err(not_supported)?;
+        let status = request.Status()?;
+        if status != BluetoothLEPreferredConnectionParametersRequestStatus::Success {
+            return Err(Error::Other(
+                format!("Requesting connection parameters failed: {:?}", status).into(),
+            ));
+        }
+        self.connection_parameters_request = Some(request);
+        Ok(())
+    }
+
+    #[cfg(feature = "unstable-raw-handles")]
+    pub fn raw(&self) -> BluetoothLEDevice {
+        self.device.clone()
+    }
+
+    /// Returns the name of the device, which Windows reads from the GAP service after connecting,
+    /// or an empty string.
+    pub fn name(&self) -> Result<String> {
+        Ok(self.device.Name()?.to_string())
+    }
+
+    /// Returns the ATT MTU Windows negotiated for the connection.
+    pub async fn max_pdu_size(&self) -> Result<u16> {
+        let device_id = self.device.BluetoothDeviceId()?;
+        let session = GattSession::FromDeviceIdAsync(&device_id)?.await?;
+        Ok(session.MaxPduSize()?)
+    }
+
+    /// Returns the PHY the connection transmits on, which needs Windows 11.
+    pub fn phy(&self) -> Option<Phy> {
+        let info = self.device.GetConnectionPhy().ok()?.TransmitInfo().ok()?;
+        if info.IsCodedPhy().ok()? {
+            Some(Phy::LeCoded)
+        } else if info.IsUncoded2MPhy().ok()? {
+            Some(Phy::Le2M)
+        } else if info.IsUncoded1MPhy().ok()? {
+            Some(Phy::Le1M)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the security level of the device's pairing, or `SecurityLevel::None` if it isn't
+    /// paired.
+    pub fn security_level(&self) -> Result<SecurityLevel> {
+        let pairing = self.device.DeviceInformation()?.Pairing()?;
+        if !pairing.IsPaired()? {
+            return Ok(SecurityLevel::None);
+        }
+        Ok(match pairing.ProtectionLevel()? {
+            DevicePairingProtectionLevel::EncryptionAndAuthentication => {
+                if self.device.WasSecureConnectionUsedForPairing()? {
+                    SecurityLevel::AuthenticatedSecureConnections
+                } else {
+                    SecurityLevel::Authenticated
+                }
+            }
+            // Pairing always results in an encrypted link.
+            _ => SecurityLevel::Unauthenticated,
+        })
+    }
+
+    /// Returns a handle to the underlying device, for operations which shouldn't keep the
+    /// `BLEDevice` locked while they run.
+    pub fn handle(&self) -> BluetoothLEDevice {
+        self.device.clone()
+    }
+
+    /// Pairs with the device, letting the agent take part in the ceremonies its capability allows,
+    /// or leaving Windows to show its own prompts if there is no agent. This takes a
+    /// [`handle`](Self::handle), as pairing waits on the user.
+    pub async fn pair(
+        device: BluetoothLEDevice,
+        id: PeripheralId,
+        agent: Option<Arc<dyn PairingAgent>>,
+    ) -> Result<()> {
+        let pairing = device.DeviceInformation()?.Pairing()?;
+        if pairing.IsPaired()? {
+            return Ok(());
+        }
+        let status = match agent {
+            None => pairing.PairAsync()?.await?.Status()?,
+            Some(agent) => {
+                let custom = pairing.Custom()?;
+                let kinds = pairing_kinds(agent.io_capability());
+                let runtime = tokio::runtime::Handle::current();
+                let handler = TypedEventHandler::new(
+                    move |_: &Option<DeviceInformationCustomPairing>,
+                          args: &Option<DevicePairingRequestedEventArgs>| {
+                        if let Some(args) = args.clone() {
+                            // Windows waits for the deferral to complete before going on.
+                            let deferral = args.GetDeferral()?;
+                            let agent = agent.clone();
+                            let id = id.clone();
+                            runtime.spawn(async move {
+                                if let Err(e) = respond(&*agent, id, &args).await {
+                                    debug!("Failed to respond to pairing request: {:?}", e);
+                                }
+                                let _ = deferral.Complete();
+                            });
+                        }
+                        Ok(())
+                    },
+                );
+                let token = custom.PairingRequested(&handler)?;
+                let result = custom.PairAsync(kinds)?.await;
+                let _ = custom.RemovePairingRequested(token);
+                result?.Status()?
+            }
+        };
+        match status {
+            DevicePairingResultStatus::Paired | DevicePairingResultStatus::AlreadyPaired => Ok(()),
+            status => Err(Error::Other(format!("Pairing failed: {:?}", status).into())),
+        }
+    }
+
+    pub async fn unpair(&self) -> Result<()> {
+        let pairing = self.device.DeviceInformation()?.Pairing()?;
+        match pairing.UnpairAsync()?.await?.Status()? {
+            DeviceUnpairingResultStatus::Unpaired
+            | DeviceUnpairingResultStatus::AlreadyUnpaired => Ok(()),
+            status => Err(Error::Other(
+                format!("Unpairing failed: {:?}", status).into(),
+            )),
+        }
+    }
+
     async fn is_connected(&self) -> Result<bool> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
         let status = self.device.ConnectionStatus().map_err(winrt_error)?;
@@ -108,6 +323,25 @@ impl BLEDevice {
         }
     }
 
+    /// Opens the service for exclusive access, which lasts until the service is closed.
+    pub async fn open_exclusive(service: &GattDeviceService) -> Result<()> {
+        let status = service.OpenAsync(GattSharingMode::Exclusive)?.await?;
+        if status == GattOpenStatus::Success || status == GattOpenStatus::AlreadyOpened {
+            Ok(())
+        } else if status == GattOpenStatus::SharingViolation {
+            Err(Error::InUse(format!(
+                "Service {:?} is open in another application",
+                service.Uuid()?
+            )))
+        } else if status == GattOpenStatus::AccessDenied {
+            Err(Error::PermissionDenied)
+        } else {
+            Err(Error::Other(
+                format!("open_exclusive for {:?} failed: {:?}", service, status).into(),
+            ))
+        }
+    }
+
     pub async fn get_characteristic_descriptors(
         characteristic: &GattCharacteristic,
     ) -> Result<Vec<GattDescriptor>> {
@@ -130,9 +364,12 @@ impl BLEDevice {
         }
     }
 
-    pub async fn discover_services(&self) -> Result<Vec<GattDeviceService>> {
+    pub async fn discover_services(
+        &self,
+        cache_mode: BluetoothCacheMode,
+    ) -> Result<Vec<GattDeviceService>> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
-        let service_result = self.get_gatt_services(BluetoothCacheMode::Cached).await?;
+        let service_result = self.get_gatt_services(cache_mode).await?;
         let status = service_result.Status().map_err(winrt_error)?;
         if status == GattCommunicationStatus::Success {
             // We need to convert the IVectorView to a Vec, because IVectorView is not Send and so
@@ -151,6 +388,12 @@ impl BLEDevice {
 
 impl Drop for BLEDevice {
     fn drop(&mut self) {
+        if let Some(request) = &self.connection_parameters_request {
+            if let Err(err) = request.Close() {
+                debug!("Drop:close connection parameters request {:?}", err);
+            }
+        }
+
         let result = self
             .device
             .RemoveConnectionStatusChanged(self.connection_token);
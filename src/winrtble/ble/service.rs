@@ -2,10 +2,12 @@ use super::characteristic::BLECharacteristic;
 use crate::api::Service;
 use std::collections::HashMap;
 use uuid::Uuid;
+use windows::Devices::Bluetooth::GenericAttributeProfile::GattDeviceService;
 
 #[derive(Debug)]
 pub struct BLEService {
     pub uuid: Uuid,
+    pub service: GattDeviceService,
     pub characteristics: HashMap<Uuid, BLECharacteristic>,
 }
 
@@ -16,10 +18,6 @@ impl BLEService {
             .values()
             .map(|ble_characteristic| ble_characteristic.to_characteristic(self.uuid))
             .collect();
-        Service {
-            uuid: self.uuid,
-            primary: true,
-            characteristics,
-        }
+        Service::new(self.uuid, true, characteristics)
     }
 }
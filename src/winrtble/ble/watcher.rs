@@ -11,8 +11,17 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::ScanFilter, Error, Result};
-use windows::{Devices::Bluetooth::Advertisement::*, Foundation::TypedEventHandler};
+use crate::{
+    api::{ScanFilter, ScanMode, ScanOptions},
+    Error, Result,
+};
+use windows::{
+    Devices::Bluetooth::Advertisement::*, Foundation::TypedEventHandler,
+    Storage::Streams::DataWriter,
+};
+
+/// The AD type of the Manufacturer Specific Data field.
+const MANUFACTURER_SPECIFIC_DATA_TYPE: u8 = 0xff;
 
 pub type AdvertismentEventHandler = Box<dyn Fn(&BluetoothLEAdvertisementReceivedEventArgs) + Send>;
 
@@ -33,14 +42,31 @@ impl BLEWatcher {
         BLEWatcher { watcher }
     }
 
-    pub fn start(&self, filter: ScanFilter, on_received: AdvertismentEventHandler) -> Result<()> {
-        let ScanFilter { services } = filter;
-        let ad = self
-            .watcher
-            .AdvertisementFilter()
-            .unwrap()
-            .Advertisement()
-            .unwrap();
+    pub fn start(&self, options: ScanOptions, on_received: AdvertismentEventHandler) -> Result<()> {
+        let ScanFilter {
+            services,
+            manufacturer_data,
+            ..
+        } = options.filter;
+        let filter = self.watcher.AdvertisementFilter().unwrap();
+        let byte_patterns = filter.BytePatterns().unwrap();
+        byte_patterns.Clear().unwrap();
+        // Byte patterns can't express a mask or an alternative, so anything else is left to
+        // `ScanFilter::matches`.
+        if let [manufacturer_data] = manufacturer_data.as_slice() {
+            if manufacturer_data.is_exact_prefix() {
+                let writer = DataWriter::new()?;
+                writer.WriteBytes(&manufacturer_data.company_id.to_le_bytes())?;
+                writer.WriteBytes(&manufacturer_data.prefix)?;
+                let pattern = BluetoothLEAdvertisementBytePattern::Create(
+                    MANUFACTURER_SPECIFIC_DATA_TYPE,
+                    0,
+                    &writer.DetachBuffer()?,
+                )?;
+                byte_patterns.Append(&pattern)?;
+            }
+        }
+        let ad = filter.Advertisement().unwrap();
         let ad_services = ad.ServiceUuids().unwrap();
         ad_services.Clear().unwrap();
         for service in services {
@@ -49,7 +75,10 @@ impl BLEWatcher {
                 .unwrap();
         }
         self.watcher
-            .SetScanningMode(BluetoothLEScanningMode::Active)
+            .SetScanningMode(match options.mode {
+                ScanMode::Active => BluetoothLEScanningMode::Active,
+                ScanMode::Passive => BluetoothLEScanningMode::Passive,
+            })
             .unwrap();
         let handler: TypedEventHandler<
             BluetoothLEAdvertisementWatcher,
@@ -68,6 +97,10 @@ impl BLEWatcher {
         Ok(())
     }
 
+    pub fn is_started(&self) -> bool {
+        self.watcher.Status() == Ok(BluetoothLEAdvertisementWatcherStatus::Started)
+    }
+
     pub fn stop(&self) -> Result<()> {
         self.watcher.Stop()?;
         Ok(())
@@ -18,8 +18,10 @@ use super::{
 use crate::{
     api::{
         bleuuid::{uuid_from_u16, uuid_from_u32},
-        AddressType, BDAddr, CentralEvent, Characteristic, Descriptor, Peripheral as ApiPeripheral,
-        PeripheralProperties, Service, ValueNotification, WriteType,
+        diagnostics::AdvertisingIntervalEstimator,
+        AdvertisingFlags, BDAddr, CacheMode, CentralEvent, Characteristic, ConnectOptions,
+        ConnectionInfo, Descriptor, LeAddress, Peripheral as ApiPeripheral, PeripheralProperties,
+        SecurityLevel, Service, ValueNotification, WriteType,
     },
     common::{adapter_manager::AdapterManager, util::notifications_stream_from_broadcast_receiver},
     Error, Result,
@@ -38,21 +40,46 @@ use std::{
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
     sync::atomic::{AtomicBool, Ordering},
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
 };
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
 use std::sync::Weak;
-use windows::Devices::Bluetooth::{Advertisement::*, BluetoothAddressType};
+use windows::Devices::Bluetooth::{
+    Advertisement::*,
+    GenericAttributeProfile::{GattCommunicationStatus, GattReliableWriteTransaction},
+};
 
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
     serde(crate = "serde_cr")
 )]
+#[cfg_attr(feature = "serde", serde(from = "SerializedPeripheralId"))]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct PeripheralId(BDAddr);
+pub struct PeripheralId(LeAddress);
+
+/// Earlier versions identified peripherals by address alone, so accept those too.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(crate = "serde_cr", untagged)]
+enum SerializedPeripheralId {
+    LeAddress(LeAddress),
+    Address(BDAddr),
+}
+
+#[cfg(feature = "serde")]
+impl From<SerializedPeripheralId> for PeripheralId {
+    fn from(id: SerializedPeripheralId) -> Self {
+        match id {
+            SerializedPeripheralId::LeAddress(address) => address.into(),
+            // Addresses were only ever serialized for public devices.
+            SerializedPeripheralId::Address(address) => LeAddress::public(address).into(),
+        }
+    }
+}
 
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -60,6 +87,14 @@ impl Display for PeripheralId {
     }
 }
 
+#[cfg(test)]
+impl PeripheralId {
+    /// Makes up the ID of the device with the given public address.
+    pub(crate) fn for_test(address: BDAddr) -> Self {
+        PeripheralId(LeAddress::public(address))
+    }
+}
+
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone)]
 pub struct Peripheral {
@@ -69,24 +104,28 @@ pub struct Peripheral {
 struct Shared {
     device: tokio::sync::Mutex<Option<BLEDevice>>,
     adapter: Weak<AdapterManager<Peripheral>>,
-    address: BDAddr,
+    address: LeAddress,
     connected: AtomicBool,
+    connected_since: RwLock<Option<SystemTime>>,
     ble_services: DashMap<Uuid, BLEService>,
     notifications_channel: broadcast::Sender<ValueNotification>,
 
     // Mutable, advertised, state...
-    address_type: RwLock<Option<AddressType>>,
     local_name: RwLock<Option<String>>,
     last_tx_power_level: RwLock<Option<i16>>, // XXX: would be nice to avoid lock here!
     last_rssi: RwLock<Option<i16>>,           // XXX: would be nice to avoid lock here!
     latest_manufacturer_data: RwLock<HashMap<u16, Vec<u8>>>,
     latest_service_data: RwLock<HashMap<Uuid, Vec<u8>>>,
     services: RwLock<HashSet<Uuid>>,
+    appearance: RwLock<Option<u16>>,
     class: RwLock<Option<u32>>,
+    advertising_flags: RwLock<Option<AdvertisingFlags>>,
+    advertising_interval: Mutex<AdvertisingIntervalEstimator>,
+    last_advertisement: RwLock<Option<SystemTime>>,
 }
 
 impl Peripheral {
-    pub(crate) fn new(adapter: Weak<AdapterManager<Self>>, address: BDAddr) -> Self {
+    pub(crate) fn new(adapter: Weak<AdapterManager<Self>>, address: LeAddress) -> Self {
         let (broadcast_sender, _) = broadcast::channel(16);
         Peripheral {
             shared: Arc::new(Shared {
@@ -94,16 +133,20 @@ impl Peripheral {
                 device: tokio::sync::Mutex::new(None),
                 address: address,
                 connected: AtomicBool::new(false),
+                connected_since: RwLock::new(None),
                 ble_services: DashMap::new(),
                 notifications_channel: broadcast_sender,
-                address_type: RwLock::new(None),
                 local_name: RwLock::new(None),
                 last_tx_power_level: RwLock::new(None),
                 last_rssi: RwLock::new(None),
                 latest_manufacturer_data: RwLock::new(HashMap::new()),
                 latest_service_data: RwLock::new(HashMap::new()),
                 services: RwLock::new(HashSet::new()),
+                appearance: RwLock::new(None),
                 class: RwLock::new(None),
+                advertising_flags: RwLock::new(None),
+                advertising_interval: Mutex::new(AdvertisingIntervalEstimator::default()),
+                last_advertisement: RwLock::new(None),
             }),
         }
     }
@@ -113,7 +156,7 @@ impl Peripheral {
     fn derive_properties(&self) -> PeripheralProperties {
         PeripheralProperties {
             address: self.address(),
-            address_type: *self.shared.address_type.read().unwrap(),
+            address_type: Some(self.shared.address.address_type),
             local_name: self.shared.local_name.read().unwrap().clone(),
             tx_power_level: *self.shared.last_tx_power_level.read().unwrap(),
             rssi: *self.shared.last_rssi.read().unwrap(),
@@ -127,12 +170,32 @@ impl Peripheral {
                 .iter()
                 .map(|uuid| *uuid)
                 .collect(),
+            appearance: *self.shared.appearance.read().unwrap(),
             class: self.shared.class.read().unwrap().clone(),
+            advertising_flags: *self.shared.advertising_flags.read().unwrap(),
+            advertising_interval: self.shared.advertising_interval.lock().unwrap().estimate(),
+            last_advertisement: *self.shared.last_advertisement.read().unwrap(),
+        }
+    }
+
+    /// Takes the name Windows remembers for a paired device, until it advertises one.
+    pub(crate) fn set_cached_name(&self, name: String) {
+        let mut local_name = self.shared.local_name.write().unwrap();
+        if local_name.is_none() && !name.is_empty() {
+            *local_name = Some(name);
         }
     }
 
     pub(crate) fn update_properties(&self, args: &BluetoothLEAdvertisementReceivedEventArgs) {
         let advertisement = args.Advertisement().unwrap();
+        if let Ok(timestamp) = args.Timestamp() {
+            // Windows reports every packet, timestamped in 100 ns units.
+            let received_at = Duration::from_nanos(timestamp.UniversalTime as u64 * 100);
+            let mut advertising_interval = self.shared.advertising_interval.lock().unwrap();
+            advertising_interval.record(received_at);
+            *self.shared.last_advertisement.write().unwrap() =
+                Some(utils::to_system_time(timestamp));
+        }
 
         // Advertisements are cumulative: set/replace data only if it's set
         if let Ok(name) = advertisement.LocalName() {
@@ -145,6 +208,14 @@ impl Peripheral {
                 *local_name_guard = Some(name.to_string());
             }
         }
+        if let Ok(flags_reference) = advertisement.Flags() {
+            // See the note on IReference below.
+            if let Ok(flags) = flags_reference.Value() {
+                let mut advertising_flags_guard = self.shared.advertising_flags.write().unwrap();
+                *advertising_flags_guard =
+                    Some(AdvertisingFlags::from_bits_truncate(flags.0 as u8));
+            }
+        }
         if let Ok(manufacturer_data) = advertisement.ManufacturerData() {
             let mut manufacturer_data_guard = self.shared.latest_manufacturer_data.write().unwrap();
 
@@ -168,15 +239,24 @@ impl Peripheral {
         // The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API (like Manufacturer Data above)
         // Instead they provide data sections for access to raw advertising data. That is processed here.
         if let Ok(data_sections) = advertisement.DataSections() {
-            // See if we have any advertised service data before taking a lock to update...
+            // See if we have any advertised service data before taking a lock to update, and take
+            // the appearance on the way...
             let mut found_service_data = false;
             for section in &data_sections {
                 match section.DataType().unwrap() {
+                    advertisement_data_type::APPEARANCE => {
+                        if let Ok(appearance) = utils::to_vec(&section.Data().unwrap())
+                            .as_slice()
+                            .try_into()
+                        {
+                            *self.shared.appearance.write().unwrap() =
+                                Some(u16::from_le_bytes(appearance));
+                        }
+                    }
                     advertisement_data_type::SERVICE_DATA_16_BIT_UUID
                     | advertisement_data_type::SERVICE_DATA_32_BIT_UUID
                     | advertisement_data_type::SERVICE_DATA_128_BIT_UUID => {
                         found_service_data = true;
-                        break;
                     }
                     _ => {}
                 }
@@ -256,15 +336,6 @@ impl Peripheral {
             }
         }
 
-        if let Ok(address_type) = args.BluetoothAddressType() {
-            let mut address_type_guard = self.shared.address_type.write().unwrap();
-            *address_type_guard = match address_type {
-                BluetoothAddressType::Public => Some(AddressType::Public),
-                BluetoothAddressType::Random => Some(AddressType::Random),
-                _ => None,
-            };
-        }
-
         if let Ok(tx_reference) = args.TransmitPowerLevelInDBm() {
             // IReference is (ironically) a crazy foot gun in Rust since it very easily
             // panics if you look at it wrong. Calling GetInt16(), IsNumericScalar() or Type()
@@ -281,6 +352,23 @@ impl Peripheral {
         }
     }
 
+    /// Takes the name Windows read from the device after connecting, for devices which don't
+    /// advertise one.
+    async fn resolve_name(&self) -> Result<()> {
+        let name = match self.shared.device.lock().await.as_ref() {
+            Some(device) => device.name()?,
+            None => return Ok(()),
+        };
+        if !name.is_empty() {
+            *self.shared.local_name.write().unwrap() = Some(name.clone());
+            self.emit_event(CentralEvent::NameResolved {
+                id: self.shared.address.into(),
+                name,
+            });
+        }
+        Ok(())
+    }
+
     fn emit_event(&self, event: CentralEvent) {
         if let Some(manager) = self.shared.adapter.upgrade() {
             manager.emit(event);
@@ -328,6 +416,16 @@ impl Debug for Peripheral {
     }
 }
 
+/// Unstable access to the WinRT objects behind the peripheral, for operations btleplug doesn't
+/// cover yet.
+#[cfg(feature = "unstable-raw-handles")]
+impl Peripheral {
+    /// Returns the `BluetoothLEDevice`, or `None` if the peripheral hasn't been connected yet.
+    pub async fn raw_device(&self) -> Option<windows::Devices::Bluetooth::BluetoothLEDevice> {
+        self.shared.device.lock().await.as_ref().map(BLEDevice::raw)
+    }
+}
+
 #[async_trait]
 impl ApiPeripheral for Peripheral {
     fn id(&self) -> PeripheralId {
@@ -336,7 +434,11 @@ impl ApiPeripheral for Peripheral {
 
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr {
-        self.shared.address
+        self.shared.address.address
+    }
+
+    async fn le_address(&self) -> Result<LeAddress> {
+        Ok(self.shared.address)
     }
 
     /// Returns the set of properties associated with the peripheral. These may be updated over time
@@ -358,6 +460,27 @@ impl ApiPeripheral for Peripheral {
         Ok(self.shared.connected.load(Ordering::Relaxed))
     }
 
+    async fn connection_info(&self) -> Result<ConnectionInfo> {
+        if !self.shared.connected.load(Ordering::Relaxed) {
+            return Err(Error::NotConnected);
+        }
+        let (mtu, phy, security_level) = match self.shared.device.lock().await.as_ref() {
+            Some(device) => (
+                Some(device.max_pdu_size().await?),
+                device.phy(),
+                Some(device.security_level()?),
+            ),
+            None => (None, None, None),
+        };
+        Ok(ConnectionInfo {
+            mtu,
+            phy,
+            security_level,
+            connected_since: *self.shared.connected_since.read().unwrap(),
+            ..Default::default()
+        })
+    }
+
     /// Creates a connection to the device. This is a synchronous operation; if this method returns
     /// Ok there has been successful connection. Note that peripherals allow only one connection at
     /// a time. Operations that attempt to communicate with a device will fail until it is connected.
@@ -370,6 +493,9 @@ impl ApiPeripheral for Peripheral {
             Box::new(move |is_connected| {
                 if let Some(shared) = shared_clone.upgrade() {
                     shared.connected.store(is_connected, Ordering::Relaxed);
+                    if !is_connected {
+                        *shared.connected_since.write().unwrap() = None;
+                    }
                 }
 
                 if !is_connected {
@@ -385,27 +511,74 @@ impl ApiPeripheral for Peripheral {
         let mut d = self.shared.device.lock().await;
         *d = Some(device);
         self.shared.connected.store(true, Ordering::Relaxed);
+        *self.shared.connected_since.write().unwrap() = Some(SystemTime::now());
         self.emit_event(CentralEvent::DeviceConnected(self.shared.address.into()));
         Ok(())
     }
 
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        match options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.connect())
+                .await
+                .map_err(|_| Error::TimedOut(timeout))??,
+            None => self.connect().await?,
+        }
+        if options.resolve_name && self.shared.local_name.read().unwrap().is_none() {
+            self.resolve_name().await?;
+        }
+        Ok(())
+    }
+
+    async fn optimize_throughput(&self) -> Result<ConnectionInfo> {
+        {
+            let mut device = self.shared.device.lock().await;
+            let device = device.as_mut().ok_or(Error::NotConnected)?;
+            // The MTU is negotiated by Windows, and the PHY can't be chosen.
+            device.request_throughput_optimized()?;
+        }
+        self.connection_info().await
+    }
+
+    async fn request_exclusive_access(&self) -> Result<()> {
+        // Don't hold the map's locks across the awaits.
+        let services: Vec<_> = self
+            .shared
+            .ble_services
+            .iter()
+            .map(|service| service.service.clone())
+            .collect();
+        for service in &services {
+            BLEDevice::open_exclusive(service).await?;
+        }
+        Ok(())
+    }
+
     /// Terminates a connection to the device. This is a synchronous operation.
     async fn disconnect(&self) -> Result<()> {
         let mut device = self.shared.device.lock().await;
         *device = None;
         self.shared.connected.store(false, Ordering::Relaxed);
+        *self.shared.connected_since.write().unwrap() = None;
         self.emit_event(CentralEvent::DeviceDisconnected(self.shared.address.into()));
         Ok(())
     }
 
     /// Discovers all characteristics for the device. This is a synchronous operation.
     async fn discover_services(&self) -> Result<()> {
+        self.discover_services_with_cache_mode(CacheMode::Cached)
+            .await
+    }
+
+    /// Services already known are only re-discovered when bypassing the cache.
+    async fn discover_services_with_cache_mode(&self, cache_mode: CacheMode) -> Result<()> {
         let device = self.shared.device.lock().await;
         if let Some(ref device) = *device {
-            let gatt_services = device.discover_services().await?;
+            let gatt_services = device.discover_services(cache_mode.into()).await?;
             for service in &gatt_services {
                 let uuid = utils::to_uuid(&service.Uuid().unwrap());
-                if !self.shared.ble_services.contains_key(&uuid) {
+                if cache_mode == CacheMode::Uncached
+                    || !self.shared.ble_services.contains_key(&uuid)
+                {
                     match BLEDevice::get_characteristics(&service).await {
                         Ok(characteristics) => {
                             let characteristics =
@@ -445,6 +618,7 @@ impl ApiPeripheral for Peripheral {
                                 uuid,
                                 BLEService {
                                     uuid,
+                                    service: service.clone(),
                                     characteristics,
                                 },
                             );
@@ -480,6 +654,30 @@ impl ApiPeripheral for Peripheral {
         ble_characteristic.write_value(data, write_type).await
     }
 
+    async fn reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        let transaction = GattReliableWriteTransaction::new()?;
+        for (characteristic, data) in writes {
+            let ble_service = self
+                .shared
+                .ble_services
+                .get(&characteristic.service_uuid)
+                .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
+            let ble_characteristic = ble_service
+                .characteristics
+                .get(&characteristic.uuid)
+                .ok_or_else(|| Error::NotSupported("Characteristic not found for write".into()))?;
+            ble_characteristic.queue_reliable_write(&transaction, data)?;
+        }
+        let status = transaction.CommitAsync()?.await?;
+        if status == GattCommunicationStatus::Success {
+            Ok(())
+        } else {
+            Err(Error::Other(
+                format!("Windows UWP threw error on reliable write: {:?}", status).into(),
+            ))
+        }
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
@@ -495,8 +693,9 @@ impl ApiPeripheral for Peripheral {
         let notifications_sender = self.shared.notifications_channel.clone();
         let uuid = characteristic.uuid;
         ble_characteristic
-            .subscribe(Box::new(move |value| {
-                let notification = ValueNotification { uuid: uuid, value };
+            .subscribe(Box::new(move |value, received_at| {
+                let notification =
+                    ValueNotification::new(uuid, value).with_received_at(received_at);
                 // Note: we ignore send errors here which may happen while there are no
                 // receivers...
                 let _ = notifications_sender.send(notification);
@@ -531,7 +730,81 @@ impl ApiPeripheral for Peripheral {
             .characteristics
             .get(&characteristic.uuid)
             .ok_or_else(|| Error::NotSupported("Characteristic not found for read".into()))?;
-        ble_characteristic.read_value().await
+        ble_characteristic.read_value(CacheMode::Uncached).await
+    }
+
+    async fn read_with_cache_mode(
+        &self,
+        characteristic: &Characteristic,
+        cache_mode: CacheMode,
+    ) -> Result<Vec<u8>> {
+        let ble_service = &*self
+            .shared
+            .ble_services
+            .get(&characteristic.service_uuid)
+            .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
+        let ble_characteristic = ble_service
+            .characteristics
+            .get(&characteristic.uuid)
+            .ok_or_else(|| Error::NotSupported("Characteristic not found for read".into()))?;
+        ble_characteristic.read_value(cache_mode).await
+    }
+
+    async fn pair(&self) -> Result<()> {
+        let agent = self
+            .shared
+            .adapter
+            .upgrade()
+            .and_then(|adapter| adapter.pairing_agent());
+        // Don't hold the lock while the user takes part in pairing, which would block every other
+        // operation on the device, including disconnecting.
+        let device = self
+            .shared
+            .device
+            .lock()
+            .await
+            .as_ref()
+            .ok_or(Error::NotConnected)?
+            .handle();
+        BLEDevice::pair(device, self.id(), agent).await
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        let device = self.shared.device.lock().await;
+        device.as_ref().ok_or(Error::NotConnected)?.unpair().await
+    }
+
+    async fn set_protection_level(
+        &self,
+        characteristic: &Characteristic,
+        level: SecurityLevel,
+    ) -> Result<()> {
+        let ble_service = &*self
+            .shared
+            .ble_services
+            .get(&characteristic.service_uuid)
+            .ok_or_else(|| Error::NotSupported("Service not found".into()))?;
+        let ble_characteristic = ble_service
+            .characteristics
+            .get(&characteristic.uuid)
+            .ok_or_else(|| Error::NotSupported("Characteristic not found".into()))?;
+        ble_characteristic.set_protection_level(level)
+    }
+
+    async fn subscriptions(&self) -> Result<BTreeSet<Characteristic>> {
+        Ok(self
+            .shared
+            .ble_services
+            .iter()
+            .flat_map(|service| {
+                service
+                    .characteristics
+                    .values()
+                    .filter(|characteristic| characteristic.is_subscribed())
+                    .map(|characteristic| characteristic.to_characteristic(service.uuid))
+                    .collect::<Vec<_>>()
+            })
+            .collect())
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
@@ -574,8 +847,17 @@ impl ApiPeripheral for Peripheral {
     }
 }
 
+impl From<LeAddress> for PeripheralId {
+    fn from(address: LeAddress) -> Self {
+        PeripheralId(address)
+    }
+}
+
+/// For code which identified peripherals by address alone. This assumes a public address, so
+/// prefer [`LeAddress::public`] or [`LeAddress::random`], which make the address type explicit.
+// Rust doesn't allow `#[deprecated]` on trait impls, so this can only be deprecated in its docs.
 impl From<BDAddr> for PeripheralId {
     fn from(address: BDAddr) -> Self {
-        PeripheralId(address)
+        PeripheralId(LeAddress::public(address))
     }
 }
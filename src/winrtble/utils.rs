@@ -11,15 +11,23 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::CharPropFlags, Error, Result};
+use crate::{
+    api::{AddressType, CharPropFlags},
+    Error, Result,
+};
 use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 use windows::core::GUID;
 use windows::{
-    Devices::Bluetooth::GenericAttributeProfile::{
-        GattCharacteristicProperties, GattClientCharacteristicConfigurationDescriptorValue,
-        GattCommunicationStatus,
+    Devices::Bluetooth::{
+        BluetoothAddressType,
+        GenericAttributeProfile::{
+            GattCharacteristicProperties, GattClientCharacteristicConfigurationDescriptorValue,
+            GattCommunicationStatus,
+        },
     },
+    Foundation::DateTime,
     Storage::Streams::{DataReader, IBuffer},
 };
 
@@ -37,6 +45,22 @@ pub fn to_error(status: GattCommunicationStatus) -> Result<()> {
     }
 }
 
+/// Converts an address type, taking an unspecified type to be public as Windows does.
+pub fn to_address_type(address_type: BluetoothAddressType) -> AddressType {
+    if address_type == BluetoothAddressType::Random {
+        AddressType::Random
+    } else {
+        AddressType::Public
+    }
+}
+
+pub fn to_bluetooth_address_type(address_type: AddressType) -> BluetoothAddressType {
+    match address_type {
+        AddressType::Public => BluetoothAddressType::Public,
+        AddressType::Random => BluetoothAddressType::Random,
+    }
+}
+
 pub fn to_descriptor_value(
     properties: GattCharacteristicProperties,
 ) -> GattClientCharacteristicConfigurationDescriptorValue {
@@ -51,6 +75,30 @@ pub fn to_descriptor_value(
     }
 }
 
+/// The number of 100 ns ticks between the `DateTime` epoch, 1601-01-01, and the Unix epoch.
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+
+pub fn to_system_time(date_time: DateTime) -> SystemTime {
+    let ticks = date_time.UniversalTime - UNIX_EPOCH_TICKS;
+    let since_epoch = Duration::from_nanos(ticks.unsigned_abs() * 100);
+    if ticks >= 0 {
+        UNIX_EPOCH + since_epoch
+    } else {
+        UNIX_EPOCH - since_epoch
+    }
+}
+
+/// Converts a wall clock time in the recent past to the monotonic clock, assuming that the wall
+/// clock hasn't been adjusted since.
+pub fn to_instant(time: SystemTime) -> Instant {
+    let now = Instant::now();
+    SystemTime::now()
+        .duration_since(time)
+        .ok()
+        .and_then(|age| now.checked_sub(age))
+        .unwrap_or(now)
+}
+
 pub fn to_uuid(uuid: &GUID) -> Uuid {
     let guid_s = format!("{:?}", uuid);
     Uuid::from_str(&guid_s).unwrap()
@@ -120,6 +168,17 @@ mod tests {
         assert_eq!(guid_converted, guid_expected);
     }
 
+    #[test]
+    fn check_date_time_to_system_time_conversion() {
+        let date_time = DateTime {
+            UniversalTime: UNIX_EPOCH_TICKS + 15_000_000,
+        };
+        assert_eq!(
+            to_system_time(date_time),
+            UNIX_EPOCH + Duration::from_millis(1500)
+        );
+    }
+
     #[test]
     fn check_guid_to_uuid_conversion() {
         let uuid_str = "10B201FF-5B3B-45A1-9508-CF3EFCD7BBAF";
@@ -0,0 +1,186 @@
+//! End-to-end conformance tests against a reference GATT server.
+//!
+//! These need real hardware, so they only build with the `conformance-tests` feature. The
+//! reference server can run on a second adapter or a separate machine, and must advertise the local
+//! name given in the `BTLEPLUG_CONFORMANCE_NAME` environment variable (default `btleplug-ref`) and
+//! expose the following service:
+//!
+//! | UUID                                   | Properties             | Behaviour                       |
+//! |----------------------------------------|------------------------|---------------------------------|
+//! | `b1e90000-2f70-4c12-9a2e-1d7a0c5e0000` | primary service        |                                 |
+//! | `b1e90001-2f70-4c12-9a2e-1d7a0c5e0000` | read                   | always reads `btleplug`         |
+//! | `b1e90002-2f70-4c12-9a2e-1d7a0c5e0000` | read, write, write w/o | reads back the last value written |
+//! | `b1e90003-2f70-4c12-9a2e-1d7a0c5e0000` | notify                 | notifies a counter byte every 100ms while subscribed |
+//!
+//! The tests share the one device, so run them serially:
+//!
+//! ```sh
+//! cargo test --features conformance-tests --test conformance -- --test-threads=1
+//! ```
+#![cfg(feature = "conformance-tests")]
+
+use btleplug::api::{Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _};
+use btleplug::api::{ScanFilter, WriteType};
+use btleplug::platform::{Manager, Peripheral};
+use futures::stream::StreamExt;
+use std::time::Duration;
+use tokio::time;
+use uuid::Uuid;
+
+const SERVICE: Uuid = Uuid::from_u128(0xb1e90000_2f70_4c12_9a2e_1d7a0c5e0000);
+const STATIC_VALUE: Uuid = Uuid::from_u128(0xb1e90001_2f70_4c12_9a2e_1d7a0c5e0000);
+const ECHO: Uuid = Uuid::from_u128(0xb1e90002_2f70_4c12_9a2e_1d7a0c5e0000);
+const COUNTER: Uuid = Uuid::from_u128(0xb1e90003_2f70_4c12_9a2e_1d7a0c5e0000);
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn reference_name() -> String {
+    std::env::var("BTLEPLUG_CONFORMANCE_NAME").unwrap_or_else(|_| "btleplug-ref".to_string())
+}
+
+/// Scans on the first adapter until the reference server is found.
+async fn find_reference() -> Peripheral {
+    let manager = Manager::new().await.unwrap();
+    let central = manager
+        .adapters()
+        .await
+        .unwrap()
+        .into_iter()
+        .next()
+        .expect("No Bluetooth adapters");
+    central
+        .start_scan(ScanFilter::default().with_services([SERVICE]))
+        .await
+        .unwrap();
+    let name = reference_name();
+    let peripheral = time::timeout(TIMEOUT, async {
+        loop {
+            for peripheral in central.peripherals().await.unwrap() {
+                let properties = peripheral.properties().await.unwrap();
+                if properties.and_then(|p| p.local_name).as_ref() == Some(&name) {
+                    return peripheral;
+                }
+            }
+            time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await
+    .expect("Reference server not found");
+    central.stop_scan().await.unwrap();
+    peripheral
+}
+
+async fn connect_reference() -> Peripheral {
+    let peripheral = find_reference().await;
+    if !peripheral.is_connected().await.unwrap() {
+        peripheral.connect().await.unwrap();
+    }
+    peripheral.discover_services().await.unwrap();
+    peripheral
+}
+
+fn characteristic(peripheral: &Peripheral, uuid: Uuid) -> Characteristic {
+    peripheral
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == uuid)
+        .unwrap_or_else(|| panic!("Characteristic {} not found", uuid))
+}
+
+#[tokio::test]
+async fn discovery() {
+    let peripheral = connect_reference().await;
+    let service = peripheral
+        .services()
+        .into_iter()
+        .find(|s| s.uuid == SERVICE)
+        .expect("Reference service not found");
+    assert!(service.primary);
+    assert!(characteristic(&peripheral, STATIC_VALUE)
+        .properties
+        .contains(CharPropFlags::READ));
+    assert!(characteristic(&peripheral, ECHO)
+        .properties
+        .contains(CharPropFlags::READ | CharPropFlags::WRITE));
+    assert!(characteristic(&peripheral, COUNTER)
+        .properties
+        .contains(CharPropFlags::NOTIFY));
+    peripheral.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn read() {
+    let peripheral = connect_reference().await;
+    let value = peripheral
+        .read(&characteristic(&peripheral, STATIC_VALUE))
+        .await
+        .unwrap();
+    assert_eq!(value, b"btleplug");
+    peripheral.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn write_and_read_back() {
+    let peripheral = connect_reference().await;
+    let echo = characteristic(&peripheral, ECHO);
+    for (value, write_type) in [
+        (&b"with response"[..], WriteType::WithResponse),
+        (&b"without response"[..], WriteType::WithoutResponse),
+    ] {
+        peripheral.write(&echo, value, write_type).await.unwrap();
+        // Writes without response aren't acknowledged, so give the server a moment.
+        time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(peripheral.read(&echo).await.unwrap(), value);
+    }
+    peripheral.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn notify() {
+    let peripheral = connect_reference().await;
+    let counter = characteristic(&peripheral, COUNTER);
+    let mut notifications = peripheral.notifications().await.unwrap();
+    peripheral.subscribe(&counter).await.unwrap();
+    let received: Vec<_> = time::timeout(
+        TIMEOUT,
+        notifications
+            .by_ref()
+            .filter(|n| futures::future::ready(n.uuid == COUNTER))
+            .take(3)
+            .collect(),
+    )
+    .await
+    .expect("Timed out waiting for notifications");
+    assert_eq!(received.len(), 3);
+    peripheral.unsubscribe(&counter).await.unwrap();
+    peripheral.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let peripheral = connect_reference().await;
+    for _ in 0..3 {
+        peripheral.disconnect().await.unwrap();
+        assert!(!peripheral.is_connected().await.unwrap());
+        peripheral.connect().await.unwrap();
+        assert!(peripheral.is_connected().await.unwrap());
+        peripheral.discover_services().await.unwrap();
+        let value = peripheral
+            .read(&characteristic(&peripheral, STATIC_VALUE))
+            .await
+            .unwrap();
+        assert_eq!(value, b"btleplug");
+    }
+    peripheral.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn mtu() {
+    let peripheral = connect_reference().await;
+    let info = peripheral.connection_info().await.unwrap();
+    // Not every platform reports the MTU, but if it does it must be at least the LE minimum.
+    if let Some(mtu) = info.mtu {
+        assert!(mtu >= 23, "MTU {} is below the minimum", mtu);
+    }
+    peripheral.disconnect().await.unwrap();
+}